@@ -0,0 +1,93 @@
+// Measures the instruction dispatch of the cpu over a million random opcodes, through the handler tables and through
+// the match expression they replaced. Memory is a flat 64K array filled with pseudo random bytes, undefined opcodes as
+// well as HALT and STOP are replaced by NOP so the program never stops. The final registers and a hash of the memory
+// are printed for both, they must be the same.
+//
+// $ cargo run --release --features dispatch-match --example dispatch
+use gameboy::convention::Term;
+use gameboy::cpu::Cpu;
+use gameboy::memory::Memory;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const STEPS: u32 = 1_000_000;
+const ROUNDS: u32 = 20;
+
+struct Flat {
+    data: Vec<u8>,
+}
+
+fn sanitize(v: u8) -> u8 {
+    match v {
+        0x10 | 0x76 | 0xd3 | 0xdb | 0xdd | 0xe3 | 0xe4 | 0xeb | 0xec | 0xed | 0xf4 | 0xfc | 0xfd => 0x00,
+        _ => v,
+    }
+}
+
+impl Memory for Flat {
+    fn get(&self, a: u16) -> u8 {
+        self.data[a as usize]
+    }
+
+    fn set(&mut self, a: u16, v: u8) {
+        self.data[a as usize] = sanitize(v);
+    }
+}
+
+fn flat() -> Flat {
+    // Xorshift, so every run executes the same program.
+    let mut x: u32 = 0x2545_f491;
+    let data = (0..0x10000)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            sanitize(x as u8)
+        })
+        .collect();
+    Flat { data }
+}
+
+fn run(next: fn(&mut Cpu) -> u32) -> (Cpu, Rc<RefCell<Flat>>) {
+    let mem = Rc::new(RefCell::new(flat()));
+    let mut cpu = Cpu::power_up(Term::GB, mem.clone());
+    // Keep interrupts out of the way, IE is part of the random memory.
+    cpu.ei = false;
+    for _ in 0..STEPS {
+        next(&mut cpu);
+    }
+    (cpu, mem)
+}
+
+fn bench(name: &str, next: fn(&mut Cpu) -> u32) {
+    let (cpu, mem) = run(next);
+    let hash = mem
+        .borrow()
+        .data
+        .iter()
+        .fold(0x811c_9dc5u32, |h, &b| (h ^ u32::from(b)).wrapping_mul(0x0100_0193));
+    rog::println!(
+        "{:6} af={:04x} bc={:04x} de={:04x} hl={:04x} sp={:04x} pc={:04x} cycles={} mem={:08x}",
+        name,
+        cpu.reg.get_af(),
+        cpu.reg.get_bc(),
+        cpu.reg.get_de(),
+        cpu.reg.get_hl(),
+        cpu.reg.sp,
+        cpu.reg.pc,
+        cpu.cycles(),
+        hash
+    );
+
+    let now = std::time::Instant::now();
+    for _ in 0..ROUNDS {
+        run(next);
+    }
+    let ns = now.elapsed().as_nanos() as f64 / f64::from(STEPS * ROUNDS);
+    rog::println!("{:6} {:.2} ns per instruction", name, ns);
+}
+
+fn main() {
+    bench("match", Cpu::next_match);
+    bench("tables", Cpu::next);
+}
@@ -0,0 +1,37 @@
+// Measures the ways of writing the four flags: one set_flag per flag, a read-modify-write of F each, against
+// set_flags and set_flags_raw, which store F once. The flags come from a pseudo random sequence, the same for every
+// way, and the final F of each is printed to show they agree.
+//
+// $ cargo run --release --example flags
+use gameboy::register::Flag::{C, H, N, Z};
+use gameboy::register::Register;
+use std::hint::black_box;
+
+const STEPS: u32 = 100_000_000;
+
+fn bench(name: &str, f: impl Fn(&mut Register, u32)) {
+    let mut r = Register::default();
+    let mut x: u32 = 0x2545_f491;
+    let now = std::time::Instant::now();
+    for _ in 0..STEPS {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        f(black_box(&mut r), x);
+    }
+    let ns = now.elapsed().as_nanos() as f64 / f64::from(STEPS);
+    rog::println!("{:<14} f={:02x} {:.2} ns per update", name, r.f, ns);
+}
+
+fn main() {
+    bench("set_flag x4", |r, x| {
+        r.set_flag(Z, x & 0x80 != 0);
+        r.set_flag(N, x & 0x40 != 0);
+        r.set_flag(H, x & 0x20 != 0);
+        r.set_flag(C, x & 0x10 != 0);
+    });
+    bench("set_flags", |r, x| {
+        r.set_flags(x & 0x80 != 0, x & 0x40 != 0, x & 0x20 != 0, x & 0x10 != 0);
+    });
+    bench("set_flags_raw", |r, x| r.set_flags_raw(x as u8));
+}
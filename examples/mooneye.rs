@@ -0,0 +1,25 @@
+// Runs test ROMs of the mooneye-test-suite without a window. They signal their result with the registers when they
+// execute LD B,B, see MotherBoard::run_test, so the exit status tells whether every ROM passed.
+//
+// $ cargo run --release --example mooneye -- ./mts/acceptance/*.gb
+use gameboy::motherboard::{MotherBoard, TestResult};
+
+// The longest of the acceptance tests finish within a few seconds.
+const FRAMES: u64 = 60 * 20;
+
+fn main() {
+    let mut passed = true;
+    for path in std::env::args().skip(1) {
+        let mut mbrd = MotherBoard::power_up(&path);
+        let r = mbrd.run_test(FRAMES);
+        match &r {
+            TestResult::Passed => rog::println!("Passed  {}", path),
+            TestResult::Failed { registers } => rog::println!("Failed  {} {:?}", path, registers),
+            TestResult::Timeout => rog::println!("Timeout {}", path),
+        }
+        passed &= matches!(r, TestResult::Passed);
+    }
+    if !passed {
+        std::process::exit(1);
+    }
+}
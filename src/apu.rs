@@ -1,13 +1,16 @@
 use super::clock::Clock;
+use super::convention::Term;
 use super::cpu;
 use super::memory::Memory;
+use super::state::{Reader, StateError, Stateful, Writer};
 use blip_buf::BlipBuf;
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
+// The part of the APU a set of registers belongs to.
 #[derive(Clone, Eq, PartialEq)]
-enum Channel {
+enum Unit {
     Square1,
     Square2,
     Wave,
@@ -60,7 +63,7 @@ enum Channel {
 //      ....
 //      FF3F 0000 1111 Samples 30 and 31
 struct Register {
-    channel: Channel,
+    unit: Unit,
     nrx0: u8,
     nrx1: u8,
     nrx2: u8,
@@ -70,32 +73,32 @@ struct Register {
 
 impl Register {
     fn get_sweep_period(&self) -> u8 {
-        assert!(self.channel == Channel::Square1);
+        assert!(self.unit == Unit::Square1);
         (self.nrx0 >> 4) & 0x07
     }
 
     fn get_negate(&self) -> bool {
-        assert!(self.channel == Channel::Square1);
+        assert!(self.unit == Unit::Square1);
         self.nrx0 & 0x08 != 0x00
     }
 
     fn get_shift(&self) -> u8 {
-        assert!(self.channel == Channel::Square1);
+        assert!(self.unit == Unit::Square1);
         self.nrx0 & 0x07
     }
 
     fn get_dac_power(&self) -> bool {
-        assert!(self.channel == Channel::Wave);
+        assert!(self.unit == Unit::Wave);
         self.nrx0 & 0x80 != 0x00
     }
 
     fn get_duty(&self) -> u8 {
-        assert!(self.channel == Channel::Square1 || self.channel == Channel::Square2);
+        assert!(self.unit == Unit::Square1 || self.unit == Unit::Square2);
         self.nrx1 >> 6
     }
 
     fn get_length_load(&self) -> u16 {
-        if self.channel == Channel::Wave {
+        if self.unit == Unit::Wave {
             (1 << 8) - u16::from(self.nrx1)
         } else {
             (1 << 6) - u16::from(self.nrx1 & 0x3f)
@@ -103,32 +106,37 @@ impl Register {
     }
 
     fn get_starting_volume(&self) -> u8 {
-        assert!(self.channel != Channel::Wave);
+        assert!(self.unit != Unit::Wave);
         self.nrx2 >> 4
     }
 
     fn get_volume_code(&self) -> u8 {
-        assert!(self.channel == Channel::Wave);
+        assert!(self.unit == Unit::Wave);
         (self.nrx2 >> 5) & 0x03
     }
 
+    // The DAC of the square and noise channels is powered as long as the upper 5 bits of NRx2 are not all zero.
+    fn get_envelope_dac_power(&self) -> bool {
+        self.nrx2 & 0xf8 != 0x00
+    }
+
     fn get_envelope_add_mode(&self) -> bool {
-        assert!(self.channel != Channel::Wave);
+        assert!(self.unit != Unit::Wave);
         self.nrx2 & 0x08 != 0x00
     }
 
     fn get_period(&self) -> u8 {
-        assert!(self.channel != Channel::Wave);
+        assert!(self.unit != Unit::Wave);
         self.nrx2 & 0x07
     }
 
     fn get_frequency(&self) -> u16 {
-        assert!(self.channel != Channel::Noise);
+        assert!(self.unit != Unit::Noise);
         u16::from(self.nrx4 & 0x07) << 8 | u16::from(self.nrx3)
     }
 
     fn set_frequency(&mut self, f: u16) {
-        assert!(self.channel != Channel::Noise);
+        assert!(self.unit != Unit::Noise);
         let h = ((f >> 8) & 0x07) as u8;
         let l = f as u8;
         self.nrx4 = (self.nrx4 & 0xf8) | h;
@@ -136,17 +144,17 @@ impl Register {
     }
 
     fn get_clock_shift(&self) -> u8 {
-        assert!(self.channel == Channel::Noise);
+        assert!(self.unit == Unit::Noise);
         self.nrx3 >> 4
     }
 
     fn get_width_mode_of_lfsr(&self) -> bool {
-        assert!(self.channel == Channel::Noise);
+        assert!(self.unit == Unit::Noise);
         self.nrx3 & 0x08 != 0x00
     }
 
     fn get_dividor_code(&self) -> u8 {
-        assert!(self.channel == Channel::Noise);
+        assert!(self.unit == Unit::Noise);
         self.nrx3 & 0x07
     }
 
@@ -167,29 +175,29 @@ impl Register {
     }
 
     fn get_l_vol(&self) -> u8 {
-        assert!(self.channel == Channel::Mixer);
+        assert!(self.unit == Unit::Mixer);
         (self.nrx0 >> 4) & 0x07
     }
 
     fn get_r_vol(&self) -> u8 {
-        assert!(self.channel == Channel::Mixer);
+        assert!(self.unit == Unit::Mixer);
         self.nrx0 & 0x07
     }
 
     fn get_power(&self) -> bool {
-        assert!(self.channel == Channel::Mixer);
+        assert!(self.unit == Unit::Mixer);
         self.nrx2 & 0x80 != 0x00
     }
 }
 
 impl Register {
-    fn power_up(channel: Channel) -> Self {
-        let nrx1 = match channel {
-            Channel::Square1 | Channel::Square2 => 0x40,
+    fn power_up(unit: Unit) -> Self {
+        let nrx1 = match unit {
+            Unit::Square1 | Unit::Square2 => 0x40,
             _ => 0x00,
         };
         Self {
-            channel,
+            unit,
             nrx0: 0x00,
             nrx1,
             nrx2: 0x00,
@@ -238,14 +246,46 @@ impl FrameSequencer {
 // counter can enable/disable the channel as well.
 // Each length counter is clocked at 256 Hz by the frame sequencer. When clocked while enabled by NRx4 and the counter
 // is not zero, it is decremented. If it becomes zero, the channel is disabled.
+// Between two length clocks of the frame sequencer there is an extra clock: enabling length through NRx4 while the
+// next step of the frame sequencer doesn't clock length decrements a counter that isn't zero, which disables the
+// channel if it becomes zero and the write doesn't trigger it. A trigger in that half that reloads a zero counter
+// with length enabled loads 63 (255 for the wave channel) instead.
 struct LengthCounter {
     reg: Rc<RefCell<Register>>,
     n: u16,
+    // Set while the next step of the frame sequencer doesn't clock the length counters.
+    half: bool,
 }
 
 impl LengthCounter {
     fn power_up(reg: Rc<RefCell<Register>>) -> Self {
-        Self { reg, n: 0x0000 }
+        Self {
+            reg,
+            n: 0x0000,
+            half: true,
+        }
+    }
+
+    // Write NRx4. Bit 7 reads as the enabled flag of the channel, a write with it clear leaves the channel as it is.
+    // Returns true if the write triggers the channel: it is enabled and the length counter reloaded, the caller
+    // completes the trigger event.
+    fn set_nrx4(&mut self, v: u8) -> bool {
+        let was_enabled = self.reg.borrow().get_length_enable();
+        let on = self.reg.borrow().get_trigger();
+        self.reg.borrow_mut().nrx4 = v;
+        self.reg.borrow_mut().set_trigger(on);
+        let trigger = v & 0x80 != 0x00;
+        if self.half && !was_enabled && self.reg.borrow().get_length_enable() && self.n != 0 {
+            self.n -= 1;
+            if self.n == 0 && !trigger {
+                self.reg.borrow_mut().set_trigger(false);
+            }
+        }
+        if trigger {
+            self.reg.borrow_mut().set_trigger(true);
+            self.reload();
+        }
+        trigger
     }
 
     fn next(&mut self) {
@@ -259,11 +299,14 @@ impl LengthCounter {
 
     fn reload(&mut self) {
         if self.n == 0x0000 {
-            self.n = if self.reg.borrow().channel == Channel::Wave {
+            self.n = if self.reg.borrow().unit == Unit::Wave {
                 1 << 8
             } else {
                 1 << 6
             };
+            if self.half && self.reg.borrow().get_length_enable() {
+                self.n -= 1;
+            }
         }
     }
 }
@@ -337,12 +380,16 @@ impl VolumeEnvelope {
 // run AGAIN immediately using this new value, but this second new frequency is not written back.
 // Square 1's frequency can be modified via NR13 and NR14 while sweep is active, but the shadow frequency won't be
 // affected so the next time the sweep updates the channel's frequency this modification will be lost.
+// Clearing the negate bit of NR10 after at least one calculation in negate mode since the trigger disables square 1.
+// The sweep timer is clocked even with a sweep period of 0, it is reloaded from NR10 every time it runs out.
 struct FrequencySweep {
     reg: Rc<RefCell<Register>>,
     timer: Clock,
     enable: bool,
     shadow: u16,
     newfeq: u16,
+    // Set by a calculation in negate mode, cleared by a trigger.
+    negated: bool,
 }
 
 impl FrequencySweep {
@@ -353,10 +400,12 @@ impl FrequencySweep {
             enable: false,
             shadow: 0x0000,
             newfeq: 0x0000,
+            negated: false,
         }
     }
 
     fn reload(&mut self) {
+        self.negated = false;
         self.shadow = self.reg.borrow().get_frequency();
         let p = self.reg.borrow().get_sweep_period();
         // The volume envelope and sweep timers treat a period of 0 as 8.
@@ -372,6 +421,7 @@ impl FrequencySweep {
         let offset = self.shadow >> self.reg.borrow().get_shift();
         if self.reg.borrow().get_negate() {
             self.newfeq = self.shadow.wrapping_sub(offset);
+            self.negated = true;
         } else {
             self.newfeq = self.shadow.wrapping_add(offset);
         }
@@ -384,10 +434,12 @@ impl FrequencySweep {
     }
 
     fn next(&mut self) {
-        if !self.enable || self.reg.borrow().get_sweep_period() == 0 {
+        if self.timer.next(1) == 0x00 {
             return;
         }
-        if self.timer.next(1) == 0x00 {
+        let p = self.reg.borrow().get_sweep_period();
+        self.timer.period = if p == 0 { 8 } else { u32::from(p) };
+        if !self.enable || p == 0 {
             return;
         }
         self.frequency_calculation();
@@ -404,6 +456,9 @@ impl FrequencySweep {
 
 struct Blip {
     data: BlipBuf,
+    // Clock of the last amplitude change, relative to the start of the frame. When a frame ends past it, it is moved
+    // back below 0 of the next frame, wrapping around: only the following change, one timer period later, has to be
+    // positive.
     from: u32,
     ampl: i32,
 }
@@ -445,7 +500,7 @@ struct ChannelSquare {
 }
 
 impl ChannelSquare {
-    fn power_up(blip: BlipBuf, mode: Channel) -> ChannelSquare {
+    fn power_up(blip: BlipBuf, mode: Unit) -> ChannelSquare {
         let reg = Rc::new(RefCell::new(Register::power_up(mode.clone())));
         ChannelSquare {
             reg: reg.clone(),
@@ -474,9 +529,9 @@ impl ChannelSquare {
             } else if (pat >> self.idx) & 0x01 != 0x00 {
                 vol
             } else {
-                vol * -1
+                -vol
             };
-            self.blip.set(self.blip.from + self.timer.period, ampl);
+            self.blip.set(self.blip.from.wrapping_add(self.timer.period), ampl);
             self.idx = (self.idx + 1) % 8;
         }
     }
@@ -496,18 +551,28 @@ impl Memory for ChannelSquare {
 
     fn set(&mut self, a: u16, v: u8) {
         match a {
-            0xff10 | 0xff15 => self.reg.borrow_mut().nrx0 = v,
+            0xff10 | 0xff15 => {
+                self.reg.borrow_mut().nrx0 = v;
+                if self.reg.borrow().unit == Unit::Square1 && self.fs.negated && !self.reg.borrow().get_negate() {
+                    self.reg.borrow_mut().set_trigger(false);
+                }
+            }
             0xff11 | 0xff16 => {
                 self.reg.borrow_mut().nrx1 = v;
                 self.lc.n = self.reg.borrow().get_length_load();
             }
-            0xff12 | 0xff17 => self.reg.borrow_mut().nrx2 = v,
+            0xff12 | 0xff17 => {
+                self.reg.borrow_mut().nrx2 = v;
+                if !self.reg.borrow().get_envelope_dac_power() {
+                    self.reg.borrow_mut().set_trigger(false);
+                }
+            }
             0xff13 | 0xff18 => {
                 self.reg.borrow_mut().nrx3 = v;
                 self.timer.period = period(self.reg.clone());
             }
             0xff14 | 0xff19 => {
-                self.reg.borrow_mut().nrx4 = v;
+                let trigger = self.lc.set_nrx4(v);
                 self.timer.period = period(self.reg.clone());
                 // Trigger Event
                 //
@@ -524,12 +589,15 @@ impl Memory for ChannelSquare {
                 //
                 // Note that if the channel's DAC is off, after the above actions occur the channel will be immediately
                 // disabled again.
-                if self.reg.borrow().get_trigger() {
-                    self.lc.reload();
+                if trigger {
+                    self.timer.n = 0;
                     self.ve.reload();
-                    if self.reg.borrow().channel == Channel::Square1 {
+                    if self.reg.borrow().unit == Unit::Square1 {
                         self.fs.reload();
                     }
+                    if !self.reg.borrow().get_envelope_dac_power() {
+                        self.reg.borrow_mut().set_trigger(false);
+                    }
                 }
             }
             _ => unreachable!(),
@@ -559,11 +627,13 @@ struct ChannelWave {
     blip: Blip,
     waveram: [u8; 16],
     waveidx: usize,
+    // Whether triggering the channel as it reads a sample corrupts wave RAM, as on the monochrome models.
+    corrupt_on_trigger: bool,
 }
 
 impl ChannelWave {
     fn power_up(blip: BlipBuf) -> ChannelWave {
-        let reg = Rc::new(RefCell::new(Register::power_up(Channel::Wave)));
+        let reg = Rc::new(RefCell::new(Register::power_up(Unit::Wave)));
         ChannelWave {
             reg: reg.clone(),
             timer: Clock::power_up(8192),
@@ -571,6 +641,27 @@ impl ChannelWave {
             blip: Blip::power_up(blip),
             waveram: [0x00; 16],
             waveidx: 0x00,
+            corrupt_on_trigger: true,
+        }
+    }
+
+    // Triggering the channel on the DMG while it is playing and reads a sample byte, within the 2 clock cycles before
+    // its timer clocks, overwrites the start of wave RAM with what it reads: only the first byte if the read is among
+    // the first four bytes, otherwise the first four bytes with the four aligned bytes the read is in. elapsed is the
+    // cycles the channel is yet to run up to the trigger.
+    fn trigger_corruption(&mut self, elapsed: u32) {
+        if !self.corrupt_on_trigger || !self.reg.borrow().get_trigger() || !self.reg.borrow().get_dac_power() {
+            return;
+        }
+        let n = self.timer.n + elapsed;
+        if n % self.timer.period < self.timer.period.saturating_sub(2) {
+            return;
+        }
+        let i = ((self.waveidx + (n / self.timer.period) as usize + 1) % 32) / 2;
+        if i < 4 {
+            self.waveram[0] = self.waveram[i];
+        } else {
+            self.waveram.copy_within(i & !0x03..(i & !0x03) + 4, 0);
         }
     }
 
@@ -583,18 +674,20 @@ impl ChannelWave {
             _ => unreachable!(),
         };
         for _ in 0..self.timer.next(cycles) {
+            // The position is advanced first, then the sample is read from the new position. Samples are stored high
+            // nibble first.
+            self.waveidx = (self.waveidx + 1) % 32;
             let sample = if self.waveidx & 0x01 == 0x00 {
-                self.waveram[self.waveidx / 2] & 0x0f
-            } else {
                 self.waveram[self.waveidx / 2] >> 4
+            } else {
+                self.waveram[self.waveidx / 2] & 0x0f
             };
             let ampl = if !self.reg.borrow().get_trigger() || !self.reg.borrow().get_dac_power() {
                 0x00
             } else {
                 i32::from(sample >> s)
             };
-            self.blip.set(self.blip.from + self.timer.period, ampl);
-            self.waveidx = (self.waveidx + 1) % 32;
+            self.blip.set(self.blip.from.wrapping_add(self.timer.period), ampl);
         }
     }
 }
@@ -614,7 +707,13 @@ impl Memory for ChannelWave {
 
     fn set(&mut self, a: u16, v: u8) {
         match a {
-            0xff1a => self.reg.borrow_mut().nrx0 = v,
+            0xff1a => {
+                self.reg.borrow_mut().nrx0 = v;
+                // Clearing the DAC power bit disables the channel, it stays disabled until the next trigger.
+                if !self.reg.borrow().get_dac_power() {
+                    self.reg.borrow_mut().set_trigger(false);
+                }
+            }
             0xff1b => {
                 self.reg.borrow_mut().nrx1 = v;
                 self.lc.n = self.reg.borrow().get_length_load();
@@ -625,11 +724,14 @@ impl Memory for ChannelWave {
                 self.timer.period = period(self.reg.clone());
             }
             0xff1e => {
-                self.reg.borrow_mut().nrx4 = v;
+                let trigger = self.lc.set_nrx4(v);
                 self.timer.period = period(self.reg.clone());
-                if self.reg.borrow().get_trigger() {
-                    self.lc.reload();
+                if trigger {
+                    self.timer.n = 0;
                     self.waveidx = 0x00;
+                    if !self.reg.borrow().get_dac_power() {
+                        self.reg.borrow_mut().set_trigger(false);
+                    }
                 }
             }
             0xff30..=0xff3f => self.waveram[a as usize - 0xff30] = v,
@@ -681,7 +783,7 @@ struct ChannelNoise {
 
 impl ChannelNoise {
     fn power_up(blip: BlipBuf) -> ChannelNoise {
-        let reg = Rc::new(RefCell::new(Register::power_up(Channel::Noise)));
+        let reg = Rc::new(RefCell::new(Register::power_up(Unit::Noise)));
         ChannelNoise {
             reg: reg.clone(),
             timer: Clock::power_up(4096),
@@ -699,9 +801,9 @@ impl ChannelNoise {
             } else if self.lfsr.next() {
                 i32::from(self.ve.volume)
             } else {
-                i32::from(self.ve.volume) * -1
+                -i32::from(self.ve.volume)
             };
-            self.blip.set(self.blip.from + self.timer.period, ampl);
+            self.blip.set(self.blip.from.wrapping_add(self.timer.period), ampl);
         }
     }
 }
@@ -725,17 +827,24 @@ impl Memory for ChannelNoise {
                 self.reg.borrow_mut().nrx1 = v;
                 self.lc.n = self.reg.borrow().get_length_load();
             }
-            0xff21 => self.reg.borrow_mut().nrx2 = v,
+            0xff21 => {
+                self.reg.borrow_mut().nrx2 = v;
+                if !self.reg.borrow().get_envelope_dac_power() {
+                    self.reg.borrow_mut().set_trigger(false);
+                }
+            }
             0xff22 => {
                 self.reg.borrow_mut().nrx3 = v;
                 self.timer.period = period(self.reg.clone());
             }
             0xff23 => {
-                self.reg.borrow_mut().nrx4 = v;
-                if self.reg.borrow().get_trigger() {
-                    self.lc.reload();
+                if self.lc.set_nrx4(v) {
+                    self.timer.n = 0;
                     self.ve.reload();
                     self.lfsr.reload();
+                    if !self.reg.borrow().get_envelope_dac_power() {
+                        self.reg.borrow_mut().set_trigger(false);
+                    }
                 }
             }
             _ => unreachable!(),
@@ -743,6 +852,36 @@ impl Memory for ChannelNoise {
     }
 }
 
+// The four sound channels, see Apu::set_channel_muted and Apu::channel_state.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+// What a channel is playing right now.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChannelState {
+    // Set by a trigger, cleared when the length counter expires, the DAC is powered off or the sweep overflows. The
+    // same as the channel bits of NR52.
+    pub enabled: bool,
+    // The 11 bit frequency of NRx3 and NRx4, 0 for the noise channel.
+    pub frequency: u16,
+    // Clock cycles between two steps of the waveform, or of the LFSR for the noise channel. A square wave plays at
+    // 4194304 / (period * 8) Hz, the wave channel at 4194304 / (period * 32) Hz.
+    pub period: u32,
+    // Current volume, 0-15. The envelope volume for the square and noise channels, the volume code of NR32 scaled to
+    // the same range for the wave channel.
+    pub volume: u8,
+    // Length counter, the steps of 1/256 s left before the channel is disabled if length is enabled in NRx4.
+    pub length: u16,
+}
+
+// Each channel writes amplitude changes into its own blip buffer, timed in CPU clocks. The blip buffer resamples them
+// to the output rate with band-limited synthesis and keeps the fractional clock remainder between frames, so the
+// number of samples produced follows the elapsed emulated time without long-term drift, whatever the output rate.
 pub struct Apu {
     pub buffer: Arc<Mutex<Vec<(f32, f32)>>>,
     reg: Register,
@@ -753,6 +892,8 @@ pub struct Apu {
     channel3: ChannelWave,
     channel4: ChannelNoise,
     sample_rate: u32,
+    // Channels left out of the mix, indexed by Channel. Not part of the save state.
+    muted: [bool; 4],
 }
 
 impl Apu {
@@ -763,17 +904,100 @@ impl Apu {
         let blipbuf4 = create_blipbuf(sample_rate);
         Self {
             buffer: Arc::new(Mutex::new(Vec::new())),
-            reg: Register::power_up(Channel::Mixer),
+            reg: Register::power_up(Unit::Mixer),
             timer: Clock::power_up(cpu::CLOCK_FREQUENCY / 512),
             fs: FrameSequencer::power_up(),
-            channel1: ChannelSquare::power_up(blipbuf1, Channel::Square1),
-            channel2: ChannelSquare::power_up(blipbuf2, Channel::Square2),
+            channel1: ChannelSquare::power_up(blipbuf1, Unit::Square1),
+            channel2: ChannelSquare::power_up(blipbuf2, Unit::Square2),
             channel3: ChannelWave::power_up(blipbuf3),
             channel4: ChannelNoise::power_up(blipbuf4),
             sample_rate,
+            muted: [false; 4],
         }
     }
 
+    // Back to the power up state, the buffer stays shared with the audio output and muted channels stay muted.
+    pub fn reset(&mut self) {
+        let buffer = self.buffer.clone();
+        let muted = self.muted;
+        let corrupt_on_trigger = self.channel3.corrupt_on_trigger;
+        *self = Self::power_up(self.sample_rate);
+        self.buffer = buffer;
+        self.muted = muted;
+        self.channel3.corrupt_on_trigger = corrupt_on_trigger;
+    }
+
+    // Follow the quirks of the model, set by Mmunit::set_apu. Only the GameBoy Color doesn't corrupt wave RAM when its
+    // wave channel is triggered while reading.
+    pub fn set_term(&mut self, term: Term) {
+        self.channel3.corrupt_on_trigger = term != Term::GBC;
+    }
+
+    // Leave a channel out of the output. The channel keeps running as usual, its length counter, envelope and sweep
+    // go on and NR52 reports it the same, so unmuting resumes where the game is.
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    pub fn channel_muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    pub fn channel_state(&self, channel: Channel) -> ChannelState {
+        let (reg, timer, lc, volume) = match channel {
+            Channel::Square1 => (
+                &self.channel1.reg,
+                &self.channel1.timer,
+                &self.channel1.lc,
+                self.channel1.ve.volume,
+            ),
+            Channel::Square2 => (
+                &self.channel2.reg,
+                &self.channel2.timer,
+                &self.channel2.lc,
+                self.channel2.ve.volume,
+            ),
+            Channel::Wave => {
+                let volume = match self.channel3.reg.borrow().get_volume_code() {
+                    0 => 0,
+                    n => 15 >> (n - 1),
+                };
+                (&self.channel3.reg, &self.channel3.timer, &self.channel3.lc, volume)
+            }
+            Channel::Noise => (
+                &self.channel4.reg,
+                &self.channel4.timer,
+                &self.channel4.lc,
+                self.channel4.ve.volume,
+            ),
+        };
+        let reg = reg.borrow();
+        let enabled = reg.get_trigger() && (channel != Channel::Wave || reg.get_dac_power());
+        ChannelState {
+            enabled,
+            frequency: if channel == Channel::Noise {
+                0
+            } else {
+                reg.get_frequency()
+            },
+            period: timer.period,
+            volume,
+            length: lc.n,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Take all samples produced so far, as signed 16 bit stereo pairs at the sample rate given on power up. This is
+    // an alternative to consuming the shared buffer directly, for hosts that pull audio from the emulation thread.
+    pub fn drain_samples(&mut self) -> Vec<(i16, i16)> {
+        let f = |v: f32| (v.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.drain(..).map(|(l, r)| (f(l), f(r))).collect()
+    }
+
     fn play(&mut self, l: &[f32], r: &[f32]) {
         assert_eq!(l.len(), r.len());
         let mut buffer = self.buffer.lock().unwrap();
@@ -805,6 +1029,7 @@ impl Apu {
                 self.channel3.lc.next();
                 self.channel4.lc.next();
             }
+            self.set_length_half();
             if step == 7 {
                 self.channel1.ve.next();
                 self.channel2.ve.next();
@@ -819,14 +1044,23 @@ impl Apu {
             self.channel2.blip.data.end_frame(self.timer.period);
             self.channel3.blip.data.end_frame(self.timer.period);
             self.channel4.blip.data.end_frame(self.timer.period);
-            self.channel1.blip.from -= self.timer.period;
-            self.channel2.blip.from -= self.timer.period;
-            self.channel3.blip.from -= self.timer.period;
-            self.channel4.blip.from -= self.timer.period;
+            self.channel1.blip.from = self.channel1.blip.from.wrapping_sub(self.timer.period);
+            self.channel2.blip.from = self.channel2.blip.from.wrapping_sub(self.timer.period);
+            self.channel3.blip.from = self.channel3.blip.from.wrapping_sub(self.timer.period);
+            self.channel4.blip.from = self.channel4.blip.from.wrapping_sub(self.timer.period);
             self.mix();
         }
     }
 
+    // The length counters are clocked on the even steps of the frame sequencer.
+    fn set_length_half(&mut self) {
+        let half = self.fs.step & 0x01 == 0x00;
+        self.channel1.lc.half = half;
+        self.channel2.lc.half = half;
+        self.channel3.lc.half = half;
+        self.channel4.lc.half = half;
+    }
+
     fn mix(&mut self) {
         let sc1 = self.channel1.blip.data.samples_avail();
         let sc2 = self.channel2.blip.data.samples_avail();
@@ -839,8 +1073,11 @@ impl Apu {
         let sample_count = sc1 as usize;
         let mut sum = 0;
 
-        let l_vol = (f32::from(self.reg.get_l_vol()) / 7.0) * (1.0 / 15.0) * 0.25;
-        let r_vol = (f32::from(self.reg.get_r_vol()) / 7.0) * (1.0 / 15.0) * 0.25;
+        // NR51 routes each channel to the left and right outputs, then NR50 scales each output by (volume + 1) / 8, a
+        // volume of 0 is quiet but not silent. The cartridge VIN input is not emulated, its NR50 enable bits are
+        // ignored.
+        let l_vol = (f32::from(self.reg.get_l_vol() + 1) / 8.0) * (1.0 / 15.0) * 0.25;
+        let r_vol = (f32::from(self.reg.get_r_vol() + 1) / 8.0) * (1.0 / 15.0) * 0.25;
 
         while sum < sample_count {
             let buf_l = &mut [0f32; 2048];
@@ -848,41 +1085,53 @@ impl Apu {
             let buf = &mut [0i16; 2048];
 
             let count1 = self.channel1.blip.data.read_samples(buf, false);
-            for (i, v) in buf[..count1].iter().enumerate() {
-                if self.reg.nrx1 & 0x01 == 0x01 {
+            let n = if self.muted[Channel::Square1 as usize] {
+                0
+            } else {
+                count1
+            };
+            for (i, v) in buf[..n].iter().enumerate() {
+                if self.reg.nrx1 & 0x10 == 0x10 {
                     buf_l[i] += f32::from(*v) * l_vol;
                 }
-                if self.reg.nrx1 & 0x10 == 0x10 {
+                if self.reg.nrx1 & 0x01 == 0x01 {
                     buf_r[i] += f32::from(*v) * r_vol;
                 }
             }
 
             let count2 = self.channel2.blip.data.read_samples(buf, false);
-            for (i, v) in buf[..count2].iter().enumerate() {
-                if self.reg.nrx1 & 0x02 == 0x02 {
+            let n = if self.muted[Channel::Square2 as usize] {
+                0
+            } else {
+                count2
+            };
+            for (i, v) in buf[..n].iter().enumerate() {
+                if self.reg.nrx1 & 0x20 == 0x20 {
                     buf_l[i] += f32::from(*v) * l_vol;
                 }
-                if self.reg.nrx1 & 0x20 == 0x20 {
+                if self.reg.nrx1 & 0x02 == 0x02 {
                     buf_r[i] += f32::from(*v) * r_vol;
                 }
             }
 
             let count3 = self.channel3.blip.data.read_samples(buf, false);
-            for (i, v) in buf[..count3].iter().enumerate() {
-                if self.reg.nrx1 & 0x04 == 0x04 {
+            let n = if self.muted[Channel::Wave as usize] { 0 } else { count3 };
+            for (i, v) in buf[..n].iter().enumerate() {
+                if self.reg.nrx1 & 0x40 == 0x40 {
                     buf_l[i] += f32::from(*v) * l_vol;
                 }
-                if self.reg.nrx1 & 0x40 == 0x40 {
+                if self.reg.nrx1 & 0x04 == 0x04 {
                     buf_r[i] += f32::from(*v) * r_vol;
                 }
             }
 
             let count4 = self.channel4.blip.data.read_samples(buf, false);
-            for (i, v) in buf[..count4].iter().enumerate() {
-                if self.reg.nrx1 & 0x08 == 0x08 {
+            let n = if self.muted[Channel::Noise as usize] { 0 } else { count4 };
+            for (i, v) in buf[..n].iter().enumerate() {
+                if self.reg.nrx1 & 0x80 == 0x80 {
                     buf_l[i] += f32::from(*v) * l_vol;
                 }
-                if self.reg.nrx1 & 0x80 == 0x80 {
+                if self.reg.nrx1 & 0x08 == 0x08 {
                     buf_r[i] += f32::from(*v) * r_vol;
                 }
             }
@@ -939,6 +1188,11 @@ impl Memory for Apu {
         match a {
             0xff10..=0xff14 => self.channel1.set(a, v),
             0xff15..=0xff19 => self.channel2.set(a, v),
+            0xff1e if v & 0x80 != 0x00 => {
+                // The channels only run at the end of every frame sequencer step.
+                self.channel3.trigger_corruption(self.timer.n);
+                self.channel3.set(a, v)
+            }
             0xff1a..=0xff1e => self.channel3.set(a, v),
             0xff1f..=0xff23 => self.channel4.set(a, v),
             0xff24 => self.reg.nrx0 = v,
@@ -989,16 +1243,446 @@ fn create_blipbuf(sample_rate: u32) -> BlipBuf {
 }
 
 fn period(reg: Rc<RefCell<Register>>) -> u32 {
-    match reg.borrow().channel {
-        Channel::Square1 | Channel::Square2 => 4 * (2048 - u32::from(reg.borrow().get_frequency())),
-        Channel::Wave => 2 * (2048 - u32::from(reg.borrow().get_frequency())),
-        Channel::Noise => {
+    match reg.borrow().unit {
+        Unit::Square1 | Unit::Square2 => 4 * (2048 - u32::from(reg.borrow().get_frequency())),
+        Unit::Wave => 2 * (2048 - u32::from(reg.borrow().get_frequency())),
+        Unit::Noise => {
             let d = match reg.borrow().get_dividor_code() {
                 0 => 8,
                 n => (u32::from(n) + 1) * 16,
             };
             d << reg.borrow().get_clock_shift()
         }
-        Channel::Mixer => cpu::CLOCK_FREQUENCY / 512,
+        Unit::Mixer => cpu::CLOCK_FREQUENCY / 512,
+    }
+}
+
+impl Stateful for Register {
+    fn save_state(&self, w: &mut Writer) {
+        for v in [self.nrx0, self.nrx1, self.nrx2, self.nrx3, self.nrx4].iter() {
+            w.u8(*v);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.nrx0 = r.u8()?;
+        self.nrx1 = r.u8()?;
+        self.nrx2 = r.u8()?;
+        self.nrx3 = r.u8()?;
+        self.nrx4 = r.u8()?;
+        Ok(())
+    }
+}
+
+// Samples already in the blip buffer are dropped on load, only the last amplitude is kept so the waveform continues
+// without a jump.
+impl Stateful for Blip {
+    fn save_state(&self, w: &mut Writer) {
+        w.u32(self.from);
+        w.u32(self.ampl as u32);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.data.clear();
+        self.from = r.u32()?;
+        self.ampl = r.u32()? as i32;
+        Ok(())
+    }
+}
+
+impl Stateful for VolumeEnvelope {
+    fn save_state(&self, w: &mut Writer) {
+        self.timer.save_state(w);
+        w.u8(self.volume);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.timer.load_state(r)?;
+        self.volume = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Stateful for ChannelSquare {
+    fn save_state(&self, w: &mut Writer) {
+        self.reg.borrow().save_state(w);
+        self.timer.save_state(w);
+        w.u16(self.lc.n);
+        self.ve.save_state(w);
+        self.fs.timer.save_state(w);
+        w.bool(self.fs.enable);
+        w.u16(self.fs.shadow);
+        w.u16(self.fs.newfeq);
+        w.bool(self.fs.negated);
+        self.blip.save_state(w);
+        w.u8(self.idx);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.reg.borrow_mut().load_state(r)?;
+        self.timer.load_state(r)?;
+        self.lc.n = r.u16()?;
+        self.ve.load_state(r)?;
+        self.fs.timer.load_state(r)?;
+        self.fs.enable = r.bool()?;
+        self.fs.shadow = r.u16()?;
+        self.fs.newfeq = r.u16()?;
+        self.fs.negated = r.bool()?;
+        self.blip.load_state(r)?;
+        self.idx = r.u8()? & 0x07;
+        Ok(())
+    }
+}
+
+impl Stateful for ChannelWave {
+    fn save_state(&self, w: &mut Writer) {
+        self.reg.borrow().save_state(w);
+        self.timer.save_state(w);
+        w.u16(self.lc.n);
+        self.blip.save_state(w);
+        w.bytes(&self.waveram);
+        w.u8(self.waveidx as u8);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.reg.borrow_mut().load_state(r)?;
+        self.timer.load_state(r)?;
+        self.lc.n = r.u16()?;
+        self.blip.load_state(r)?;
+        r.bytes_into(&mut self.waveram, "wave ram")?;
+        self.waveidx = usize::from(r.u8()?) % 32;
+        Ok(())
+    }
+}
+
+impl Stateful for ChannelNoise {
+    fn save_state(&self, w: &mut Writer) {
+        self.reg.borrow().save_state(w);
+        self.timer.save_state(w);
+        w.u16(self.lc.n);
+        self.ve.save_state(w);
+        w.u16(self.lfsr.n);
+        self.blip.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.reg.borrow_mut().load_state(r)?;
+        self.timer.load_state(r)?;
+        self.lc.n = r.u16()?;
+        self.ve.load_state(r)?;
+        self.lfsr.n = r.u16()?;
+        self.blip.load_state(r)
+    }
+}
+
+// The output buffer is left alone, it belongs to the audio host rather than the machine.
+impl Stateful for Apu {
+    fn save_state(&self, w: &mut Writer) {
+        self.reg.save_state(w);
+        self.timer.save_state(w);
+        w.u8(self.fs.step);
+        self.channel1.save_state(w);
+        self.channel2.save_state(w);
+        self.channel3.save_state(w);
+        self.channel4.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.reg.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.fs.step = r.u8()? % 8;
+        self.channel1.load_state(r)?;
+        self.channel2.load_state(r)?;
+        self.channel3.load_state(r)?;
+        self.channel4.load_state(r)?;
+        self.set_length_half();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 512 Hz tone on channel 2, 128 samples per cycle at a 65536 Hz output rate.
+    fn square2(duty: u8) -> Apu {
+        let mut apu = Apu::power_up(65536);
+        apu.set(0xff26, 0x80);
+        apu.set(0xff24, 0x77);
+        apu.set(0xff25, 0x22);
+        apu.set(0xff16, duty << 6);
+        apu.set(0xff17, 0xf0);
+        apu.set(0xff18, 0x00);
+        apu.set(0xff19, 0x87);
+        apu
+    }
+
+    fn run(apu: &mut Apu, seconds: f64) -> Vec<(i16, i16)> {
+        let mut n = (f64::from(cpu::CLOCK_FREQUENCY) * seconds) as u32;
+        while n > 0 {
+            let c = n.min(4096);
+            apu.next(c);
+            n -= c;
+        }
+        apu.drain_samples()
+    }
+
+    #[test]
+    fn square_duty_cycle() {
+        for &(duty, high) in &[(0, 16), (1, 32), (2, 64), (3, 96)] {
+            let mut apu = square2(duty);
+            let s = run(&mut apu, 0.25);
+            // Leave the start to the high-pass filter of the output.
+            let s: Vec<bool> = s[4096..].iter().map(|&(l, _)| l > 0).collect();
+            let rising: Vec<usize> = (1..s.len()).filter(|&i| !s[i - 1] && s[i]).collect();
+            assert!(rising.len() > 50);
+            for w in rising.windows(2) {
+                assert!((127..=129).contains(&(w[1] - w[0])), "duty {}: {:?}", duty, w);
+                let n = s[w[0]..w[1]].iter().filter(|&&b| b).count();
+                assert!((high - 4..=high + 4).contains(&n), "duty {}: {} high", duty, n);
+            }
+        }
+    }
+
+    #[test]
+    fn length_expiry_clears_the_channel_flag() {
+        let mut apu = square2(2);
+        assert_eq!(apu.get(0xff26) & 0x02, 0x02);
+        // Length 1, the first length clock of the frame sequencer expires it. Written right before that clock, the
+        // extra length clock of enabling length doesn't apply.
+        apu.next(cpu::CLOCK_FREQUENCY / 512);
+        apu.set(0xff16, 0x3f);
+        apu.set(0xff19, 0xc7);
+        assert_eq!(apu.get(0xff26) & 0x02, 0x02);
+        run(&mut apu, 0.01);
+        assert_eq!(apu.get(0xff26) & 0x02, 0x00);
+    }
+
+    // The wave channel playing 0, 1, .. 15, 15, 14, .. 0, advancing one sample every 32 clock cycles.
+    fn ramp(volume: u8) -> ChannelWave {
+        let mut wave = ChannelWave::power_up(create_blipbuf(48000));
+        for i in 0..16 {
+            let (h, l) = if i < 8 {
+                (2 * i, 2 * i + 1)
+            } else {
+                (31 - 2 * i, 30 - 2 * i)
+            };
+            wave.set(0xff30 + i, (h << 4 | l) as u8);
+        }
+        wave.set(0xff1a, 0x80);
+        wave.set(0xff1c, volume << 5);
+        wave.set(0xff1d, 0xf0);
+        wave.set(0xff1e, 0x87);
+        wave
+    }
+
+    #[test]
+    fn wave_ramp_and_volume_shift() {
+        for &(volume, shift) in &[(1, 0), (2, 1), (3, 2), (0, 4)] {
+            let mut wave = ramp(volume);
+            for k in 1..=64 {
+                wave.next(32);
+                let k = k % 32;
+                let sample = if k < 16 { k } else { 31 - k };
+                assert_eq!(wave.blip.ampl, sample >> shift, "volume {} tick {}", volume, k);
+            }
+        }
+    }
+
+    #[test]
+    fn wave_dac_off_silences_and_disables() {
+        let mut wave = ramp(1);
+        wave.next(32 * 5);
+        assert_eq!(wave.blip.ampl, 5);
+        wave.set(0xff1a, 0x00);
+        assert!(!wave.reg.borrow().get_trigger());
+        wave.next(32);
+        assert_eq!(wave.blip.ampl, 0);
+        // Triggering does not bring it back while the DAC is off.
+        wave.set(0xff1e, 0x87);
+        assert!(!wave.reg.borrow().get_trigger());
+    }
+
+    #[test]
+    fn muted_noise_leaves_the_square() {
+        let play = |noise: bool, muted: bool| {
+            let mut apu = square2(2);
+            apu.set(0xff25, 0xaa);
+            if noise {
+                apu.set(0xff21, 0xf0);
+                apu.set(0xff22, 0x00);
+                apu.set(0xff23, 0x80);
+            }
+            apu.set_channel_muted(Channel::Noise, muted);
+            let s = run(&mut apu, 0.1);
+            (s, apu.channel_state(Channel::Noise))
+        };
+        let (square, _) = play(false, false);
+        let (mixed, heard) = play(true, false);
+        let (muted, silent) = play(true, true);
+        assert!(square.iter().any(|&(l, _)| l != 0));
+        assert!(mixed != square);
+        assert!(muted == square);
+        // The muted channel plays on.
+        assert_eq!(heard, silent);
+        assert!(silent.enabled);
+        assert_eq!(silent.volume, 15);
+    }
+
+    #[test]
+    fn nr51_routes_and_nr50_scales_the_outputs() {
+        let peak = |nr50: u8, nr51: u8| {
+            let mut apu = square2(2);
+            apu.set(0xff24, nr50);
+            apu.set(0xff25, nr51);
+            let s = run(&mut apu, 0.1);
+            let l = s.iter().map(|&(l, _)| i32::from(l).abs()).max().unwrap();
+            let r = s.iter().map(|&(_, r)| i32::from(r).abs()).max().unwrap();
+            (l, r)
+        };
+        let (l, r) = peak(0x77, 0x20);
+        assert!(l > 0 && r == 0, "{} {}", l, r);
+        let (l, r) = peak(0x77, 0x02);
+        assert!(l == 0 && r > 0);
+        // The outputs are scaled by volume + 1, so volume 0 is an eighth of volume 7 rather than silent.
+        let (full, half) = peak(0x73, 0x22);
+        assert!((half * 2 - full).abs() <= full / 50, "{} {}", full, half);
+        let (quiet, _) = peak(0x00, 0x22);
+        assert!((quiet * 8 - full).abs() <= full / 20, "{} {}", full, quiet);
+    }
+
+    fn square1(nr10: u8, frequency: u16) -> Apu {
+        let mut apu = Apu::power_up(65536);
+        apu.set(0xff26, 0x80);
+        apu.set(0xff10, nr10);
+        apu.set(0xff12, 0xf0);
+        apu.set(0xff13, frequency as u8);
+        apu.set(0xff14, 0x80 | (frequency >> 8) as u8);
+        apu
+    }
+
+    #[test]
+    fn sweep_up_disables_on_overflow() {
+        // Period 1, shift 1: 400h, then 600h, and the next step would be 900h.
+        let mut apu = square1(0x11, 0x400);
+        assert!(apu.channel_state(Channel::Square1).enabled);
+        run(&mut apu, 0.02);
+        let state = apu.channel_state(Channel::Square1);
+        assert_eq!(state.frequency, 0x600);
+        assert!(!state.enabled);
+        assert_eq!(apu.get(0xff26) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn sweep_down_and_the_negate_lockout() {
+        // The first two sweep clocks of the frame sequencer, at 3/512 s and 7/512 s.
+        let mut apu = square1(0x19, 0x400);
+        run(&mut apu, 0.015);
+        assert_eq!(apu.channel_state(Channel::Square1).frequency, 0x100);
+        assert!(apu.channel_state(Channel::Square1).enabled);
+        // Back to adding after a negated calculation disables the channel until the next trigger.
+        apu.set(0xff10, 0x11);
+        assert!(!apu.channel_state(Channel::Square1).enabled);
+        // Without a calculation since the trigger, the negate bit can be cleared.
+        let mut apu = square1(0x08, 0x400);
+        apu.set(0xff10, 0x00);
+        assert!(apu.channel_state(Channel::Square1).enabled);
+    }
+
+    #[test]
+    fn periods_off_the_frame_boundary() {
+        let mut apu = square2(2);
+        apu.set(0xff18, 0x23);
+        apu.set(0xff19, 0x87);
+        assert!(run(&mut apu, 0.1).iter().any(|&(l, _)| l != 0));
+    }
+
+    // Trigger square 2 with a length of 4 after the given number of frame sequencer steps, and count the length
+    // clocks until it stops.
+    fn length_clocks(steps: u32) -> u32 {
+        let mut apu = square2(2);
+        for _ in 0..steps {
+            apu.next(cpu::CLOCK_FREQUENCY / 512);
+        }
+        apu.set(0xff16, 0x80 | (64 - 4));
+        apu.set(0xff19, 0xc7);
+        let mut clocks = 0;
+        let mut length = apu.channel_state(Channel::Square2).length;
+        while apu.channel_state(Channel::Square2).enabled {
+            assert!(clocks < 64);
+            apu.next(cpu::CLOCK_FREQUENCY / 512);
+            let now = apu.channel_state(Channel::Square2).length;
+            clocks += length - now;
+            length = now;
+        }
+        u32::from(clocks)
+    }
+
+    #[test]
+    fn length_stops_the_note_after_its_clocks() {
+        // Triggered right before a length clock, the note lasts exactly 4 clocks. In the other half enabling length
+        // takes the extra clock itself.
+        assert_eq!(length_clocks(1), 4);
+        assert_eq!(length_clocks(0), 3);
+        // Enabling length without a trigger keeps the channel on until the counter runs out.
+        let mut apu = square2(2);
+        apu.set(0xff16, 0x80 | (64 - 4));
+        apu.set(0xff19, 0x87);
+        apu.set(0xff19, 0x47);
+        assert!(apu.channel_state(Channel::Square2).enabled);
+        run(&mut apu, 0.03);
+        assert!(!apu.channel_state(Channel::Square2).enabled);
+    }
+
+    #[test]
+    fn sample_count_follows_emulated_time() {
+        for &rate in &[48000, 44100, 32768] {
+            let mut apu = Apu::power_up(rate);
+            apu.set(0xff26, 0x80);
+            // Samples are handed out at the end of each 1/512 s frame sequencer step, count in whole steps.
+            let mut n = 0;
+            for _ in 0..6 {
+                n += run(&mut apu, 0.5).len() as i64;
+            }
+            assert!((n - i64::from(rate) * 3).abs() <= 1, "{} Hz: {} samples", rate, n);
+        }
+    }
+
+    // Wave RAM after triggering channel 3 playing 00 11 .. FF at a period of 512 cycles, and again after cycles.
+    fn retrigger_wave(term: Term, cycles: u32) -> Vec<u8> {
+        let mut apu = Apu::power_up(65536);
+        apu.set_term(term);
+        apu.set(0xff26, 0x80);
+        for i in 0..16 {
+            apu.set(0xff30 + i, i as u8 * 0x11);
+        }
+        apu.set(0xff1a, 0x80);
+        apu.set(0xff1c, 0x20);
+        apu.set(0xff1d, 0x00);
+        apu.set(0xff1e, 0x87);
+        apu.next(cycles);
+        apu.set(0xff1e, 0x87);
+        // Turning the DAC off stops the channel, so wave RAM reads back as it is.
+        apu.set(0xff1a, 0x00);
+        (0..16).map(|i| apu.get(0xff30 + i)).collect()
+    }
+
+    #[test]
+    fn retriggering_the_wave_channel_corrupts_wave_ram() {
+        let intact: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+        let with = |start: &[u8]| {
+            let mut want = intact.clone();
+            want[..start.len()].copy_from_slice(start);
+            want
+        };
+        // Not about to read a sample.
+        assert_eq!(retrigger_wave(Term::GB, 509), intact);
+        assert_eq!(retrigger_wave(Term::GB, 511), intact);
+        // Reading byte 1, among the first four: only byte 0 is overwritten.
+        assert_eq!(retrigger_wave(Term::GB, 1022), with(&[0x11]));
+        // Further on the four aligned bytes holding the read are copied over the first four.
+        assert_eq!(retrigger_wave(Term::GB, 4606), with(&[0x44, 0x55, 0x66, 0x77]));
+        assert_eq!(retrigger_wave(Term::GB, 8190), with(&[0x88, 0x99, 0xaa, 0xbb]));
+        assert_eq!(retrigger_wave(Term::GBC, 4606), intact);
     }
 }
@@ -0,0 +1,475 @@
+// ROM images are often distributed compressed, as a gzip file or inside a zip archive. Both hold deflate streams,
+// which are decoded here so loading them doesn't need an external crate.
+//
+// A deflate stream is a sequence of blocks, each starting with 3 bits: BFINAL set on the last block, then BTYPE:
+// 00     Stored, the bytes follow as is after the next byte boundary, preceded by their length and its complement
+// 01     Compressed with the fixed Huffman codes
+// 10     Compressed with Huffman codes sent at the start of the block
+// 11     Reserved (error)
+// Compressed blocks are made of literal bytes and of (length, distance) pairs copying bytes already output.
+//
+// See: https://www.rfc-editor.org/rfc/rfc1951 (deflate)
+//      https://www.rfc-editor.org/rfc/rfc1952 (gzip)
+//      https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT (zip)
+use std::path::Path;
+
+// Nothing larger than the largest cartridge, 8MB, is extracted.
+pub const MAX_SIZE: usize = 0x0080_0000;
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(std::io::Error),
+    // The data isn't a valid gzip file or zip archive, or a deflate stream in it is damaged.
+    Corrupt(&'static str),
+    // The zip entry is encrypted or compressed with another method than deflate.
+    Unsupported(String),
+    // The uncompressed data is larger than MAX_SIZE.
+    TooLarge,
+    // The zip archive holds no entry named .gb or .gbc, or none named as requested.
+    NoRom,
+    // The zip archive holds several .gb or .gbc entries, which one to load has to be given to unzip.
+    SeveralRoms(Vec<String>),
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArchiveError::Io(e) => write!(f, "{}", e),
+            ArchiveError::Corrupt(s) => write!(f, "Archive is corrupt: {}", s),
+            ArchiveError::Unsupported(s) => write!(f, "Unsupported archive entry: {}", s),
+            ArchiveError::TooLarge => write!(f, "Archive entry is larger than {} bytes", MAX_SIZE),
+            ArchiveError::NoRom => write!(f, "Archive has no rom"),
+            ArchiveError::SeveralRoms(names) => write!(f, "Archive has several roms: {}", names.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(e: std::io::Error) -> Self {
+        ArchiveError::Io(e)
+    }
+}
+
+// Read a ROM image from a file, decompressed first if its extension is .gz or .zip. A zip archive must hold a single
+// .gb or .gbc entry.
+pub fn read_rom(path: impl AsRef<Path>) -> Result<Vec<u8>, ArchiveError> {
+    let data = std::fs::read(path.as_ref())?;
+    let ext = path
+        .as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("gz") => gunzip(&data),
+        Some("zip") => unzip(&data, None),
+        _ => Ok(data),
+    }
+}
+
+// The .gb and .gbc entries of a zip archive, in the order they are stored.
+pub fn zip_roms(data: &[u8]) -> Result<Vec<String>, ArchiveError> {
+    Ok(zip_entries(data)?
+        .into_iter()
+        .map(|e| e.name)
+        .filter(|n| is_rom_name(n))
+        .collect())
+}
+
+// Extract the entry named name from a zip archive, or without a name its only .gb or .gbc entry.
+pub fn unzip(data: &[u8], name: Option<&str>) -> Result<Vec<u8>, ArchiveError> {
+    let entries = zip_entries(data)?;
+    let entry = match name {
+        Some(name) => entries.iter().find(|e| e.name == name).ok_or(ArchiveError::NoRom)?,
+        None => {
+            let roms: Vec<&ZipEntry> = entries.iter().filter(|e| is_rom_name(&e.name)).collect();
+            match roms.len() {
+                0 => return Err(ArchiveError::NoRom),
+                1 => roms[0],
+                _ => return Err(ArchiveError::SeveralRoms(roms.iter().map(|e| e.name.clone()).collect())),
+            }
+        }
+    };
+    if entry.flags & 0x0001 != 0x0000 {
+        return Err(ArchiveError::Unsupported(format!("{} is encrypted", entry.name)));
+    }
+    // The local header repeats the name, and its extra field may differ from the one in the central directory.
+    let at = entry.offset;
+    if le32(data, at)? != 0x0403_4b50 {
+        return Err(ArchiveError::Corrupt("bad local header"));
+    }
+    let start = at + 30 + usize::from(le16(data, at + 26)?) + usize::from(le16(data, at + 28)?);
+    let raw = data
+        .get(start..start + entry.size)
+        .ok_or(ArchiveError::Corrupt("truncated entry"))?;
+    let r = match entry.method {
+        0 => raw.to_vec(),
+        8 => inflate(raw)?,
+        n => {
+            return Err(ArchiveError::Unsupported(format!(
+                "{} uses compression method {}",
+                entry.name, n
+            )))
+        }
+    };
+    if crc32(&r) != entry.crc {
+        return Err(ArchiveError::Corrupt("crc mismatch"));
+    }
+    Ok(r)
+}
+
+// Decompress a gzip file of a single member.
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    if data.len() < 18 || data[0] != 0x1f || data[1] != 0x8b || data[2] != 0x08 {
+        return Err(ArchiveError::Corrupt("not a gzip file"));
+    }
+    let flags = data[3];
+    let mut at = 10;
+    // FEXTRA, FNAME, FCOMMENT and FHCRC.
+    if flags & 0x04 != 0x00 {
+        at += 2 + usize::from(le16(data, at)?);
+    }
+    for bit in [0x08, 0x10] {
+        if flags & bit != 0x00 {
+            let n = data.get(at..).and_then(|s| s.iter().position(|&c| c == 0x00));
+            at += n.ok_or(ArchiveError::Corrupt("truncated header"))? + 1;
+        }
+    }
+    if flags & 0x02 != 0x00 {
+        at += 2;
+    }
+    let body = data
+        .get(at..data.len() - 8)
+        .ok_or(ArchiveError::Corrupt("truncated header"))?;
+    let r = inflate(body)?;
+    let n = data.len() - 8;
+    if crc32(&r) != le32(data, n)? || r.len() as u32 != le32(data, n + 4)? {
+        return Err(ArchiveError::Corrupt("crc mismatch"));
+    }
+    Ok(r)
+}
+
+struct ZipEntry {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    size: usize,
+    offset: usize,
+}
+
+fn is_rom_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with(".gb") || name.ends_with(".gbc")
+}
+
+// The central directory at the end of the archive lists every entry, it is found from the end of central directory
+// record that closes the archive, followed by a comment of up to 65535 bytes.
+fn zip_entries(data: &[u8]) -> Result<Vec<ZipEntry>, ArchiveError> {
+    let eocd = (0..data.len().saturating_sub(21))
+        .rev()
+        .take(0x10000)
+        .find(|&i| data[i..i + 4] == [0x50, 0x4b, 0x05, 0x06])
+        .ok_or(ArchiveError::Corrupt("not a zip archive"))?;
+    let n = le16(data, eocd + 10)?;
+    let mut at = le32(data, eocd + 16)? as usize;
+    let mut r = Vec::new();
+    for _ in 0..n {
+        if le32(data, at)? != 0x0201_4b50 {
+            return Err(ArchiveError::Corrupt("bad central directory"));
+        }
+        let name_len = usize::from(le16(data, at + 28)?);
+        let name = data
+            .get(at + 46..at + 46 + name_len)
+            .ok_or(ArchiveError::Corrupt("truncated entry"))?;
+        r.push(ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            flags: le16(data, at + 8)?,
+            method: le16(data, at + 10)?,
+            crc: le32(data, at + 16)?,
+            size: le32(data, at + 20)? as usize,
+            offset: le32(data, at + 42)? as usize,
+        });
+        at += 46 + name_len + usize::from(le16(data, at + 30)?) + usize::from(le16(data, at + 32)?);
+    }
+    Ok(r)
+}
+
+fn le16(data: &[u8], at: usize) -> Result<u16, ArchiveError> {
+    let b = data.get(at..at + 2).ok_or(ArchiveError::Corrupt("truncated"))?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn le32(data: &[u8], at: usize) -> Result<u32, ArchiveError> {
+    let b = data.get(at..at + 4).ok_or(ArchiveError::Corrupt("truncated"))?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut c = 0xffff_ffff_u32;
+    for &b in data {
+        c ^= u32::from(b);
+        for _ in 0..8 {
+            let poly = if c & 0x01 != 0x00 { 0xedb8_8320 } else { 0x0000_0000 };
+            c = poly ^ (c >> 1);
+        }
+    }
+    !c
+}
+
+// Bits are read from the least significant bit of each byte. Huffman codes are packed starting with their most
+// significant bit, all other values starting with their least significant bit.
+struct Bits<'a> {
+    data: &'a [u8],
+    at: usize,
+    // Bits left over from the last byte read, and how many.
+    buf: u32,
+    n: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn bits(&mut self, n: u32) -> Result<u32, ArchiveError> {
+        while self.n < n {
+            let b = *self
+                .data
+                .get(self.at)
+                .ok_or(ArchiveError::Corrupt("truncated deflate stream"))?;
+            self.at += 1;
+            self.buf |= u32::from(b) << self.n;
+            self.n += 8;
+        }
+        let r = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.n -= n;
+        Ok(r)
+    }
+
+    fn align(&mut self) {
+        self.buf = 0;
+        self.n = 0;
+    }
+}
+
+// A canonical Huffman code, given by the number of codes of each length and the symbols ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn from_lengths(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &l in lengths {
+            counts[usize::from(l)] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for l in 1..16 {
+            for (s, _) in lengths.iter().enumerate().filter(|(_, &v)| v == l) {
+                symbols.push(s as u16);
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, r: &mut Bits) -> Result<u16, ArchiveError> {
+        // Codes of each length follow the last code of the previous length shifted left.
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for l in 1..16 {
+            code |= r.bits(1)? as i32;
+            let count = i32::from(self.counts[l]);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(ArchiveError::Corrupt("bad huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// Order the lengths of the code length code are sent in.
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Decompress a raw deflate stream.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let mut r = Bits {
+        data,
+        at: 0,
+        buf: 0,
+        n: 0,
+    };
+    let mut w = Vec::new();
+    loop {
+        let last = r.bits(1)? == 1;
+        match r.bits(2)? {
+            0 => {
+                r.align();
+                let n = le16(data, r.at)?;
+                if le16(data, r.at + 2)? != !n {
+                    return Err(ArchiveError::Corrupt("bad stored block length"));
+                }
+                let start = r.at + 4;
+                let block = data
+                    .get(start..start + usize::from(n))
+                    .ok_or(ArchiveError::Corrupt("truncated"))?;
+                w.extend_from_slice(block);
+                r.at = start + usize::from(n);
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let lit = Huffman::from_lengths(&lengths);
+                let dist = Huffman::from_lengths(&[5; 30]);
+                codes(&mut r, &mut w, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic(&mut r)?;
+                codes(&mut r, &mut w, &lit, &dist)?;
+            }
+            _ => return Err(ArchiveError::Corrupt("bad block type")),
+        }
+        if w.len() > MAX_SIZE {
+            return Err(ArchiveError::TooLarge);
+        }
+        if last {
+            return Ok(w);
+        }
+    }
+}
+
+// The code lengths of a dynamic block are themselves Huffman coded, with runs: 16 repeats the previous length 3-6
+// times, 17 and 18 repeat a zero 3-10 and 11-138 times.
+fn dynamic(r: &mut Bits) -> Result<(Huffman, Huffman), ArchiveError> {
+    let nlit = r.bits(5)? as usize + 257;
+    let ndist = r.bits(5)? as usize + 1;
+    let nclen = r.bits(4)? as usize + 4;
+    let mut clen = [0u8; 19];
+    for &i in CLEN_ORDER.iter().take(nclen) {
+        clen[i] = r.bits(3)? as u8;
+    }
+    let clen = Huffman::from_lengths(&clen);
+    let mut lengths = Vec::with_capacity(nlit + ndist);
+    while lengths.len() < nlit + ndist {
+        let (v, n) = match clen.decode(r)? {
+            l @ 0..=15 => (l as u8, 1),
+            16 => {
+                let prev = *lengths.last().ok_or(ArchiveError::Corrupt("repeat without a length"))?;
+                (prev, 3 + r.bits(2)? as usize)
+            }
+            17 => (0, 3 + r.bits(3)? as usize),
+            _ => (0, 11 + r.bits(7)? as usize),
+        };
+        if lengths.len() + n > nlit + ndist {
+            return Err(ArchiveError::Corrupt("too many code lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(v, n));
+    }
+    Ok((
+        Huffman::from_lengths(&lengths[..nlit]),
+        Huffman::from_lengths(&lengths[nlit..]),
+    ))
+}
+
+fn codes(r: &mut Bits, w: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) -> Result<(), ArchiveError> {
+    loop {
+        let s = usize::from(lit.decode(r)?);
+        if s < 256 {
+            w.push(s as u8);
+            continue;
+        }
+        if s == 256 {
+            return Ok(());
+        }
+        let s = s - 257;
+        if s >= 29 {
+            return Err(ArchiveError::Corrupt("bad length code"));
+        }
+        let n = usize::from(LENGTH_BASE[s]) + r.bits(u32::from(LENGTH_EXTRA[s]))? as usize;
+        let d = usize::from(dist.decode(r)?);
+        if d >= 30 {
+            return Err(ArchiveError::Corrupt("bad distance code"));
+        }
+        let d = usize::from(DIST_BASE[d]) + r.bits(u32::from(DIST_EXTRA[d]))? as usize;
+        if d > w.len() {
+            return Err(ArchiveError::Corrupt("distance too far back"));
+        }
+        if w.len() + n > MAX_SIZE {
+            return Err(ArchiveError::TooLarge);
+        }
+        // The copy may overlap the bytes it produces.
+        let start = w.len() - d;
+        for i in 0..n {
+            w.push(w[start + i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The fox sentence of inflate_the_three_block_types deflated by zlib at level 9, a single block with the fixed
+    // Huffman codes.
+    const FOX_FIXED: [u8; 63] = [
+        0x0b, 0xc9, 0x48, 0x55, 0x28, 0x2c, 0xcd, 0x4c, 0xce, 0x56, 0x48, 0x2a, 0xca, 0x2f, 0xcf, 0x53, 0x48, 0xcb,
+        0xaf, 0x50, 0xc8, 0x2a, 0xcd, 0x2d, 0x28, 0x56, 0xc8, 0x2f, 0x4b, 0x2d, 0x52, 0x28, 0x01, 0x4a, 0xe7, 0x24,
+        0x56, 0x55, 0x2a, 0xa4, 0xe4, 0xa7, 0xeb, 0x29, 0x84, 0xd0, 0x4c, 0xb1, 0x7b, 0x62, 0x6e, 0xaa, 0x53, 0x7e,
+        0x25, 0x9c, 0x76, 0xce, 0xcf, 0xc9, 0x2f, 0x02, 0x00,
+    ];
+
+    // The text deflated into SOUND_DYNAMIC.
+    const SOUND: &str =
+        "The Game Boy has four sound channels: two square waves with adjustable duty, a programmable wave \
+        table and a noise generator. Each channel has a length counter, and the square and noise channels \
+        have volume envelopes. Channel 1 has a frequency sweep. The sound controller mixes them into two \
+        outputs, left and right, with a master volume for each.";
+
+    // SOUND deflated by zlib at level 9, a single block with its own Huffman codes.
+    const SOUND_DYNAMIC: [u8; 211] = [
+        0x35, 0x90, 0x4b, 0x72, 0x84, 0x30, 0x0c, 0x44, 0xaf, 0xd2, 0x07, 0xa0, 0xa8, 0x9a, 0x6d, 0x96, 0x49, 0xa5,
+        0x72, 0x81, 0xb9, 0x80, 0x02, 0x02, 0x33, 0x65, 0x5b, 0x8c, 0x2c, 0x43, 0xb8, 0x7d, 0xc4, 0x6f, 0x69, 0x5b,
+        0x7a, 0xfd, 0xda, 0xcf, 0xc0, 0xf8, 0xa1, 0xc4, 0xf8, 0x94, 0x0d, 0x81, 0x0a, 0x06, 0xa9, 0x8a, 0x22, 0x35,
+        0xf7, 0xe8, 0x02, 0xe5, 0xcc, 0xb1, 0x7c, 0xc0, 0x56, 0x41, 0x79, 0x57, 0x52, 0xc6, 0x4a, 0x0b, 0x17, 0xac,
+        0x93, 0x05, 0x50, 0xff, 0xaa, 0xc5, 0xe8, 0x37, 0x32, 0xfa, 0x6a, 0x5b, 0x03, 0xc2, 0xac, 0x32, 0x2a, 0xa5,
+        0x74, 0x5c, 0xee, 0xa3, 0x38, 0xdf, 0xc9, 0x71, 0x84, 0x2c, 0x53, 0x61, 0x8c, 0x9c, 0x59, 0xc9, 0x44, 0x5b,
+        0x7c, 0x53, 0x17, 0xee, 0x98, 0x23, 0x9d, 0x10, 0x39, 0x8f, 0xce, 0xee, 0xdc, 0xc0, 0x58, 0x9b, 0x63, 0xd3,
+        0x5c, 0xf2, 0x8a, 0xdf, 0x8f, 0x27, 0xe6, 0xb6, 0xf3, 0x3d, 0x8f, 0x59, 0x24, 0x56, 0x6f, 0xc1, 0x79, 0xe1,
+        0x28, 0x33, 0x97, 0x16, 0x5f, 0x17, 0xf6, 0x71, 0x81, 0x07, 0xe5, 0x77, 0xe5, 0xdc, 0x6d, 0x28, 0x2b, 0xf3,
+        0xdc, 0xe2, 0xb9, 0x53, 0xcf, 0xa2, 0x92, 0x4d, 0x25, 0x46, 0x56, 0xa4, 0xe9, 0xcf, 0xeb, 0x79, 0x60, 0xc2,
+        0x94, 0x4d, 0x8e, 0xe6, 0x52, 0x6d, 0xae, 0x56, 0x1a, 0x77, 0x1b, 0xec, 0x30, 0xd0, 0x69, 0x0c, 0xd6, 0x5c,
+        0xbf, 0x80, 0x44, 0xc5, 0x55, 0x6f, 0x85, 0x41, 0x14, 0xec, 0xbd, 0xda, 0x7f,
+    ];
+
+    #[test]
+    fn inflate_the_three_block_types() {
+        let fox = "The quick brown fox jumps over the lazy dog. ".repeat(3) + "GameBoy GameBoy Color";
+        assert_eq!(FOX_FIXED[0] & 0x07, 0x03);
+        assert_eq!(inflate(&FOX_FIXED).unwrap(), fox.as_bytes());
+        assert_eq!(SOUND_DYNAMIC[0] & 0x07, 0x05);
+        assert_eq!(inflate(&SOUND_DYNAMIC).unwrap(), SOUND.as_bytes());
+        // A stored block that isn't the last, then the fixed block.
+        let mut stored = vec![0x00, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        stored.extend_from_slice(&FOX_FIXED);
+        assert_eq!(inflate(&stored).unwrap(), ("abc".to_string() + &fox).as_bytes());
+        assert!(matches!(inflate(&[0x07]), Err(ArchiveError::Corrupt(_))));
+    }
+}
@@ -8,39 +8,231 @@
 // Reference:
 //   - http://gbdev.gg8.se/wiki/articles/The_Cartridge_Header
 //   - http://gbdev.gg8.se/wiki/articles/Memory_Bank_Controllers
+#[cfg(feature = "compression")]
+use super::archive::{self, ArchiveError};
 use super::memory::Memory;
-use std::fs::File;
-use std::io::{Read, Write};
+use super::state::{Reader, StateError, Stateful, Writer};
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Deref;
+#[cfg(feature = "std")]
 use std::path::{Path, PathBuf};
+#[cfg(feature = "std")]
 use std::time::SystemTime;
 
 pub trait Stable {
     fn sav(&self);
 }
 
-// This is a 32kB (256kb) ROM and occupies 0000-7FFF.
+// The file a cartridge keeps its battery backed RAM or its clock in, none for a cartridge without a battery or loaded
+// from memory. Without std there is no file system and a cartridge never has one: the host keeps what it needs of the
+// machine through the save states.
+#[derive(Clone, Default)]
+pub struct SavFile {
+    #[cfg(feature = "std")]
+    path: Option<PathBuf>,
+}
+
+#[cfg(feature = "std")]
+impl SavFile {
+    pub fn is_empty(&self) -> bool {
+        self.path.is_none()
+    }
+
+    // The file of the same name with another extension, game.gb keeps the RAM in game.sav.
+    fn with_extension(&self, ext: &str) -> SavFile {
+        SavFile {
+            path: self.path.as_ref().map(|p| p.with_extension(ext)),
+        }
+    }
+
+    fn read(&self) -> Option<Vec<u8>> {
+        self.path.as_ref().and_then(|p| std::fs::read(p).ok())
+    }
+
+    fn write(&self, data: &[u8]) {
+        if let Some(p) = &self.path {
+            rog::debugln!("Ram is being persisted");
+            std::fs::write(p, data).unwrap()
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl SavFile {
+    pub fn is_empty(&self) -> bool {
+        true
+    }
+
+    fn with_extension(&self, _: &str) -> SavFile {
+        SavFile {}
+    }
+
+    fn read(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn write(&self, _: &[u8]) {}
+}
+
+// An empty path is no file.
+#[cfg(feature = "std")]
+impl From<&Path> for SavFile {
+    fn from(p: &Path) -> Self {
+        SavFile {
+            path: Some(p.to_path_buf()).filter(|p| !p.as_os_str().is_empty()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<PathBuf> for SavFile {
+    fn from(p: PathBuf) -> Self {
+        SavFile::from(p.as_path())
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<&str> for SavFile {
+    fn from(p: &str) -> Self {
+        SavFile::from(Path::new(p))
+    }
+}
+
+// The ROM image of a cartridge. It is never written, so the forks of a machine share it instead of copying it: a
+// static image is borrowed, any other one is reference counted.
+#[derive(Clone)]
+pub enum Rom {
+    Static(&'static [u8]),
+    Shared(Arc<[u8]>),
+}
+
+impl Deref for Rom {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Rom::Static(rom) => rom,
+            Rom::Shared(rom) => rom,
+        }
+    }
+}
+
+impl From<Cow<'static, [u8]>> for Rom {
+    fn from(rom: Cow<'static, [u8]>) -> Self {
+        match rom {
+            Cow::Borrowed(rom) => Rom::Static(rom),
+            Cow::Owned(rom) => Rom::Shared(rom.into()),
+        }
+    }
+}
+
+impl From<&'static [u8]> for Rom {
+    fn from(rom: &'static [u8]) -> Self {
+        Rom::Static(rom)
+    }
+}
+
+impl From<Vec<u8>> for Rom {
+    fn from(rom: Vec<u8>) -> Self {
+        Rom::Shared(rom.into())
+    }
+}
+
+impl From<Arc<[u8]>> for Rom {
+    fn from(rom: Arc<[u8]>) -> Self {
+        Rom::Shared(rom)
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    // The cartridge has no battery backed RAM to restore.
+    NoBattery,
+    // The supplied buffer doesn't match the RAM size of the cartridge.
+    SizeMismatch { expected: usize, actual: usize },
+}
+
+impl core::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            LoadError::NoBattery => write!(f, "Cartridge has no battery backed ram"),
+            LoadError::SizeMismatch { expected, actual } => {
+                write!(f, "Ram size mismatch, expected {} bytes but got {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl core::error::Error for LoadError {}
+
+// Restore battery backed RAM, the buffer must match the size of the RAM exactly.
+fn ram_load(ram: &mut [u8], data: &[u8]) -> Result<(), LoadError> {
+    if ram.len() != data.len() {
+        return Err(LoadError::SizeMismatch {
+            expected: ram.len(),
+            actual: data.len(),
+        });
+    }
+    ram.copy_from_slice(data);
+    Ok(())
+}
+
+// Write v at A000-BFFF of the given 8KB RAM bank, out of range writes are dropped.
+fn ram_poke(ram: &mut [u8], bank: usize, a: u16, v: u8) {
+    if let Some(b) = ram.get_mut(bank * 0x2000 + (a as usize & 0x1fff)) {
+        *b = v;
+    }
+}
+
+// This is a 32kB (256kb) ROM and occupies 0000-7FFF. There is no MBC, writes to the ROM area are ignored. The
+// cartridge may have up to 8kB of RAM at A000-BFFF, always enabled and optionally backed by a battery.
+#[derive(Clone)]
 pub struct RomOnly {
-    rom: Vec<u8>,
+    rom: Rom,
+    ram: Vec<u8>,
+    sav_path: SavFile,
 }
 
 impl RomOnly {
-    pub fn power_up(rom: Vec<u8>) -> Self {
-        RomOnly { rom }
+    pub fn power_up(rom: impl Into<Rom>, ram: Vec<u8>, sav: impl Into<SavFile>) -> Self {
+        RomOnly {
+            rom: rom.into(),
+            ram,
+            sav_path: sav.into(),
+        }
     }
 }
 
 impl Memory for RomOnly {
     fn get(&self, a: u16) -> u8 {
-        self.rom[a as usize]
+        match a {
+            0x0000..=0x7fff => self.rom[a as usize % self.rom.len()],
+            0xa000..=0xbfff => self.ram.get(a as usize - 0xa000).copied().unwrap_or(0xff),
+            _ => 0x00,
+        }
     }
 
-    fn set(&mut self, _: u16, _: u8) {}
+    fn set(&mut self, a: u16, v: u8) {
+        if let 0xa000..=0xbfff = a {
+            if let Some(b) = self.ram.get_mut(a as usize - 0xa000) {
+                *b = v;
+            }
+        }
+    }
 }
 
 impl Stable for RomOnly {
-    fn sav(&self) {}
+    fn sav(&self) {
+        self.sav_path.write(&self.ram);
+    }
 }
 
+#[derive(Clone)]
 enum BankMode {
     Rom,
     Ram,
@@ -71,7 +263,8 @@ enum BankMode {
 // gameboy. Usually the following values are used:
 //   00h  Disable RAM (default)
 //   0Ah  Enable RAM
-// Practically any value with 0Ah in the lower 4 bits enables RAM, and any other value disables RAM.
+// Practically any value with 0Ah in the lower 4 bits enables RAM, and any other value disables RAM. While it is
+// disabled A000-BFFF reads FFh, as an open bus, and writes are ignored. So it is on all the MBCs.
 //
 // 2000-3FFF - ROM Bank Number (Write Only)
 // Writing to this address space selects the lower 5 bits of the ROM Bank Number (in range 01-1Fh). When 00h is written,
@@ -91,60 +284,86 @@ enum BankMode {
 //   01h = RAM Banking Mode (up to 32KByte RAM, 512KByte ROM)
 // The program may freely switch between both modes, the only limitiation is that only RAM Bank 00h can be used during
 // Mode 0, and only ROM Banks 00-1Fh can be used during Mode 1.
+// In fact the two bits always make the upper bits of the bank at 4000-7FFF. Mode 1 only applies them to the rest of
+// the cartridge as well: they select the RAM bank, and on 1MByte and larger ROMs bank 00h, 20h, 40h or 60h at
+// 0000-3FFF. Only bit 0 of the mode is decoded. Bank numbers beyond the ROM or RAM size wrap around.
+//
+// The 1MByte multicarts (MBC1M) leave bit 4 of the ROM bank number unconnected and wire the 2bit register to bits 4-5
+// instead, so it selects one of four 256KByte games and the lower 4 bits the bank within the game. Bank 00h still
+// translates to 01h from the full 5 bits, writing 10h maps bank 0 of the game at 4000-7FFF as well.
+#[derive(Clone)]
 pub struct Mbc1 {
-    rom: Vec<u8>,
+    rom: Rom,
     ram: Vec<u8>,
     bank_mode: BankMode, // MBC1 has two different maximum memory modes: 16Mbit ROM/8KByte RAM or 4Mbit ROM/32KByte RAM.
     bank: u8,
     ram_enable: bool,
-    sav_path: PathBuf,
+    multicart: bool,
+    sav_path: SavFile,
 }
 
 impl Mbc1 {
-    pub fn power_up(rom: Vec<u8>, ram: Vec<u8>, sav: impl AsRef<Path>) -> Self {
+    pub fn power_up(rom: impl Into<Rom>, ram: Vec<u8>, sav: impl Into<SavFile>) -> Self {
         Mbc1 {
-            rom,
+            rom: rom.into(),
             ram,
             bank_mode: BankMode::Rom, // The MBC1 defaults to 16Mbit ROM/8KByte RAM mode on power up.
             bank: 0x01,
             ram_enable: false,
-            sav_path: PathBuf::from(sav.as_ref()),
+            multicart: false,
+            sav_path: sav.into(),
+        }
+    }
+
+    // Use the MBC1M wiring of the multicarts.
+    pub fn power_up_multicart(rom: impl Into<Rom>, ram: Vec<u8>, sav: impl Into<SavFile>) -> Self {
+        Mbc1 {
+            multicart: true,
+            ..Self::power_up(rom, ram, sav)
         }
     }
 
-    fn rom_bank(&self) -> usize {
-        let n = match self.bank_mode {
-            BankMode::Rom => self.bank & 0x7f,
-            BankMode::Ram => self.bank & 0x1f,
+    fn rom_index(&self, a: u16) -> usize {
+        let (upper, lower) = if self.multicart {
+            ((self.bank & 0x60) >> 1, self.bank & 0x0f)
+        } else {
+            (self.bank & 0x60, self.bank & 0x1f)
+        };
+        let bank = match a {
+            0x0000..=0x3fff => match self.bank_mode {
+                BankMode::Rom => 0x00,
+                BankMode::Ram => upper,
+            },
+            _ => upper | lower,
         };
-        n as usize
+        (usize::from(bank) * 0x4000 + (a as usize & 0x3fff)) % self.rom.len()
     }
 
+    // With 32KB of RAM, the 2 bit register at 4000-5FFF selects one of the four 8KB banks in mode 1. Mode 0 locks the
+    // RAM to bank 0, whatever the register holds.
     fn ram_bank(&self) -> usize {
-        let n = match self.bank_mode {
+        match self.bank_mode {
             BankMode::Rom => 0x00,
-            BankMode::Ram => (self.bank & 0x60) >> 5,
-        };
-        n as usize
+            BankMode::Ram => usize::from((self.bank & 0x60) >> 5),
+        }
+    }
+
+    fn ram_index(&self, a: u16) -> Option<usize> {
+        if !self.ram_enable || self.ram.is_empty() {
+            return None;
+        }
+        Some((self.ram_bank() * 0x2000 + a as usize - 0xa000) % self.ram.len())
     }
 }
 
 impl Memory for Mbc1 {
     fn get(&self, a: u16) -> u8 {
         match a {
-            0x0000..=0x3fff => self.rom[a as usize],
-            0x4000..=0x7fff => {
-                let i = self.rom_bank() * 0x4000 + a as usize - 0x4000;
-                self.rom[i]
-            }
-            0xa000..=0xbfff => {
-                if self.ram_enable {
-                    let i = self.ram_bank() * 0x2000 + a as usize - 0xa000;
-                    self.ram[i]
-                } else {
-                    0x00
-                }
-            }
+            0x0000..=0x7fff => self.rom[self.rom_index(a)],
+            0xa000..=0xbfff => match self.ram_index(a) {
+                Some(i) => self.ram[i],
+                None => 0xff,
+            },
             _ => 0x00,
         }
     }
@@ -152,8 +371,7 @@ impl Memory for Mbc1 {
     fn set(&mut self, a: u16, v: u8) {
         match a {
             0xa000..=0xbfff => {
-                if self.ram_enable {
-                    let i = self.ram_bank() * 0x2000 + a as usize - 0xa000;
+                if let Some(i) = self.ram_index(a) {
                     self.ram[i] = v;
                 }
             }
@@ -172,11 +390,9 @@ impl Memory for Mbc1 {
                 let n = v & 0x03;
                 self.bank = self.bank & 0x9f | (n << 5)
             }
-            0x6000..=0x7fff => match v {
-                0x00 => self.bank_mode = BankMode::Rom,
-                0x01 => self.bank_mode = BankMode::Ram,
-                n => panic!("Invalid cartridge type {}", n),
-            },
+            0x6000..=0x7fff => {
+                self.bank_mode = if v & 0x01 == 0x00 { BankMode::Rom } else { BankMode::Ram };
+            }
             _ => {}
         }
     }
@@ -184,13 +400,7 @@ impl Memory for Mbc1 {
 
 impl Stable for Mbc1 {
     fn sav(&self) {
-        rog::debugln!("Ram is being persisted");
-        if self.sav_path.to_str().unwrap().is_empty() {
-            return;
-        }
-        File::create(self.sav_path.clone())
-            .and_then(|mut f| f.write_all(&self.ram))
-            .unwrap()
+        self.sav_path.write(&self.ram);
     }
 }
 
@@ -203,35 +413,37 @@ impl Stable for Mbc1 {
 // A000-A1FF - 512x4bits RAM, built-in into the MBC2 chip (Read/Write)
 // The MBC2 doesn't support external RAM, instead it includes 512x4 bits of built-in RAM (in the MBC2 chip itself). It
 // still requires an external battery to save data during power-off though. As the data consists of 4bit values, only
-// the lower 4 bits of the "bytes" in this memory area are used.
+// the lower 4 bits of the "bytes" in this memory area are used, the upper 4 bits read back as 1s. Only 9 address bits
+// are decoded, so the RAM is mirrored 16 times through A200-BFFF.
 //
-// 0000-1FFF - RAM Enable (Write Only)
-// The least significant bit of the upper address byte must be zero to enable/disable cart RAM. For example the
-// following addresses can be used to enable/disable cart RAM: 0000-00FF, 0200-02FF, 0400-04FF, ..., 1E00-1EFF.
-// The suggested address range to use for MBC2 ram enable/disable is 0000-00FF.
-//
-// 2000-3FFF - ROM Bank Number (Write Only)
-// Writing a value (XXXXBBBB - X = Don't cares, B = bank select bits) into 2000-3FFF area will select an appropriate ROM
-// bank at 4000-7FFF.
-// The least significant bit of the upper address byte must be one to select a ROM bank. For example the following
-// addresses can be used to select a ROM bank: 2100-21FF, 2300-23FF, 2500-25FF, ..., 3F00-3FFF. The suggested address
-// range to use for MBC2 rom bank selection is 2100-21FF.
+// 0000-3FFF - RAM Enable, ROM Bank Number (Write Only)
+// The least significant bit of the upper address byte selects the register. When it is zero the write enables or
+// disables cart RAM, like on the MBC1 a value of 0Ah in the lower 4 bits enables it. For example the following
+// addresses can be used to enable/disable cart RAM: 0000-00FF, 0200-02FF, 0400-04FF, ..., 3E00-3EFF.
+// When it is one the write (XXXXBBBB - X = Don't cares, B = bank select bits) selects the ROM bank at 4000-7FFF. Like
+// on the MBC1, writing 00h selects bank 01h. For example the following addresses can be used to select a ROM bank:
+// 0100-01FF, 0300-03FF, 0500-05FF, ..., 3F00-3FFF. The suggested address range to use for MBC2 rom bank selection is
+// 2100-21FF.
+#[derive(Clone)]
 pub struct Mbc2 {
-    rom: Vec<u8>,
+    rom: Rom,
     ram: Vec<u8>,
     rom_bank: usize,
     ram_enable: bool,
-    sav_path: PathBuf,
+    sav_path: SavFile,
 }
 
 impl Mbc2 {
-    pub fn power_up(rom: Vec<u8>, ram: Vec<u8>, sav: impl AsRef<Path>) -> Self {
+    pub fn power_up(rom: impl Into<Rom>, mut ram: Vec<u8>, sav: impl Into<SavFile>) -> Self {
+        // Other emulators may store each half byte with its upper bits set.
+        ram.resize(512, 0x00);
+        ram.iter_mut().for_each(|b| *b &= 0x0f);
         Self {
-            rom,
+            rom: rom.into(),
             ram,
             rom_bank: 1,
             ram_enable: false,
-            sav_path: PathBuf::from(sav.as_ref()),
+            sav_path: sav.into(),
         }
     }
 }
@@ -239,16 +451,16 @@ impl Mbc2 {
 impl Memory for Mbc2 {
     fn get(&self, a: u16) -> u8 {
         match a {
-            0x0000..=0x3fff => self.rom[a as usize],
+            0x0000..=0x3fff => self.rom[a as usize % self.rom.len()],
             0x4000..=0x7fff => {
                 let i = self.rom_bank * 0x4000 + a as usize - 0x4000;
-                self.rom[i]
+                self.rom[i % self.rom.len()]
             }
-            0xa000..=0xa1ff => {
+            0xa000..=0xbfff => {
                 if self.ram_enable {
-                    self.ram[(a - 0xa000) as usize]
+                    0xf0 | self.ram[a as usize & 0x01ff]
                 } else {
-                    0x00
+                    0xff
                 }
             }
             _ => 0x00,
@@ -259,19 +471,12 @@ impl Memory for Mbc2 {
         // Only the lower 4 bits of the "bytes" in this memory area are used.
         let v = v & 0x0f;
         match a {
-            0xa000..=0xa1ff => {
-                if self.ram_enable {
-                    self.ram[(a - 0xa000) as usize] = v
-                }
-            }
-            0x0000..=0x1fff => {
+            0xa000..=0xbfff if self.ram_enable => self.ram[a as usize & 0x01ff] = v,
+            0x0000..=0x3fff => {
                 if a & 0x0100 == 0 {
                     self.ram_enable = v == 0x0a;
-                }
-            }
-            0x2000..=0x3fff => {
-                if a & 0x0100 != 0 {
-                    self.rom_bank = v as usize;
+                } else {
+                    self.rom_bank = if v == 0x00 { 0x01 } else { v as usize };
                 }
             }
             _ => {}
@@ -281,107 +486,215 @@ impl Memory for Mbc2 {
 
 impl Stable for Mbc2 {
     fn sav(&self) {
-        rog::debugln!("Ram is being persisted");
-        if self.sav_path.to_str().unwrap().is_empty() {
-            return;
-        }
-        File::create(self.sav_path.clone())
-            .and_then(|mut f| f.write_all(&self.ram))
-            .unwrap()
+        self.sav_path.write(&self.ram);
     }
 }
 
+// The clock counters of the MBC3. The live registers keep ticking in background while the latched registers hold the
+// frozen copy that is visible at A000-BFFF.
+//
+// Time is driven by tick(), so a host may advance the clock by any amount of seconds in a deterministic way. When the
+// clock is backed by a file, the elapsed wall-clock time since the last sync is also applied before every latch and
+// write, and on power up for the time the emulator wasn't running. The wall-clock time comes from a ClockSource, the
+// host clock unless the host sets its own.
+#[derive(Clone)]
 struct RealTimeClock {
     s: u8,
     m: u8,
     h: u8,
     dl: u8,
     dh: u8,
-    zero: u64,
-    sav_path: PathBuf,
+    latched: [u8; 5],
+    latch_ready: bool,
+    wall: Option<u64>,
+    clock: Arc<dyn ClockSource>,
+    sav_path: SavFile,
+}
+
+const RTC_DUMP_SIZE: usize = 18;
+
+// The wall-clock time seen by the real time clock of a cartridge, in seconds. Any epoch works as long as it doesn't
+// change while the cartridge runs, the clock only uses the time elapsed between two reads. Timestamps stored in the
+// save file are taken from the source, a file written with one source should be loaded with a source of the same
+// epoch.
+pub trait ClockSource: Send + Sync {
+    fn now_seconds(&self) -> u64;
+}
+
+// The host clock, seconds since the unix epoch.
+#[cfg(feature = "std")]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl ClockSource for SystemClock {
+    fn now_seconds(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
+
+// The clock of a cartridge until the host sets one. Without std there is no host clock, it stands still so the real
+// time clock only follows the emulated cycles.
+#[cfg(feature = "std")]
+fn host_clock() -> Arc<dyn ClockSource> {
+    Arc::new(SystemClock)
+}
+
+#[cfg(not(feature = "std"))]
+fn host_clock() -> Arc<dyn ClockSource> {
+    struct Stopped;
+
+    impl ClockSource for Stopped {
+        fn now_seconds(&self) -> u64 {
+            0
+        }
+    }
+    Arc::new(Stopped)
 }
 
 impl RealTimeClock {
-    fn power_up(sav_path: impl AsRef<Path>) -> Self {
-        let zero = match std::fs::read(sav_path.as_ref()) {
-            Ok(ok) => {
-                let mut b: [u8; 8] = Default::default();
-                b.copy_from_slice(&ok);
-                u64::from_be_bytes(b)
-            }
-            Err(_) => SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-        };
-        Self {
-            zero,
+    fn power_up(sav_path: SavFile) -> Self {
+        let mut r = Self {
             s: 0,
             m: 0,
             h: 0,
             dl: 0,
             dh: 0,
-            sav_path: sav_path.as_ref().to_path_buf(),
+            latched: [0x00; 5],
+            latch_ready: false,
+            wall: None,
+            clock: host_clock(),
+            sav_path,
+        };
+        if r.sav_path.is_empty() {
+            return r;
+        }
+        r.wall = Some(r.clock.now_seconds());
+        if let Some(ok) = r.sav_path.read() {
+            // Older saves only stored the timestamp at which the clock was zero.
+            if ok.len() == 8 {
+                let mut b: [u8; 8] = Default::default();
+                b.copy_from_slice(&ok);
+                r.wall = Some(u64::from_be_bytes(b));
+                r.sync();
+            } else {
+                r.load(&ok);
+            }
         }
+        r
     }
 
-    fn tic(&mut self) {
-        let d = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-            - self.zero;
-
-        self.s = (d % 60) as u8;
-        self.m = (d / 60 % 60) as u8;
-        self.h = (d / 3600 % 24) as u8;
-        let days = (d / 3600 / 24) as u16;
-        self.dl = (days % 256) as u8;
-        match days {
-            0x0000..=0x00ff => {}
-            0x0100..=0x01ff => {
-                self.dh |= 0x01;
-            }
-            _ => {
-                self.dh |= 0x01;
-                self.dh |= 0x80;
-            }
+    // Live registers, latched registers and the big-endian timestamp of the last sync.
+    fn dump(&self) -> Vec<u8> {
+        let mut b = vec![self.s, self.m, self.h, self.dl, self.dh];
+        b.extend_from_slice(&self.latched);
+        b.extend_from_slice(&self.wall.unwrap_or_else(|| self.clock.now_seconds()).to_be_bytes());
+        b
+    }
+
+    fn load(&mut self, b: &[u8]) {
+        if b.len() != RTC_DUMP_SIZE {
+            return;
+        }
+        self.s = b[0];
+        self.m = b[1];
+        self.h = b[2];
+        self.dl = b[3];
+        self.dh = b[4];
+        self.latched.copy_from_slice(&b[5..10]);
+        if self.wall.is_some() {
+            let mut t: [u8; 8] = Default::default();
+            t.copy_from_slice(&b[10..18]);
+            self.wall = Some(u64::from_be_bytes(t));
+        }
+        self.sync();
+    }
+
+    // Advance the live registers by n seconds. Nothing happens while the halt flag is set. When the 9 bits day counter
+    // overflows the carry bit is set, and it stays set until the program resets it.
+    fn tick(&mut self, n: u64) {
+        if self.dh & 0x40 != 0x00 {
+            return;
         }
+        let mut t = n + u64::from(self.s);
+        self.s = (t % 60) as u8;
+        t = t / 60 + u64::from(self.m);
+        self.m = (t % 60) as u8;
+        t = t / 60 + u64::from(self.h);
+        self.h = (t % 24) as u8;
+        t = t / 24 + (u64::from(self.dh & 0x01) << 8 | u64::from(self.dl));
+        if t > 0x01ff {
+            self.dh |= 0x80;
+        }
+        self.dl = t as u8;
+        self.dh = (self.dh & 0xfe) | ((t >> 8) & 0x01) as u8;
+    }
+
+    fn sync(&mut self) {
+        if let Some(last) = self.wall {
+            let now = self.clock.now_seconds();
+            self.tick(now.saturating_sub(last));
+            self.wall = Some(now);
+        }
+    }
+
+    // Follow the time of the source from now on. The time elapsed on the previous source since the last sync is applied
+    // first, so switching sources doesn't lose or repeat any second.
+    fn set_clock(&mut self, clock: Arc<dyn ClockSource>) {
+        self.sync();
+        self.wall = Some(clock.now_seconds());
+        self.clock = clock;
+    }
+
+    // Copy the live registers into the latched ones.
+    fn latch(&mut self) {
+        self.sync();
+        self.latched = [self.s, self.m, self.h, self.dl, self.dh];
     }
 }
 
 impl Memory for RealTimeClock {
     fn get(&self, a: u16) -> u8 {
         match a {
-            0x08 => self.s,
-            0x09 => self.m,
-            0x0a => self.h,
-            0x0b => self.dl,
-            0x0c => self.dh,
+            0x08..=0x0c => self.latched[a as usize - 0x08],
             _ => panic!("No entry"),
         }
     }
 
     fn set(&mut self, a: u16, v: u8) {
-        match a {
-            0x08 => self.s = v,
-            0x09 => self.m = v,
-            0x0a => self.h = v,
-            0x0b => self.dl = v,
-            0x0c => self.dh = v,
+        self.sync();
+        let v = match a {
+            0x08 => {
+                self.s = v & 0x3f;
+                self.s
+            }
+            0x09 => {
+                self.m = v & 0x3f;
+                self.m
+            }
+            0x0a => {
+                self.h = v & 0x1f;
+                self.h
+            }
+            0x0b => {
+                self.dl = v;
+                self.dl
+            }
+            0x0c => {
+                self.dh = v & 0xc1;
+                self.dh
+            }
             _ => panic!("No entry"),
-        }
+        };
+        self.latched[a as usize - 0x08] = v;
     }
 }
 
 impl Stable for RealTimeClock {
     fn sav(&self) {
-        if self.sav_path.to_str().unwrap().is_empty() {
-            return;
-        }
-        File::create(self.sav_path.clone())
-            .and_then(|mut f| f.write_all(&self.zero.to_be_bytes()))
-            .unwrap()
+        self.sav_path.write(&self.dump());
     }
 }
 
@@ -406,7 +719,7 @@ impl Stable for RealTimeClock {
 // 2000-3FFF - ROM Bank Number (Write Only)
 // Same as for MBC1, except that the whole 7 bits of the RAM Bank Number are written directly to this address. As for
 // the MBC1, writing a value of 00h, will select Bank 01h instead. All other values 01-7Fh select the corresponding
-// ROM Banks.
+// ROM Banks, banks beyond the ROM size wrap around.
 //
 // 4000-5FFF - RAM Bank Number - or - RTC Register Select (Write Only)
 // As for the MBC1s RAM Banking Mode, writing a value in range for 00h-07h maps the corresponding external RAM Bank (
@@ -441,48 +754,56 @@ impl Stable for RealTimeClock {
 // Delays
 // When accessing the RTC Registers it is recommended to execute a 4ms delay (4 Cycles in Normal Speed Mode) between
 // the separate accesses.
+#[derive(Clone)]
 pub struct Mbc3 {
-    rom: Vec<u8>,
+    rom: Rom,
     ram: Vec<u8>,
     rtc: RealTimeClock,
     rom_bank: usize,
     ram_bank: usize,
     ram_enable: bool,
-    sav_path: PathBuf,
+    sav_path: SavFile,
 }
 
 impl Mbc3 {
-    pub fn power_up(rom: Vec<u8>, ram: Vec<u8>, sav: impl AsRef<Path>, rtc: impl AsRef<Path>) -> Self {
+    pub fn power_up(rom: impl Into<Rom>, ram: Vec<u8>, sav: impl Into<SavFile>, rtc: impl Into<SavFile>) -> Self {
         Self {
-            rom,
+            rom: rom.into(),
             ram,
-            rtc: RealTimeClock::power_up(rtc),
+            rtc: RealTimeClock::power_up(rtc.into()),
             rom_bank: 1,
             ram_bank: 0,
             ram_enable: false,
-            sav_path: PathBuf::from(sav.as_ref()),
+            sav_path: sav.into(),
         }
     }
 }
 
+impl Mbc3 {
+    fn has_rtc(&self) -> bool {
+        !self.rtc.sav_path.is_empty()
+    }
+}
+
 impl Memory for Mbc3 {
     fn get(&self, a: u16) -> u8 {
         match a {
-            0x0000..=0x3fff => self.rom[a as usize],
+            0x0000..=0x3fff => self.rom[a as usize % self.rom.len()],
             0x4000..=0x7fff => {
                 let i = self.rom_bank * 0x4000 + a as usize - 0x4000;
-                self.rom[i]
+                self.rom[i % self.rom.len()]
             }
             0xa000..=0xbfff => {
-                if self.ram_enable {
-                    if self.ram_bank <= 0x03 {
+                if !self.ram_enable {
+                    return 0xff;
+                }
+                match self.ram_bank {
+                    0x00..=0x03 => {
                         let i = self.ram_bank * 0x2000 + a as usize - 0xa000;
-                        self.ram[i]
-                    } else {
-                        self.rtc.get(self.ram_bank as u16)
+                        self.ram.get(i).copied().unwrap_or(0xff)
                     }
-                } else {
-                    0x00
+                    0x08..=0x0c => self.rtc.get(self.ram_bank as u16),
+                    _ => 0xff,
                 }
             }
             _ => 0x00,
@@ -492,13 +813,18 @@ impl Memory for Mbc3 {
     fn set(&mut self, a: u16, v: u8) {
         match a {
             0xa000..=0xbfff => {
-                if self.ram_enable {
-                    if self.ram_bank <= 0x03 {
+                if !self.ram_enable {
+                    return;
+                }
+                match self.ram_bank {
+                    0x00..=0x03 => {
                         let i = self.ram_bank * 0x2000 + a as usize - 0xa000;
-                        self.ram[i] = v;
-                    } else {
-                        self.rtc.set(self.ram_bank as u16, v)
+                        if let Some(b) = self.ram.get_mut(i) {
+                            *b = v;
+                        }
                     }
+                    0x08..=0x0c => self.rtc.set(self.ram_bank as u16, v),
+                    _ => {}
                 }
             }
             0x0000..=0x1fff => {
@@ -517,9 +843,10 @@ impl Memory for Mbc3 {
                 self.ram_bank = n;
             }
             0x6000..=0x7fff => {
-                if v & 0x01 != 0 {
-                    self.rtc.tic();
+                if self.rtc.latch_ready && v == 0x01 {
+                    self.rtc.latch();
                 }
+                self.rtc.latch_ready = v == 0x00;
             }
             _ => {}
         }
@@ -528,53 +855,86 @@ impl Memory for Mbc3 {
 
 impl Stable for Mbc3 {
     fn sav(&self) {
-        rog::debugln!("Ram is being persisted");
         self.rtc.sav();
-        if self.sav_path.to_str().unwrap().is_empty() {
-            return;
-        }
-        File::create(self.sav_path.clone())
-            .and_then(|mut f| f.write_all(&self.ram))
-            .unwrap();
+        self.sav_path.write(&self.ram);
     }
 }
 
+// It can map up to 64Mbits (8MByte) of ROM.
+//
+// 0000-3FFF - ROM Bank 00 (Read Only)
+// Same as for MBC1.
+//
+// 4000-7FFF - ROM Bank 00-1FF (Read Only)
+// Same as for MBC1, except that accessing up to bank 1E0h is supported now. Also, bank 0 is actually bank 0.
+//
+// A000-BFFF - RAM Bank 00-0F, if any (Read/Write)
+// Same as for MBC1, except RAM sizes are 8KiB, 32KiB and 128KiB.
+//
+// 0000-1FFF - RAM Enable (Write Only)
+// Mostly the same as for MBC1, a value of 0Ah will enable reading and writing to external RAM. A value of 00h will
+// disable it.
+//
+// 2000-2FFF - Low 8 bits of ROM Bank Number (Write Only)
+// The lower 8 bits of the ROM bank number goes here. Writing 0 will indeed give bank 0 on MBC5, unlike other MBCs.
+//
+// 3000-3FFF - High bit of ROM Bank Number (Write Only)
+// The 9th bit of the ROM bank number goes here.
+//
+// 4000-5FFF - RAM Bank Number (Write Only)
+// As for the MBC1s RAM Banking Mode, writing a value in range for 00h-0Fh maps the corresponding external RAM Bank (if
+// any) into memory at A000-BFFF.
+//
+// Unlike the MBC1 there is no ROM/RAM mode select. Bank numbers beyond the actual ROM/RAM size wrap around, as the
+// unused address lines are simply not connected on smaller cartridges.
+#[derive(Clone)]
 pub struct Mbc5 {
-    rom: Vec<u8>,
+    rom: Rom,
     ram: Vec<u8>,
     rom_bank: usize,
     ram_bank: usize,
     ram_enable: bool,
-    sav_path: PathBuf,
+    sav_path: SavFile,
 }
 
 impl Mbc5 {
-    pub fn power_up(rom: Vec<u8>, ram: Vec<u8>, sav: impl AsRef<Path>) -> Self {
+    pub fn power_up(rom: impl Into<Rom>, ram: Vec<u8>, sav: impl Into<SavFile>) -> Self {
         Self {
-            rom,
+            rom: rom.into(),
             ram,
             rom_bank: 1,
             ram_bank: 0,
             ram_enable: false,
-            sav_path: PathBuf::from(sav.as_ref()),
+            sav_path: sav.into(),
+        }
+    }
+}
+
+impl Mbc5 {
+    fn rom_index(&self, a: u16) -> usize {
+        (self.rom_bank * 0x4000 + a as usize - 0x4000) % self.rom.len()
+    }
+
+    fn ram_index(&self, a: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
         }
+        Some((self.ram_bank * 0x2000 + a as usize - 0xa000) % self.ram.len())
     }
 }
 
 impl Memory for Mbc5 {
     fn get(&self, a: u16) -> u8 {
         match a {
-            0x0000..=0x3fff => self.rom[a as usize],
-            0x4000..=0x7fff => {
-                let i = self.rom_bank * 0x4000 + a as usize - 0x4000;
-                self.rom[i]
-            }
+            0x0000..=0x3fff => self.rom[a as usize % self.rom.len()],
+            0x4000..=0x7fff => self.rom[self.rom_index(a)],
             0xa000..=0xbfff => {
-                if self.ram_enable {
-                    let i = self.ram_bank * 0x2000 + a as usize - 0xa000;
-                    self.ram[i]
-                } else {
-                    0x00
+                if !self.ram_enable {
+                    return 0xff;
+                }
+                match self.ram_index(a) {
+                    Some(i) => self.ram[i],
+                    None => 0xff,
                 }
             }
             _ => 0x00,
@@ -583,9 +943,8 @@ impl Memory for Mbc5 {
 
     fn set(&mut self, a: u16, v: u8) {
         match a {
-            0xa000..=0xbfff => {
-                if self.ram_enable {
-                    let i = self.ram_bank * 0x2000 + a as usize - 0xa000;
+            0xa000..=0xbfff if self.ram_enable => {
+                if let Some(i) = self.ram_index(a) {
                     self.ram[i] = v;
                 }
             }
@@ -602,25 +961,20 @@ impl Memory for Mbc5 {
 
 impl Stable for Mbc5 {
     fn sav(&self) {
-        rog::debugln!("Ram is being persisted");
-        if self.sav_path.to_str().unwrap().is_empty() {
-            return;
-        }
-        File::create(self.sav_path.clone())
-            .and_then(|mut f| f.write_all(&self.ram))
-            .unwrap()
+        self.sav_path.write(&self.ram);
     }
 }
 
 // This controller (made by Hudson Soft) appears to be very similar to an MBC1 with the main difference being that it
 // supports infrared LED input / output. (Similiar to the infrared port that has been later invented in CGBs.)
 // The Japanese cart "Fighting Phoenix" (internal cart name: SUPER B DAMAN) is known to contain this chip.
+#[derive(Clone)]
 pub struct HuC1 {
     cart: Mbc1,
 }
 
 impl HuC1 {
-    pub fn power_up(rom: Vec<u8>, ram: Vec<u8>, sav: impl AsRef<Path>) -> Self {
+    pub fn power_up(rom: impl Into<Rom>, ram: Vec<u8>, sav: impl Into<SavFile>) -> Self {
         Self {
             cart: Mbc1::power_up(rom, ram, sav),
         }
@@ -643,154 +997,378 @@ impl Stable for HuC1 {
     }
 }
 
-// Specifies which Memory Bank Controller (if any) is used in the cartridge, and if further external hardware exists in
-// the cartridge.
-//  00h  ROM ONLY                 19h  MBC5
-//  01h  MBC1                     1Ah  MBC5+RAM
-//  02h  MBC1+RAM                 1Bh  MBC5+RAM+BATTERY
-//  03h  MBC1+RAM+BATTERY         1Ch  MBC5+RUMBLE
-//  05h  MBC2                     1Dh  MBC5+RUMBLE+RAM
-//  06h  MBC2+BATTERY             1Eh  MBC5+RUMBLE+RAM+BATTERY
-//  08h  ROM+RAM                  20h  MBC6
-//  09h  ROM+RAM+BATTERY          22h  MBC7+SENSOR+RUMBLE+RAM+BATTERY
-//  0Bh  MMM01
-//  0Ch  MMM01+RAM
-//  0Dh  MMM01+RAM+BATTERY
-//  0Fh  MBC3+TIMER+BATTERY
-//  10h  MBC3+TIMER+RAM+BATTERY   FCh  POCKET CAMERA
-//  11h  MBC3                     FDh  BANDAI TAMA5
-//  12h  MBC3+RAM                 FEh  HuC3
-//  13h  MBC3+RAM+BATTERY         FFh  HuC1+RAM+BATTERY
-pub fn power_up(path: impl AsRef<Path>) -> Box<dyn Cartridge> {
-    rog::debugln!("Loading cartridge from {:?}", path.as_ref());
-    let mut f = File::open(path.as_ref()).unwrap();
-    let mut rom = Vec::new();
-    f.read_to_end(&mut rom).unwrap();
-    if rom.len() < 0x150 {
-        panic!("Missing required information area which located at 0100-014F")
-    }
-    let rom_max = rom_size(rom[0x0148]);
-    if rom.len() > rom_max {
-        panic!("Rom size more than {}", rom_max);
-    }
-    let cart: Box<dyn Cartridge> = match rom[0x0147] {
-        0x00 => Box::new(RomOnly::power_up(rom)),
-        0x01 => Box::new(Mbc1::power_up(rom, vec![], "")),
-        0x02 => {
-            let ram_max = ram_size(rom[0x0149]);
-            Box::new(Mbc1::power_up(rom, vec![0; ram_max], ""))
-        }
-        0x03 => {
-            let ram_max = ram_size(rom[0x0149]);
-            let sav_path = path.as_ref().to_path_buf().with_extension("sav");
-            let ram = ram_read(sav_path.clone(), ram_max);
-            Box::new(Mbc1::power_up(rom, ram, sav_path))
-        }
-        0x05 => {
-            let ram_max = 512;
-            Box::new(Mbc2::power_up(rom, vec![0; ram_max], ""))
-        }
-        0x06 => {
-            let ram_max = 512;
-            let sav_path = path.as_ref().to_path_buf().with_extension("sav");
-            let ram = ram_read(sav_path.clone(), ram_max);
-            Box::new(Mbc2::power_up(rom, ram, sav_path))
-        }
-        0x0f => {
-            let sav_path = path.as_ref().to_path_buf().with_extension("sav");
-            let rtc_path = path.as_ref().to_path_buf().with_extension("rtc");
-            Box::new(Mbc3::power_up(rom, vec![], sav_path, rtc_path))
-        }
-        0x10 => {
-            let ram_max = ram_size(rom[0x0149]);
-            let sav_path = path.as_ref().to_path_buf().with_extension("sav");
-            let ram = ram_read(sav_path.clone(), ram_max);
-            let rtc_path = path.as_ref().to_path_buf().with_extension("rtc");
-            Box::new(Mbc3::power_up(rom, ram, sav_path, rtc_path))
-        }
-        0x11 => Box::new(Mbc3::power_up(rom, vec![], "", "")),
-        0x12 => {
-            let ram_max = ram_size(rom[0x0149]);
-            Box::new(Mbc3::power_up(rom, vec![0; ram_max], "", ""))
-        }
-        0x13 => {
-            let ram_max = ram_size(rom[0x0149]);
-            let sav_path = path.as_ref().to_path_buf().with_extension("sav");
-            let ram = ram_read(sav_path.clone(), ram_max);
-            Box::new(Mbc3::power_up(rom, ram, sav_path, ""))
-        }
-        0x19 => Box::new(Mbc5::power_up(rom, vec![], "")),
-        0x1a => {
-            let ram_max = ram_size(rom[0x0149]);
-            Box::new(Mbc5::power_up(rom, vec![0; ram_max], ""))
-        }
-        0x1b => {
-            let ram_max = ram_size(rom[0x0149]);
-            let sav_path = path.as_ref().to_path_buf().with_extension("sav");
-            let ram = ram_read(sav_path.clone(), ram_max);
-            Box::new(Mbc5::power_up(rom, ram, sav_path))
-        }
-        0xff => {
-            let ram_max = ram_size(rom[0x0149]);
-            let sav_path = path.as_ref().to_path_buf().with_extension("sav");
-            let ram = ram_read(sav_path.clone(), ram_max);
-            Box::new(HuC1::power_up(rom, ram, sav_path))
+#[derive(Debug)]
+pub enum HeaderError {
+    // The ROM ends before the header area at 0100-014F.
+    TooShort(usize),
+}
+
+impl core::fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            HeaderError::TooShort(n) => write!(f, "Rom of {} bytes is missing the header area at 0100-014F", n),
         }
-        n => panic!("Unsupported cartridge type: 0x{:02x}", n),
-    };
-    rog::debugln!("Cartridge name is {}", cart.title());
-    rog::debugln!("Cartridge type is {}", mbc_info(cart.get(0x0147)));
-    ensure_logo(cart.as_ref());
-    ensure_header_checksum(cart.as_ref());
-    cart
+    }
 }
 
-// Specifies the ROM Size of the cartridge. Typically calculated as "32KB shl N".
-fn rom_size(b: u8) -> usize {
-    let bank = 16384;
-    match b {
-        0x00 => bank * 2,
-        0x01 => bank * 4,
-        0x02 => bank * 8,
-        0x03 => bank * 16,
-        0x04 => bank * 32,
-        0x05 => bank * 64,
-        0x06 => bank * 128,
-        0x07 => bank * 256,
-        0x08 => bank * 512,
+impl core::error::Error for HeaderError {}
+
+// Why a ROM image can't be loaded as a cartridge.
+#[derive(Debug)]
+pub enum CartridgeError {
+    // The ROM ends before the header area at 0100-014F.
+    TooSmall(usize),
+    // The cartridge type at 0147 is a memory bank controller that isn't emulated.
+    UnsupportedMapper(u8),
+    // The ROM size at 0148 or the RAM size at 0149 isn't a known value.
+    UnsupportedRomSize(u8),
+    UnsupportedRamSize(u8),
+    // The length of the ROM doesn't agree with the ROM size declared in the header.
+    SizeMismatch { declared: usize, actual: usize },
+    // The Nintendo logo at 0104-0133 is damaged, the boot ROM would lock up.
+    BadLogo,
+    // The header checksum at 014D doesn't match, the boot ROM would lock up.
+    BadHeaderChecksum,
+}
+
+impl core::fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            CartridgeError::TooSmall(n) => write!(f, "Rom of {} bytes is missing the header area at 0100-014F", n),
+            CartridgeError::UnsupportedMapper(n) => write!(f, "Unsupported cartridge type: 0x{:02x}", n),
+            CartridgeError::UnsupportedRomSize(n) => write!(f, "Unsupported rom size: 0x{:02x}", n),
+            CartridgeError::UnsupportedRamSize(n) => write!(f, "Unsupported ram size: 0x{:02x}", n),
+            CartridgeError::SizeMismatch { declared, actual } => {
+                write!(
+                    f,
+                    "Rom size mismatch, the header declares {} bytes but got {}",
+                    declared, actual
+                )
+            }
+            CartridgeError::BadLogo => write!(f, "Nintendo logo is incorrect"),
+            CartridgeError::BadHeaderChecksum => write!(f, "Cartridge's header checksum is incorrect"),
+        }
+    }
+}
+
+impl core::error::Error for CartridgeError {}
+
+// Why a ROM file can't be loaded by from_path.
+#[cfg(feature = "compression")]
+#[derive(Debug)]
+pub enum OpenError {
+    // The file can't be read or decompressed.
+    Archive(ArchiveError),
+    Cartridge(CartridgeError),
+}
+
+#[cfg(feature = "compression")]
+impl core::fmt::Display for OpenError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            OpenError::Archive(e) => write!(f, "{}", e),
+            OpenError::Cartridge(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+impl core::error::Error for OpenError {}
+
+// An internal information area is located at 0100-014F in each cartridge.
+//
+//  0134-0143  Title. Upper case ASCII, the remaining bytes are filled with 00's.
+//  0143       CGB Flag. 80h means the game supports CGB functions, C0h means the game works on CGB only.
+//  0146       SGB Flag. 03h means the game supports SGB functions.
+//  0147       Cartridge Type.
+//  0148       ROM Size.
+//  0149       RAM Size.
+//  014A       Destination Code. 00h means Japanese, 01h means Non-Japanese.
+//  014D       Header Checksum.
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub cgb_flag: u8,
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub destination_code: u8,
+    pub header_checksum: u8,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, HeaderError> {
+        if rom.len() < 0x0150 {
+            return Err(HeaderError::TooShort(rom.len()));
+        }
+        let title = rom[0x0134..0x0144]
+            .iter()
+            .take_while(|&&c| c != 0x00)
+            .map(|&c| c as char)
+            .collect();
+        Ok(Self {
+            title,
+            cgb_flag: rom[0x0143],
+            sgb_flag: rom[0x0146],
+            cartridge_type: rom[0x0147],
+            rom_size: rom[0x0148],
+            ram_size: rom[0x0149],
+            destination_code: rom[0x014a],
+            header_checksum: rom[0x014d],
+        })
+    }
+
+    // Bytes of ROM declared, see rom_size_bytes.
+    pub fn rom_size_bytes(&self) -> Result<usize, CartridgeError> {
+        rom_size_bytes(self.rom_size)
+    }
+
+    // Bytes of external RAM declared, see ram_size_bytes.
+    pub fn ram_size_bytes(&self) -> Result<usize, CartridgeError> {
+        ram_size_bytes(self.ram_size)
+    }
+
+    // See ensure_header_checksum for the algorithm.
+    pub fn verify_header_checksum(&self, rom: &[u8]) -> bool {
+        if rom.len() < 0x0150 {
+            return false;
+        }
+        let mut v: u8 = 0;
+        for &b in &rom[0x0134..0x014d] {
+            v = v.wrapping_sub(b).wrapping_sub(1);
+        }
+        v == self.header_checksum
+    }
+}
+
+// What the header doesn't tell about a cartridge.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CartridgeOptions {
+    // Wire a MBC1 cartridge as a MBC1M multicart, for the compilations not detected from their ROM. See Mbc1.
+    pub mbc1_multicart: bool,
+    // Use this mapper whatever the cartridge type at 0147, for the dumps and homebrew declaring the wrong one, or a
+    // type that isn't supported. The RAM and battery of the declared type are kept, a type that isn't known declares
+    // RAM of the size at 0149 and no battery. Only a MBC3 declared with a clock keeps it.
+    pub force_mapper: Option<Mapper>,
+    // Bytes of external RAM instead of the size at 0149. The MBC2 always has its 512 half-bytes.
+    pub force_ram_size: Option<usize>,
+    // Whether the RAM is battery backed and saved, instead of what the cartridge type says.
+    pub has_battery: Option<bool>,
+}
+
+// The memory bank controllers emulated, see CartridgeOptions::force_mapper.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mapper {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    HuC1,
+}
+
+// Specifies which Memory Bank Controller (if any) is used in the cartridge, and if further external hardware exists in
+// the cartridge.
+//  00h  ROM ONLY                 19h  MBC5
+//  01h  MBC1                     1Ah  MBC5+RAM
+//  02h  MBC1+RAM                 1Bh  MBC5+RAM+BATTERY
+//  03h  MBC1+RAM+BATTERY         1Ch  MBC5+RUMBLE
+//  05h  MBC2                     1Dh  MBC5+RUMBLE+RAM
+//  06h  MBC2+BATTERY             1Eh  MBC5+RUMBLE+RAM+BATTERY
+//  08h  ROM+RAM                  20h  MBC6
+//  09h  ROM+RAM+BATTERY          22h  MBC7+SENSOR+RUMBLE+RAM+BATTERY
+//  0Bh  MMM01
+//  0Ch  MMM01+RAM
+//  0Dh  MMM01+RAM+BATTERY
+//  0Fh  MBC3+TIMER+BATTERY
+//  10h  MBC3+TIMER+RAM+BATTERY   FCh  POCKET CAMERA
+//  11h  MBC3                     FDh  BANDAI TAMA5
+//  12h  MBC3+RAM                 FEh  HuC3
+//  13h  MBC3+RAM+BATTERY         FFh  HuC1+RAM+BATTERY
+
+#[cfg(feature = "std")]
+pub fn power_up(path: impl AsRef<Path>) -> Box<dyn Cartridge> {
+    power_up_with(path, CartridgeOptions::default())
+}
+
+// Same as power_up, with the wiring overridden by options.
+#[cfg(feature = "std")]
+pub fn power_up_with(path: impl AsRef<Path>, options: CartridgeOptions) -> Box<dyn Cartridge> {
+    rog::debugln!("Loading cartridge from {:?}", path.as_ref());
+    let rom = std::fs::read(path.as_ref()).unwrap();
+    build(rom.into(), SavFile::from(path.as_ref()), options).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// Load a cartridge from a ROM image in memory, such as one embedded with include_bytes!, without copying it. There is
+// no file to persist battery backed RAM and the MBC3 clock to, they start cleared and are never saved.
+pub fn from_rom(rom: impl Into<Cow<'static, [u8]>>) -> Box<dyn Cartridge> {
+    from_rom_with(rom, CartridgeOptions::default())
+}
+
+// Same as from_rom, with the wiring overridden by options.
+pub fn from_rom_with(rom: impl Into<Cow<'static, [u8]>>, options: CartridgeOptions) -> Box<dyn Cartridge> {
+    build(Rom::from(rom.into()), SavFile::default(), options).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// Like from_rom, but a ROM that can't be loaded is reported instead of panicking. The image must also be exactly the
+// size declared in its header, where from_rom accepts images shorter than that.
+pub fn from_bytes(rom: Vec<u8>) -> Result<Box<dyn Cartridge>, CartridgeError> {
+    from_bytes_with(rom, CartridgeOptions::default())
+}
+
+// Same as from_bytes, with the wiring detected from the header overridden by options.
+pub fn from_bytes_with(rom: Vec<u8>, options: CartridgeOptions) -> Result<Box<dyn Cartridge>, CartridgeError> {
+    if rom.len() < 0x0150 {
+        return Err(CartridgeError::TooSmall(rom.len()));
+    }
+    let declared = rom_size_bytes(rom[0x0148])?;
+    if rom.len() != declared {
+        return Err(CartridgeError::SizeMismatch {
+            declared,
+            actual: rom.len(),
+        });
+    }
+    build(rom.into(), SavFile::default(), options)
+}
+
+// Like power_up, but a ROM that can't be loaded is reported instead of panicking, and a ROM compressed in a .gz file
+// or a .zip archive is decompressed first, see archive::read_rom. The save files are kept next to the file given,
+// game.zip saves to game.sav. A zip archive with several ROMs is refused with the list of their names, one of them can
+// be extracted with archive::unzip and loaded with from_bytes.
+#[cfg(feature = "compression")]
+pub fn from_path(path: impl AsRef<Path>) -> Result<Box<dyn Cartridge>, OpenError> {
+    let rom = archive::read_rom(path.as_ref()).map_err(OpenError::Archive)?;
+    build(rom.into(), SavFile::from(path.as_ref()), CartridgeOptions::default()).map_err(OpenError::Cartridge)
+}
+
+// The save files of a cartridge loaded from a file are kept next to it, with the same name.
+fn build(rom: Rom, path: SavFile, options: CartridgeOptions) -> Result<Box<dyn Cartridge>, CartridgeError> {
+    let file = |ext: &str| path.with_extension(ext);
+    if rom.len() < 0x0150 {
+        return Err(CartridgeError::TooSmall(rom.len()));
+    }
+    let declared = rom_size_bytes(rom[0x0148])?;
+    if rom.len() > declared {
+        return Err(CartridgeError::SizeMismatch {
+            declared,
+            actual: rom.len(),
+        });
+    }
+    let (mapper, has_ram, battery, rtc) = match (wiring(rom[0x0147]), options.force_mapper) {
+        (Ok(w), None) => w,
+        (Ok((_, has_ram, battery, rtc)), Some(m)) => (m, has_ram, battery, rtc && m == Mapper::Mbc3),
+        (Err(_), Some(m)) => (m, true, false, false),
+        (Err(e), None) => return Err(e),
+    };
+    let ram_size = match (mapper, options.force_ram_size) {
+        (Mapper::Mbc2, _) => 512,
+        (_, Some(n)) => n,
+        (Mapper::RomOnly, None) if has_ram => ram_size_bytes(rom[0x0149])?.min(0x2000),
+        (_, None) if has_ram => ram_size_bytes(rom[0x0149])?,
+        _ => 0,
+    };
+    let battery = options.has_battery.unwrap_or(battery);
+    let (ram, sav_path) = if battery {
+        let sav_path = file("sav");
+        (ram_read(&sav_path, ram_size), sav_path)
+    } else {
+        (vec![0; ram_size], SavFile::default())
+    };
+    let rtc_path = if rtc && battery {
+        file("rtc")
+    } else {
+        SavFile::default()
+    };
+    let multicart = options.mbc1_multicart || is_mbc1_multicart(&rom);
+    let cart: Box<dyn Cartridge> = match mapper {
+        Mapper::RomOnly => Box::new(RomOnly::power_up(rom, ram, sav_path)),
+        Mapper::Mbc1 if multicart => Box::new(Mbc1::power_up_multicart(rom, ram, sav_path)),
+        Mapper::Mbc1 => Box::new(Mbc1::power_up(rom, ram, sav_path)),
+        Mapper::Mbc2 => Box::new(Mbc2::power_up(rom, ram, sav_path)),
+        Mapper::Mbc3 => Box::new(Mbc3::power_up(rom, ram, sav_path, rtc_path)),
+        Mapper::Mbc5 => Box::new(Mbc5::power_up(rom, ram, sav_path)),
+        Mapper::HuC1 => Box::new(HuC1::power_up(rom, ram, sav_path)),
+    };
+    #[cfg(feature = "std")]
+    rog::debugln!("Cartridge name is {}", cart.title());
+    #[cfg(feature = "std")]
+    rog::debugln!("Cartridge type is {}", mbc_info(cart.get(0x0147)));
+    ensure_logo(cart.as_ref())?;
+    ensure_header_checksum(cart.as_ref())?;
+    Ok(cart)
+}
+
+// The mapper of a cartridge type, and whether the cartridge has external RAM of the size at 0149, a battery and a
+// clock. The MBC2 has its RAM built in whatever 0149 says.
+fn wiring(kind: u8) -> Result<(Mapper, bool, bool, bool), CartridgeError> {
+    let w = match kind {
+        0x00 => (Mapper::RomOnly, false, false, false),
+        0x08 => (Mapper::RomOnly, true, false, false),
+        0x09 => (Mapper::RomOnly, true, true, false),
+        0x01 => (Mapper::Mbc1, false, false, false),
+        0x02 => (Mapper::Mbc1, true, false, false),
+        0x03 => (Mapper::Mbc1, true, true, false),
+        0x05 => (Mapper::Mbc2, false, false, false),
+        0x06 => (Mapper::Mbc2, false, true, false),
+        0x0f => (Mapper::Mbc3, false, true, true),
+        0x10 => (Mapper::Mbc3, true, true, true),
+        0x11 => (Mapper::Mbc3, false, false, false),
+        0x12 => (Mapper::Mbc3, true, false, false),
+        0x13 => (Mapper::Mbc3, true, true, false),
+        0x19 | 0x1c => (Mapper::Mbc5, false, false, false),
+        0x1a | 0x1d => (Mapper::Mbc5, true, false, false),
+        0x1b | 0x1e => (Mapper::Mbc5, true, true, false),
+        0xff => (Mapper::HuC1, true, true, false),
+        n => return Err(CartridgeError::UnsupportedMapper(n)),
+    };
+    Ok(w)
+}
+
+// Bytes of ROM declared by the ROM Size byte at 0148. Typically calculated as "32KB shl N", up to 8MB for 08h. The
+// codes 52h-54h (1.1MB, 1.2MB and 1.5MB) appear in some documentation only, no known cartridge uses them.
+pub fn rom_size_bytes(b: u8) -> Result<usize, CartridgeError> {
+    let bank = 16384;
+    let n = match b {
+        0x00 => bank * 2,
+        0x01 => bank * 4,
+        0x02 => bank * 8,
+        0x03 => bank * 16,
+        0x04 => bank * 32,
+        0x05 => bank * 64,
+        0x06 => bank * 128,
+        0x07 => bank * 256,
+        0x08 => bank * 512,
         0x52 => bank * 72,
         0x53 => bank * 80,
         0x54 => bank * 96,
-        n => panic!("Unsupported rom size: 0x{:02x}", n),
-    }
+        n => return Err(CartridgeError::UnsupportedRomSize(n)),
+    };
+    Ok(n)
 }
 
-// Specifies the size of the external RAM in the cartridge (if any).
-fn ram_size(b: u8) -> usize {
-    match b {
+// Bytes of external RAM declared by the RAM Size byte at 0149, whichever the MBC. 01h (2KB) is unofficial, the MBC2
+// declares 00h for its built-in 512 half-bytes.
+pub fn ram_size_bytes(b: u8) -> Result<usize, CartridgeError> {
+    let n = match b {
         0x00 => 0,
         0x01 => 1024 * 2,
         0x02 => 1024 * 8,
         0x03 => 1024 * 32,
         0x04 => 1024 * 128,
         0x05 => 1024 * 64,
-        n => panic!("Unsupported ram size: 0x{:02x}", n),
-    }
+        n => return Err(CartridgeError::UnsupportedRamSize(n)),
+    };
+    Ok(n)
 }
 
 // Specifies the size of the external RAM in the cartridge (if any).
-fn ram_read(path: impl AsRef<Path>, size: usize) -> Vec<u8> {
-    match File::open(path) {
-        Ok(mut ok) => {
-            let mut ram = Vec::new();
-            ok.read_to_end(&mut ram).unwrap();
-            ram
-        }
-        Err(_) => vec![0; size],
-    }
+fn ram_read(sav: &SavFile, size: usize) -> Vec<u8> {
+    sav.read().unwrap_or_else(|| vec![0; size])
 }
 
 // Readable form of MBC representation
+#[cfg(feature = "std")]
 fn mbc_info(b: u8) -> String {
     String::from(match b {
         0x00 => "ROM ONLY",
@@ -837,13 +1415,20 @@ const NINTENDO_LOGO: [u8; 48] = [
     0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
 ];
 
+// The MBC1M multicarts can't be told apart by their header, but every game of the compilation has one. A 1MByte
+// MBC1 ROM with the logo again in bank 10h, the first bank of the second game, is taken for a multicart.
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    rom.len() == 0x10_0000 && rom[0x4_0104..0x4_0134] == NINTENDO_LOGO
+}
+
 // Ensure Nintendo Logo.
-fn ensure_logo(cart: &dyn Cartridge) {
+fn ensure_logo(cart: &dyn Cartridge) -> Result<(), CartridgeError> {
     for i in 0..48 {
         if cart.get(0x0104 + i as u16) != NINTENDO_LOGO[i as usize] {
-            panic!("Nintendo logo is incorrect")
+            return Err(CartridgeError::BadLogo);
         }
     }
+    Ok(())
 }
 
 // In position 0x14d, contains an 8 bit checksum across the cartridge header bytes 0134-014C. The checksum is
@@ -853,17 +1438,81 @@ fn ensure_logo(cart: &dyn Cartridge) {
 //
 // The lower 8 bits of the result must be the same than the value in this entry. The GAME WON'T WORK if this
 // checksum is incorrect.
-fn ensure_header_checksum(cart: &dyn Cartridge) {
+fn ensure_header_checksum(cart: &dyn Cartridge) -> Result<(), CartridgeError> {
     let mut v: u8 = 0;
     for i in 0x0134..0x014d {
         v = v.wrapping_sub(cart.get(i)).wrapping_sub(1);
     }
     if cart.get(0x014d) != v {
-        panic!("Cartridge's header checksum is incorrect")
+        return Err(CartridgeError::BadHeaderChecksum);
     }
+    Ok(())
 }
 
-pub trait Cartridge: Memory + Stable + Send {
+pub trait Cartridge: Memory + Stable + Stateful + Send {
+    // The whole ROM image, regardless of the bank currently mapped.
+    fn rom(&self) -> &[u8];
+
+    // An independent copy of the cartridge, sharing the ROM image. The copy saves to the same files.
+    fn fork(&self) -> Box<dyn Cartridge>;
+
+    // Contains a 16 bit checksum (upper byte first) across the whole cartridge ROM. Produced by adding all bytes of
+    // the cartridge (except for the two checksum bytes). The gameboy doesn't verify this checksum, so the result is
+    // informational only.
+    fn verify_global_checksum(&self) -> bool {
+        let rom = self.rom();
+        if rom.len() < 0x0150 {
+            return false;
+        }
+        let mut v: u16 = 0;
+        for (i, &b) in rom.iter().enumerate() {
+            if i != 0x014e && i != 0x014f {
+                v = v.wrapping_add(u16::from(b));
+            }
+        }
+        v == u16::from_be_bytes([rom[0x014e], rom[0x014f]])
+    }
+
+    // The bank mapped at the address, in 0000-3FFF, 4000-7FFF or A000-BFFF. For RAM it is the bank selected whether RAM
+    // is enabled or not, on the MBC3 it may be a clock register (08h-0Ch).
+    fn bank(&self, a: u16) -> usize {
+        match a {
+            0x4000..=0x7fff => 1,
+            _ => 0,
+        }
+    }
+
+    // Whether A000-BFFF can be read and written, as set through RAM Enable on the cartridges with a bank controller.
+    fn ram_enabled(&self) -> bool {
+        false
+    }
+
+    // The banking mode selected at 6000-7FFF on the MBC1, 0 on the controllers without modes.
+    fn bank_mode(&self) -> u8 {
+        0
+    }
+
+    // Advance the cartridge's real time clock by n seconds, if it has one.
+    fn tick_rtc(&mut self, _: u64) {}
+
+    // Take the wall-clock time of the real time clock from the source, if the cartridge has a clock. The clock then
+    // also follows the source when it isn't backed by a file.
+    fn set_clock(&mut self, _: Arc<dyn ClockSource>) {}
+
+    // Write external RAM at A000-BFFF in the given bank, whatever bank is mapped and whether RAM is enabled. Used by
+    // cheat devices.
+    fn poke_ram(&mut self, _: usize, _: u16, _: u8) {}
+
+    // Copy of the battery backed RAM, None if the cartridge has no battery.
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    // Restore the battery backed RAM from a buffer returned by save_ram.
+    fn load_ram(&mut self, _: &[u8]) -> Result<(), LoadError> {
+        Err(LoadError::NoBattery)
+    }
+
     // Title of the game in UPPER CASE ASCII. If it is less than 16 characters then the remaining bytes are filled with
     // 00's. When inventing the CGB, Nintendo has reduced the length of this area to 15 characters, and some months
     // later they had the fantastic idea to reduce it to 11 characters only. The new meaning of the ex-title bytes is
@@ -882,9 +1531,817 @@ pub trait Cartridge: Memory + Stable + Send {
     }
 }
 
-impl Cartridge for RomOnly {}
-impl Cartridge for Mbc1 {}
-impl Cartridge for Mbc2 {}
-impl Cartridge for Mbc3 {}
-impl Cartridge for Mbc5 {}
-impl Cartridge for HuC1 {}
+impl Cartridge for RomOnly {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn fork(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+
+    fn ram_enabled(&self) -> bool {
+        !self.ram.is_empty()
+    }
+
+    fn poke_ram(&mut self, _: usize, a: u16, v: u8) {
+        ram_poke(&mut self.ram, 0, a, v)
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if self.sav_path.is_empty() {
+            return None;
+        }
+        Some(self.ram.clone())
+    }
+
+    fn load_ram(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        if self.sav_path.is_empty() {
+            return Err(LoadError::NoBattery);
+        }
+        ram_load(&mut self.ram, data)
+    }
+}
+impl Cartridge for Mbc1 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn fork(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+
+    fn bank(&self, a: u16) -> usize {
+        match a {
+            0x0000..=0x7fff => self.rom_index(a) / 0x4000,
+            _ => self.ram_bank(),
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable
+    }
+
+    fn bank_mode(&self) -> u8 {
+        match self.bank_mode {
+            BankMode::Rom => 0,
+            BankMode::Ram => 1,
+        }
+    }
+
+    fn poke_ram(&mut self, bank: usize, a: u16, v: u8) {
+        ram_poke(&mut self.ram, bank, a, v)
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if self.sav_path.is_empty() {
+            return None;
+        }
+        Some(self.ram.clone())
+    }
+
+    fn load_ram(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        if self.sav_path.is_empty() {
+            return Err(LoadError::NoBattery);
+        }
+        ram_load(&mut self.ram, data)
+    }
+}
+impl Cartridge for Mbc2 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn fork(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+
+    fn bank(&self, a: u16) -> usize {
+        match a {
+            0x4000..=0x7fff => self.rom_bank % (self.rom.len() / 0x4000).max(1),
+            _ => 0,
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable
+    }
+
+    // The built-in RAM has a single bank of 512 half bytes.
+    fn poke_ram(&mut self, _: usize, a: u16, v: u8) {
+        self.ram[a as usize & 0x01ff] = v & 0x0f;
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if self.sav_path.is_empty() {
+            return None;
+        }
+        Some(self.ram.clone())
+    }
+
+    fn load_ram(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        if self.sav_path.is_empty() {
+            return Err(LoadError::NoBattery);
+        }
+        ram_load(&mut self.ram, data)?;
+        self.ram.iter_mut().for_each(|b| *b &= 0x0f);
+        Ok(())
+    }
+}
+impl Cartridge for Mbc3 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn fork(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+
+    fn bank(&self, a: u16) -> usize {
+        match a {
+            0x0000..=0x3fff => 0,
+            0x4000..=0x7fff => self.rom_bank,
+            _ => self.ram_bank,
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable
+    }
+
+    fn poke_ram(&mut self, bank: usize, a: u16, v: u8) {
+        ram_poke(&mut self.ram, bank, a, v)
+    }
+
+    fn tick_rtc(&mut self, n: u64) {
+        self.rtc.tick(n)
+    }
+
+    fn set_clock(&mut self, clock: Arc<dyn ClockSource>) {
+        self.rtc.set_clock(clock)
+    }
+
+    // The clock state is appended after the RAM for cartridges with a timer.
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if self.sav_path.is_empty() {
+            return None;
+        }
+        let mut r = self.ram.clone();
+        if self.has_rtc() {
+            r.extend_from_slice(&self.rtc.dump());
+        }
+        Some(r)
+    }
+
+    fn load_ram(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        if self.sav_path.is_empty() {
+            return Err(LoadError::NoBattery);
+        }
+        if !self.has_rtc() {
+            return ram_load(&mut self.ram, data);
+        }
+        let expected = self.ram.len() + RTC_DUMP_SIZE;
+        if data.len() != expected {
+            return Err(LoadError::SizeMismatch {
+                expected,
+                actual: data.len(),
+            });
+        }
+        let (ram, rtc) = data.split_at(self.ram.len());
+        self.ram.copy_from_slice(ram);
+        self.rtc.load(rtc);
+        Ok(())
+    }
+}
+impl Cartridge for Mbc5 {
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    fn fork(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+
+    fn bank(&self, a: u16) -> usize {
+        match a {
+            0x0000..=0x3fff => 0,
+            0x4000..=0x7fff => self.rom_index(a) / 0x4000,
+            _ => self.ram_bank,
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable
+    }
+
+    fn poke_ram(&mut self, bank: usize, a: u16, v: u8) {
+        ram_poke(&mut self.ram, bank, a, v)
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if self.sav_path.is_empty() {
+            return None;
+        }
+        Some(self.ram.clone())
+    }
+
+    fn load_ram(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        if self.sav_path.is_empty() {
+            return Err(LoadError::NoBattery);
+        }
+        ram_load(&mut self.ram, data)
+    }
+}
+impl Cartridge for HuC1 {
+    fn rom(&self) -> &[u8] {
+        self.cart.rom()
+    }
+
+    fn fork(&self) -> Box<dyn Cartridge> {
+        Box::new(self.clone())
+    }
+
+    fn bank(&self, a: u16) -> usize {
+        self.cart.bank(a)
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.cart.ram_enabled()
+    }
+
+    fn bank_mode(&self) -> u8 {
+        self.cart.bank_mode()
+    }
+
+    fn poke_ram(&mut self, bank: usize, a: u16, v: u8) {
+        self.cart.poke_ram(bank, a, v)
+    }
+
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        self.cart.save_ram()
+    }
+
+    fn load_ram(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        self.cart.load_ram(data)
+    }
+}
+
+// Save states hold the bank registers and the RAM whether it is battery backed or not. The RAM must have the size the
+// cartridge header asks for, so a state can't be loaded into a cartridge of another type.
+impl Stateful for RomOnly {
+    fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.ram, "cartridge ram")
+    }
+}
+
+impl Stateful for Mbc1 {
+    fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.ram);
+        w.bool(match self.bank_mode {
+            BankMode::Rom => false,
+            BankMode::Ram => true,
+        });
+        w.u8(self.bank);
+        w.bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.ram, "cartridge ram")?;
+        self.bank_mode = if r.bool()? { BankMode::Ram } else { BankMode::Rom };
+        self.bank = r.u8()?;
+        self.ram_enable = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Stateful for Mbc2 {
+    fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.ram);
+        w.u32(self.rom_bank as u32);
+        w.bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.ram, "cartridge ram")?;
+        self.rom_bank = (r.u32()? as usize & 0x0f).max(1);
+        self.ram_enable = r.bool()?;
+        Ok(())
+    }
+}
+
+// In wall-clock mode the clock carries on from the moment of loading, the time spent between saving and loading the
+// state is not applied.
+impl Stateful for RealTimeClock {
+    fn save_state(&self, w: &mut Writer) {
+        for v in [self.s, self.m, self.h, self.dl, self.dh].iter() {
+            w.u8(*v);
+        }
+        w.bytes(&self.latched);
+        w.bool(self.latch_ready);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.s = r.u8()?;
+        self.m = r.u8()?;
+        self.h = r.u8()?;
+        self.dl = r.u8()?;
+        self.dh = r.u8()?;
+        r.bytes_into(&mut self.latched, "rtc")?;
+        self.latch_ready = r.bool()?;
+        if self.wall.is_some() {
+            self.wall = Some(self.clock.now_seconds());
+        }
+        Ok(())
+    }
+}
+
+impl Stateful for Mbc3 {
+    fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.ram);
+        self.rtc.save_state(w);
+        w.u32(self.rom_bank as u32);
+        w.u32(self.ram_bank as u32);
+        w.bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.ram, "cartridge ram")?;
+        self.rtc.load_state(r)?;
+        self.rom_bank = r.u32()? as usize & 0x7f;
+        self.ram_bank = r.u32()? as usize & 0x0f;
+        self.ram_enable = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Stateful for Mbc5 {
+    fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.ram);
+        w.u32(self.rom_bank as u32);
+        w.u32(self.ram_bank as u32);
+        w.bool(self.ram_enable);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.ram, "cartridge ram")?;
+        self.rom_bank = r.u32()? as usize & 0x1ff;
+        self.ram_bank = r.u32()? as usize & 0x0f;
+        self.ram_enable = r.bool()?;
+        Ok(())
+    }
+}
+
+impl Stateful for HuC1 {
+    fn save_state(&self, w: &mut Writer) {
+        self.cart.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.cart.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    // A ROM of the given number of banks, each starting with its bank number in little endian.
+    fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0x00; banks * 0x4000];
+        for bank in 0..banks {
+            rom[bank * 0x4000..bank * 0x4000 + 2].copy_from_slice(&(bank as u16).to_le_bytes());
+        }
+        rom
+    }
+
+    fn bank_at_4000(cart: &dyn Memory) -> u16 {
+        u16::from_le_bytes([cart.get(0x4000), cart.get(0x4001)])
+    }
+
+    #[test]
+    fn header_size_codes() {
+        let kb = 1024;
+        let rom = [
+            (0x00, 32 * kb),
+            (0x01, 64 * kb),
+            (0x02, 128 * kb),
+            (0x03, 256 * kb),
+            (0x04, 512 * kb),
+            (0x05, 1024 * kb),
+            (0x06, 2048 * kb),
+            (0x07, 4096 * kb),
+            (0x08, 8192 * kb),
+            (0x52, 1152 * kb),
+            (0x53, 1280 * kb),
+            (0x54, 1536 * kb),
+        ];
+        for &(code, size) in &rom {
+            assert_eq!(rom_size_bytes(code).unwrap(), size, "{:02x}", code);
+        }
+        let ram = [
+            (0x00, 0),
+            (0x01, 2 * kb),
+            (0x02, 8 * kb),
+            (0x03, 32 * kb),
+            (0x04, 128 * kb),
+            (0x05, 64 * kb),
+        ];
+        for &(code, size) in &ram {
+            assert_eq!(ram_size_bytes(code).unwrap(), size, "{:02x}", code);
+        }
+        assert!(matches!(
+            rom_size_bytes(0x09),
+            Err(CartridgeError::UnsupportedRomSize(0x09))
+        ));
+        assert!(matches!(
+            ram_size_bytes(0x06),
+            Err(CartridgeError::UnsupportedRamSize(0x06))
+        ));
+    }
+
+    #[test]
+    fn mbc5_nine_bit_rom_bank() {
+        let mut cart = Mbc5::power_up(banked_rom(512), vec![], SavFile::default());
+        assert_eq!(bank_at_4000(&cart), 0x001);
+        cart.set(0x2000, 0x00);
+        cart.set(0x3000, 0x01);
+        assert_eq!(bank_at_4000(&cart), 0x100);
+        cart.set(0x2000, 0x23);
+        assert_eq!(bank_at_4000(&cart), 0x123);
+        // Bank 0 is bank 0, not bank 1 as on the MBC1.
+        cart.set(0x2000, 0x00);
+        cart.set(0x3000, 0x00);
+        assert_eq!(bank_at_4000(&cart), 0x000);
+        // Bank numbers past the end of a smaller ROM wrap around.
+        let mut cart = Mbc5::power_up(banked_rom(64), vec![], SavFile::default());
+        cart.set(0x2000, 0x41);
+        assert_eq!(bank_at_4000(&cart), 0x001);
+    }
+
+    #[test]
+    fn mbc1_bank_fixup_and_mode_1() {
+        let mut cart = Mbc1::power_up(banked_rom(64), vec![0x00; 0x8000], SavFile::default());
+        // A low bank of 0 maps to 1, also with the upper bits set, so 20h is unreachable at 4000.
+        cart.set(0x2000, 0x00);
+        assert_eq!(bank_at_4000(&cart), 0x01);
+        cart.set(0x4000, 0x01);
+        assert_eq!(bank_at_4000(&cart), 0x21);
+        cart.set(0x2000, 0x05);
+        assert_eq!(bank_at_4000(&cart), 0x25);
+        // Bank 0 at 0000 in mode 0, the upper bits of the bank in mode 1.
+        assert_eq!(cart.get(0x0000), 0x00);
+        cart.set(0x6000, 0x01);
+        assert_eq!(cart.get(0x0000), 0x20);
+        assert_eq!(bank_at_4000(&cart), 0x25);
+        // Bank 60h doesn't exist in 1MB, it wraps to 20h.
+        cart.set(0x4000, 0x03);
+        assert_eq!(cart.get(0x0000), 0x20);
+        assert_eq!(bank_at_4000(&cart), 0x25);
+
+        // In mode 1 the upper bits select one of four RAM banks, mode 0 only sees bank 0.
+        cart.set(0x0000, 0x0a);
+        for bank in 0..4 {
+            cart.set(0x4000, bank);
+            cart.set(0xa000, 0x10 + bank);
+        }
+        for bank in 0..4 {
+            cart.set(0x4000, bank);
+            assert_eq!(cart.get(0xa000), 0x10 + bank);
+        }
+        cart.set(0x6000, 0x00);
+        assert_eq!(cart.get(0xa000), 0x10);
+        // Disabled RAM reads FFh.
+        cart.set(0x0000, 0x00);
+        assert_eq!(cart.get(0xa000), 0xff);
+    }
+
+    #[test]
+    fn mbc1_multicart_selects_a_256kb_game() {
+        let mut cart = Mbc1::power_up_multicart(banked_rom(64), vec![], SavFile::default());
+        // The 2bit register makes bits 4-5 of the bank, bit 4 of the bank register is ignored.
+        cart.set(0x4000, 0x02);
+        cart.set(0x2000, 0x13);
+        assert_eq!(bank_at_4000(&cart), 0x23);
+        cart.set(0x6000, 0x01);
+        assert_eq!(cart.get(0x0000), 0x20);
+        // 10h is not translated to 01h, it maps the first bank of the third game.
+        cart.set(0x2000, 0x10);
+        assert_eq!(bank_at_4000(&cart), 0x20);
+
+        let mut cart = Mbc1::power_up(banked_rom(64), vec![], SavFile::default());
+        cart.set(0x4000, 0x02);
+        cart.set(0x2000, 0x13);
+        assert_eq!(bank_at_4000(&cart), 0x13);
+
+        // Only a 1MB ROM with the logo again at the start of the second game is detected.
+        let mut rom = banked_rom(64);
+        assert!(!is_mbc1_multicart(&rom));
+        rom[0x4_0104..0x4_0134].copy_from_slice(&NINTENDO_LOGO);
+        assert!(is_mbc1_multicart(&rom));
+        assert!(!is_mbc1_multicart(&rom[..0x8_0000]));
+    }
+
+    #[test]
+    fn options_override_the_header_wiring() {
+        // A 64KB cartridge of the given type without RAM.
+        let with_type = |cart_type: u8| {
+            let mut rom = banked_rom(4);
+            rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+            rom[0x0147] = cart_type;
+            rom[0x0148] = 0x01;
+            rom[0x014d] = rom[0x0134..0x014d]
+                .iter()
+                .fold(0u8, |c, &b| c.wrapping_sub(b).wrapping_sub(1));
+            rom
+        };
+        let rom = with_type(0x01);
+        let mut cart = from_bytes_with(rom.clone(), CartridgeOptions::default()).unwrap();
+        cart.set(0x2000, 0x00);
+        assert_eq!(bank_at_4000(cart.as_ref()), 0x01);
+        // The MBC5 maps bank 0 at 4000 where the MBC1 maps bank 1.
+        let options = CartridgeOptions {
+            force_mapper: Some(Mapper::Mbc5),
+            force_ram_size: Some(0x2000),
+            ..CartridgeOptions::default()
+        };
+        let mut cart = from_bytes_with(rom, options).unwrap();
+        cart.set(0x2000, 0x00);
+        assert_eq!(bank_at_4000(cart.as_ref()), 0x00);
+        cart.set(0x2000, 0x03);
+        assert_eq!(bank_at_4000(cart.as_ref()), 0x03);
+        cart.set(0x0000, 0x0a);
+        cart.set(0xa000, 0x42);
+        assert_eq!(cart.get(0xa000), 0x42);
+        // A type that isn't known loads only with a mapper forced.
+        let rom = with_type(0x22);
+        assert!(from_bytes_with(rom.clone(), CartridgeOptions::default()).is_err());
+        assert!(from_bytes_with(rom, options).is_ok());
+    }
+
+    #[test]
+    fn mbc2_register_select_and_half_byte_ram() {
+        let mut cart = Mbc2::power_up(banked_rom(16), vec![], SavFile::default());
+        // Address bit 8 set: 0Ah selects ROM bank 10, RAM stays disabled.
+        cart.set(0x0100, 0x0a);
+        assert_eq!(bank_at_4000(&cart), 10);
+        cart.set(0xa000, 0x05);
+        assert_eq!(cart.get(0xa000), 0xff);
+        // Address bit 8 clear: RAM enable, the bank stays.
+        cart.set(0x2000, 0x0a);
+        assert_eq!(bank_at_4000(&cart), 10);
+        cart.set(0x2100, 0x00);
+        assert_eq!(bank_at_4000(&cart), 1);
+        cart.set(0x3f00, 0xf3);
+        assert_eq!(bank_at_4000(&cart), 3);
+
+        cart.set(0xa000, 0xab);
+        assert_eq!(cart.get(0xa000), 0xfb);
+        // Mirrored every 200h up to BFFF.
+        assert_eq!(cart.get(0xa200), 0xfb);
+        assert_eq!(cart.get(0xbe00), 0xfb);
+        cart.set(0xbfff, 0x12);
+        assert_eq!(cart.get(0xa1ff), 0xf2);
+        cart.set(0x0000, 0x00);
+        assert_eq!(cart.get(0xa000), 0xff);
+    }
+
+    fn rtc(cart: &mut Mbc3) -> [u8; 5] {
+        cart.set(0x6000, 0x00);
+        cart.set(0x6000, 0x01);
+        let mut r = [0x00; 5];
+        for (i, v) in r.iter_mut().enumerate() {
+            cart.set(0x4000, 0x08 + i as u8);
+            *v = cart.get(0xa000);
+        }
+        r
+    }
+
+    #[test]
+    fn mbc3_rtc_latch_halt_and_carry() {
+        let mut cart = Mbc3::power_up(
+            banked_rom(128),
+            vec![0x00; 0x8000],
+            SavFile::default(),
+            SavFile::default(),
+        );
+        cart.set(0x0000, 0x0a);
+        // One day, one hour, two minutes and three seconds.
+        cart.tick_rtc(86400 + 3600 + 2 * 60 + 3);
+        assert_eq!(rtc(&mut cart), [3, 2, 1, 1, 0x00]);
+        // The latched registers hold still until the next 00h, 01h sequence.
+        cart.tick_rtc(10);
+        cart.set(0x4000, 0x08);
+        assert_eq!(cart.get(0xa000), 3);
+        assert_eq!(rtc(&mut cart), [13, 2, 1, 1, 0x00]);
+        // Halted, the clock doesn't count.
+        cart.set(0x4000, 0x0c);
+        cart.set(0xa000, 0x40);
+        cart.tick_rtc(100);
+        assert_eq!(rtc(&mut cart), [13, 2, 1, 1, 0x40]);
+        // Day 511 rolls over to day 0 and sets the carry, which sticks.
+        cart.set(0x4000, 0x0b);
+        cart.set(0xa000, 0xff);
+        cart.set(0x4000, 0x0c);
+        cart.set(0xa000, 0x01);
+        cart.tick_rtc(86400);
+        assert_eq!(rtc(&mut cart), [13, 2, 1, 0, 0x80]);
+        cart.tick_rtc(86400);
+        assert_eq!(rtc(&mut cart), [13, 2, 1, 1, 0x80]);
+    }
+
+    // A clock the test moves by hand.
+    struct FakeClock(AtomicU64);
+
+    impl ClockSource for FakeClock {
+        fn now_seconds(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn mbc3_rtc_follows_the_clock_source() {
+        let clock = Arc::new(FakeClock(AtomicU64::new(1_000_000)));
+        let advance = |s: u64| clock.0.fetch_add(s, Ordering::Relaxed);
+        let mut cart = Mbc3::power_up(
+            banked_rom(128),
+            vec![0x00; 0x8000],
+            SavFile::default(),
+            SavFile::default(),
+        );
+        cart.set(0x0000, 0x0a);
+        cart.set_clock(clock.clone());
+        assert_eq!(rtc(&mut cart), [0, 0, 0, 0, 0x00]);
+        advance(3600);
+        assert_eq!(rtc(&mut cart), [0, 0, 1, 0, 0x00]);
+        advance(86400 + 61);
+        assert_eq!(rtc(&mut cart), [1, 1, 1, 1, 0x00]);
+    }
+
+    #[test]
+    fn disabled_ram_reads_open_bus() {
+        let carts: Vec<(Box<dyn Memory>, u8)> = vec![
+            (
+                Box::new(Mbc1::power_up(banked_rom(4), vec![0x00; 0x2000], SavFile::default())),
+                0x05,
+            ),
+            (
+                Box::new(Mbc2::power_up(banked_rom(4), vec![], SavFile::default())),
+                0xf5,
+            ),
+            (
+                Box::new(Mbc3::power_up(
+                    banked_rom(4),
+                    vec![0x00; 0x2000],
+                    SavFile::default(),
+                    SavFile::default(),
+                )),
+                0x05,
+            ),
+            (
+                Box::new(Mbc5::power_up(banked_rom(4), vec![0x00; 0x2000], SavFile::default())),
+                0x05,
+            ),
+        ];
+        for (i, (mut cart, want)) in carts.into_iter().enumerate() {
+            cart.set(0x0000, 0x0a);
+            cart.set(0xa000, 0x05);
+            cart.set(0x0000, 0x00);
+            // Disabled, the write is ignored and the read is an open bus.
+            cart.set(0xa000, 0x09);
+            assert_eq!(cart.get(0xa000), 0xff, "cart {}", i);
+            cart.set(0x0000, 0x0a);
+            assert_eq!(cart.get(0xa000), want, "cart {}", i);
+        }
+        // A MBC5 without RAM.
+        let mut cart = Mbc5::power_up(banked_rom(4), vec![], SavFile::default());
+        cart.set(0x0000, 0x0a);
+        assert_eq!(cart.get(0xa000), 0xff);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn battery_ram_round_trip() {
+        let dir = std::env::temp_dir();
+        let mut cart = Mbc1::power_up(banked_rom(4), vec![0x00; 0x2000], dir.join("gameboy-unused.sav"));
+        cart.set(0x0000, 0x0a);
+        cart.set(0xa000, 0x42);
+        let ram = cart.save_ram().unwrap();
+        assert_eq!((ram.len(), ram[0]), (0x2000, 0x42));
+        assert!(matches!(
+            cart.load_ram(&ram[..5]),
+            Err(LoadError::SizeMismatch {
+                expected: 0x2000,
+                actual: 5
+            })
+        ));
+        let mut other = Mbc1::power_up(banked_rom(4), vec![0x00; 0x2000], dir.join("gameboy-unused.sav"));
+        other.set(0x0000, 0x0a);
+        assert!(other.load_ram(&ram).is_ok());
+        assert_eq!(other.get(0xa000), 0x42);
+        // Without a battery there is nothing to save or restore.
+        let mut cart = Mbc1::power_up(banked_rom(4), vec![0x00; 0x2000], SavFile::default());
+        assert!(cart.save_ram().is_none());
+        assert!(matches!(cart.load_ram(&ram), Err(LoadError::NoBattery)));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn battery_ram_keeps_the_clock() {
+        let dir = std::env::temp_dir();
+        let power_up = || {
+            let rtc = dir.join("gameboy-unused.rtc");
+            Mbc3::power_up(banked_rom(4), vec![0x00; 0x8000], dir.join("gameboy-unused.sav"), rtc)
+        };
+        let mut cart = power_up();
+        cart.set(0x0000, 0x0a);
+        cart.set(0xa000, 0x42);
+        // Halted, so the wall clock doesn't move it on between the save and the load.
+        cart.set(0x4000, 0x0c);
+        cart.set(0xa000, 0x40);
+        cart.set(0x4000, 0x0a);
+        cart.set(0xa000, 0x07);
+        let ram = cart.save_ram().unwrap();
+        assert_eq!(ram.len(), 0x8000 + RTC_DUMP_SIZE);
+        let mut other = power_up();
+        other.set(0x0000, 0x0a);
+        assert!(other.load_ram(&ram).is_ok());
+        assert_eq!(rtc(&mut other), [0, 0, 7, 0, 0x40]);
+        other.set(0x4000, 0x00);
+        assert_eq!(other.get(0xa000), 0x42);
+        assert!(other.load_ram(&ram[..0x8000]).is_err());
+    }
+
+    #[test]
+    fn header_parse_and_checksum() {
+        let mut rom = vec![0x00; 0x0150];
+        rom[0x0134..0x013b].copy_from_slice(b"TETRIS\x00");
+        rom[0x013c] = b'X';
+        rom[0x0143] = 0x80;
+        rom[0x0146] = 0x03;
+        rom[0x0147] = 0x1b;
+        rom[0x0148] = 0x05;
+        rom[0x0149] = 0x03;
+        rom[0x014a] = 0x01;
+        rom[0x014d] = rom[0x0134..0x014d]
+            .iter()
+            .fold(0u8, |c, &b| c.wrapping_sub(b).wrapping_sub(1));
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(
+            [
+                header.cgb_flag,
+                header.sgb_flag,
+                header.cartridge_type,
+                header.rom_size,
+                header.ram_size
+            ],
+            [0x80, 0x03, 0x1b, 0x05, 0x03]
+        );
+        assert_eq!(header.destination_code, 0x01);
+        assert!(header.verify_header_checksum(&rom));
+        rom[0x0140] ^= 0x01;
+        assert!(!header.verify_header_checksum(&rom));
+        assert!(matches!(
+            CartridgeHeader::parse(&rom[..0x014f]),
+            Err(HeaderError::TooShort(0x014f))
+        ));
+    }
+
+    #[test]
+    fn global_checksum() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0000] = 0x01;
+        rom[0x0134] = 0x10;
+        rom[0x7fff] = 0xff;
+        // 01h + 10h + FFh, upper byte first, the two checksum bytes left out of the sum.
+        rom[0x014e] = 0x01;
+        rom[0x014f] = 0x10;
+        assert!(RomOnly::power_up(rom.clone(), vec![], SavFile::default()).verify_global_checksum());
+        rom[0x014e] = 0x10;
+        rom[0x014f] = 0x01;
+        assert!(!RomOnly::power_up(rom, vec![], SavFile::default()).verify_global_checksum());
+    }
+
+    #[test]
+    fn rom_only_with_ram() {
+        let rom: Vec<u8> = (0..0x8000).map(|a: usize| (a ^ (a >> 8)) as u8).collect();
+        let mut cart = RomOnly::power_up(rom.clone(), vec![0x00; 0x2000], SavFile::default());
+        for a in (0x0000..0x8000).step_by(0x0123) {
+            cart.set(a as u16, !rom[a]);
+            assert_eq!(cart.get(a as u16), rom[a]);
+        }
+        assert_eq!(cart.get(0x7fff), rom[0x7fff]);
+        cart.set(0xa000, 0x12);
+        cart.set(0xbfff, 0x34);
+        assert_eq!((cart.get(0xa000), cart.get(0xbfff)), (0x12, 0x34));
+        // Without RAM the area reads as open bus.
+        let mut cart = RomOnly::power_up(rom, vec![], SavFile::default());
+        cart.set(0xa000, 0x12);
+        assert_eq!(cart.get(0xa000), 0xff);
+    }
+}
@@ -0,0 +1,201 @@
+// Cheat devices sit between the gameboy and the cartridge.
+//
+// The Game Genie patches ROM reads. Its codes are 6 or 9 hex digits, written ABC-DEF or ABC-DEF-GHI:
+//   AB      New value
+//   FCDE    Address, with digit F XORed with Fh
+//   GI      Compare value, rotated right by 2 and XORed with BAh. H is ignored.
+// When a compare value is given, the read is only patched while the original byte matches it, so the code only
+// applies to the intended ROM bank.
+//
+// The GameShark pokes RAM once every frame. Its codes are 8 hex digits ABCDEFGH:
+//   AB      External RAM bank, the WRAM bank for D000-DFFF on the GameBoy Color
+//   CD      New value
+//   GHEF    Address, little-endian
+//
+// See: https://gbdev.gg8.se/wiki/articles/Gameboy_Genie_Codes
+use alloc::vec::Vec;
+
+#[derive(Debug)]
+pub enum CheatError {
+    // The code doesn't have the number of digits of its format.
+    InvalidLength(usize),
+    // The code contains something else than hex digits and dashes.
+    InvalidDigit(char),
+    // A Game Genie address outside the ROM area.
+    InvalidAddress(u16),
+}
+
+impl core::fmt::Display for CheatError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            CheatError::InvalidLength(n) => write!(f, "Invalid cheat code length {}", n),
+            CheatError::InvalidDigit(c) => write!(f, "Invalid cheat code digit {:?}", c),
+            CheatError::InvalidAddress(a) => write!(f, "Cheat code address {:#06x} is not in ROM", a),
+        }
+    }
+}
+
+impl core::error::Error for CheatError {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GameGenie {
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GameShark {
+    pub bank: u8,
+    pub addr: u16,
+    pub value: u8,
+}
+
+#[derive(Clone, Default)]
+pub struct Cheats {
+    genie: Vec<GameGenie>,
+    shark: Vec<GameShark>,
+}
+
+fn digits(code: &str) -> Result<Vec<u8>, CheatError> {
+    code.chars()
+        .filter(|&c| c != '-')
+        .map(|c| c.to_digit(16).map(|d| d as u8).ok_or(CheatError::InvalidDigit(c)))
+        .collect()
+}
+
+impl Cheats {
+    pub fn power_up() -> Self {
+        Self::default()
+    }
+
+    pub fn add_game_genie(&mut self, code: &str) -> Result<(), CheatError> {
+        let d = digits(code)?;
+        if d.len() != 6 && d.len() != 9 {
+            return Err(CheatError::InvalidLength(d.len()));
+        }
+        let value = (d[0] << 4) | d[1];
+        let addr = (u16::from(d[5] ^ 0x0f) << 12) | (u16::from(d[2]) << 8) | (u16::from(d[3]) << 4) | u16::from(d[4]);
+        if addr >= 0x8000 {
+            return Err(CheatError::InvalidAddress(addr));
+        }
+        let compare = if d.len() == 9 {
+            Some(((d[6] << 4) | d[8]).rotate_right(2) ^ 0xba)
+        } else {
+            None
+        };
+        self.genie.push(GameGenie { addr, value, compare });
+        Ok(())
+    }
+
+    pub fn add_gameshark(&mut self, code: &str) -> Result<(), CheatError> {
+        let d = digits(code)?;
+        if d.len() != 8 {
+            return Err(CheatError::InvalidLength(d.len()));
+        }
+        let b = |i: usize| (d[i] << 4) | d[i + 1];
+        self.shark.push(GameShark {
+            bank: b(0),
+            value: b(2),
+            addr: u16::from_le_bytes([b(4), b(6)]),
+        });
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.genie.clear();
+        self.shark.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.genie.is_empty() && self.shark.is_empty()
+    }
+
+    // Byte seen by the cpu when reading v from ROM address a.
+    pub fn rom(&self, a: u16, v: u8) -> u8 {
+        for g in self.genie.iter() {
+            if g.addr == a && g.compare.is_none_or(|c| c == v) {
+                return g.value;
+            }
+        }
+        v
+    }
+
+    pub fn gameshark(&self) -> &[GameShark] {
+        &self.shark
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn genie(code: &str) -> GameGenie {
+        let mut c = Cheats::power_up();
+        c.add_game_genie(code).unwrap();
+        c.genie[0]
+    }
+
+    // Decoded by hand from the digit layout at the top of the file.
+    #[test]
+    fn game_genie_codes_decode() {
+        let vectors = [
+            ("01A-17B-C49", 0x4a17, 0x01, Some(0xc8)),
+            ("3EF-00F", 0x0f00, 0x3e, None),
+            ("3ef00f", 0x0f00, 0x3e, None),
+            ("FF0-12E-8A1", 0x1012, 0xff, Some(0xda)),
+            ("005-6AC-000", 0x356a, 0x00, Some(0xba)),
+        ];
+        for &(code, addr, value, compare) in vectors.iter() {
+            assert_eq!(genie(code), GameGenie { addr, value, compare }, "{}", code);
+        }
+    }
+
+    #[test]
+    fn game_genie_errors() {
+        let mut c = Cheats::power_up();
+        assert!(matches!(c.add_game_genie("123-45"), Err(CheatError::InvalidLength(5))));
+        assert!(matches!(
+            c.add_game_genie("123-45G"),
+            Err(CheatError::InvalidDigit('G'))
+        ));
+        assert!(matches!(
+            c.add_game_genie("123-457"),
+            Err(CheatError::InvalidAddress(0x8345))
+        ));
+        assert!(c.is_empty());
+    }
+
+    #[test]
+    fn game_genie_compare_selects_the_bank() {
+        let mut c = Cheats::power_up();
+        c.add_game_genie("01A-17B-C49").unwrap();
+        assert_eq!(c.rom(0x4a17, 0xc8), 0x01);
+        assert_eq!(c.rom(0x4a17, 0xc9), 0xc9);
+        assert_eq!(c.rom(0x4a18, 0xc8), 0xc8);
+    }
+
+    #[test]
+    fn gameshark_codes_decode() {
+        let mut c = Cheats::power_up();
+        c.add_gameshark("016300C0").unwrap();
+        c.add_gameshark("8A-FF-34-D1").unwrap();
+        assert_eq!(
+            c.gameshark()[0],
+            GameShark {
+                bank: 0x01,
+                addr: 0xc000,
+                value: 0x63
+            }
+        );
+        assert_eq!(
+            c.gameshark()[1],
+            GameShark {
+                bank: 0x8a,
+                addr: 0xd134,
+                value: 0xff
+            }
+        );
+        assert!(matches!(c.add_gameshark("016300C"), Err(CheatError::InvalidLength(7))));
+    }
+}
@@ -1,18 +1,36 @@
-// Clock is outputed 1 cycle every N cycles.
-pub struct Clock {
-    pub period: u32,
-    pub n: u32,
-}
-
-impl Clock {
-    pub fn power_up(period: u32) -> Self {
-        Self { period, n: 0x00 }
-    }
-
-    pub fn next(&mut self, cycles: u32) -> u32 {
-        self.n += cycles;
-        let rs = self.n / self.period;
-        self.n = self.n % self.period;
-        rs
-    }
-}
+use super::state::{Reader, StateError, Stateful, Writer};
+
+// Clock is outputed 1 cycle every N cycles.
+pub struct Clock {
+    pub period: u32,
+    pub n: u32,
+}
+
+impl Clock {
+    pub fn power_up(period: u32) -> Self {
+        Self { period, n: 0x00 }
+    }
+
+    pub fn next(&mut self, cycles: u32) -> u32 {
+        self.n += cycles;
+        let rs = self.n / self.period;
+        self.n %= self.period;
+        rs
+    }
+}
+
+impl Stateful for Clock {
+    fn save_state(&self, w: &mut Writer) {
+        w.u32(self.period);
+        w.u32(self.n);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.period = r.u32()?;
+        self.n = r.u32()?;
+        if self.period == 0 {
+            return Err(StateError::Mismatch("clock period"));
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use super::cartridge::CartridgeHeader;
+
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub enum Term {
     GB,  // Original GameBoy (GameBoy Classic)
@@ -5,3 +7,16 @@ pub enum Term {
     GBC, // GameBoy Color
     SGB, // Super GameBoy
 }
+
+impl Term {
+    // The console a cartridge is meant for. Games supporting CGB functions (CGB flag 80h) and CGB only games (C0h)
+    // run on the GameBoy Color, games supporting SGB functions (SGB flag 03h) on the Super GameBoy, and everything
+    // else on the original GameBoy.
+    pub fn detect(header: &CartridgeHeader) -> Self {
+        match (header.cgb_flag, header.sgb_flag) {
+            (0x80 | 0xc0, _) => Term::GBC,
+            (_, 0x03) => Term::SGB,
+            _ => Term::GB,
+        }
+    }
+}
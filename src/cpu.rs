@@ -1,16 +1,26 @@
 // The chip behind the NINTENDO GAME BOY: The sharp LR35902.
 use super::convention::Term;
-use super::memory::Memory;
+use super::intf::Flag;
+use super::memory::{Memory, OamBug};
 use super::register::Flag::{C, H, N, Z};
 use super::register::Register;
-use std::cell::RefCell;
-use std::rc::Rc;
+use super::state::{Reader, StateError, Stateful, Writer};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::thread;
+#[cfg(feature = "std")]
 use std::time;
 
 pub const CLOCK_FREQUENCY: u32 = 4_194_304;
 pub const STEP_TIME: u32 = 16;
 pub const STEP_CYCLES: u32 = (STEP_TIME as f64 / (1000_f64 / CLOCK_FREQUENCY as f64)) as u32;
+// Instructions leading up to a watchpoint hit reported in BreakReason::Watchpoint.
+pub const RECENT_PCS: usize = 8;
 
 // Nintendo documents describe the CPU & instructions speed in machine cycles while this document describes them in
 // clock cycles. Here is the translation:
@@ -22,13 +32,13 @@ pub const STEP_CYCLES: u32 = (STEP_TIME as f64 / (1000_f64 / CLOCK_FREQUENCY as
 //  0  1  2  3  4  5  6  7  8  9  a  b  c  d  e  f
 const OP_CYCLES: [u32; 256] = [
     1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1, // 0
-    0, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, // 1
+    1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1, // 1
     2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 2
     2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1, // 3
     1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 4
     1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 5
     1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 6
-    2, 2, 2, 2, 2, 2, 0, 2, 1, 1, 1, 1, 1, 1, 2, 1, // 7
+    2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1, // 7
     1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 8
     1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // 9
     1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1, // a
@@ -59,111 +69,168 @@ const CB_CYCLES: [u32; 256] = [
     2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2, // f
 ];
 
+// The machine cycles a conditional branch takes on top of OP_CYCLES when taken, 0 for the other opcodes.
+fn branch_cycles(opcode: u8) -> u32 {
+    match opcode {
+        0x20 | 0x28 | 0x30 | 0x38 => 1,
+        0xc2 | 0xca | 0xd2 | 0xda => 1,
+        0xc0 | 0xc8 | 0xd0 | 0xd8 => 3,
+        0xc4 | 0xcc | 0xd4 | 0xdc => 3,
+        _ => 0,
+    }
+}
+
+// The clock cycles of an instruction, both the same except for the conditional jumps, calls and returns.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Cycles {
+    pub taken: u8,
+    pub not_taken: u8,
+}
+
+// The clock cycles of an opcode, from the tables the cpu runs with, so a tool can time code without running it. The
+// illegal opcodes and the CB prefix take 0, see cb_opcode_cycles for the opcodes following CB, whose cycles already
+// include the prefix.
+pub fn opcode_cycles(opcode: u8) -> Cycles {
+    let mac = OP_CYCLES[usize::from(opcode)];
+    Cycles {
+        taken: ((mac + branch_cycles(opcode)) * 4) as u8,
+        not_taken: (mac * 4) as u8,
+    }
+}
+
+pub fn cb_opcode_cycles(opcode: u8) -> Cycles {
+    let c = (CB_CYCLES[usize::from(opcode)] * 4) as u8;
+    Cycles { taken: c, not_taken: c }
+}
+
 pub struct Cpu {
     pub reg: Register,
     pub mem: Rc<RefCell<dyn Memory>>,
     pub halted: bool,
+    // Set by STOP, the cpu, timer and LCD are frozen until a joypad line goes low.
+    pub stopped: bool,
+    // Set by an illegal opcode. The cpu is locked up as on the real hardware: it executes nothing and ignores the
+    // interrupts while the rest of the hardware keeps running. Clearing it resumes at the byte after the opcode.
+    pub fault: Option<StepError>,
     pub ei: bool,
+    // Set when HALT is executed with IME reset and an interrupt already pending. The CPU then fails to increment PC
+    // when fetching the next opcode, so the byte following HALT is read twice.
+    halt_bug: bool,
+    // EI takes effect after the instruction following it, DI cancels a pending enable.
+    ei_pending: bool,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(u16, Access)>,
+    watch_hit: Option<BreakReason>,
+    // The address of the instruction being executed, or of the one interrupted while dispatching an interrupt, and
+    // a ring of the addresses of the instructions executed before it, next overwritten at recent_at. Not part of the
+    // save state.
+    op_pc: u16,
+    recent_pcs: [Option<u16>; RECENT_PCS],
+    recent_at: usize,
+    // Whether LD B,B stops run_until_break, see set_break_on_ld_b_b.
+    break_on_ld_b_b: bool,
+    // Clock cycles executed since power up.
+    cycles: u64,
+    // Instructions executed and interrupts dispatched since power up. Not part of the save state.
+    instructions: u64,
+    interrupts: u64,
+    // Clock cycles of the current step the memory was already ticked for.
+    ticked: u32,
+    trace: Option<Box<TraceSink>>,
+    // Called by STOP to perform an armed CGB speed switch, see set_speed_switch. Not part of the save state.
+    speed_switch: Option<Box<SpeedSwitch>>,
+    // Interrupts dispatched since the last drain, when capturing. Not part of the save state.
+    interrupt_log: Option<Vec<InterruptEvent>>,
+    // Executions and clock cycles per opcode while profiling, CB opcodes at 0x100 and up.
+    profile: Option<Box<[(u64, u64); 512]>>,
+    // The undo records of the last steps, the newest at the back, and how many to keep.
+    history: Option<(VecDeque<StepUndo>, usize)>,
 }
 
 // The GameBoy CPU is based on a subset of the Z80 microprocessor. A summary of these commands is given below.
 // If 'Flags affected' is not given for a command then none are affected.
 impl Cpu {
+    // Every memory access takes a machine cycle. The rest of the hardware is advanced through Memory::tick before the
+    // access, so it sees the state at the end of that cycle. The cycles of an instruction without a memory access are
+    // ticked once it completes.
+    fn tick(&mut self) {
+        self.mem.borrow_mut().tick(4);
+        self.ticked += 4;
+    }
+
+    // A machine cycle where the 16 bit incrementer alone drives the bus with a, without a memory access.
+    fn idu(&mut self, a: u16) {
+        self.tick();
+        self.oam_bug(a, OamBug::Write);
+    }
+
+    fn oam_bug(&mut self, a: u16, kind: OamBug) {
+        if (0xfe00..=0xfeff).contains(&a) {
+            self.mem.borrow_mut().oam_bug(a, kind);
+        }
+    }
+
     fn imm(&mut self) -> u8 {
+        self.tick();
         let v = self.mem.borrow().get(self.reg.pc);
-        self.reg.pc += 1;
+        self.reg.pc = self.reg.pc.wrapping_add(1);
         v
     }
 
     fn imm_word(&mut self) -> u16 {
-        let v = self.mem.borrow().get_word(self.reg.pc);
-        self.reg.pc += 2;
-        v
+        let lo = self.imm();
+        let hi = self.imm();
+        u16::from_le_bytes([lo, hi])
     }
 
-    fn stack_add(&mut self, v: u16) {
-        self.reg.sp -= 2;
-        self.mem.borrow_mut().set_word(self.reg.sp, v);
+    // Data accesses made by instructions go through these, so they can be caught by watchpoints. Opcode and immediate
+    // fetches are not considered data accesses.
+    fn rd(&mut self, a: u16) -> u8 {
+        self.tick();
+        self.oam_bug(a, OamBug::Read);
+        let v = self.mem.borrow().get(a);
+        self.watch(a, Access::Read, v);
+        v
     }
 
-    fn stack_pop(&mut self) -> u16 {
-        let r = self.mem.borrow().get_word(self.reg.sp);
-        self.reg.sp += 2;
-        r
+    // A read while the incrementer moves the address register on.
+    fn rd_inc(&mut self, a: u16) -> u8 {
+        self.tick();
+        self.oam_bug(a, OamBug::ReadIncrease);
+        let v = self.mem.borrow().get(a);
+        self.watch(a, Access::Read, v);
+        v
     }
 
-    // Add n to A.
-    // n = A,B,C,D,E,H,L,(HL),#
-    //
-    // Flags affected:
-    // Z - Set if result is zero.
-    // N - Reset.
-    // H - Set if carry from bit 3.
-    // C - Set if carry from bit 7.
-    fn alu_add(&mut self, n: u8) {
-        let a = self.reg.a;
-        let r = a.wrapping_add(n);
-        self.reg.set_flag(C, u16::from(a) + u16::from(n) > 0xff);
-        self.reg.set_flag(H, (a & 0x0f) + (n & 0x0f) > 0x0f);
-        self.reg.set_flag(N, false);
-        self.reg.set_flag(Z, r == 0x00);
-        self.reg.a = r;
+    fn wr(&mut self, a: u16, v: u8) {
+        self.watch(a, Access::Write, v);
+        self.record_write(a);
+        self.tick();
+        self.oam_bug(a, OamBug::Write);
+        self.mem.borrow_mut().set(a, v);
     }
 
-    // Add n + Carry flag to A.
-    // n = A,B,C,D,E,H,L,(HL),#
-    //
-    // Flags affected:
-    // Z - Set if result is zero.
-    // N - Reset.
-    // H - Set if carry from bit 3.
-    // C - Set if carry from bit 7.
-    fn alu_adc(&mut self, n: u8) {
-        let a = self.reg.a;
-        let c = u8::from(self.reg.get_flag(C));
-        let r = a.wrapping_add(n).wrapping_add(c);
-        self.reg.set_flag(C, u16::from(a) + u16::from(n) + u16::from(c) > 0xff);
-        self.reg.set_flag(H, (a & 0x0f) + (n & 0x0f) + (c & 0x0f) > 0x0f);
-        self.reg.set_flag(N, false);
-        self.reg.set_flag(Z, r == 0x00);
-        self.reg.a = r;
+    fn wr_word(&mut self, a: u16, v: u16) {
+        let [lo, hi] = v.to_le_bytes();
+        self.wr(a, lo);
+        self.wr(a.wrapping_add(1), hi);
     }
 
-    // Subtract n from A.
-    // n = A,B,C,D,E,H,L,(HL),#
-    //
-    // Flags affected:
-    // Z - Set if result is zero.
-    // N - Set.
-    // H - Set if no borrow from bit 4.
-    // C - Set if no borrow
-    fn alu_sub(&mut self, n: u8) {
-        let a = self.reg.a;
-        let r = a.wrapping_sub(n);
-        self.reg.set_flag(C, u16::from(a) < u16::from(n));
-        self.reg.set_flag(H, (a & 0x0f) < (n & 0x0f));
-        self.reg.set_flag(N, true);
-        self.reg.set_flag(Z, r == 0x00);
-        self.reg.a = r;
+    // The stack pointer is decremented in a cycle of its own, then the high byte is written first.
+    fn stack_add(&mut self, v: u16) {
+        let [lo, hi] = v.to_le_bytes();
+        self.idu(self.reg.sp);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.wr(self.reg.sp, hi);
+        self.reg.sp = self.reg.sp.wrapping_sub(1);
+        self.wr(self.reg.sp, lo);
     }
 
-    // Subtract n + Carry flag from A.
-    // n = A,B,C,D,E,H,L,(HL),#
-    //
-    // Flags affected:
-    // Z - Set if result is zero.
-    // N - Set.
-    // H - Set if no borrow from bit 4.
-    // C - Set if no borrow.
-    fn alu_sbc(&mut self, n: u8) {
-        let a = self.reg.a;
-        let c = u8::from(self.reg.get_flag(C));
-        let r = a.wrapping_sub(n).wrapping_sub(c);
-        self.reg.set_flag(C, u16::from(a) < u16::from(n) + u16::from(c));
-        self.reg.set_flag(H, (a & 0x0f) < (n & 0x0f) + c);
-        self.reg.set_flag(N, true);
-        self.reg.set_flag(Z, r == 0x00);
-        self.reg.a = r;
+    fn stack_pop(&mut self) -> u16 {
+        let lo = self.rd_inc(self.reg.sp);
+        let hi = self.rd_inc(self.reg.sp.wrapping_add(1));
+        self.reg.sp = self.reg.sp.wrapping_add(2);
+        u16::from_le_bytes([lo, hi])
     }
 
     // Logically AND n with A, result in A.
@@ -227,7 +294,7 @@ impl Cpu {
     // C - Set for no borrow. (Set if A < n.)
     fn alu_cp(&mut self, n: u8) {
         let r = self.reg.a;
-        self.alu_sub(n);
+        self.reg.alu_sub(n);
         self.reg.a = r;
     }
 
@@ -263,41 +330,6 @@ impl Cpu {
         r
     }
 
-    // Add n to HL
-    // n = BC,DE,HL,SP
-    //
-    // Flags affected:
-    // Z - Not affected.
-    // N - Reset.
-    // H - Set if carry from bit 11.
-    // C - Set if carry from bit 15.
-    fn alu_add_hl(&mut self, n: u16) {
-        let a = self.reg.get_hl();
-        let r = a.wrapping_add(n);
-        self.reg.set_flag(C, a > 0xffff - n);
-        self.reg.set_flag(H, (a & 0x0fff) + (n & 0x0fff) > 0x0fff);
-        self.reg.set_flag(N, false);
-        self.reg.set_hl(r);
-    }
-
-    // Add n to Stack Pointer (SP).
-    // n = one byte signed immediate value (#).
-    //
-    // Flags affected:
-    // Z - Reset.
-    // N - Reset.
-    // H - Set or reset according to operation.
-    // C - Set or reset according to operation.
-    fn alu_add_sp(&mut self) {
-        let a = self.reg.sp;
-        let b = i16::from(self.imm() as i8) as u16;
-        self.reg.set_flag(C, (a & 0x00ff) + (b & 0x00ff) > 0x00ff);
-        self.reg.set_flag(H, (a & 0x000f) + (b & 0x000f) > 0x000f);
-        self.reg.set_flag(N, false);
-        self.reg.set_flag(Z, false);
-        self.reg.sp = a.wrapping_add(b);
-    }
-
     // Swap upper & lower nibles of n.
     // n = A,B,C,D,E,H,L,(HL)
     //
@@ -311,38 +343,7 @@ impl Cpu {
         self.reg.set_flag(H, false);
         self.reg.set_flag(N, false);
         self.reg.set_flag(Z, a == 0x00);
-        (a >> 4) | (a << 4)
-    }
-
-    // Decimal adjust register A. This instruction adjusts register A so that the correct representation of Binary
-    // Coded Decimal (BCD) is obtained.
-    //
-    // Flags affected:
-    // Z - Set if register A is zero.
-    // N - Not affected.
-    // H - Reset.
-    // C - Set or reset according to operation
-    fn alu_daa(&mut self) {
-        let mut a = self.reg.a;
-        let mut adjust = if self.reg.get_flag(C) { 0x60 } else { 0x00 };
-        if self.reg.get_flag(H) {
-            adjust |= 0x06;
-        };
-        if !self.reg.get_flag(N) {
-            if a & 0x0f > 0x09 {
-                adjust |= 0x06;
-            };
-            if a > 0x99 {
-                adjust |= 0x60;
-            };
-            a = a.wrapping_add(adjust);
-        } else {
-            a = a.wrapping_sub(adjust);
-        }
-        self.reg.set_flag(C, adjust >= 0x60);
-        self.reg.set_flag(H, false);
-        self.reg.set_flag(Z, a == 0x00);
-        self.reg.a = a;
+        a.rotate_left(4)
     }
 
     // Complement A register. (Flip all bits.)
@@ -385,7 +386,8 @@ impl Cpu {
         self.reg.set_flag(N, false);
     }
 
-    // Rotate A left. Old bit 7 to Carry flag.
+    // Rotate n left. Old bit 7 to Carry flag.
+    // n = A,B,C,D,E,H,L,(HL)
     //
     // Flags affected:
     // Z - Set if result is zero.
@@ -402,7 +404,8 @@ impl Cpu {
         r
     }
 
-    // Rotate A left through Carry flag.
+    // Rotate n left through Carry flag.
+    // n = A,B,C,D,E,H,L,(HL)
     //
     // Flags affected:
     // Z - Set if result is zero.
@@ -419,7 +422,8 @@ impl Cpu {
         r
     }
 
-    // Rotate A right. Old bit 0 to Carry flag.
+    // Rotate n right. Old bit 0 to Carry flag.
+    // n = A,B,C,D,E,H,L,(HL)
     //
     // Flags affected:
     // Z - Set if result is zero.
@@ -436,7 +440,8 @@ impl Cpu {
         r
     }
 
-    // Rotate A right through Carry flag.
+    // Rotate n right through Carry flag.
+    // n = A,B,C,D,E,H,L,(HL)
     //
     // Flags affected:
     // Z - Set if result is zero.
@@ -552,7 +557,27 @@ impl Cpu {
             reg: Register::power_up(term),
             mem,
             halted: false,
+            stopped: false,
+            fault: None,
             ei: true,
+            halt_bug: false,
+            ei_pending: false,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            op_pc: 0x0000,
+            recent_pcs: [None; RECENT_PCS],
+            recent_at: 0,
+            break_on_ld_b_b: false,
+            cycles: 0,
+            instructions: 0,
+            interrupts: 0,
+            ticked: 0,
+            trace: None,
+            speed_switch: None,
+            interrupt_log: None,
+            profile: None,
+            history: None,
         }
     }
 
@@ -563,13 +588,20 @@ impl Cpu {
     // 3. Reset the IME flag and prevent all interrupts.
     // 4. The PC (program counter) is pushed onto the stack.
     // 5. Jump to the starting address of the interrupt.
-    fn hi(&mut self) -> u32 {
+    //
+    // When several interrupts are requested at the same time, the one with the lowest bit (V-Blank) has the highest
+    // priority. Dispatching takes 5 machine cycles (20 clock cycles), which is returned so the caller can advance the
+    // rest of the hardware.
+    //
+    // A pending interrupt enabled in IE ends HALT whatever IME: with IME clear nothing is dispatched, IF is left as is
+    // and execution resumes after HALT, this is how games poll IF without interrupt handlers.
+    pub fn service_interrupt(&mut self) -> u32 {
         if !self.halted && !self.ei {
             return 0;
         }
         let intf = self.mem.borrow().get(0xff0f);
         let inte = self.mem.borrow().get(0xffff);
-        let ii = intf & inte;
+        let ii = intf & inte & 0x1f;
         if ii == 0x00 {
             return 0;
         }
@@ -583,7 +615,20 @@ impl Cpu {
         let n = ii.trailing_zeros();
         let intf = intf & !(1 << n);
         self.mem.borrow_mut().set(0xff0f, intf);
+        if let Some(log) = self.interrupt_log.as_mut() {
+            log.push(InterruptEvent {
+                flag: Flag::from_bit(n),
+                cycles: self.cycles,
+            });
+        }
 
+        // EI then HALT with an interrupt pending triggers the HALT bug, but IME is set by the time the next fetch would
+        // skip the PC increment. The interrupt is dispatched with the PC still on HALT: the handler returns to it and
+        // HALT is executed again, rather than the first byte of the handler being read twice.
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.reg.pc = self.reg.pc.wrapping_sub(1);
+        }
         self.stack_add(self.reg.pc);
         // Set the PC to correspond interrupt process program:
         // V-Blank: 0x40
@@ -592,1109 +637,1640 @@ impl Cpu {
         // JOYPAD: 0x60
         // Serial: 0x58
         self.reg.pc = 0x0040 | ((n as u16) << 3);
-        4
-    }
-
-    fn ex(&mut self) -> u32 {
-        let opcode = self.imm();
-        let mut cbcode: u8 = 0;
-        match opcode {
-            // LD r8, d8
-            0x06 => self.reg.b = self.imm(),
-            0x0e => self.reg.c = self.imm(),
-            0x16 => self.reg.d = self.imm(),
-            0x1e => self.reg.e = self.imm(),
-            0x26 => self.reg.h = self.imm(),
-            0x2e => self.reg.l = self.imm(),
-            0x36 => {
-                let a = self.reg.get_hl();
-                let v = self.imm();
-                self.mem.borrow_mut().set(a, v);
-            }
-            0x3e => self.reg.a = self.imm(),
-
-            // LD (r16), A
-            0x02 => self.mem.borrow_mut().set(self.reg.get_bc(), self.reg.a),
-            0x12 => self.mem.borrow_mut().set(self.reg.get_de(), self.reg.a),
-
-            // LD A, (r16)
-            0x0a => self.reg.a = self.mem.borrow().get(self.reg.get_bc()),
-            0x1a => self.reg.a = self.mem.borrow().get(self.reg.get_de()),
+        5
+    }
 
-            // LD (HL+), A
-            0x22 => {
-                let a = self.reg.get_hl();
-                self.mem.borrow_mut().set(a, self.reg.a);
-                self.reg.set_hl(a + 1);
-            }
-            // LD (HL-), A
-            0x32 => {
-                let a = self.reg.get_hl();
-                self.mem.borrow_mut().set(a, self.reg.a);
-                self.reg.set_hl(a - 1);
-            }
-            // LD A, (HL+)
-            0x2a => {
-                let v = self.reg.get_hl();
-                self.reg.a = self.mem.borrow().get(v);
-                self.reg.set_hl(v + 1);
-            }
-            // LD A, (HL-)
-            0x3a => {
-                let v = self.reg.get_hl();
-                self.reg.a = self.mem.borrow().get(v);
-                self.reg.set_hl(v - 1);
+    // STOP is officially encoded as 10 00, but what actually happens depends on the joypad, pending interrupts and
+    // KEY1. When STOP is said to be a 2-byte opcode the following byte is skipped whatever its value, otherwise it is
+    // executed as the next instruction.
+    //   Button held, interrupt pending:     1-byte, nothing happens.
+    //   Button held, no interrupt pending:  2-byte, enters HALT mode.
+    //   Speed switch armed:                 DIV reset and speed switched, 1-byte if an interrupt is pending.
+    //   Otherwise:                          DIV reset and STOP mode entered, 1-byte if an interrupt is pending.
+    //
+    // See: https://gbdev.io/pandocs/Reducing_Power_Consumption.html
+    fn stop(&mut self) {
+        let pressed = self.mem.borrow().get(0xff00) & 0x0f != 0x0f;
+        let intf = self.mem.borrow().get(0xff0f);
+        let inte = self.mem.borrow().get(0xffff);
+        let pending = intf & inte & 0x1f != 0x00;
+        if pressed {
+            if !pending {
+                self.reg.pc = self.reg.pc.wrapping_add(1);
+                self.halted = true;
             }
+            return;
+        }
+        if !pending {
+            self.reg.pc = self.reg.pc.wrapping_add(1);
+        }
+        self.mem.borrow_mut().set(0xff04, 0x00);
+        if !self.speed_switch.as_mut().is_some_and(|f| f()) {
+            self.stopped = true;
+        }
+    }
 
-            // LD r8, r8
-            0x40 => {}
-            0x41 => self.reg.b = self.reg.c,
-            0x42 => self.reg.b = self.reg.d,
-            0x43 => self.reg.b = self.reg.e,
-            0x44 => self.reg.b = self.reg.h,
-            0x45 => self.reg.b = self.reg.l,
-            0x46 => self.reg.b = self.mem.borrow().get(self.reg.get_hl()),
-            0x47 => self.reg.b = self.reg.a,
-            0x48 => self.reg.c = self.reg.b,
-            0x49 => {}
-            0x4a => self.reg.c = self.reg.d,
-            0x4b => self.reg.c = self.reg.e,
-            0x4c => self.reg.c = self.reg.h,
-            0x4d => self.reg.c = self.reg.l,
-            0x4e => self.reg.c = self.mem.borrow().get(self.reg.get_hl()),
-            0x4f => self.reg.c = self.reg.a,
-            0x50 => self.reg.d = self.reg.b,
-            0x51 => self.reg.d = self.reg.c,
-            0x52 => {}
-            0x53 => self.reg.d = self.reg.e,
-            0x54 => self.reg.d = self.reg.h,
-            0x55 => self.reg.d = self.reg.l,
-            0x56 => self.reg.d = self.mem.borrow().get(self.reg.get_hl()),
-            0x57 => self.reg.d = self.reg.a,
-            0x58 => self.reg.e = self.reg.b,
-            0x59 => self.reg.e = self.reg.c,
-            0x5a => self.reg.e = self.reg.d,
-            0x5b => {}
-            0x5c => self.reg.e = self.reg.h,
-            0x5d => self.reg.e = self.reg.l,
-            0x5e => self.reg.e = self.mem.borrow().get(self.reg.get_hl()),
-            0x5f => self.reg.e = self.reg.a,
-            0x60 => self.reg.h = self.reg.b,
-            0x61 => self.reg.h = self.reg.c,
-            0x62 => self.reg.h = self.reg.d,
-            0x63 => self.reg.h = self.reg.e,
-            0x64 => {}
-            0x65 => self.reg.h = self.reg.l,
-            0x66 => self.reg.h = self.mem.borrow().get(self.reg.get_hl()),
-            0x67 => self.reg.h = self.reg.a,
-            0x68 => self.reg.l = self.reg.b,
-            0x69 => self.reg.l = self.reg.c,
-            0x6a => self.reg.l = self.reg.d,
-            0x6b => self.reg.l = self.reg.e,
-            0x6c => self.reg.l = self.reg.h,
-            0x6d => {}
-            0x6e => self.reg.l = self.mem.borrow().get(self.reg.get_hl()),
-            0x6f => self.reg.l = self.reg.a,
-            0x70 => self.mem.borrow_mut().set(self.reg.get_hl(), self.reg.b),
-            0x71 => self.mem.borrow_mut().set(self.reg.get_hl(), self.reg.c),
-            0x72 => self.mem.borrow_mut().set(self.reg.get_hl(), self.reg.d),
-            0x73 => self.mem.borrow_mut().set(self.reg.get_hl(), self.reg.e),
-            0x74 => self.mem.borrow_mut().set(self.reg.get_hl(), self.reg.h),
-            0x75 => self.mem.borrow_mut().set(self.reg.get_hl(), self.reg.l),
-            0x77 => self.mem.borrow_mut().set(self.reg.get_hl(), self.reg.a),
-            0x78 => self.reg.a = self.reg.b,
-            0x79 => self.reg.a = self.reg.c,
-            0x7a => self.reg.a = self.reg.d,
-            0x7b => self.reg.a = self.reg.e,
-            0x7c => self.reg.a = self.reg.h,
-            0x7d => self.reg.a = self.reg.l,
-            0x7e => self.reg.a = self.mem.borrow().get(self.reg.get_hl()),
-            0x7f => {}
-
-            // LDH (a8), A
-            0xe0 => {
-                let a = 0xff00 | u16::from(self.imm());
-                self.mem.borrow_mut().set(a, self.reg.a);
-            }
-            // LDH A, (a8)
-            0xf0 => {
-                let a = 0xff00 | u16::from(self.imm());
-                self.reg.a = self.mem.borrow().get(a);
+    // MATCH selects the match expressions of dispatch_match instead of the tables, see examples/dispatch.rs.
+    fn ex<const MATCH: bool>(&mut self) -> u32 {
+        if self.trace.is_some() {
+            self.emit_trace();
+        }
+        let opcode = if self.halt_bug {
+            self.halt_bug = false;
+            self.tick();
+            self.mem.borrow().get(self.reg.pc)
+        } else {
+            self.imm()
+        };
+        if opcode == 0xcb {
+            let cbcode = self.imm();
+            if MATCH {
+                #[cfg(feature = "dispatch-match")]
+                dispatch_match::cb(self, cbcode);
+            } else {
+                CB_OPS[usize::from(cbcode)](self);
             }
+            let mac = CB_CYCLES[usize::from(cbcode)];
+            self.tally(0x100 | usize::from(cbcode), mac);
+            return mac;
+        }
+        // Branches leave the flags as they are, the condition tells whether the branch was taken.
+        let taken = branch_cycles(opcode) != 0 && self.cond((opcode >> 3) & 0x03);
+        if MATCH {
+            #[cfg(feature = "dispatch-match")]
+            dispatch_match::op(self, opcode);
+        } else {
+            OPS[usize::from(opcode)](self);
+        }
+        let ecycle = if taken { branch_cycles(opcode) } else { 0x00 };
+        let mac = OP_CYCLES[opcode as usize] + ecycle;
+        self.tally(usize::from(opcode), mac);
+        mac
+    }
 
-            // LD (C), A
-            0xe2 => self.mem.borrow_mut().set(0xff00 | u16::from(self.reg.c), self.reg.a),
-            // LD A, (C)
-            0xf2 => self.reg.a = self.mem.borrow().get(0xff00 | u16::from(self.reg.c)),
+    // Execute one step, returns the clock cycles it took. Outside STOP mode the memory has been ticked for all of
+    // them by the time this returns.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> u32 {
+        self.next_with::<false>()
+    }
 
-            // LD (a16), A
-            0xea => {
-                let a = self.imm_word();
-                self.mem.borrow_mut().set(a, self.reg.a);
-            }
-            // LD A, (a16)
-            0xfa => {
-                let a = self.imm_word();
-                self.reg.a = self.mem.borrow().get(a);
-            }
+    // Same as next, with the opcodes dispatched by a match expression rather than the tables.
+    #[cfg(feature = "dispatch-match")]
+    pub fn next_match(&mut self) -> u32 {
+        self.next_with::<true>()
+    }
 
-            // LD r16, d16
-            0x01 | 0x11 | 0x21 | 0x31 => {
-                let v = self.imm_word();
-                match opcode {
-                    0x01 => self.reg.set_bc(v),
-                    0x11 => self.reg.set_de(v),
-                    0x21 => self.reg.set_hl(v),
-                    0x31 => self.reg.sp = v,
-                    _ => {}
-                }
+    fn next_with<const MATCH: bool>(&mut self) -> u32 {
+        let ei_pending = self.ei_pending;
+        self.ticked = 0;
+        self.op_pc = self.reg.pc;
+        self.record_step();
+        let mac = if self.fault.is_some() {
+            OP_CYCLES[0]
+        } else if self.stopped {
+            // Any selected joypad line going low ends STOP mode. The rest of the hardware is frozen meanwhile.
+            self.stopped = self.mem.borrow().get(0xff00) & 0x0f == 0x0f;
+            self.ticked = OP_CYCLES[0] * 4;
+            OP_CYCLES[0]
+        } else {
+            let c = self.service_interrupt();
+            if c != 0 {
+                self.interrupts += 1;
+                c
+            } else if self.halted {
+                OP_CYCLES[0]
+            } else {
+                self.instructions += 1;
+                let mac = self.ex::<MATCH>();
+                self.recent_pcs[self.recent_at] = Some(self.op_pc);
+                self.recent_at = (self.recent_at + 1) % RECENT_PCS;
+                mac
             }
+        };
+        if ei_pending && self.ei_pending {
+            self.ei = true;
+            self.ei_pending = false;
+        }
+        let rest = (mac * 4).saturating_sub(self.ticked);
+        if rest != 0 {
+            self.mem.borrow_mut().tick(rest);
+        }
+        self.cycles += u64::from(mac * 4);
+        mac * 4
+    }
 
-            // LD SP, HL
-            0xf9 => self.reg.sp = self.reg.get_hl(),
-            // LD SP, d8
-            0xf8 => {
-                let a = self.reg.sp;
-                let b = i16::from(self.imm() as i8) as u16;
-                self.reg.set_flag(C, (a & 0x00ff) + (b & 0x00ff) > 0x00ff);
-                self.reg.set_flag(H, (a & 0x000f) + (b & 0x000f) > 0x000f);
-                self.reg.set_flag(N, false);
-                self.reg.set_flag(Z, false);
-                self.reg.set_hl(a.wrapping_add(b));
-            }
-            // LD (d16), SP
-            0x08 => {
-                let a = self.imm_word();
-                self.mem.borrow_mut().set_word(a, self.reg.sp);
-            }
+    // Same as next, but fails while the cpu is locked up, including the step that executed the illegal opcode. The
+    // cycles are spent either way, the caller decides whether to stop or to clear the fault and continue.
+    pub fn try_next(&mut self) -> Result<u32, StepError> {
+        let cycles = self.next();
+        match self.fault {
+            Some(e) => Err(e),
+            None => Ok(cycles),
+        }
+    }
+}
 
-            // PUSH
-            0xc5 => self.stack_add(self.reg.get_bc()),
-            0xd5 => self.stack_add(self.reg.get_de()),
-            0xe5 => self.stack_add(self.reg.get_hl()),
-            0xf5 => self.stack_add(self.reg.get_af()),
-
-            // POP
-            0xc1 | 0xf1 | 0xd1 | 0xe1 => {
-                let v = self.stack_pop();
-                match opcode {
-                    0xc1 => self.reg.set_bc(v),
-                    0xd1 => self.reg.set_de(v),
-                    0xe1 => self.reg.set_hl(v),
-                    0xf1 => self.reg.set_af(v),
-                    _ => {}
-                }
-            }
+#[cfg(feature = "dispatch-match")]
+mod dispatch_match;
 
-            // ADD A, r8/d8
-            0x80 => self.alu_add(self.reg.b),
-            0x81 => self.alu_add(self.reg.c),
-            0x82 => self.alu_add(self.reg.d),
-            0x83 => self.alu_add(self.reg.e),
-            0x84 => self.alu_add(self.reg.h),
-            0x85 => self.alu_add(self.reg.l),
-            0x86 => {
-                let v = self.mem.borrow().get(self.reg.get_hl());
-                self.alu_add(v);
-            }
-            0x87 => self.alu_add(self.reg.a),
-            0xc6 => {
-                let v = self.imm();
-                self.alu_add(v);
-            }
+type Op = fn(&mut Cpu);
 
-            // ADC A, r8/d8
-            0x88 => self.alu_adc(self.reg.b),
-            0x89 => self.alu_adc(self.reg.c),
-            0x8a => self.alu_adc(self.reg.d),
-            0x8b => self.alu_adc(self.reg.e),
-            0x8c => self.alu_adc(self.reg.h),
-            0x8d => self.alu_adc(self.reg.l),
-            0x8e => {
-                let a = self.mem.borrow().get(self.reg.get_hl());
-                self.alu_adc(a);
-            }
-            0x8f => self.alu_adc(self.reg.a),
-            0xce => {
-                let v = self.imm();
-                self.alu_adc(v);
-            }
+// Opcode handlers, one small function per instruction. Operands are selected by const parameters numbered as in the
+// opcode bit fields (see disasm.rs):
+//   r8   0-7  B, C, D, E, H, L, (HL), A
+//   r16  0-3  BC, DE, HL, SP (AF for PUSH and POP)
+//   cc   0-3  NZ, Z, NC, C
+impl Cpu {
+    fn read_r8(&mut self, r: u8) -> u8 {
+        match r {
+            0 => self.reg.b,
+            1 => self.reg.c,
+            2 => self.reg.d,
+            3 => self.reg.e,
+            4 => self.reg.h,
+            5 => self.reg.l,
+            6 => self.rd(self.reg.get_hl()),
+            _ => self.reg.a,
+        }
+    }
 
-            // SUB A, r8/d8
-            0x90 => self.alu_sub(self.reg.b),
-            0x91 => self.alu_sub(self.reg.c),
-            0x92 => self.alu_sub(self.reg.d),
-            0x93 => self.alu_sub(self.reg.e),
-            0x94 => self.alu_sub(self.reg.h),
-            0x95 => self.alu_sub(self.reg.l),
-            0x96 => {
-                let a = self.mem.borrow().get(self.reg.get_hl());
-                self.alu_sub(a);
-            }
-            0x97 => self.alu_sub(self.reg.a),
-            0xd6 => {
-                let v = self.imm();
-                self.alu_sub(v);
-            }
+    fn write_r8(&mut self, r: u8, v: u8) {
+        match r {
+            0 => self.reg.b = v,
+            1 => self.reg.c = v,
+            2 => self.reg.d = v,
+            3 => self.reg.e = v,
+            4 => self.reg.h = v,
+            5 => self.reg.l = v,
+            6 => self.wr(self.reg.get_hl(), v),
+            _ => self.reg.a = v,
+        }
+    }
 
-            // SBC A, r8/d8
-            0x98 => self.alu_sbc(self.reg.b),
-            0x99 => self.alu_sbc(self.reg.c),
-            0x9a => self.alu_sbc(self.reg.d),
-            0x9b => self.alu_sbc(self.reg.e),
-            0x9c => self.alu_sbc(self.reg.h),
-            0x9d => self.alu_sbc(self.reg.l),
-            0x9e => {
-                let a = self.mem.borrow().get(self.reg.get_hl());
-                self.alu_sbc(a);
-            }
-            0x9f => self.alu_sbc(self.reg.a),
-            0xde => {
-                let v = self.imm();
-                self.alu_sbc(v);
-            }
+    fn read_r16(&self, r: u8) -> u16 {
+        match r {
+            0 => self.reg.get_bc(),
+            1 => self.reg.get_de(),
+            2 => self.reg.get_hl(),
+            _ => self.reg.sp,
+        }
+    }
 
-            // AND A, r8/d8
-            0xa0 => self.alu_and(self.reg.b),
-            0xa1 => self.alu_and(self.reg.c),
-            0xa2 => self.alu_and(self.reg.d),
-            0xa3 => self.alu_and(self.reg.e),
-            0xa4 => self.alu_and(self.reg.h),
-            0xa5 => self.alu_and(self.reg.l),
-            0xa6 => {
-                let a = self.mem.borrow().get(self.reg.get_hl());
-                self.alu_and(a);
-            }
-            0xa7 => self.alu_and(self.reg.a),
-            0xe6 => {
-                let v = self.imm();
-                self.alu_and(v);
-            }
+    fn write_r16(&mut self, r: u8, v: u16) {
+        match r {
+            0 => self.reg.set_bc(v),
+            1 => self.reg.set_de(v),
+            2 => self.reg.set_hl(v),
+            _ => self.reg.sp = v,
+        }
+    }
 
-            // OR A, r8/d8
-            0xb0 => self.alu_or(self.reg.b),
-            0xb1 => self.alu_or(self.reg.c),
-            0xb2 => self.alu_or(self.reg.d),
-            0xb3 => self.alu_or(self.reg.e),
-            0xb4 => self.alu_or(self.reg.h),
-            0xb5 => self.alu_or(self.reg.l),
-            0xb6 => {
-                let a = self.mem.borrow().get(self.reg.get_hl());
-                self.alu_or(a);
-            }
-            0xb7 => self.alu_or(self.reg.a),
-            0xf6 => {
-                let v = self.imm();
-                self.alu_or(v);
-            }
+    fn cond(&self, cc: u8) -> bool {
+        match cc {
+            0 => !self.reg.get_flag(Z),
+            1 => self.reg.get_flag(Z),
+            2 => !self.reg.get_flag(C),
+            _ => self.reg.get_flag(C),
+        }
+    }
 
-            // XOR A, r8/d8
-            0xa8 => self.alu_xor(self.reg.b),
-            0xa9 => self.alu_xor(self.reg.c),
-            0xaa => self.alu_xor(self.reg.d),
-            0xab => self.alu_xor(self.reg.e),
-            0xac => self.alu_xor(self.reg.h),
-            0xad => self.alu_xor(self.reg.l),
-            0xae => {
-                let a = self.mem.borrow().get(self.reg.get_hl());
-                self.alu_xor(a);
-            }
-            0xaf => self.alu_xor(self.reg.a),
-            0xee => {
-                let v = self.imm();
-                self.alu_xor(v);
-            }
+    fn alu(&mut self, op: u8, v: u8) {
+        match op {
+            0 => self.reg.alu_add(v),
+            1 => self.reg.alu_adc(v),
+            2 => self.reg.alu_sub(v),
+            3 => self.reg.alu_sbc(v),
+            4 => self.alu_and(v),
+            5 => self.alu_xor(v),
+            6 => self.alu_or(v),
+            _ => self.alu_cp(v),
+        }
+    }
 
-            // CP A, r8/d8
-            0xb8 => self.alu_cp(self.reg.b),
-            0xb9 => self.alu_cp(self.reg.c),
-            0xba => self.alu_cp(self.reg.d),
-            0xbb => self.alu_cp(self.reg.e),
-            0xbc => self.alu_cp(self.reg.h),
-            0xbd => self.alu_cp(self.reg.l),
-            0xbe => {
-                let a = self.mem.borrow().get(self.reg.get_hl());
-                self.alu_cp(a);
-            }
-            0xbf => self.alu_cp(self.reg.a),
-            0xfe => {
-                let v = self.imm();
-                self.alu_cp(v);
-            }
+    // NOP
+    fn nop(&mut self) {}
 
-            // INC r8
-            0x04 => self.reg.b = self.alu_inc(self.reg.b),
-            0x0c => self.reg.c = self.alu_inc(self.reg.c),
-            0x14 => self.reg.d = self.alu_inc(self.reg.d),
-            0x1c => self.reg.e = self.alu_inc(self.reg.e),
-            0x24 => self.reg.h = self.alu_inc(self.reg.h),
-            0x2c => self.reg.l = self.alu_inc(self.reg.l),
-            0x34 => {
-                let a = self.reg.get_hl();
-                let v = self.mem.borrow().get(a);
-                let h = self.alu_inc(v);
-                self.mem.borrow_mut().set(a, h);
-            }
-            0x3c => self.reg.a = self.alu_inc(self.reg.a),
-
-            // DEC r8
-            0x05 => self.reg.b = self.alu_dec(self.reg.b),
-            0x0d => self.reg.c = self.alu_dec(self.reg.c),
-            0x15 => self.reg.d = self.alu_dec(self.reg.d),
-            0x1d => self.reg.e = self.alu_dec(self.reg.e),
-            0x25 => self.reg.h = self.alu_dec(self.reg.h),
-            0x2d => self.reg.l = self.alu_dec(self.reg.l),
-            0x35 => {
-                let a = self.reg.get_hl();
-                let v = self.mem.borrow().get(a);
-                let h = self.alu_dec(v);
-                self.mem.borrow_mut().set(a, h);
-            }
-            0x3d => self.reg.a = self.alu_dec(self.reg.a),
+    // LD r8, r8
+    fn ld_r8_r8<const D: u8, const S: u8>(&mut self) {
+        let v = self.read_r8(S);
+        self.write_r8(D, v);
+        if D == 0 && S == 0 && self.break_on_ld_b_b && self.watch_hit.is_none() {
+            self.watch_hit = Some(BreakReason::Software(self.reg.pc.wrapping_sub(1)));
+        }
+    }
 
-            // ADD HL, r16
-            0x09 => self.alu_add_hl(self.reg.get_bc()),
-            0x19 => self.alu_add_hl(self.reg.get_de()),
-            0x29 => self.alu_add_hl(self.reg.get_hl()),
-            0x39 => self.alu_add_hl(self.reg.sp),
+    // LD r8, d8
+    fn ld_r8_d8<const D: u8>(&mut self) {
+        let v = self.imm();
+        self.write_r8(D, v);
+    }
 
-            // ADD SP, d8
-            0xe8 => self.alu_add_sp(),
+    // LD (BC), A
+    fn ld_bc_a(&mut self) {
+        self.wr(self.reg.get_bc(), self.reg.a);
+    }
 
-            // INC r16
-            0x03 => {
-                let v = self.reg.get_bc().wrapping_add(1);
-                self.reg.set_bc(v);
-            }
-            0x13 => {
-                let v = self.reg.get_de().wrapping_add(1);
-                self.reg.set_de(v);
-            }
-            0x23 => {
-                let v = self.reg.get_hl().wrapping_add(1);
-                self.reg.set_hl(v);
-            }
-            0x33 => {
-                let v = self.reg.sp.wrapping_add(1);
-                self.reg.sp = v;
-            }
+    // LD (DE), A
+    fn ld_de_a(&mut self) {
+        self.wr(self.reg.get_de(), self.reg.a);
+    }
 
-            // DEC r16
-            0x0b => {
-                let v = self.reg.get_bc().wrapping_sub(1);
-                self.reg.set_bc(v);
-            }
-            0x1b => {
-                let v = self.reg.get_de().wrapping_sub(1);
-                self.reg.set_de(v);
-            }
-            0x2b => {
-                let v = self.reg.get_hl().wrapping_sub(1);
-                self.reg.set_hl(v);
-            }
-            0x3b => {
-                let v = self.reg.sp.wrapping_sub(1);
-                self.reg.sp = v;
-            }
+    // LD A, (BC)
+    fn ld_a_bc(&mut self) {
+        self.reg.a = self.rd(self.reg.get_bc());
+    }
 
-            // DAA
-            0x27 => self.alu_daa(),
+    // LD A, (DE)
+    fn ld_a_de(&mut self) {
+        self.reg.a = self.rd(self.reg.get_de());
+    }
 
-            // CPL
-            0x2f => self.alu_cpl(),
+    // LD (HL+), A
+    // The four HL auto increment and decrement loads access memory at HL first, then update HL, which wraps between
+    // FFFFh and 0000h.
+    fn ld_hli_a(&mut self) {
+        let a = self.reg.get_hl();
+        self.wr(a, self.reg.a);
+        self.reg.set_hl(a.wrapping_add(1));
+    }
 
-            // CCF
-            0x3f => self.alu_ccf(),
+    // LD (HL-), A
+    fn ld_hld_a(&mut self) {
+        let a = self.reg.get_hl();
+        self.wr(a, self.reg.a);
+        self.reg.set_hl(a.wrapping_sub(1));
+    }
 
-            // SCF
-            0x37 => self.alu_scf(),
+    // LD A, (HL+)
+    fn ld_a_hli(&mut self) {
+        let v = self.reg.get_hl();
+        self.reg.a = self.rd_inc(v);
+        self.reg.set_hl(v.wrapping_add(1));
+    }
 
-            // NOP
-            0x00 => {}
+    // LD A, (HL-)
+    fn ld_a_hld(&mut self) {
+        let v = self.reg.get_hl();
+        self.reg.a = self.rd_inc(v);
+        self.reg.set_hl(v.wrapping_sub(1));
+    }
 
-            // HALT
-            0x76 => self.halted = true,
+    // LDH (a8), A
+    fn ldh_a8_a(&mut self) {
+        let a = 0xff00 | u16::from(self.imm());
+        self.wr(a, self.reg.a);
+    }
 
-            // STOP
-            0x10 => {}
+    // LDH A, (a8)
+    fn ldh_a_a8(&mut self) {
+        let a = 0xff00 | u16::from(self.imm());
+        self.reg.a = self.rd(a);
+    }
 
-            // DI/EI
-            0xf3 => self.ei = false,
-            0xfb => self.ei = true,
+    // LD (C), A
+    fn ld_c_a(&mut self) {
+        self.wr(0xff00 | u16::from(self.reg.c), self.reg.a);
+    }
 
-            // RLCA
-            0x07 => {
-                self.reg.a = self.alu_rlc(self.reg.a);
-                self.reg.set_flag(Z, false);
-            }
+    // LD A, (C)
+    fn ld_a_c(&mut self) {
+        self.reg.a = self.rd(0xff00 | u16::from(self.reg.c));
+    }
 
-            // RLA
-            0x17 => {
-                self.reg.a = self.alu_rl(self.reg.a);
-                self.reg.set_flag(Z, false);
-            }
+    // LD (a16), A
+    fn ld_a16_a(&mut self) {
+        let a = self.imm_word();
+        self.wr(a, self.reg.a);
+    }
 
-            // RRCA
-            0x0f => {
-                self.reg.a = self.alu_rrc(self.reg.a);
-                self.reg.set_flag(Z, false);
-            }
+    // LD A, (a16)
+    fn ld_a_a16(&mut self) {
+        let a = self.imm_word();
+        self.reg.a = self.rd(a);
+    }
 
-            // RRA
-            0x1f => {
-                self.reg.a = self.alu_rr(self.reg.a);
-                self.reg.set_flag(Z, false);
-            }
+    // LD r16, d16
+    fn ld_r16_d16<const R: u8>(&mut self) {
+        let v = self.imm_word();
+        self.write_r16(R, v);
+    }
 
-            // JUMP
-            0xc3 => self.reg.pc = self.imm_word(),
-            0xe9 => self.reg.pc = self.reg.get_hl(),
-
-            // JUMP IF
-            0xc2 | 0xca | 0xd2 | 0xda => {
-                let pc = self.imm_word();
-                let cond = match opcode {
-                    0xc2 => !self.reg.get_flag(Z),
-                    0xca => self.reg.get_flag(Z),
-                    0xd2 => !self.reg.get_flag(C),
-                    0xda => self.reg.get_flag(C),
-                    _ => panic!(""),
-                };
-                if cond {
-                    self.reg.pc = pc;
-                }
-            }
+    // LD SP, HL
+    fn ld_sp_hl(&mut self) {
+        self.reg.sp = self.reg.get_hl();
+    }
 
-            // JR
-            0x18 => {
-                let n = self.imm();
-                self.alu_jr(n);
-            }
+    // LD HL, SP+e8
+    fn ld_hl_sp_e8(&mut self) {
+        let e = self.imm() as i8;
+        let v = self.reg.add_sp_e8(e);
+        self.reg.set_hl(v);
+    }
 
-            // JR IF
-            0x20 | 0x28 | 0x30 | 0x38 => {
-                let cond = match opcode {
-                    0x20 => !self.reg.get_flag(Z),
-                    0x28 => self.reg.get_flag(Z),
-                    0x30 => !self.reg.get_flag(C),
-                    0x38 => self.reg.get_flag(C),
-                    _ => panic!(""),
-                };
-                let n = self.imm();
-                if cond {
-                    self.alu_jr(n);
-                }
-            }
+    // LD (a16), SP
+    fn ld_a16_sp(&mut self) {
+        let a = self.imm_word();
+        self.wr_word(a, self.reg.sp);
+    }
 
-            // CALL
-            0xcd => {
-                let nn = self.imm_word();
-                self.stack_add(self.reg.pc);
-                self.reg.pc = nn;
-            }
+    // PUSH r16, the high byte at SP - 1 and the low byte at SP - 2.
+    fn push<const R: u8>(&mut self) {
+        let v = if R == 3 { self.reg.get_af() } else { self.read_r16(R) };
+        self.stack_add(v);
+    }
 
-            // CALL IF
-            0xc4 | 0xcc | 0xd4 | 0xdc => {
-                let cond = match opcode {
-                    0xc4 => !self.reg.get_flag(Z),
-                    0xcc => self.reg.get_flag(Z),
-                    0xd4 => !self.reg.get_flag(C),
-                    0xdc => self.reg.get_flag(C),
-                    _ => panic!(""),
-                };
-                let nn = self.imm_word();
-                if cond {
-                    self.stack_add(self.reg.pc);
-                    self.reg.pc = nn;
-                }
-            }
+    // POP r16, the low byte from SP and the high byte from SP + 1. The low 4 bits of F don't exist, POP AF discards
+    // them.
+    fn pop<const R: u8>(&mut self) {
+        let v = self.stack_pop();
+        if R == 3 {
+            self.reg.set_af(v);
+        } else {
+            self.write_r16(R, v);
+        }
+    }
 
-            // RST
-            0xc7 => {
-                self.stack_add(self.reg.pc);
-                self.reg.pc = 0x00;
-            }
-            0xcf => {
-                self.stack_add(self.reg.pc);
-                self.reg.pc = 0x08;
-            }
-            0xd7 => {
-                self.stack_add(self.reg.pc);
-                self.reg.pc = 0x10;
-            }
-            0xdf => {
-                self.stack_add(self.reg.pc);
-                self.reg.pc = 0x18;
-            }
-            0xe7 => {
-                self.stack_add(self.reg.pc);
-                self.reg.pc = 0x20;
-            }
-            0xef => {
-                self.stack_add(self.reg.pc);
-                self.reg.pc = 0x28;
-            }
-            0xf7 => {
-                self.stack_add(self.reg.pc);
-                self.reg.pc = 0x30;
-            }
-            0xff => {
-                self.stack_add(self.reg.pc);
-                self.reg.pc = 0x38;
-            }
+    // ADD, ADC, SUB, SBC, AND, XOR, OR, CP A, r8
+    fn alu_r8<const OP: u8, const S: u8>(&mut self) {
+        let v = self.read_r8(S);
+        self.alu(OP, v);
+    }
 
-            // RET
-            0xc9 => self.reg.pc = self.stack_pop(),
-
-            // RET IF
-            0xc0 | 0xc8 | 0xd0 | 0xd8 => {
-                let cond = match opcode {
-                    0xc0 => !self.reg.get_flag(Z),
-                    0xc8 => self.reg.get_flag(Z),
-                    0xd0 => !self.reg.get_flag(C),
-                    0xd8 => self.reg.get_flag(C),
-                    _ => panic!(""),
-                };
-                if cond {
-                    self.reg.pc = self.stack_pop();
-                }
-            }
+    // ADD, ADC, SUB, SBC, AND, XOR, OR, CP A, d8
+    fn alu_d8<const OP: u8>(&mut self) {
+        let v = self.imm();
+        self.alu(OP, v);
+    }
 
-            // RETI
-            0xd9 => {
-                self.reg.pc = self.stack_pop();
-                self.ei = true;
-            }
+    // INC r8
+    fn inc_r8<const R: u8>(&mut self) {
+        let v = self.read_r8(R);
+        let v = self.alu_inc(v);
+        self.write_r8(R, v);
+    }
 
-            // Extended Bit Operations
-            0xcb => {
-                cbcode = self.mem.borrow().get(self.reg.pc);
-                self.reg.pc += 1;
-                match cbcode {
-                    // RLC r8
-                    0x00 => self.reg.b = self.alu_rlc(self.reg.b),
-                    0x01 => self.reg.c = self.alu_rlc(self.reg.c),
-                    0x02 => self.reg.d = self.alu_rlc(self.reg.d),
-                    0x03 => self.reg.e = self.alu_rlc(self.reg.e),
-                    0x04 => self.reg.h = self.alu_rlc(self.reg.h),
-                    0x05 => self.reg.l = self.alu_rlc(self.reg.l),
-                    0x06 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_rlc(v);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x07 => self.reg.a = self.alu_rlc(self.reg.a),
-
-                    // RRC r8
-                    0x08 => self.reg.b = self.alu_rrc(self.reg.b),
-                    0x09 => self.reg.c = self.alu_rrc(self.reg.c),
-                    0x0a => self.reg.d = self.alu_rrc(self.reg.d),
-                    0x0b => self.reg.e = self.alu_rrc(self.reg.e),
-                    0x0c => self.reg.h = self.alu_rrc(self.reg.h),
-                    0x0d => self.reg.l = self.alu_rrc(self.reg.l),
-                    0x0e => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_rrc(v);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x0f => self.reg.a = self.alu_rrc(self.reg.a),
-
-                    // RL r8
-                    0x10 => self.reg.b = self.alu_rl(self.reg.b),
-                    0x11 => self.reg.c = self.alu_rl(self.reg.c),
-                    0x12 => self.reg.d = self.alu_rl(self.reg.d),
-                    0x13 => self.reg.e = self.alu_rl(self.reg.e),
-                    0x14 => self.reg.h = self.alu_rl(self.reg.h),
-                    0x15 => self.reg.l = self.alu_rl(self.reg.l),
-                    0x16 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_rl(v);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x17 => self.reg.a = self.alu_rl(self.reg.a),
-
-                    // RR r8
-                    0x18 => self.reg.b = self.alu_rr(self.reg.b),
-                    0x19 => self.reg.c = self.alu_rr(self.reg.c),
-                    0x1a => self.reg.d = self.alu_rr(self.reg.d),
-                    0x1b => self.reg.e = self.alu_rr(self.reg.e),
-                    0x1c => self.reg.h = self.alu_rr(self.reg.h),
-                    0x1d => self.reg.l = self.alu_rr(self.reg.l),
-                    0x1e => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_rr(v);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x1f => self.reg.a = self.alu_rr(self.reg.a),
-
-                    // SLA r8
-                    0x20 => self.reg.b = self.alu_sla(self.reg.b),
-                    0x21 => self.reg.c = self.alu_sla(self.reg.c),
-                    0x22 => self.reg.d = self.alu_sla(self.reg.d),
-                    0x23 => self.reg.e = self.alu_sla(self.reg.e),
-                    0x24 => self.reg.h = self.alu_sla(self.reg.h),
-                    0x25 => self.reg.l = self.alu_sla(self.reg.l),
-                    0x26 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_sla(v);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x27 => self.reg.a = self.alu_sla(self.reg.a),
-
-                    // SRA r8
-                    0x28 => self.reg.b = self.alu_sra(self.reg.b),
-                    0x29 => self.reg.c = self.alu_sra(self.reg.c),
-                    0x2a => self.reg.d = self.alu_sra(self.reg.d),
-                    0x2b => self.reg.e = self.alu_sra(self.reg.e),
-                    0x2c => self.reg.h = self.alu_sra(self.reg.h),
-                    0x2d => self.reg.l = self.alu_sra(self.reg.l),
-                    0x2e => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_sra(v);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x2f => self.reg.a = self.alu_sra(self.reg.a),
-
-                    // SWAP r8
-                    0x30 => self.reg.b = self.alu_swap(self.reg.b),
-                    0x31 => self.reg.c = self.alu_swap(self.reg.c),
-                    0x32 => self.reg.d = self.alu_swap(self.reg.d),
-                    0x33 => self.reg.e = self.alu_swap(self.reg.e),
-                    0x34 => self.reg.h = self.alu_swap(self.reg.h),
-                    0x35 => self.reg.l = self.alu_swap(self.reg.l),
-                    0x36 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_swap(v);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x37 => self.reg.a = self.alu_swap(self.reg.a),
-
-                    // SRL r8
-                    0x38 => self.reg.b = self.alu_srl(self.reg.b),
-                    0x39 => self.reg.c = self.alu_srl(self.reg.c),
-                    0x3a => self.reg.d = self.alu_srl(self.reg.d),
-                    0x3b => self.reg.e = self.alu_srl(self.reg.e),
-                    0x3c => self.reg.h = self.alu_srl(self.reg.h),
-                    0x3d => self.reg.l = self.alu_srl(self.reg.l),
-                    0x3e => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_srl(v);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x3f => self.reg.a = self.alu_srl(self.reg.a),
-
-                    // BIT b, r8
-                    0x40 => self.alu_bit(self.reg.b, 0),
-                    0x41 => self.alu_bit(self.reg.c, 0),
-                    0x42 => self.alu_bit(self.reg.d, 0),
-                    0x43 => self.alu_bit(self.reg.e, 0),
-                    0x44 => self.alu_bit(self.reg.h, 0),
-                    0x45 => self.alu_bit(self.reg.l, 0),
-                    0x46 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        self.alu_bit(v, 0);
-                    }
-                    0x47 => self.alu_bit(self.reg.a, 0),
-                    0x48 => self.alu_bit(self.reg.b, 1),
-                    0x49 => self.alu_bit(self.reg.c, 1),
-                    0x4a => self.alu_bit(self.reg.d, 1),
-                    0x4b => self.alu_bit(self.reg.e, 1),
-                    0x4c => self.alu_bit(self.reg.h, 1),
-                    0x4d => self.alu_bit(self.reg.l, 1),
-                    0x4e => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        self.alu_bit(v, 1);
-                    }
-                    0x4f => self.alu_bit(self.reg.a, 1),
-                    0x50 => self.alu_bit(self.reg.b, 2),
-                    0x51 => self.alu_bit(self.reg.c, 2),
-                    0x52 => self.alu_bit(self.reg.d, 2),
-                    0x53 => self.alu_bit(self.reg.e, 2),
-                    0x54 => self.alu_bit(self.reg.h, 2),
-                    0x55 => self.alu_bit(self.reg.l, 2),
-                    0x56 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        self.alu_bit(v, 2);
-                    }
-                    0x57 => self.alu_bit(self.reg.a, 2),
-                    0x58 => self.alu_bit(self.reg.b, 3),
-                    0x59 => self.alu_bit(self.reg.c, 3),
-                    0x5a => self.alu_bit(self.reg.d, 3),
-                    0x5b => self.alu_bit(self.reg.e, 3),
-                    0x5c => self.alu_bit(self.reg.h, 3),
-                    0x5d => self.alu_bit(self.reg.l, 3),
-                    0x5e => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        self.alu_bit(v, 3);
-                    }
-                    0x5f => self.alu_bit(self.reg.a, 3),
-                    0x60 => self.alu_bit(self.reg.b, 4),
-                    0x61 => self.alu_bit(self.reg.c, 4),
-                    0x62 => self.alu_bit(self.reg.d, 4),
-                    0x63 => self.alu_bit(self.reg.e, 4),
-                    0x64 => self.alu_bit(self.reg.h, 4),
-                    0x65 => self.alu_bit(self.reg.l, 4),
-                    0x66 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        self.alu_bit(v, 4);
-                    }
-                    0x67 => self.alu_bit(self.reg.a, 4),
-                    0x68 => self.alu_bit(self.reg.b, 5),
-                    0x69 => self.alu_bit(self.reg.c, 5),
-                    0x6a => self.alu_bit(self.reg.d, 5),
-                    0x6b => self.alu_bit(self.reg.e, 5),
-                    0x6c => self.alu_bit(self.reg.h, 5),
-                    0x6d => self.alu_bit(self.reg.l, 5),
-                    0x6e => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        self.alu_bit(v, 5);
-                    }
-                    0x6f => self.alu_bit(self.reg.a, 5),
-                    0x70 => self.alu_bit(self.reg.b, 6),
-                    0x71 => self.alu_bit(self.reg.c, 6),
-                    0x72 => self.alu_bit(self.reg.d, 6),
-                    0x73 => self.alu_bit(self.reg.e, 6),
-                    0x74 => self.alu_bit(self.reg.h, 6),
-                    0x75 => self.alu_bit(self.reg.l, 6),
-                    0x76 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        self.alu_bit(v, 6);
-                    }
-                    0x77 => self.alu_bit(self.reg.a, 6),
-                    0x78 => self.alu_bit(self.reg.b, 7),
-                    0x79 => self.alu_bit(self.reg.c, 7),
-                    0x7a => self.alu_bit(self.reg.d, 7),
-                    0x7b => self.alu_bit(self.reg.e, 7),
-                    0x7c => self.alu_bit(self.reg.h, 7),
-                    0x7d => self.alu_bit(self.reg.l, 7),
-                    0x7e => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        self.alu_bit(v, 7);
-                    }
-                    0x7f => self.alu_bit(self.reg.a, 7),
-
-                    // RES b, r8
-                    0x80 => self.reg.b = self.alu_res(self.reg.b, 0),
-                    0x81 => self.reg.c = self.alu_res(self.reg.c, 0),
-                    0x82 => self.reg.d = self.alu_res(self.reg.d, 0),
-                    0x83 => self.reg.e = self.alu_res(self.reg.e, 0),
-                    0x84 => self.reg.h = self.alu_res(self.reg.h, 0),
-                    0x85 => self.reg.l = self.alu_res(self.reg.l, 0),
-                    0x86 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_res(v, 0);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x87 => self.reg.a = self.alu_res(self.reg.a, 0),
-                    0x88 => self.reg.b = self.alu_res(self.reg.b, 1),
-                    0x89 => self.reg.c = self.alu_res(self.reg.c, 1),
-                    0x8a => self.reg.d = self.alu_res(self.reg.d, 1),
-                    0x8b => self.reg.e = self.alu_res(self.reg.e, 1),
-                    0x8c => self.reg.h = self.alu_res(self.reg.h, 1),
-                    0x8d => self.reg.l = self.alu_res(self.reg.l, 1),
-                    0x8e => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_res(v, 1);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x8f => self.reg.a = self.alu_res(self.reg.a, 1),
-                    0x90 => self.reg.b = self.alu_res(self.reg.b, 2),
-                    0x91 => self.reg.c = self.alu_res(self.reg.c, 2),
-                    0x92 => self.reg.d = self.alu_res(self.reg.d, 2),
-                    0x93 => self.reg.e = self.alu_res(self.reg.e, 2),
-                    0x94 => self.reg.h = self.alu_res(self.reg.h, 2),
-                    0x95 => self.reg.l = self.alu_res(self.reg.l, 2),
-                    0x96 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_res(v, 2);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x97 => self.reg.a = self.alu_res(self.reg.a, 2),
-                    0x98 => self.reg.b = self.alu_res(self.reg.b, 3),
-                    0x99 => self.reg.c = self.alu_res(self.reg.c, 3),
-                    0x9a => self.reg.d = self.alu_res(self.reg.d, 3),
-                    0x9b => self.reg.e = self.alu_res(self.reg.e, 3),
-                    0x9c => self.reg.h = self.alu_res(self.reg.h, 3),
-                    0x9d => self.reg.l = self.alu_res(self.reg.l, 3),
-                    0x9e => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_res(v, 3);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0x9f => self.reg.a = self.alu_res(self.reg.a, 3),
-                    0xa0 => self.reg.b = self.alu_res(self.reg.b, 4),
-                    0xa1 => self.reg.c = self.alu_res(self.reg.c, 4),
-                    0xa2 => self.reg.d = self.alu_res(self.reg.d, 4),
-                    0xa3 => self.reg.e = self.alu_res(self.reg.e, 4),
-                    0xa4 => self.reg.h = self.alu_res(self.reg.h, 4),
-                    0xa5 => self.reg.l = self.alu_res(self.reg.l, 4),
-                    0xa6 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_res(v, 4);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xa7 => self.reg.a = self.alu_res(self.reg.a, 4),
-                    0xa8 => self.reg.b = self.alu_res(self.reg.b, 5),
-                    0xa9 => self.reg.c = self.alu_res(self.reg.c, 5),
-                    0xaa => self.reg.d = self.alu_res(self.reg.d, 5),
-                    0xab => self.reg.e = self.alu_res(self.reg.e, 5),
-                    0xac => self.reg.h = self.alu_res(self.reg.h, 5),
-                    0xad => self.reg.l = self.alu_res(self.reg.l, 5),
-                    0xae => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_res(v, 5);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xaf => self.reg.a = self.alu_res(self.reg.a, 5),
-                    0xb0 => self.reg.b = self.alu_res(self.reg.b, 6),
-                    0xb1 => self.reg.c = self.alu_res(self.reg.c, 6),
-                    0xb2 => self.reg.d = self.alu_res(self.reg.d, 6),
-                    0xb3 => self.reg.e = self.alu_res(self.reg.e, 6),
-                    0xb4 => self.reg.h = self.alu_res(self.reg.h, 6),
-                    0xb5 => self.reg.l = self.alu_res(self.reg.l, 6),
-                    0xb6 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_res(v, 6);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xb7 => self.reg.a = self.alu_res(self.reg.a, 6),
-                    0xb8 => self.reg.b = self.alu_res(self.reg.b, 7),
-                    0xb9 => self.reg.c = self.alu_res(self.reg.c, 7),
-                    0xba => self.reg.d = self.alu_res(self.reg.d, 7),
-                    0xbb => self.reg.e = self.alu_res(self.reg.e, 7),
-                    0xbc => self.reg.h = self.alu_res(self.reg.h, 7),
-                    0xbd => self.reg.l = self.alu_res(self.reg.l, 7),
-                    0xbe => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_res(v, 7);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xbf => self.reg.a = self.alu_res(self.reg.a, 7),
-
-                    // SET b, r8
-                    0xc0 => self.reg.b = self.alu_set(self.reg.b, 0),
-                    0xc1 => self.reg.c = self.alu_set(self.reg.c, 0),
-                    0xc2 => self.reg.d = self.alu_set(self.reg.d, 0),
-                    0xc3 => self.reg.e = self.alu_set(self.reg.e, 0),
-                    0xc4 => self.reg.h = self.alu_set(self.reg.h, 0),
-                    0xc5 => self.reg.l = self.alu_set(self.reg.l, 0),
-                    0xc6 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_set(v, 0);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xc7 => self.reg.a = self.alu_set(self.reg.a, 0),
-                    0xc8 => self.reg.b = self.alu_set(self.reg.b, 1),
-                    0xc9 => self.reg.c = self.alu_set(self.reg.c, 1),
-                    0xca => self.reg.d = self.alu_set(self.reg.d, 1),
-                    0xcb => self.reg.e = self.alu_set(self.reg.e, 1),
-                    0xcc => self.reg.h = self.alu_set(self.reg.h, 1),
-                    0xcd => self.reg.l = self.alu_set(self.reg.l, 1),
-                    0xce => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_set(v, 1);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xcf => self.reg.a = self.alu_set(self.reg.a, 1),
-                    0xd0 => self.reg.b = self.alu_set(self.reg.b, 2),
-                    0xd1 => self.reg.c = self.alu_set(self.reg.c, 2),
-                    0xd2 => self.reg.d = self.alu_set(self.reg.d, 2),
-                    0xd3 => self.reg.e = self.alu_set(self.reg.e, 2),
-                    0xd4 => self.reg.h = self.alu_set(self.reg.h, 2),
-                    0xd5 => self.reg.l = self.alu_set(self.reg.l, 2),
-                    0xd6 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_set(v, 2);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xd7 => self.reg.a = self.alu_set(self.reg.a, 2),
-                    0xd8 => self.reg.b = self.alu_set(self.reg.b, 3),
-                    0xd9 => self.reg.c = self.alu_set(self.reg.c, 3),
-                    0xda => self.reg.d = self.alu_set(self.reg.d, 3),
-                    0xdb => self.reg.e = self.alu_set(self.reg.e, 3),
-                    0xdc => self.reg.h = self.alu_set(self.reg.h, 3),
-                    0xdd => self.reg.l = self.alu_set(self.reg.l, 3),
-                    0xde => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_set(v, 3);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xdf => self.reg.a = self.alu_set(self.reg.a, 3),
-                    0xe0 => self.reg.b = self.alu_set(self.reg.b, 4),
-                    0xe1 => self.reg.c = self.alu_set(self.reg.c, 4),
-                    0xe2 => self.reg.d = self.alu_set(self.reg.d, 4),
-                    0xe3 => self.reg.e = self.alu_set(self.reg.e, 4),
-                    0xe4 => self.reg.h = self.alu_set(self.reg.h, 4),
-                    0xe5 => self.reg.l = self.alu_set(self.reg.l, 4),
-                    0xe6 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_set(v, 4);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xe7 => self.reg.a = self.alu_set(self.reg.a, 4),
-                    0xe8 => self.reg.b = self.alu_set(self.reg.b, 5),
-                    0xe9 => self.reg.c = self.alu_set(self.reg.c, 5),
-                    0xea => self.reg.d = self.alu_set(self.reg.d, 5),
-                    0xeb => self.reg.e = self.alu_set(self.reg.e, 5),
-                    0xec => self.reg.h = self.alu_set(self.reg.h, 5),
-                    0xed => self.reg.l = self.alu_set(self.reg.l, 5),
-                    0xee => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_set(v, 5);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xef => self.reg.a = self.alu_set(self.reg.a, 5),
-                    0xf0 => self.reg.b = self.alu_set(self.reg.b, 6),
-                    0xf1 => self.reg.c = self.alu_set(self.reg.c, 6),
-                    0xf2 => self.reg.d = self.alu_set(self.reg.d, 6),
-                    0xf3 => self.reg.e = self.alu_set(self.reg.e, 6),
-                    0xf4 => self.reg.h = self.alu_set(self.reg.h, 6),
-                    0xf5 => self.reg.l = self.alu_set(self.reg.l, 6),
-                    0xf6 => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_set(v, 6);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xf7 => self.reg.a = self.alu_set(self.reg.a, 6),
-                    0xf8 => self.reg.b = self.alu_set(self.reg.b, 7),
-                    0xf9 => self.reg.c = self.alu_set(self.reg.c, 7),
-                    0xfa => self.reg.d = self.alu_set(self.reg.d, 7),
-                    0xfb => self.reg.e = self.alu_set(self.reg.e, 7),
-                    0xfc => self.reg.h = self.alu_set(self.reg.h, 7),
-                    0xfd => self.reg.l = self.alu_set(self.reg.l, 7),
-                    0xfe => {
-                        let a = self.reg.get_hl();
-                        let v = self.mem.borrow().get(a);
-                        let h = self.alu_set(v, 7);
-                        self.mem.borrow_mut().set(a, h);
-                    }
-                    0xff => self.reg.a = self.alu_set(self.reg.a, 7),
-                }
-            }
-            0xd3 => panic!("Opcode 0xd3 is not implemented"),
-            0xdb => panic!("Opcode 0xdb is not implemented"),
-            0xdd => panic!("Opcode 0xdd is not implemented"),
-            0xe3 => panic!("Opcode 0xe3 is not implemented"),
-            0xe4 => panic!("Opcode 0xd4 is not implemented"),
-            0xeb => panic!("Opcode 0xeb is not implemented"),
-            0xec => panic!("Opcode 0xec is not implemented"),
-            0xed => panic!("Opcode 0xed is not implemented"),
-            0xf4 => panic!("Opcode 0xf4 is not implemented"),
-            0xfc => panic!("Opcode 0xfc is not implemented"),
-            0xfd => panic!("Opcode 0xfd is not implemented"),
-        };
+    // DEC r8
+    fn dec_r8<const R: u8>(&mut self) {
+        let v = self.read_r8(R);
+        let v = self.alu_dec(v);
+        self.write_r8(R, v);
+    }
 
-        let ecycle = match opcode {
-            0x20 | 0x30 => {
-                if self.reg.get_flag(Z) {
-                    0x00
-                } else {
-                    0x01
-                }
-            }
-            0x28 | 0x38 => {
-                if self.reg.get_flag(Z) {
-                    0x01
-                } else {
-                    0x00
-                }
-            }
-            0xc0 | 0xd0 => {
-                if self.reg.get_flag(Z) {
-                    0x00
-                } else {
-                    0x03
-                }
-            }
-            0xc8 | 0xcc | 0xd8 | 0xdc => {
-                if self.reg.get_flag(Z) {
-                    0x03
-                } else {
-                    0x00
-                }
-            }
-            0xc2 | 0xd2 => {
-                if self.reg.get_flag(Z) {
-                    0x00
-                } else {
-                    0x01
-                }
-            }
-            0xca | 0xda => {
-                if self.reg.get_flag(Z) {
-                    0x01
-                } else {
-                    0x00
-                }
-            }
-            0xc4 | 0xd4 => {
-                if self.reg.get_flag(Z) {
-                    0x00
-                } else {
-                    0x03
-                }
-            }
-            _ => 0x00,
-        };
-        if opcode == 0xcb {
-            CB_CYCLES[cbcode as usize]
-        } else {
-            OP_CYCLES[opcode as usize] + ecycle
-        }
+    // ADD HL, r16
+    fn add_hl_r16<const R: u8>(&mut self) {
+        self.reg.add_hl(self.read_r16(R));
     }
 
-    pub fn next(&mut self) -> u32 {
-        let mac = {
-            let c = self.hi();
-            if c != 0 {
-                c
-            } else if self.halted {
-                OP_CYCLES[0]
-            } else {
-                self.ex()
+    // ADD SP, e8
+    fn add_sp_e8(&mut self) {
+        let e = self.imm() as i8;
+        self.reg.sp = self.reg.add_sp_e8(e);
+    }
+
+    // INC r16
+    fn inc_r16<const R: u8>(&mut self) {
+        let v = self.read_r16(R);
+        self.idu(v);
+        self.write_r16(R, v.wrapping_add(1));
+    }
+
+    // DEC r16
+    fn dec_r16<const R: u8>(&mut self) {
+        let v = self.read_r16(R);
+        self.idu(v);
+        self.write_r16(R, v.wrapping_sub(1));
+    }
+
+    // DAA
+    fn daa(&mut self) {
+        self.reg.daa();
+    }
+
+    // CPL
+    fn cpl(&mut self) {
+        self.alu_cpl();
+    }
+
+    // CCF
+    fn ccf(&mut self) {
+        self.alu_ccf();
+    }
+
+    // SCF
+    fn scf(&mut self) {
+        self.alu_scf();
+    }
+
+    // HALT
+    fn halt(&mut self) {
+        let intf = self.mem.borrow().get(0xff0f);
+        let inte = self.mem.borrow().get(0xffff);
+        if !self.ei && intf & inte & 0x1f != 0x00 {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+    }
+
+    // DI
+    fn di(&mut self) {
+        self.ei = false;
+        self.ei_pending = false;
+    }
+
+    // EI
+    fn ei(&mut self) {
+        self.ei_pending = true;
+    }
+
+    // RLCA
+    // RLCA, RLA, RRCA and RRA always clear Z, even when A becomes zero. Their CB versions RLC A, RL A, RRC A and RR A
+    // set it from the result.
+    fn rlca(&mut self) {
+        self.reg.a = self.alu_rlc(self.reg.a);
+        self.reg.set_flag(Z, false);
+    }
+
+    // RLA
+    fn rla(&mut self) {
+        self.reg.a = self.alu_rl(self.reg.a);
+        self.reg.set_flag(Z, false);
+    }
+
+    // RRCA
+    fn rrca(&mut self) {
+        self.reg.a = self.alu_rrc(self.reg.a);
+        self.reg.set_flag(Z, false);
+    }
+
+    // RRA
+    fn rra(&mut self) {
+        self.reg.a = self.alu_rr(self.reg.a);
+        self.reg.set_flag(Z, false);
+    }
+
+    // JP a16
+    fn jp(&mut self) {
+        self.reg.pc = self.imm_word();
+    }
+
+    // JP HL
+    fn jp_hl(&mut self) {
+        self.reg.pc = self.reg.get_hl();
+    }
+
+    // JP cc, a16
+    fn jp_cc<const CC: u8>(&mut self) {
+        let pc = self.imm_word();
+        if self.cond(CC) {
+            self.reg.pc = pc;
+        }
+    }
+
+    // JR e8
+    fn jr(&mut self) {
+        let n = self.imm();
+        self.alu_jr(n);
+    }
+
+    // JR cc, e8
+    fn jr_cc<const CC: u8>(&mut self) {
+        let cond = self.cond(CC);
+        let n = self.imm();
+        if cond {
+            self.alu_jr(n);
+        }
+    }
+
+    // CALL a16
+    fn call(&mut self) {
+        let nn = self.imm_word();
+        self.stack_add(self.reg.pc);
+        self.reg.pc = nn;
+    }
+
+    // CALL cc, a16
+    fn call_cc<const CC: u8>(&mut self) {
+        let cond = self.cond(CC);
+        let nn = self.imm_word();
+        if cond {
+            self.stack_add(self.reg.pc);
+            self.reg.pc = nn;
+        }
+    }
+
+    // RST n
+    fn rst<const N: u16>(&mut self) {
+        self.stack_add(self.reg.pc);
+        self.reg.pc = N;
+    }
+
+    // RET
+    fn ret(&mut self) {
+        self.reg.pc = self.stack_pop();
+    }
+
+    // RET cc
+    fn ret_cc<const CC: u8>(&mut self) {
+        if self.cond(CC) {
+            self.reg.pc = self.stack_pop();
+        }
+    }
+
+    // RETI
+    // Unlike EI, interrupts are enabled at once, a pending interrupt is serviced before the instruction returned to.
+    fn reti(&mut self) {
+        self.reg.pc = self.stack_pop();
+        self.ei = true;
+    }
+
+    // Extended Bit Operations
+    fn prefix_cb(&mut self) {
+        let cbcode = self.imm();
+        CB_OPS[usize::from(cbcode)](self);
+    }
+
+    // 0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc and 0xfd have no instruction and lock the cpu up.
+    fn undefined<const OP: u8>(&mut self) {
+        self.fault = Some(StepError::IllegalOpcode {
+            opcode: OP,
+            pc: self.reg.pc.wrapping_sub(1),
+        });
+    }
+
+    // RLC, RRC, RL, RR, SLA, SRA, SWAP, SRL r8
+    fn rot_r8<const OP: u8, const R: u8>(&mut self) {
+        let v = self.read_r8(R);
+        let v = match OP {
+            0 => self.alu_rlc(v),
+            1 => self.alu_rrc(v),
+            2 => self.alu_rl(v),
+            3 => self.alu_rr(v),
+            4 => self.alu_sla(v),
+            5 => self.alu_sra(v),
+            6 => self.alu_swap(v),
+            _ => self.alu_srl(v),
+        };
+        self.write_r8(R, v);
+    }
+
+    // BIT b, r8
+    fn bit_r8<const B: u8, const R: u8>(&mut self) {
+        let v = self.read_r8(R);
+        self.alu_bit(v, B);
+    }
+
+    // RES b, r8
+    fn res_r8<const B: u8, const R: u8>(&mut self) {
+        let v = self.read_r8(R);
+        let v = self.alu_res(v, B);
+        self.write_r8(R, v);
+    }
+
+    // SET b, r8
+    fn set_r8<const B: u8, const R: u8>(&mut self) {
+        let v = self.read_r8(R);
+        let v = self.alu_set(v, B);
+        self.write_r8(R, v);
+    }
+}
+
+// Handlers of the 256 opcodes.
+static OPS: [Op; 256] = [
+    // 0x00
+    Cpu::nop,
+    Cpu::ld_r16_d16::<0>,
+    Cpu::ld_bc_a,
+    Cpu::inc_r16::<0>,
+    Cpu::inc_r8::<0>,
+    Cpu::dec_r8::<0>,
+    Cpu::ld_r8_d8::<0>,
+    Cpu::rlca,
+    Cpu::ld_a16_sp,
+    Cpu::add_hl_r16::<0>,
+    Cpu::ld_a_bc,
+    Cpu::dec_r16::<0>,
+    Cpu::inc_r8::<1>,
+    Cpu::dec_r8::<1>,
+    Cpu::ld_r8_d8::<1>,
+    Cpu::rrca,
+    // 0x10
+    Cpu::stop,
+    Cpu::ld_r16_d16::<1>,
+    Cpu::ld_de_a,
+    Cpu::inc_r16::<1>,
+    Cpu::inc_r8::<2>,
+    Cpu::dec_r8::<2>,
+    Cpu::ld_r8_d8::<2>,
+    Cpu::rla,
+    Cpu::jr,
+    Cpu::add_hl_r16::<1>,
+    Cpu::ld_a_de,
+    Cpu::dec_r16::<1>,
+    Cpu::inc_r8::<3>,
+    Cpu::dec_r8::<3>,
+    Cpu::ld_r8_d8::<3>,
+    Cpu::rra,
+    // 0x20
+    Cpu::jr_cc::<0>,
+    Cpu::ld_r16_d16::<2>,
+    Cpu::ld_hli_a,
+    Cpu::inc_r16::<2>,
+    Cpu::inc_r8::<4>,
+    Cpu::dec_r8::<4>,
+    Cpu::ld_r8_d8::<4>,
+    Cpu::daa,
+    Cpu::jr_cc::<1>,
+    Cpu::add_hl_r16::<2>,
+    Cpu::ld_a_hli,
+    Cpu::dec_r16::<2>,
+    Cpu::inc_r8::<5>,
+    Cpu::dec_r8::<5>,
+    Cpu::ld_r8_d8::<5>,
+    Cpu::cpl,
+    // 0x30
+    Cpu::jr_cc::<2>,
+    Cpu::ld_r16_d16::<3>,
+    Cpu::ld_hld_a,
+    Cpu::inc_r16::<3>,
+    Cpu::inc_r8::<6>,
+    Cpu::dec_r8::<6>,
+    Cpu::ld_r8_d8::<6>,
+    Cpu::scf,
+    Cpu::jr_cc::<3>,
+    Cpu::add_hl_r16::<3>,
+    Cpu::ld_a_hld,
+    Cpu::dec_r16::<3>,
+    Cpu::inc_r8::<7>,
+    Cpu::dec_r8::<7>,
+    Cpu::ld_r8_d8::<7>,
+    Cpu::ccf,
+    // 0x40
+    Cpu::ld_r8_r8::<0, 0>,
+    Cpu::ld_r8_r8::<0, 1>,
+    Cpu::ld_r8_r8::<0, 2>,
+    Cpu::ld_r8_r8::<0, 3>,
+    Cpu::ld_r8_r8::<0, 4>,
+    Cpu::ld_r8_r8::<0, 5>,
+    Cpu::ld_r8_r8::<0, 6>,
+    Cpu::ld_r8_r8::<0, 7>,
+    Cpu::ld_r8_r8::<1, 0>,
+    Cpu::ld_r8_r8::<1, 1>,
+    Cpu::ld_r8_r8::<1, 2>,
+    Cpu::ld_r8_r8::<1, 3>,
+    Cpu::ld_r8_r8::<1, 4>,
+    Cpu::ld_r8_r8::<1, 5>,
+    Cpu::ld_r8_r8::<1, 6>,
+    Cpu::ld_r8_r8::<1, 7>,
+    // 0x50
+    Cpu::ld_r8_r8::<2, 0>,
+    Cpu::ld_r8_r8::<2, 1>,
+    Cpu::ld_r8_r8::<2, 2>,
+    Cpu::ld_r8_r8::<2, 3>,
+    Cpu::ld_r8_r8::<2, 4>,
+    Cpu::ld_r8_r8::<2, 5>,
+    Cpu::ld_r8_r8::<2, 6>,
+    Cpu::ld_r8_r8::<2, 7>,
+    Cpu::ld_r8_r8::<3, 0>,
+    Cpu::ld_r8_r8::<3, 1>,
+    Cpu::ld_r8_r8::<3, 2>,
+    Cpu::ld_r8_r8::<3, 3>,
+    Cpu::ld_r8_r8::<3, 4>,
+    Cpu::ld_r8_r8::<3, 5>,
+    Cpu::ld_r8_r8::<3, 6>,
+    Cpu::ld_r8_r8::<3, 7>,
+    // 0x60
+    Cpu::ld_r8_r8::<4, 0>,
+    Cpu::ld_r8_r8::<4, 1>,
+    Cpu::ld_r8_r8::<4, 2>,
+    Cpu::ld_r8_r8::<4, 3>,
+    Cpu::ld_r8_r8::<4, 4>,
+    Cpu::ld_r8_r8::<4, 5>,
+    Cpu::ld_r8_r8::<4, 6>,
+    Cpu::ld_r8_r8::<4, 7>,
+    Cpu::ld_r8_r8::<5, 0>,
+    Cpu::ld_r8_r8::<5, 1>,
+    Cpu::ld_r8_r8::<5, 2>,
+    Cpu::ld_r8_r8::<5, 3>,
+    Cpu::ld_r8_r8::<5, 4>,
+    Cpu::ld_r8_r8::<5, 5>,
+    Cpu::ld_r8_r8::<5, 6>,
+    Cpu::ld_r8_r8::<5, 7>,
+    // 0x70
+    Cpu::ld_r8_r8::<6, 0>,
+    Cpu::ld_r8_r8::<6, 1>,
+    Cpu::ld_r8_r8::<6, 2>,
+    Cpu::ld_r8_r8::<6, 3>,
+    Cpu::ld_r8_r8::<6, 4>,
+    Cpu::ld_r8_r8::<6, 5>,
+    Cpu::halt,
+    Cpu::ld_r8_r8::<6, 7>,
+    Cpu::ld_r8_r8::<7, 0>,
+    Cpu::ld_r8_r8::<7, 1>,
+    Cpu::ld_r8_r8::<7, 2>,
+    Cpu::ld_r8_r8::<7, 3>,
+    Cpu::ld_r8_r8::<7, 4>,
+    Cpu::ld_r8_r8::<7, 5>,
+    Cpu::ld_r8_r8::<7, 6>,
+    Cpu::ld_r8_r8::<7, 7>,
+    // 0x80
+    Cpu::alu_r8::<0, 0>,
+    Cpu::alu_r8::<0, 1>,
+    Cpu::alu_r8::<0, 2>,
+    Cpu::alu_r8::<0, 3>,
+    Cpu::alu_r8::<0, 4>,
+    Cpu::alu_r8::<0, 5>,
+    Cpu::alu_r8::<0, 6>,
+    Cpu::alu_r8::<0, 7>,
+    Cpu::alu_r8::<1, 0>,
+    Cpu::alu_r8::<1, 1>,
+    Cpu::alu_r8::<1, 2>,
+    Cpu::alu_r8::<1, 3>,
+    Cpu::alu_r8::<1, 4>,
+    Cpu::alu_r8::<1, 5>,
+    Cpu::alu_r8::<1, 6>,
+    Cpu::alu_r8::<1, 7>,
+    // 0x90
+    Cpu::alu_r8::<2, 0>,
+    Cpu::alu_r8::<2, 1>,
+    Cpu::alu_r8::<2, 2>,
+    Cpu::alu_r8::<2, 3>,
+    Cpu::alu_r8::<2, 4>,
+    Cpu::alu_r8::<2, 5>,
+    Cpu::alu_r8::<2, 6>,
+    Cpu::alu_r8::<2, 7>,
+    Cpu::alu_r8::<3, 0>,
+    Cpu::alu_r8::<3, 1>,
+    Cpu::alu_r8::<3, 2>,
+    Cpu::alu_r8::<3, 3>,
+    Cpu::alu_r8::<3, 4>,
+    Cpu::alu_r8::<3, 5>,
+    Cpu::alu_r8::<3, 6>,
+    Cpu::alu_r8::<3, 7>,
+    // 0xa0
+    Cpu::alu_r8::<4, 0>,
+    Cpu::alu_r8::<4, 1>,
+    Cpu::alu_r8::<4, 2>,
+    Cpu::alu_r8::<4, 3>,
+    Cpu::alu_r8::<4, 4>,
+    Cpu::alu_r8::<4, 5>,
+    Cpu::alu_r8::<4, 6>,
+    Cpu::alu_r8::<4, 7>,
+    Cpu::alu_r8::<5, 0>,
+    Cpu::alu_r8::<5, 1>,
+    Cpu::alu_r8::<5, 2>,
+    Cpu::alu_r8::<5, 3>,
+    Cpu::alu_r8::<5, 4>,
+    Cpu::alu_r8::<5, 5>,
+    Cpu::alu_r8::<5, 6>,
+    Cpu::alu_r8::<5, 7>,
+    // 0xb0
+    Cpu::alu_r8::<6, 0>,
+    Cpu::alu_r8::<6, 1>,
+    Cpu::alu_r8::<6, 2>,
+    Cpu::alu_r8::<6, 3>,
+    Cpu::alu_r8::<6, 4>,
+    Cpu::alu_r8::<6, 5>,
+    Cpu::alu_r8::<6, 6>,
+    Cpu::alu_r8::<6, 7>,
+    Cpu::alu_r8::<7, 0>,
+    Cpu::alu_r8::<7, 1>,
+    Cpu::alu_r8::<7, 2>,
+    Cpu::alu_r8::<7, 3>,
+    Cpu::alu_r8::<7, 4>,
+    Cpu::alu_r8::<7, 5>,
+    Cpu::alu_r8::<7, 6>,
+    Cpu::alu_r8::<7, 7>,
+    // 0xc0
+    Cpu::ret_cc::<0>,
+    Cpu::pop::<0>,
+    Cpu::jp_cc::<0>,
+    Cpu::jp,
+    Cpu::call_cc::<0>,
+    Cpu::push::<0>,
+    Cpu::alu_d8::<0>,
+    Cpu::rst::<0x00>,
+    Cpu::ret_cc::<1>,
+    Cpu::ret,
+    Cpu::jp_cc::<1>,
+    Cpu::prefix_cb,
+    Cpu::call_cc::<1>,
+    Cpu::call,
+    Cpu::alu_d8::<1>,
+    Cpu::rst::<0x08>,
+    // 0xd0
+    Cpu::ret_cc::<2>,
+    Cpu::pop::<1>,
+    Cpu::jp_cc::<2>,
+    Cpu::undefined::<0xd3>,
+    Cpu::call_cc::<2>,
+    Cpu::push::<1>,
+    Cpu::alu_d8::<2>,
+    Cpu::rst::<0x10>,
+    Cpu::ret_cc::<3>,
+    Cpu::reti,
+    Cpu::jp_cc::<3>,
+    Cpu::undefined::<0xdb>,
+    Cpu::call_cc::<3>,
+    Cpu::undefined::<0xdd>,
+    Cpu::alu_d8::<3>,
+    Cpu::rst::<0x18>,
+    // 0xe0
+    Cpu::ldh_a8_a,
+    Cpu::pop::<2>,
+    Cpu::ld_c_a,
+    Cpu::undefined::<0xe3>,
+    Cpu::undefined::<0xe4>,
+    Cpu::push::<2>,
+    Cpu::alu_d8::<4>,
+    Cpu::rst::<0x20>,
+    Cpu::add_sp_e8,
+    Cpu::jp_hl,
+    Cpu::ld_a16_a,
+    Cpu::undefined::<0xeb>,
+    Cpu::undefined::<0xec>,
+    Cpu::undefined::<0xed>,
+    Cpu::alu_d8::<5>,
+    Cpu::rst::<0x28>,
+    // 0xf0
+    Cpu::ldh_a_a8,
+    Cpu::pop::<3>,
+    Cpu::ld_a_c,
+    Cpu::di,
+    Cpu::undefined::<0xf4>,
+    Cpu::push::<3>,
+    Cpu::alu_d8::<6>,
+    Cpu::rst::<0x30>,
+    Cpu::ld_hl_sp_e8,
+    Cpu::ld_sp_hl,
+    Cpu::ld_a_a16,
+    Cpu::ei,
+    Cpu::undefined::<0xfc>,
+    Cpu::undefined::<0xfd>,
+    Cpu::alu_d8::<7>,
+    Cpu::rst::<0x38>,
+];
+
+// Handlers of the 256 extended bit operations, indexed by the byte following the 0xcb prefix. Bits 2-0 select the
+// operand in the order B, C, D, E, H, L, (HL), A. Below 0x40 bits 5-3 select the rotate or shift: RLC, RRC, RL, RR,
+// SLA, SRA, SWAP, SRL. From 0x40 on bits 7-6 select BIT, RES or SET and bits 5-3 the bit number. RLCA, RLA, RRCA
+// and RRA share the rotates but always reset Z.
+static CB_OPS: [Op; 256] = [
+    // 0x00
+    Cpu::rot_r8::<0, 0>,
+    Cpu::rot_r8::<0, 1>,
+    Cpu::rot_r8::<0, 2>,
+    Cpu::rot_r8::<0, 3>,
+    Cpu::rot_r8::<0, 4>,
+    Cpu::rot_r8::<0, 5>,
+    Cpu::rot_r8::<0, 6>,
+    Cpu::rot_r8::<0, 7>,
+    Cpu::rot_r8::<1, 0>,
+    Cpu::rot_r8::<1, 1>,
+    Cpu::rot_r8::<1, 2>,
+    Cpu::rot_r8::<1, 3>,
+    Cpu::rot_r8::<1, 4>,
+    Cpu::rot_r8::<1, 5>,
+    Cpu::rot_r8::<1, 6>,
+    Cpu::rot_r8::<1, 7>,
+    // 0x10
+    Cpu::rot_r8::<2, 0>,
+    Cpu::rot_r8::<2, 1>,
+    Cpu::rot_r8::<2, 2>,
+    Cpu::rot_r8::<2, 3>,
+    Cpu::rot_r8::<2, 4>,
+    Cpu::rot_r8::<2, 5>,
+    Cpu::rot_r8::<2, 6>,
+    Cpu::rot_r8::<2, 7>,
+    Cpu::rot_r8::<3, 0>,
+    Cpu::rot_r8::<3, 1>,
+    Cpu::rot_r8::<3, 2>,
+    Cpu::rot_r8::<3, 3>,
+    Cpu::rot_r8::<3, 4>,
+    Cpu::rot_r8::<3, 5>,
+    Cpu::rot_r8::<3, 6>,
+    Cpu::rot_r8::<3, 7>,
+    // 0x20
+    Cpu::rot_r8::<4, 0>,
+    Cpu::rot_r8::<4, 1>,
+    Cpu::rot_r8::<4, 2>,
+    Cpu::rot_r8::<4, 3>,
+    Cpu::rot_r8::<4, 4>,
+    Cpu::rot_r8::<4, 5>,
+    Cpu::rot_r8::<4, 6>,
+    Cpu::rot_r8::<4, 7>,
+    Cpu::rot_r8::<5, 0>,
+    Cpu::rot_r8::<5, 1>,
+    Cpu::rot_r8::<5, 2>,
+    Cpu::rot_r8::<5, 3>,
+    Cpu::rot_r8::<5, 4>,
+    Cpu::rot_r8::<5, 5>,
+    Cpu::rot_r8::<5, 6>,
+    Cpu::rot_r8::<5, 7>,
+    // 0x30
+    Cpu::rot_r8::<6, 0>,
+    Cpu::rot_r8::<6, 1>,
+    Cpu::rot_r8::<6, 2>,
+    Cpu::rot_r8::<6, 3>,
+    Cpu::rot_r8::<6, 4>,
+    Cpu::rot_r8::<6, 5>,
+    Cpu::rot_r8::<6, 6>,
+    Cpu::rot_r8::<6, 7>,
+    Cpu::rot_r8::<7, 0>,
+    Cpu::rot_r8::<7, 1>,
+    Cpu::rot_r8::<7, 2>,
+    Cpu::rot_r8::<7, 3>,
+    Cpu::rot_r8::<7, 4>,
+    Cpu::rot_r8::<7, 5>,
+    Cpu::rot_r8::<7, 6>,
+    Cpu::rot_r8::<7, 7>,
+    // 0x40
+    Cpu::bit_r8::<0, 0>,
+    Cpu::bit_r8::<0, 1>,
+    Cpu::bit_r8::<0, 2>,
+    Cpu::bit_r8::<0, 3>,
+    Cpu::bit_r8::<0, 4>,
+    Cpu::bit_r8::<0, 5>,
+    Cpu::bit_r8::<0, 6>,
+    Cpu::bit_r8::<0, 7>,
+    Cpu::bit_r8::<1, 0>,
+    Cpu::bit_r8::<1, 1>,
+    Cpu::bit_r8::<1, 2>,
+    Cpu::bit_r8::<1, 3>,
+    Cpu::bit_r8::<1, 4>,
+    Cpu::bit_r8::<1, 5>,
+    Cpu::bit_r8::<1, 6>,
+    Cpu::bit_r8::<1, 7>,
+    // 0x50
+    Cpu::bit_r8::<2, 0>,
+    Cpu::bit_r8::<2, 1>,
+    Cpu::bit_r8::<2, 2>,
+    Cpu::bit_r8::<2, 3>,
+    Cpu::bit_r8::<2, 4>,
+    Cpu::bit_r8::<2, 5>,
+    Cpu::bit_r8::<2, 6>,
+    Cpu::bit_r8::<2, 7>,
+    Cpu::bit_r8::<3, 0>,
+    Cpu::bit_r8::<3, 1>,
+    Cpu::bit_r8::<3, 2>,
+    Cpu::bit_r8::<3, 3>,
+    Cpu::bit_r8::<3, 4>,
+    Cpu::bit_r8::<3, 5>,
+    Cpu::bit_r8::<3, 6>,
+    Cpu::bit_r8::<3, 7>,
+    // 0x60
+    Cpu::bit_r8::<4, 0>,
+    Cpu::bit_r8::<4, 1>,
+    Cpu::bit_r8::<4, 2>,
+    Cpu::bit_r8::<4, 3>,
+    Cpu::bit_r8::<4, 4>,
+    Cpu::bit_r8::<4, 5>,
+    Cpu::bit_r8::<4, 6>,
+    Cpu::bit_r8::<4, 7>,
+    Cpu::bit_r8::<5, 0>,
+    Cpu::bit_r8::<5, 1>,
+    Cpu::bit_r8::<5, 2>,
+    Cpu::bit_r8::<5, 3>,
+    Cpu::bit_r8::<5, 4>,
+    Cpu::bit_r8::<5, 5>,
+    Cpu::bit_r8::<5, 6>,
+    Cpu::bit_r8::<5, 7>,
+    // 0x70
+    Cpu::bit_r8::<6, 0>,
+    Cpu::bit_r8::<6, 1>,
+    Cpu::bit_r8::<6, 2>,
+    Cpu::bit_r8::<6, 3>,
+    Cpu::bit_r8::<6, 4>,
+    Cpu::bit_r8::<6, 5>,
+    Cpu::bit_r8::<6, 6>,
+    Cpu::bit_r8::<6, 7>,
+    Cpu::bit_r8::<7, 0>,
+    Cpu::bit_r8::<7, 1>,
+    Cpu::bit_r8::<7, 2>,
+    Cpu::bit_r8::<7, 3>,
+    Cpu::bit_r8::<7, 4>,
+    Cpu::bit_r8::<7, 5>,
+    Cpu::bit_r8::<7, 6>,
+    Cpu::bit_r8::<7, 7>,
+    // 0x80
+    Cpu::res_r8::<0, 0>,
+    Cpu::res_r8::<0, 1>,
+    Cpu::res_r8::<0, 2>,
+    Cpu::res_r8::<0, 3>,
+    Cpu::res_r8::<0, 4>,
+    Cpu::res_r8::<0, 5>,
+    Cpu::res_r8::<0, 6>,
+    Cpu::res_r8::<0, 7>,
+    Cpu::res_r8::<1, 0>,
+    Cpu::res_r8::<1, 1>,
+    Cpu::res_r8::<1, 2>,
+    Cpu::res_r8::<1, 3>,
+    Cpu::res_r8::<1, 4>,
+    Cpu::res_r8::<1, 5>,
+    Cpu::res_r8::<1, 6>,
+    Cpu::res_r8::<1, 7>,
+    // 0x90
+    Cpu::res_r8::<2, 0>,
+    Cpu::res_r8::<2, 1>,
+    Cpu::res_r8::<2, 2>,
+    Cpu::res_r8::<2, 3>,
+    Cpu::res_r8::<2, 4>,
+    Cpu::res_r8::<2, 5>,
+    Cpu::res_r8::<2, 6>,
+    Cpu::res_r8::<2, 7>,
+    Cpu::res_r8::<3, 0>,
+    Cpu::res_r8::<3, 1>,
+    Cpu::res_r8::<3, 2>,
+    Cpu::res_r8::<3, 3>,
+    Cpu::res_r8::<3, 4>,
+    Cpu::res_r8::<3, 5>,
+    Cpu::res_r8::<3, 6>,
+    Cpu::res_r8::<3, 7>,
+    // 0xa0
+    Cpu::res_r8::<4, 0>,
+    Cpu::res_r8::<4, 1>,
+    Cpu::res_r8::<4, 2>,
+    Cpu::res_r8::<4, 3>,
+    Cpu::res_r8::<4, 4>,
+    Cpu::res_r8::<4, 5>,
+    Cpu::res_r8::<4, 6>,
+    Cpu::res_r8::<4, 7>,
+    Cpu::res_r8::<5, 0>,
+    Cpu::res_r8::<5, 1>,
+    Cpu::res_r8::<5, 2>,
+    Cpu::res_r8::<5, 3>,
+    Cpu::res_r8::<5, 4>,
+    Cpu::res_r8::<5, 5>,
+    Cpu::res_r8::<5, 6>,
+    Cpu::res_r8::<5, 7>,
+    // 0xb0
+    Cpu::res_r8::<6, 0>,
+    Cpu::res_r8::<6, 1>,
+    Cpu::res_r8::<6, 2>,
+    Cpu::res_r8::<6, 3>,
+    Cpu::res_r8::<6, 4>,
+    Cpu::res_r8::<6, 5>,
+    Cpu::res_r8::<6, 6>,
+    Cpu::res_r8::<6, 7>,
+    Cpu::res_r8::<7, 0>,
+    Cpu::res_r8::<7, 1>,
+    Cpu::res_r8::<7, 2>,
+    Cpu::res_r8::<7, 3>,
+    Cpu::res_r8::<7, 4>,
+    Cpu::res_r8::<7, 5>,
+    Cpu::res_r8::<7, 6>,
+    Cpu::res_r8::<7, 7>,
+    // 0xc0
+    Cpu::set_r8::<0, 0>,
+    Cpu::set_r8::<0, 1>,
+    Cpu::set_r8::<0, 2>,
+    Cpu::set_r8::<0, 3>,
+    Cpu::set_r8::<0, 4>,
+    Cpu::set_r8::<0, 5>,
+    Cpu::set_r8::<0, 6>,
+    Cpu::set_r8::<0, 7>,
+    Cpu::set_r8::<1, 0>,
+    Cpu::set_r8::<1, 1>,
+    Cpu::set_r8::<1, 2>,
+    Cpu::set_r8::<1, 3>,
+    Cpu::set_r8::<1, 4>,
+    Cpu::set_r8::<1, 5>,
+    Cpu::set_r8::<1, 6>,
+    Cpu::set_r8::<1, 7>,
+    // 0xd0
+    Cpu::set_r8::<2, 0>,
+    Cpu::set_r8::<2, 1>,
+    Cpu::set_r8::<2, 2>,
+    Cpu::set_r8::<2, 3>,
+    Cpu::set_r8::<2, 4>,
+    Cpu::set_r8::<2, 5>,
+    Cpu::set_r8::<2, 6>,
+    Cpu::set_r8::<2, 7>,
+    Cpu::set_r8::<3, 0>,
+    Cpu::set_r8::<3, 1>,
+    Cpu::set_r8::<3, 2>,
+    Cpu::set_r8::<3, 3>,
+    Cpu::set_r8::<3, 4>,
+    Cpu::set_r8::<3, 5>,
+    Cpu::set_r8::<3, 6>,
+    Cpu::set_r8::<3, 7>,
+    // 0xe0
+    Cpu::set_r8::<4, 0>,
+    Cpu::set_r8::<4, 1>,
+    Cpu::set_r8::<4, 2>,
+    Cpu::set_r8::<4, 3>,
+    Cpu::set_r8::<4, 4>,
+    Cpu::set_r8::<4, 5>,
+    Cpu::set_r8::<4, 6>,
+    Cpu::set_r8::<4, 7>,
+    Cpu::set_r8::<5, 0>,
+    Cpu::set_r8::<5, 1>,
+    Cpu::set_r8::<5, 2>,
+    Cpu::set_r8::<5, 3>,
+    Cpu::set_r8::<5, 4>,
+    Cpu::set_r8::<5, 5>,
+    Cpu::set_r8::<5, 6>,
+    Cpu::set_r8::<5, 7>,
+    // 0xf0
+    Cpu::set_r8::<6, 0>,
+    Cpu::set_r8::<6, 1>,
+    Cpu::set_r8::<6, 2>,
+    Cpu::set_r8::<6, 3>,
+    Cpu::set_r8::<6, 4>,
+    Cpu::set_r8::<6, 5>,
+    Cpu::set_r8::<6, 6>,
+    Cpu::set_r8::<6, 7>,
+    Cpu::set_r8::<7, 0>,
+    Cpu::set_r8::<7, 1>,
+    Cpu::set_r8::<7, 2>,
+    Cpu::set_r8::<7, 3>,
+    Cpu::set_r8::<7, 4>,
+    Cpu::set_r8::<7, 5>,
+    Cpu::set_r8::<7, 6>,
+    Cpu::set_r8::<7, 7>,
+];
+
+// The outcome of a single step. When an interrupt is dispatched instead of executing an instruction, opcode holds the
+// byte at pc_before, which is not executed yet, and pc_after points at the interrupt vector.
+#[derive(Clone, Copy, Debug)]
+pub struct StepResult {
+    pub opcode: u8,
+    pub cycles: u32,
+    pub pc_before: u16,
+    pub pc_after: u16,
+}
+
+// Debugging aids. Stepping only advances the cpu, the rest of the hardware is left to the caller.
+impl Cpu {
+    pub fn step(&mut self) -> StepResult {
+        let pc_before = self.reg.pc;
+        let opcode = self.peek_next_opcode();
+        let cycles = self.next();
+        StepResult {
+            opcode,
+            cycles,
+            pc_before,
+            pc_after: self.reg.pc,
+        }
+    }
+
+    // Same as step, except that a CALL or RST is run through to its return: the cpu runs until it is back at the
+    // instruction after it with the stack as it was, so the nested and recursive calls of the routine don't stop it
+    // early. Interrupts dispatched meanwhile run along. It stops before then at a breakpoint, a watchpoint (see
+    // take_break) or a lock up, and never returns if the routine doesn't. cycles is the total of the steps run.
+    pub fn step_over(&mut self) -> StepResult {
+        let sp = self.reg.sp;
+        let ret = match self.peek_next_opcode() {
+            0xc4 | 0xcc | 0xcd | 0xd4 | 0xdc => self.reg.pc.wrapping_add(3),
+            op if op & 0xc7 == 0xc7 => self.reg.pc.wrapping_add(1),
+            _ => return self.step(),
+        };
+        self.watch_hit = None;
+        let mut r = self.step();
+        // Not taken, or an interrupt was dispatched before the call.
+        if self.reg.sp != sp.wrapping_sub(2) || self.mem.borrow().get_word(self.reg.sp) != ret {
+            return r;
+        }
+        while self.reg.pc != ret || self.reg.sp != sp {
+            if self.breakpoints.contains(&self.reg.pc) || self.watch_hit.is_some() || self.fault.is_some() {
+                break;
             }
+            r.cycles = r.cycles.saturating_add(self.next());
+        }
+        r.pc_after = self.reg.pc;
+        r
+    }
+
+    pub fn snapshot(&self) -> Register {
+        self.reg.clone()
+    }
+
+    // Whether the cpu is in STOP mode, see stop. It stays there until a button of a line selected in P1 is pressed,
+    // then resumes after STOP. The timer and the LCD are frozen meanwhile, DIV included.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn peek_next_opcode(&self) -> u8 {
+        self.mem.borrow().get(self.reg.pc)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn covers(self, other: Access) -> bool {
+        self == Access::ReadWrite || self == other
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BreakReason {
+    // Execution reached a breakpoint, the instruction at that address has not been executed yet.
+    Breakpoint(u16),
+    // The last instruction accessed a watched address, access is either Read or Write and value is the byte read or
+    // written. pc is the address of the instruction, or of the instruction interrupted when the access was the push
+    // of an interrupt dispatch. recent_pcs holds the addresses of the instructions executed before it, oldest first,
+    // None for those not executed yet since power up.
+    Watchpoint {
+        addr: u16,
+        access: Access,
+        value: u8,
+        pc: u16,
+        recent_pcs: [Option<u16>; RECENT_PCS],
+    },
+    // The last instruction locked the cpu up, see Cpu::fault.
+    Fault(StepError),
+    // The last instruction was LD B,B at this address, see Cpu::set_break_on_ld_b_b.
+    Software(u16),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StepError {
+    // pc is the address of the opcode.
+    IllegalOpcode { opcode: u8, pc: u16 },
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepError::IllegalOpcode { opcode, pc } => write!(f, "Illegal opcode {:#04x} at {:#06x}", opcode, pc),
+        }
+    }
+}
+
+impl core::error::Error for StepError {}
+
+impl Cpu {
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u16, access: Access) {
+        self.watchpoints.push((addr, access));
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16) {
+        self.watchpoints.retain(|&(a, _)| a != addr);
+    }
+
+    // LD B,B does nothing, so test ROMs and homebrew use it as a software breakpoint: while set, executing it stops
+    // run_until_break after the instruction.
+    pub fn set_break_on_ld_b_b(&mut self, enable: bool) {
+        self.break_on_ld_b_b = enable;
+    }
+
+    // The watchpoint or software breakpoint hit since the last call, for a host stepping with next instead of
+    // run_until_break.
+    pub fn take_break(&mut self) -> Option<BreakReason> {
+        self.watch_hit.take()
+    }
+
+    fn watch(&mut self, a: u16, access: Access, value: u8) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        if self.watchpoints.iter().any(|&(w, k)| w == a && k.covers(access)) {
+            let mut recent_pcs = self.recent_pcs;
+            recent_pcs.rotate_left(self.recent_at);
+            self.watch_hit = Some(BreakReason::Watchpoint {
+                addr: a,
+                access,
+                value,
+                pc: self.op_pc,
+                recent_pcs,
+            });
+        }
+    }
+
+    // Run until a breakpoint or watchpoint is hit or the cpu locks up. The hardware behind the memory runs along
+    // through Memory::tick, see run_until_break_with to drive anything else.
+    pub fn run_until_break(&mut self) -> BreakReason {
+        self.run_until_break_with(|_| {})
+    }
+
+    // Same as run_until_break, f receives the cycles of every step outside STOP mode. The instruction at PC is always
+    // executed first, so calling this again after stopping at a breakpoint resumes execution.
+    pub fn run_until_break_with(&mut self, mut f: impl FnMut(u32)) -> BreakReason {
+        self.watch_hit = None;
+        let mut first = true;
+        loop {
+            if !first && self.breakpoints.contains(&self.reg.pc) {
+                return BreakReason::Breakpoint(self.reg.pc);
+            }
+            first = false;
+            // The rest of the hardware is frozen in STOP mode.
+            let stopped = self.stopped;
+            let cycles = self.next();
+            if !stopped {
+                f(cycles);
+            }
+            if let Some(r) = self.watch_hit.take() {
+                return r;
+            }
+            if let Some(e) = self.fault {
+                return BreakReason::Fault(e);
+            }
+        }
+    }
+}
+
+// Executions of an opcode while profiling. cycles is the total in clock cycles, including the extra cycles of taken
+// branches.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OpcodeStat {
+    pub opcode: u8,
+    // Whether this is the second byte of a CB prefixed instruction.
+    pub cb: bool,
+    pub count: u64,
+    pub cycles: u64,
+}
+
+impl Cpu {
+    // Start counting executed instructions per opcode, from zero. Interrupt dispatch and halted steps are not counted.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Box::new([(0, 0); 512]));
+    }
+
+    pub fn disable_profiling(&mut self) {
+        self.profile = None;
+    }
+
+    // The opcodes executed since profiling was enabled, the most expensive first.
+    pub fn profile_report(&self) -> Vec<OpcodeStat> {
+        let profile = match self.profile.as_ref() {
+            Some(some) => some,
+            None => return Vec::new(),
         };
-        mac * 4
+        let mut r: Vec<OpcodeStat> = profile
+            .iter()
+            .enumerate()
+            .filter(|(_, &(count, _))| count != 0)
+            .map(|(i, &(count, cycles))| OpcodeStat {
+                opcode: i as u8,
+                cb: i >= 0x100,
+                count,
+                cycles,
+            })
+            .collect();
+        r.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(b.count.cmp(&a.count)));
+        r
+    }
+
+    fn tally(&mut self, i: usize, mac: u32) {
+        if let Some(p) = self.profile.as_mut() {
+            p[i].0 += 1;
+            p[i].1 += u64::from(mac * 4);
+        }
+    }
+}
+
+// What is needed to undo one step: the cpu state before it and the previous value of every byte it wrote.
+struct StepUndo {
+    reg: Register,
+    halted: bool,
+    stopped: bool,
+    fault: Option<StepError>,
+    ei: bool,
+    halt_bug: bool,
+    ei_pending: bool,
+    cycles: u64,
+    writes: Vec<(u16, u8)>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum HistoryError {
+    // Step history isn't enabled.
+    Disabled,
+    // Every recorded step was undone already.
+    Empty,
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryError::Disabled => write!(f, "Step history is disabled"),
+            HistoryError::Empty => write!(f, "No step left to undo"),
+        }
+    }
+}
+
+impl core::error::Error for HistoryError {}
+
+impl Cpu {
+    // Keep what is needed to undo the last depth steps, a step being an instruction, an interrupt dispatch or a cycle
+    // spent halted or stopped. Enabling it again starts with an empty history.
+    pub fn enable_step_history(&mut self, depth: usize) {
+        self.history = Some((VecDeque::new(), depth.max(1)));
+    }
+
+    pub fn disable_step_history(&mut self) {
+        self.history = None;
+    }
+
+    // Undo the most recent step. The registers and the bytes it wrote get their previous values back, but this is a
+    // debugging aid rather than a save state: the rest of the hardware keeps its current state and writes to the
+    // cartridge registers at 0000-7FFF or the I/O registers at FF00-FF7F are not undone, as writing the old value back
+    // would trigger their side effects again.
+    pub fn step_back(&mut self) -> Result<(), HistoryError> {
+        let undo = match self.history.as_mut() {
+            Some((steps, _)) => steps.pop_back().ok_or(HistoryError::Empty)?,
+            None => return Err(HistoryError::Disabled),
+        };
+        for &(a, v) in undo.writes.iter().rev() {
+            self.mem.borrow_mut().set(a, v);
+        }
+        self.reg = undo.reg;
+        self.halted = undo.halted;
+        self.stopped = undo.stopped;
+        self.fault = undo.fault;
+        self.ei = undo.ei;
+        self.halt_bug = undo.halt_bug;
+        self.ei_pending = undo.ei_pending;
+        self.cycles = undo.cycles;
+        Ok(())
+    }
+
+    fn record_step(&mut self) {
+        let (steps, depth) = match self.history.as_mut() {
+            Some(some) => some,
+            None => return,
+        };
+        if steps.len() == *depth {
+            steps.pop_front();
+        }
+        steps.push_back(StepUndo {
+            reg: self.reg.clone(),
+            halted: self.halted,
+            stopped: self.stopped,
+            fault: self.fault,
+            ei: self.ei,
+            halt_bug: self.halt_bug,
+            ei_pending: self.ei_pending,
+            cycles: self.cycles,
+            writes: Vec::new(),
+        });
+    }
+
+    fn record_write(&mut self, a: u16) {
+        if self.history.is_none() || a < 0x8000 || (0xff00..=0xff7f).contains(&a) {
+            return;
+        }
+        let v = self.mem.borrow().get(a);
+        if let Some(undo) = self.history.as_mut().and_then(|(steps, _)| steps.back_mut()) {
+            undo.writes.push((a, v));
+        }
+    }
+}
+
+// A record of the cpu state just before an instruction is executed. bytes holds the 4 bytes starting at pc, of which
+// only the first 1 to 3 belong to the instruction, this matches the PCMEM column of Gameboy Doctor logs. cycles is the
+// number of clock cycles executed before this instruction.
+#[derive(Clone)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub bytes: [u8; 4],
+    pub reg: Register,
+    pub cycles: u64,
+}
+
+type TraceSink = dyn FnMut(&TraceEntry);
+
+type SpeedSwitch = dyn FnMut() -> bool;
+
+// An interrupt dispatched by the cpu, see Cpu::capture_interrupts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InterruptEvent {
+    pub flag: Flag,
+    // Clock cycles since power up when the dispatch started.
+    pub cycles: u64,
+}
+
+impl Cpu {
+    // Install a sink called before every executed instruction. Interrupt dispatch and halted steps are not traced.
+    pub fn set_trace_sink(&mut self, sink: impl FnMut(&TraceEntry) + 'static) {
+        self.trace = Some(Box::new(sink));
+    }
+
+    pub fn clear_trace_sink(&mut self) {
+        self.trace = None;
+    }
+
+    // Call f on STOP, after DIV is reset, to perform the CGB speed switch armed through KEY1. f returns true if the
+    // speed changed, the cpu then carries on instead of entering STOP mode. The MotherBoard points it at
+    // Mmunit::switch_speed, without it STOP always enters STOP mode.
+    pub fn set_speed_switch(&mut self, f: impl FnMut() -> bool + 'static) {
+        self.speed_switch = Some(Box::new(f));
+    }
+
+    // Start or stop keeping the interrupts dispatched, a coarser and cheaper view than the trace sink. Starting again
+    // clears them.
+    pub fn capture_interrupts(&mut self, enable: bool) {
+        self.interrupt_log = if enable { Some(Vec::new()) } else { None };
+    }
+
+    // The interrupts dispatched since the last drain, in order. Empty unless capturing.
+    pub fn drain_interrupts(&mut self) -> Vec<InterruptEvent> {
+        self.interrupt_log.as_mut().map(core::mem::take).unwrap_or_default()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn interrupts(&self) -> u64 {
+        self.interrupts
+    }
+
+    fn emit_trace(&mut self) {
+        let pc = self.reg.pc;
+        let bytes = {
+            let mem = self.mem.borrow();
+            [
+                mem.get(pc),
+                mem.get(pc.wrapping_add(1)),
+                mem.get(pc.wrapping_add(2)),
+                mem.get(pc.wrapping_add(3)),
+            ]
+        };
+        let entry = TraceEntry {
+            pc,
+            bytes,
+            reg: self.reg.clone(),
+            cycles: self.cycles,
+        };
+        if let Some(sink) = self.trace.as_mut() {
+            sink(&entry);
+        }
     }
 }
 
-// Real time cpu provided to simulate real hardware speed.
+// Breakpoints, watchpoints and the trace sink are debugger settings rather than machine state, they are kept as is.
+impl Stateful for Cpu {
+    fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.reg.to_bytes());
+        w.bool(self.halted);
+        w.bool(self.stopped);
+        match self.fault {
+            Some(StepError::IllegalOpcode { opcode, pc }) => {
+                w.bool(true);
+                w.u8(opcode);
+                w.u16(pc);
+            }
+            None => w.bool(false),
+        }
+        w.bool(self.ei);
+        w.bool(self.halt_bug);
+        w.bool(self.ei_pending);
+        w.u64(self.cycles);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        let mut b = [0x00; 12];
+        r.bytes_into(&mut b, "registers")?;
+        self.reg = Register::from_bytes(&b);
+        self.halted = r.bool()?;
+        self.stopped = r.bool()?;
+        self.fault = if r.bool()? {
+            let opcode = r.u8()?;
+            let pc = r.u16()?;
+            Some(StepError::IllegalOpcode { opcode, pc })
+        } else {
+            None
+        };
+        self.ei = r.bool()?;
+        self.halt_bug = r.bool()?;
+        self.ei_pending = r.bool()?;
+        self.cycles = r.u64()?;
+        Ok(())
+    }
+}
+
+// Real time cpu provided to simulate real hardware speed. Without std there is nothing to sleep on, the steps are
+// only counted for flip and the host paces the frames itself.
 pub struct Rtc {
     pub cpu: Cpu,
-    step_cycles: u32,
+    // Cycles run beyond the budget of the steps slept so far. Fractional, as below a few hundred Hz a step lets less
+    // than a cycle through.
+    step_cycles: f64,
+    #[cfg(feature = "std")]
     step_zero: time::Instant,
     step_flip: bool,
+    speed: f32,
 }
 
 impl Rtc {
@@ -1702,38 +2278,56 @@ impl Rtc {
         let cpu = Cpu::power_up(term, mem);
         Self {
             cpu,
-            step_cycles: 0,
+            step_cycles: 0.0,
+            #[cfg(feature = "std")]
             step_zero: time::Instant::now(),
             step_flip: false,
+            speed: 1.0,
         }
     }
 
+    // Run that many times faster than the real hardware, or slower below 1. The speed limit lets mult times as many
+    // cycles through every STEP_TIME.
+    pub fn set_speed(&mut self, mult: f32) {
+        self.speed = mult.max(f32::MIN_POSITIVE);
+    }
+
     // Function next simulates real hardware execution speed, by limiting the frequency of the function cpu.next().
+    // When slowed down enough an instruction takes several steps, each of them sleeping.
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> u32 {
-        if self.step_cycles > STEP_CYCLES {
+        let step_cycles = f64::from(STEP_CYCLES) * f64::from(self.speed);
+        while self.step_cycles > step_cycles {
             self.step_flip = true;
-            self.step_cycles -= STEP_CYCLES;
-            let now = time::Instant::now();
-            let d = now.duration_since(self.step_zero);
-            let s = u64::from(STEP_TIME.saturating_sub(d.as_millis() as u32));
-            rog::debugln!("CPU: sleep {} millis", s);
-            thread::sleep(time::Duration::from_millis(s));
-            self.step_zero = self
-                .step_zero
-                .checked_add(time::Duration::from_millis(u64::from(STEP_TIME)))
-                .unwrap();
-
-            // If now is after the just updated target frame time, reset to
-            // avoid drift.
-            if now.checked_duration_since(self.step_zero).is_some() {
-                self.step_zero = now;
-            }
+            self.step_cycles -= step_cycles;
+            #[cfg(feature = "std")]
+            self.sleep();
         }
         let cycles = self.cpu.next();
-        self.step_cycles += cycles;
+        self.step_cycles += f64::from(cycles);
         cycles
     }
 
+    // Sleep until STEP_TIME after the start of the previous step.
+    #[cfg(feature = "std")]
+    fn sleep(&mut self) {
+        let now = time::Instant::now();
+        let d = now.duration_since(self.step_zero);
+        let s = u64::from(STEP_TIME.saturating_sub(d.as_millis() as u32));
+        rog::debugln!("CPU: sleep {} millis", s);
+        thread::sleep(time::Duration::from_millis(s));
+        self.step_zero = self
+            .step_zero
+            .checked_add(time::Duration::from_millis(u64::from(STEP_TIME)))
+            .unwrap();
+
+        // If now is after the just updated target frame time, reset to
+        // avoid drift.
+        if now.checked_duration_since(self.step_zero).is_some() {
+            self.step_zero = now;
+        }
+    }
+
     pub fn flip(&mut self) -> bool {
         let r = self.step_flip;
         if r {
@@ -1742,3 +2336,921 @@ impl Rtc {
         r
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Flat(Vec<u8>);
+
+    impl Memory for Flat {
+        fn get(&self, a: u16) -> u8 {
+            self.0[usize::from(a)]
+        }
+
+        fn set(&mut self, a: u16, v: u8) {
+            self.0[usize::from(a)] = v;
+        }
+    }
+
+    // A cpu at 0100h on a flat 64K memory holding code there, with the interrupts disabled.
+    fn cpu_with(code: &[u8]) -> Cpu {
+        let mut data = vec![0x00; 0x10000];
+        data[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        let mut cpu = Cpu::power_up(Term::GB, Rc::new(RefCell::new(Flat(data))));
+        cpu.ei = false;
+        cpu
+    }
+
+    #[test]
+    fn halt_bug_reads_the_next_byte_twice() {
+        // HALT; INC A; NOP, with IME clear and the VBlank interrupt enabled and requested.
+        let mut cpu = cpu_with(&[0x76, 0x3c, 0x00]);
+        cpu.mem.borrow_mut().set(0xffff, 0x01);
+        cpu.mem.borrow_mut().set(0xff0f, 0x01);
+        cpu.reg.a = 0x00;
+        cpu.next();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.reg.pc, 0x0101);
+        cpu.next();
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (0x01, 0x0101));
+        cpu.next();
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (0x02, 0x0102));
+        // Nothing pending: HALT halts and the byte after it is read once.
+        let mut cpu = cpu_with(&[0x76, 0x3c, 0x00]);
+        cpu.next();
+        assert!(cpu.halted);
+    }
+
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        // EI; INC A; NOP with VBlank enabled and requested.
+        let mut cpu = cpu_with(&[0xfb, 0x3c, 0x00]);
+        cpu.mem.borrow_mut().set(0xffff, 0x01);
+        cpu.mem.borrow_mut().set(0xff0f, 0x01);
+        cpu.reg.a = 0x00;
+        cpu.next();
+        cpu.next();
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (0x01, 0x0102));
+        cpu.next();
+        assert_eq!(cpu.reg.pc, 0x0040);
+        // EI; DI never lets the interrupt through.
+        let mut cpu = cpu_with(&[0xfb, 0xf3, 0x00, 0x00]);
+        cpu.mem.borrow_mut().set(0xffff, 0x01);
+        cpu.mem.borrow_mut().set(0xff0f, 0x01);
+        for _ in 0..4 {
+            cpu.next();
+        }
+        assert_eq!(cpu.reg.pc, 0x0104);
+        assert!(!cpu.ei);
+    }
+
+    #[test]
+    fn service_interrupt_priority_and_return_address() {
+        let mut cpu = cpu_with(&[]);
+        cpu.reg.pc = 0x1234;
+        cpu.reg.sp = 0xd000;
+        cpu.ei = true;
+        // VBlank and Timer both pending.
+        cpu.mem.borrow_mut().set(0xffff, 0x05);
+        cpu.mem.borrow_mut().set(0xff0f, 0x05);
+        assert_eq!(cpu.next(), 20);
+        assert_eq!(cpu.reg.pc, 0x0040);
+        assert_eq!(cpu.reg.sp, 0xcffe);
+        assert_eq!(cpu.mem.borrow().get_word(0xcffe), 0x1234);
+        assert_eq!(cpu.mem.borrow().get(0xff0f), 0x04);
+        assert!(!cpu.ei);
+        // With IME set again the Timer interrupt follows.
+        cpu.ei = true;
+        assert_eq!(cpu.service_interrupt(), 5);
+        assert_eq!(cpu.reg.pc, 0x0050);
+        assert_eq!(cpu.mem.borrow().get_word(0xcffc), 0x0040);
+        assert_eq!(cpu.mem.borrow().get(0xff0f), 0x00);
+    }
+
+    #[test]
+    fn step_reports_each_instruction() {
+        // LD A,$12; NOP; JP $0108; _; LD B,A at 0108h.
+        let mut cpu = cpu_with(&[0x3e, 0x12, 0x00, 0xc3, 0x08, 0x01, 0x00, 0x00, 0x47]);
+        assert_eq!(cpu.peek_next_opcode(), 0x3e);
+        let want = [
+            (0x3e, 8, 0x0100, 0x0102),
+            (0x00, 4, 0x0102, 0x0103),
+            (0xc3, 16, 0x0103, 0x0108),
+        ];
+        for &w in &want {
+            let r = cpu.step();
+            assert_eq!((r.opcode, r.cycles, r.pc_before, r.pc_after), w);
+        }
+        assert_eq!(cpu.peek_next_opcode(), 0x47);
+        assert_eq!(cpu.peek_next_opcode(), 0x47);
+        cpu.step();
+        let reg = cpu.snapshot();
+        assert_eq!((reg.a, reg.b, reg.pc), (0x12, 0x12, 0x0109));
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        // INC A; INC A; INC A; LD (HL),A; LD B,(HL); JR -7
+        let mut cpu = cpu_with(&[0x3c, 0x3c, 0x3c, 0x77, 0x46, 0x18, 0xf9]);
+        cpu.reg.a = 0x00;
+        cpu.reg.set_hl(0xc000);
+        cpu.add_breakpoint(0x0102);
+        assert_eq!(cpu.run_until_break(), BreakReason::Breakpoint(0x0102));
+        assert_eq!((cpu.reg.pc, cpu.reg.a), (0x0102, 0x02));
+
+        // The address, access, value and pc of a watchpoint hit.
+        let hit = |r: BreakReason| match r {
+            BreakReason::Watchpoint {
+                addr,
+                access,
+                value,
+                pc,
+                ..
+            } => (addr, access, value, pc),
+            r => panic!("{:?}", r),
+        };
+        cpu.add_watchpoint(0xc000, Access::Write);
+        assert_eq!(hit(cpu.run_until_break()), (0xc000, Access::Write, 0x03, 0x0103));
+        assert_eq!(cpu.reg.pc, 0x0104);
+
+        // A write watchpoint ignores the read, the loop comes back to the breakpoint.
+        assert_eq!(cpu.run_until_break(), BreakReason::Breakpoint(0x0102));
+        assert_eq!(cpu.reg.a, 0x05);
+        cpu.remove_breakpoint(0x0102);
+        cpu.remove_watchpoint(0xc000);
+        cpu.add_watchpoint(0xc000, Access::ReadWrite);
+        assert_eq!(hit(cpu.run_until_break()), (0xc000, Access::Write, 0x06, 0x0103));
+        assert_eq!(hit(cpu.run_until_break()), (0xc000, Access::Read, 0x06, 0x0104));
+        assert_eq!((cpu.reg.pc, cpu.reg.b), (0x0105, 0x06));
+    }
+
+    #[test]
+    fn watchpoint_reports_the_storing_instruction() {
+        // LD HL,C000h; INC A; LD (HL),A
+        let mut cpu = cpu_with(&[0x21, 0x00, 0xc0, 0x3c, 0x77]);
+        cpu.add_watchpoint(0xc000, Access::Write);
+        let mut recent_pcs = [None; RECENT_PCS];
+        recent_pcs[RECENT_PCS - 2..].copy_from_slice(&[Some(0x0100), Some(0x0103)]);
+        let want = BreakReason::Watchpoint {
+            addr: 0xc000,
+            access: Access::Write,
+            value: 0x02,
+            pc: 0x0104,
+            recent_pcs,
+        };
+        assert_eq!(cpu.run_until_break(), want);
+
+        // LD HL,C000h; 9 NOPs; LD (HL),A: only the last 8 instructions are kept.
+        let mut code = vec![0x21, 0x00, 0xc0];
+        code.extend_from_slice(&[0x00; 9]);
+        code.push(0x77);
+        let mut cpu = cpu_with(&code);
+        cpu.add_watchpoint(0xc000, Access::Write);
+        match cpu.run_until_break() {
+            BreakReason::Watchpoint { pc, recent_pcs, .. } => {
+                assert_eq!(pc, 0x010c);
+                let want: Vec<_> = (0x0104..0x010c).map(Some).collect();
+                assert_eq!(recent_pcs[..], want[..]);
+            }
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn trace_sink_sees_each_instruction_first() {
+        // LD A,$12; INC A; JP $0100
+        let mut cpu = cpu_with(&[0x3e, 0x12, 0x3c, 0xc3, 0x00, 0x01]);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let sink = log.clone();
+        cpu.set_trace_sink(move |e| sink.borrow_mut().push((e.pc, e.bytes[0], e.reg.a, e.cycles)));
+        for _ in 0..4 {
+            cpu.next();
+        }
+        let want = [
+            (0x0100, 0x3e, 0x01, 0),
+            (0x0102, 0x3c, 0x12, 8),
+            (0x0103, 0xc3, 0x13, 12),
+            (0x0100, 0x3e, 0x13, 28),
+        ];
+        assert_eq!(*log.borrow(), want);
+        assert_eq!(cpu.cycles(), 36);
+        cpu.clear_trace_sink();
+        cpu.next();
+        assert_eq!(log.borrow().len(), 4);
+    }
+
+    // One instruction run from 0100h on a cpu with SP at D000h and the other registers clear, except for those set
+    // before it.
+    struct Exec {
+        code: Vec<u8>,
+        before: Register,
+        cpu: Cpu,
+        cycles: u32,
+    }
+
+    fn exec(code: &[u8], mem: &[(u16, u8)], before: &[(&str, u16)]) -> Exec {
+        let mut cpu = cpu_with(code);
+        for &(a, v) in mem {
+            cpu.mem.borrow_mut().set(a, v);
+        }
+        let mut reg = Register {
+            sp: 0xd000,
+            pc: 0x0100,
+            ..Register::default()
+        };
+        for &(name, v) in before {
+            assert!(reg.set_named(name, v), "{}", name);
+        }
+        cpu.reg = reg.clone();
+        let cycles = cpu.next();
+        Exec {
+            code: code.to_vec(),
+            before: reg,
+            cpu,
+            cycles,
+        }
+    }
+
+    impl Exec {
+        // Check that the instruction took the cycles given and only changed the registers given, PC moving past it
+        // unless it is one of them.
+        fn changed(&self, regs: &[(&str, u16)], cycles: u32) -> &Self {
+            let mut want = self.before.clone();
+            want.pc += self.code.len() as u16;
+            for &(name, v) in regs {
+                want.set_named(name, v);
+            }
+            assert_eq!(self.cpu.reg, want, "opcode {:02x?}", self.code);
+            assert_eq!(self.cycles, cycles, "opcode {:02x?}", self.code);
+            self
+        }
+
+        fn wrote(&self, writes: &[(u16, u8)]) -> &Self {
+            for &(a, v) in writes {
+                assert_eq!(
+                    self.cpu.mem.borrow().get(a),
+                    v,
+                    "opcode {:02x?} at {:04x}",
+                    self.code,
+                    a
+                );
+            }
+            self
+        }
+    }
+
+    #[test]
+    fn loads() {
+        // NOP
+        exec(&[0x00], &[], &[]).changed(&[], 4);
+        // LD B,42h
+        exec(&[0x06, 0x42], &[], &[]).changed(&[("b", 0x42)], 8);
+        // LD D,B
+        exec(&[0x50], &[], &[("b", 0x42)]).changed(&[("d", 0x42)], 4);
+        // LD A,(HL)
+        exec(&[0x7e], &[(0xc000, 0x99)], &[("h", 0xc0)]).changed(&[("a", 0x99)], 8);
+        // LD (HL),A
+        exec(&[0x77], &[], &[("a", 0x42), ("h", 0xc0)])
+            .changed(&[], 8)
+            .wrote(&[(0xc000, 0x42)]);
+        // LD (HL+),A
+        exec(&[0x22], &[], &[("a", 0x42), ("h", 0xc0)])
+            .changed(&[("l", 0x01)], 8)
+            .wrote(&[(0xc000, 0x42)]);
+        // LD A,(C000h)
+        exec(&[0xfa, 0x00, 0xc0], &[(0xc000, 0x99)], &[]).changed(&[("a", 0x99)], 16);
+        // LDH (80h),A
+        exec(&[0xe0, 0x80], &[], &[("a", 0x42)])
+            .changed(&[], 12)
+            .wrote(&[(0xff80, 0x42)]);
+        // LD (C000h),SP
+        exec(&[0x08, 0x00, 0xc0], &[], &[("sp", 0xfff8)])
+            .changed(&[], 20)
+            .wrote(&[(0xc000, 0xf8), (0xc001, 0xff)]);
+    }
+
+    #[test]
+    fn arithmetic_and_logic() {
+        // INC B
+        exec(&[0x04], &[], &[("b", 0xff), ("f", 0x10)]).changed(&[("b", 0x00), ("f", 0xb0)], 4);
+        // DEC C
+        exec(&[0x0d], &[], &[("c", 0x01)]).changed(&[("c", 0x00), ("f", 0xc0)], 4);
+        // INC (HL)
+        exec(&[0x34], &[(0xc000, 0x0f)], &[("h", 0xc0)])
+            .changed(&[("f", 0x20)], 12)
+            .wrote(&[(0xc000, 0x10)]);
+        // ADD A,B
+        exec(&[0x80], &[], &[("a", 0x3a), ("b", 0xc6)]).changed(&[("a", 0x00), ("f", 0xb0)], 4);
+        // ADC A,01h
+        exec(&[0xce, 0x01], &[], &[("a", 0xfe), ("f", 0x10)]).changed(&[("a", 0x00), ("f", 0xb0)], 8);
+        // SUB 3Eh
+        exec(&[0xd6, 0x3e], &[], &[("a", 0x3e)]).changed(&[("a", 0x00), ("f", 0xc0)], 8);
+        // SBC A,B
+        exec(&[0x98], &[], &[("a", 0x3b), ("b", 0x2a), ("f", 0x10)]).changed(&[("a", 0x10), ("f", 0x40)], 4);
+        // AND 38h
+        exec(&[0xe6, 0x38], &[], &[("a", 0x5a)]).changed(&[("a", 0x18), ("f", 0x20)], 8);
+        // XOR A
+        exec(&[0xaf], &[], &[("a", 0x5a), ("f", 0x70)]).changed(&[("a", 0x00), ("f", 0x80)], 4);
+        // OR (HL)
+        exec(&[0xb6], &[(0xc000, 0x0f)], &[("a", 0x5a), ("h", 0xc0)]).changed(&[("a", 0x5f)], 8);
+        // CP 3Ch
+        exec(&[0xfe, 0x3c], &[], &[("a", 0x3c)]).changed(&[("f", 0xc0)], 8);
+    }
+
+    #[test]
+    fn sixteen_bit_arithmetic() {
+        // INC HL
+        exec(&[0x23], &[], &[("l", 0xff), ("f", 0xf0)]).changed(&[("h", 0x01), ("l", 0x00)], 8);
+        // ADD HL,BC
+        exec(&[0x09], &[], &[("hl", 0x8a23), ("bc", 0x0605), ("f", 0x80)]).changed(&[("hl", 0x9028), ("f", 0xa0)], 8);
+        // ADD SP,02h
+        exec(&[0xe8, 0x02], &[], &[("sp", 0xfff8), ("f", 0xc0)]).changed(&[("sp", 0xfffa), ("f", 0x00)], 16);
+        // LD HL,SP-2
+        exec(&[0xf8, 0xfe], &[], &[]).changed(&[("hl", 0xcffe)], 12);
+    }
+
+    #[test]
+    fn stack_calls_and_jumps() {
+        // PUSH BC
+        exec(&[0xc5], &[], &[("bc", 0x1234)])
+            .changed(&[("sp", 0xcffe)], 16)
+            .wrote(&[(0xcfff, 0x12), (0xcffe, 0x34)]);
+        // POP AF, the low bits of F stay clear
+        exec(&[0xf1], &[(0xcffe, 0xff), (0xcfff, 0x12)], &[("sp", 0xcffe)])
+            .changed(&[("a", 0x12), ("f", 0xf0), ("sp", 0xd000)], 12);
+        // CALL 1234h
+        exec(&[0xcd, 0x34, 0x12], &[], &[])
+            .changed(&[("sp", 0xcffe), ("pc", 0x1234)], 24)
+            .wrote(&[(0xcfff, 0x01), (0xcffe, 0x03)]);
+        // RET
+        exec(&[0xc9], &[(0xcffe, 0x34), (0xcfff, 0x12)], &[("sp", 0xcffe)])
+            .changed(&[("sp", 0xd000), ("pc", 0x1234)], 16);
+        // RST 38h
+        exec(&[0xff], &[], &[])
+            .changed(&[("sp", 0xcffe), ("pc", 0x0038)], 16)
+            .wrote(&[(0xcfff, 0x01), (0xcffe, 0x01)]);
+        // JP (HL)
+        exec(&[0xe9], &[], &[("h", 0x40)]).changed(&[("pc", 0x4000)], 4);
+    }
+
+    #[test]
+    fn accumulator_and_flag_ops() {
+        // DAA after 45h + 38h
+        exec(&[0x27], &[], &[("a", 0x7d)]).changed(&[("a", 0x83)], 4);
+        // CPL
+        exec(&[0x2f], &[], &[("a", 0x35)]).changed(&[("a", 0xca), ("f", 0x60)], 4);
+        // SCF
+        exec(&[0x37], &[], &[("f", 0xe0)]).changed(&[("f", 0x90)], 4);
+        // CCF
+        exec(&[0x3f], &[], &[("f", 0x90)]).changed(&[("f", 0x80)], 4);
+        // RRA, Z is always cleared
+        exec(&[0x1f], &[], &[("a", 0x01), ("f", 0x80)]).changed(&[("a", 0x00), ("f", 0x10)], 4);
+    }
+
+    #[test]
+    fn cb_rotates_and_shifts() {
+        // RLC B
+        exec(&[0xcb, 0x00], &[], &[("b", 0x85)]).changed(&[("b", 0x0b), ("f", 0x10)], 8);
+        // RRC C
+        exec(&[0xcb, 0x09], &[], &[("c", 0x01)]).changed(&[("c", 0x80), ("f", 0x10)], 8);
+        // RL D
+        exec(&[0xcb, 0x12], &[], &[("d", 0x80)]).changed(&[("d", 0x00), ("f", 0x90)], 8);
+        // RR E
+        exec(&[0xcb, 0x1b], &[], &[("e", 0x01), ("f", 0x10)]).changed(&[("e", 0x80), ("f", 0x10)], 8);
+        // SLA H
+        exec(&[0xcb, 0x24], &[], &[("h", 0x80)]).changed(&[("h", 0x00), ("f", 0x90)], 8);
+        // SRA L
+        exec(&[0xcb, 0x2d], &[], &[("l", 0x81)]).changed(&[("l", 0xc0), ("f", 0x10)], 8);
+        // SWAP A
+        exec(&[0xcb, 0x37], &[], &[("a", 0xf1), ("f", 0x70)]).changed(&[("a", 0x1f), ("f", 0x00)], 8);
+        // SRL B
+        exec(&[0xcb, 0x38], &[], &[("b", 0x01)]).changed(&[("b", 0x00), ("f", 0x90)], 8);
+    }
+
+    #[test]
+    fn cb_bit_res_and_set() {
+        // BIT 7,H, C is kept
+        exec(&[0xcb, 0x7c], &[], &[("h", 0x7f), ("f", 0x50)]).changed(&[("f", 0xb0)], 8);
+        // RES 0,A
+        exec(&[0xcb, 0x87], &[], &[("a", 0xff), ("f", 0xf0)]).changed(&[("a", 0xfe)], 8);
+        // SET 7,A
+        exec(&[0xcb, 0xff], &[], &[]).changed(&[("a", 0x80)], 8);
+    }
+
+    #[test]
+    fn cb_hl_operands_take_longer() {
+        // RLC (HL)
+        exec(&[0xcb, 0x06], &[(0xc000, 0x80)], &[("h", 0xc0)])
+            .changed(&[("f", 0x10)], 16)
+            .wrote(&[(0xc000, 0x01)]);
+        // SWAP (HL)
+        exec(&[0xcb, 0x36], &[(0xc000, 0xf0)], &[("h", 0xc0)])
+            .changed(&[], 16)
+            .wrote(&[(0xc000, 0x0f)]);
+        // BIT 0,(HL) only reads
+        exec(&[0xcb, 0x46], &[], &[("h", 0xc0)])
+            .changed(&[("f", 0xa0)], 12)
+            .wrote(&[(0xc000, 0x00)]);
+        // RES 0,(HL)
+        exec(&[0xcb, 0x86], &[(0xc000, 0xff)], &[("h", 0xc0)])
+            .changed(&[], 16)
+            .wrote(&[(0xc000, 0xfe)]);
+        // SET 0,(HL)
+        exec(&[0xcb, 0xc6], &[], &[("h", 0xc0)])
+            .changed(&[], 16)
+            .wrote(&[(0xc000, 0x01)]);
+    }
+
+    #[test]
+    fn jr_nc_follows_the_carry_flag() {
+        // Z set and C clear: taken, the old timing took 8 cycles from Z.
+        let mut cpu = cpu_with(&[0x30, 0x05]);
+        cpu.reg.f = 0x80;
+        assert_eq!(cpu.next(), 12);
+        assert_eq!(cpu.reg.pc, 0x0107);
+        // C set and Z clear: not taken, the old timing took 12 cycles.
+        let mut cpu = cpu_with(&[0x30, 0x05]);
+        cpu.reg.f = 0x10;
+        assert_eq!(cpu.next(), 8);
+        assert_eq!(cpu.reg.pc, 0x0102);
+    }
+
+    #[test]
+    fn profile_counts_a_loop() {
+        // LD B,5; loop: SWAP A; DEC B; JR NZ,loop.
+        let mut cpu = cpu_with(&[0x06, 0x05, 0xcb, 0x37, 0x05, 0x20, 0xfb]);
+        cpu.enable_profiling();
+        for _ in 0..1 + 5 * 3 {
+            cpu.next();
+        }
+        assert_eq!(cpu.reg.pc, 0x0107);
+        let stat = |opcode, cb, count, cycles| OpcodeStat {
+            opcode,
+            cb,
+            count,
+            cycles,
+        };
+        assert_eq!(
+            cpu.profile_report(),
+            vec![
+                stat(0x20, false, 5, 4 * 12 + 8),
+                stat(0x37, true, 5, 5 * 8),
+                stat(0x05, false, 5, 5 * 4),
+                stat(0x06, false, 1, 8)
+            ]
+        );
+        cpu.enable_profiling();
+        assert!(cpu.profile_report().is_empty());
+        cpu.disable_profiling();
+        cpu.next();
+        assert!(cpu.profile_report().is_empty());
+    }
+
+    // A flat memory with a timer behind it that records TIMA at every access.
+    struct Timed {
+        data: Vec<u8>,
+        timer: crate::timer::Timer,
+        log: RefCell<Vec<(u16, u8)>>,
+    }
+
+    impl Memory for Timed {
+        fn get(&self, a: u16) -> u8 {
+            self.log.borrow_mut().push((a, self.timer.get(0xff05)));
+            self.data[usize::from(a)]
+        }
+
+        fn set(&mut self, a: u16, v: u8) {
+            self.log.borrow_mut().push((a, self.timer.get(0xff05)));
+            self.data[usize::from(a)] = v;
+        }
+
+        fn tick(&mut self, cycles: u32) {
+            self.timer.next(cycles);
+        }
+    }
+
+    #[test]
+    fn accesses_tick_the_hardware() {
+        // NOP; LD (HL),d8 with HL=C000h.
+        let mut data = vec![0x00; 0x10000];
+        data[0x0100..0x0103].copy_from_slice(&[0x00, 0x36, 0x5a]);
+        let mut timer = crate::timer::Timer::power_up(Rc::new(RefCell::new(crate::intf::Intf::power_up())));
+        // TIMA counts every 16 clock cycles, on the machine cycle the counter goes from 12 to 16.
+        timer.set(0xff07, 0x05);
+        let mem = Rc::new(RefCell::new(Timed {
+            data,
+            timer,
+            log: RefCell::new(Vec::new()),
+        }));
+        let mut cpu = Cpu::power_up(Term::GB, mem.clone());
+        cpu.ei = false;
+        cpu.reg.set_hl(0xc000);
+        assert_eq!(cpu.next(), 4);
+        assert_eq!(cpu.next(), 12);
+        let log: Vec<_> = mem
+            .borrow()
+            .log
+            .borrow()
+            .iter()
+            .copied()
+            .filter(|&(a, _)| a < 0xff00)
+            .collect();
+        // The counter is at 8 when the opcode is read and at 16 when the byte is stored.
+        assert_eq!(log, [(0x0100, 0), (0x0101, 0), (0x0102, 0), (0xc000, 1)]);
+        assert_eq!(mem.borrow().data[0xc000], 0x5a);
+    }
+
+    #[test]
+    fn push_and_pop_af_clear_the_low_flag_bits() {
+        // LD BC,12FFh; PUSH BC; POP AF; PUSH AF; POP DE; LD BC,345Fh; PUSH BC.
+        let mut cpu = cpu_with(&[0x01, 0xff, 0x12, 0xc5, 0xf1, 0xf5, 0xd1, 0x01, 0x5f, 0x34, 0xc5]);
+        cpu.reg.sp = 0xd000;
+        for _ in 0..5 {
+            cpu.next();
+        }
+        assert_eq!((cpu.reg.get_af(), cpu.reg.get_de()), (0x12f0, 0x12f0));
+        assert_eq!(cpu.reg.sp, 0xd000);
+        cpu.next();
+        cpu.next();
+        // The high byte goes first, at the higher address.
+        assert_eq!(cpu.reg.sp, 0xcffe);
+        assert_eq!(cpu.mem.borrow().get(0xcfff), 0x34);
+        assert_eq!(cpu.mem.borrow().get(0xcffe), 0x5f);
+    }
+
+    #[test]
+    fn stack_wraps_around() {
+        // PUSH BC; POP DE; NOP at FFFFh.
+        let mut cpu = cpu_with(&[0xc5, 0xd1]);
+        cpu.reg.sp = 0x0001;
+        cpu.reg.set_bc(0x1200);
+        cpu.next();
+        assert_eq!(cpu.reg.sp, 0xffff);
+        assert_eq!(cpu.mem.borrow().get(0x0000), 0x12);
+        assert_eq!(cpu.mem.borrow().get(0xffff), 0x00);
+        cpu.next();
+        assert_eq!((cpu.reg.sp, cpu.reg.get_de()), (0x0001, 0x1200));
+        cpu.reg.pc = 0xffff;
+        cpu.next();
+        assert_eq!(cpu.reg.pc, 0x0000);
+    }
+
+    // Records the OAM bug triggers reported by the cpu.
+    struct OamWatch(Vec<u8>, Vec<(u16, OamBug)>);
+
+    impl Memory for OamWatch {
+        fn get(&self, a: u16) -> u8 {
+            self.0[usize::from(a)]
+        }
+
+        fn set(&mut self, a: u16, v: u8) {
+            self.0[usize::from(a)] = v;
+        }
+
+        fn oam_bug(&mut self, a: u16, kind: OamBug) {
+            self.1.push((a, kind));
+        }
+    }
+
+    #[test]
+    fn oam_bug_triggers() {
+        // INC HL; LD A,(HL+); PUSH BC; POP BC.
+        let mut data = vec![0x00; 0x10000];
+        data[0x0100..0x0104].copy_from_slice(&[0x23, 0x2a, 0xc5, 0xc1]);
+        let mem = Rc::new(RefCell::new(OamWatch(data, Vec::new())));
+        let mut cpu = Cpu::power_up(Term::GB, mem.clone());
+        cpu.ei = false;
+        cpu.reg.set_hl(0xfe10);
+        cpu.reg.sp = 0xfe20;
+        for _ in 0..4 {
+            cpu.next();
+        }
+        use OamBug::*;
+        assert_eq!(
+            mem.borrow().1,
+            [
+                (0xfe10, Write),
+                (0xfe11, ReadIncrease),
+                (0xfe20, Write),
+                (0xfe1f, Write),
+                (0xfe1e, Write),
+                (0xfe1e, ReadIncrease),
+                (0xfe1f, ReadIncrease),
+            ]
+        );
+    }
+
+    #[test]
+    fn step_back_undoes_three_steps() {
+        // LD A,42h; LD (C000h),A; INC A.
+        let mut cpu = cpu_with(&[0x3e, 0x42, 0xea, 0x00, 0xc0, 0x3c]);
+        cpu.mem.borrow_mut().set(0xc000, 0x99);
+        assert_eq!(cpu.step_back(), Err(HistoryError::Disabled));
+        cpu.enable_step_history(8);
+        let start = cpu.reg.clone();
+        for _ in 0..3 {
+            cpu.next();
+        }
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (0x43, 0x0106));
+        assert_eq!(cpu.mem.borrow().get(0xc000), 0x42);
+        cpu.step_back().unwrap();
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (0x42, 0x0105));
+        cpu.step_back().unwrap();
+        assert_eq!(cpu.mem.borrow().get(0xc000), 0x99);
+        cpu.step_back().unwrap();
+        assert_eq!(cpu.reg, start);
+        assert_eq!(cpu.step_back(), Err(HistoryError::Empty));
+        // Replaying gives the same result again.
+        for _ in 0..3 {
+            cpu.next();
+        }
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (0x43, 0x0106));
+    }
+
+    #[test]
+    fn step_history_is_bounded() {
+        let mut cpu = cpu_with(&[0x3c; 8]);
+        cpu.enable_step_history(2);
+        for _ in 0..5 {
+            cpu.next();
+        }
+        assert!(cpu.step_back().is_ok());
+        assert!(cpu.step_back().is_ok());
+        assert_eq!(cpu.step_back(), Err(HistoryError::Empty));
+        assert_eq!(cpu.reg.pc, 0x0103);
+    }
+
+    #[test]
+    fn illegal_opcode_locks_the_cpu_up() {
+        // NOP; 0xd3; INC A.
+        let mut cpu = cpu_with(&[0x00, 0xd3, 0x3c]);
+        cpu.reg.a = 0x00;
+        assert_eq!(cpu.try_next(), Ok(4));
+        let fault = StepError::IllegalOpcode {
+            opcode: 0xd3,
+            pc: 0x0101,
+        };
+        assert_eq!(cpu.try_next(), Err(fault));
+        assert_eq!(cpu.try_next(), Err(fault));
+        assert_eq!((cpu.reg.a, cpu.reg.pc), (0x00, 0x0102));
+        cpu.fault = None;
+        assert_eq!(cpu.try_next(), Ok(4));
+        assert_eq!(cpu.reg.a, 0x01);
+
+        for &opcode in &[0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd] {
+            let mut cpu = cpu_with(&[opcode]);
+            assert_eq!(
+                cpu.run_until_break(),
+                BreakReason::Fault(StepError::IllegalOpcode { opcode, pc: 0x0100 })
+            );
+        }
+    }
+
+    // A cpu at 0100h with the V-Blank interrupt requested and enabled in IE, and a return address of 0200h on the
+    // stack.
+    fn cpu_with_vblank(code: &[u8]) -> Cpu {
+        let mut cpu = cpu_with(code);
+        cpu.reg.sp = 0xcffe;
+        cpu.mem.borrow_mut().set_word(0xcffe, 0x0200);
+        cpu.mem.borrow_mut().set(0xff0f, 0x01);
+        cpu.mem.borrow_mut().set(0xffff, 0x01);
+        cpu
+    }
+
+    #[test]
+    fn reti_enables_interrupts_at_once() {
+        let mut cpu = cpu_with_vblank(&[0xd9]);
+        cpu.next();
+        assert_eq!(cpu.reg.pc, 0x0200);
+        // The interrupt is taken before the instruction returned to.
+        assert_eq!(cpu.next(), 20);
+        assert_eq!(cpu.reg.pc, 0x0040);
+        assert_eq!(cpu.mem.borrow().get_word(cpu.reg.sp), 0x0200);
+        assert_eq!(cpu.mem.borrow().get(0xff0f), 0x00);
+    }
+
+    #[test]
+    fn ei_enables_interrupts_after_the_next_instruction() {
+        // EI; RET
+        let mut cpu = cpu_with_vblank(&[0xfb, 0xc9]);
+        cpu.next();
+        assert_eq!(cpu.reg.pc, 0x0101);
+        // RET still runs with the interrupt pending.
+        assert_eq!(cpu.next(), 16);
+        assert_eq!(cpu.reg.pc, 0x0200);
+        assert_eq!(cpu.mem.borrow().get(0xff0f), 0x01);
+        assert_eq!(cpu.next(), 20);
+        assert_eq!(cpu.reg.pc, 0x0040);
+        assert_eq!(cpu.mem.borrow().get_word(cpu.reg.sp), 0x0200);
+    }
+
+    #[test]
+    fn rlca_clears_z_unlike_rlc_a() {
+        // RLCA on zero, then on 80h where the bit rotated out sets C.
+        exec(&[0x07], &[], &[("f", 0x80)]).changed(&[("f", 0x00)], 4);
+        exec(&[0x07], &[], &[("a", 0x80)]).changed(&[("a", 0x01), ("f", 0x10)], 4);
+        // RLA and RRA rotating the 1 into C and leaving A zero.
+        exec(&[0x17], &[], &[("a", 0x80)]).changed(&[("a", 0x00), ("f", 0x10)], 4);
+        exec(&[0x1f], &[], &[("a", 0x01)]).changed(&[("a", 0x00), ("f", 0x10)], 4);
+        // RLC A, RL A and RR A on the same values set Z.
+        exec(&[0xcb, 0x07], &[], &[]).changed(&[("f", 0x80)], 8);
+        exec(&[0xcb, 0x17], &[], &[("a", 0x80)]).changed(&[("a", 0x00), ("f", 0x90)], 8);
+        exec(&[0xcb, 0x1f], &[], &[("a", 0x01)]).changed(&[("a", 0x00), ("f", 0x90)], 8);
+    }
+
+    #[test]
+    fn halt_with_ime_clear_resumes_without_dispatching() {
+        // HALT; INC B
+        let mut cpu = cpu_with(&[0x76, 0x04]);
+        cpu.reg.b = 0x00;
+        cpu.mem.borrow_mut().set(0xffff, 0x04);
+        cpu.next();
+        assert!(cpu.halted);
+        assert_eq!(cpu.next(), 4);
+        assert_eq!(cpu.reg.pc, 0x0101);
+        // The timer interrupt ends HALT, INC B runs next with the interrupt left pending.
+        cpu.mem.borrow_mut().set(0xff0f, 0x04);
+        cpu.next();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.reg.pc, 0x0102);
+        assert_eq!(cpu.reg.b, 0x01);
+        assert_eq!(cpu.reg.sp, 0xfffe);
+        assert_eq!(cpu.mem.borrow().get(0xff0f), 0x04);
+    }
+
+    // A routine calling itself B times, then another routine at each level. The CALL at 0100 steps over it all, then
+    // RST 38h and CALL NZ.
+    fn cpu_with_calls() -> Cpu {
+        let mut cpu = cpu_with(&[0xcd, 0x00, 0x02, 0xff, 0xc4, 0x00, 0x02, 0x00]);
+        let mut mem = cpu.mem.borrow_mut();
+        // DEC B; RET Z; CALL 0200h; CALL 0300h; RET
+        let routine = [0x05, 0xc8, 0xcd, 0x00, 0x02, 0xcd, 0x00, 0x03, 0xc9];
+        for (i, &b) in routine.iter().enumerate() {
+            mem.set(0x0200 + i as u16, b);
+        }
+        // INC C; RET, and INC D; RET at the RST vector.
+        mem.set_word(0x0300, 0xc90c);
+        mem.set_word(0x0038, 0xc914);
+        drop(mem);
+        cpu.reg.b = 0x03;
+        cpu.reg.c = 0x00;
+        cpu.reg.d = 0x00;
+        cpu
+    }
+
+    #[test]
+    fn step_over_runs_through_nested_calls() {
+        let mut cpu = cpu_with_calls();
+        let sp = cpu.reg.sp;
+        let r = cpu.step_over();
+        assert_eq!((r.pc_before, r.pc_after), (0x0100, 0x0103));
+        assert_eq!(cpu.reg.sp, sp);
+        // Three levels deep, the two outer ones called the routine at 0300.
+        assert_eq!((cpu.reg.b, cpu.reg.c), (0x00, 0x02));
+        assert!(r.cycles > 24);
+        let r = cpu.step_over();
+        assert_eq!((r.pc_after, cpu.reg.d), (0x0104, 0x01));
+        assert_eq!(r.cycles, 36);
+        // Not taken, a plain step.
+        cpu.reg.f = 0x80;
+        let r = cpu.step_over();
+        assert_eq!((r.pc_after, r.cycles, cpu.reg.sp), (0x0107, 12, sp));
+
+        // Stepping over the recursive CALL itself: its return address is reached deeper in the stack first.
+        let mut cpu = cpu_with_calls();
+        cpu.reg.pc = 0x0202;
+        cpu.reg.b = 0x02;
+        let sp = cpu.reg.sp;
+        let r = cpu.step_over();
+        assert_eq!((r.pc_after, cpu.reg.sp), (0x0205, sp));
+        assert_eq!((cpu.reg.b, cpu.reg.c), (0x00, 0x01));
+
+        // A breakpoint inside the routine stops it there.
+        let mut cpu = cpu_with_calls();
+        cpu.add_breakpoint(0x0300);
+        let r = cpu.step_over();
+        assert_eq!(r.pc_after, 0x0300);
+        assert_eq!(cpu.reg.b, 0x00);
+    }
+
+    #[test]
+    fn ei_then_halt_returns_to_the_halt() {
+        // EI; HALT; INC C with VBlank requested, the handler is INC B; RETI.
+        let mut cpu = cpu_with(&[0xfb, 0x76, 0x0c]);
+        cpu.mem.borrow_mut().set_word(0x0040, 0xd904);
+        cpu.mem.borrow_mut().set(0xffff, 0x01);
+        cpu.mem.borrow_mut().set(0xff0f, 0x01);
+        cpu.reg.b = 0x00;
+        cpu.reg.c = 0x00;
+        cpu.next();
+        cpu.next();
+        // Dispatched with the HALT as the return address.
+        assert_eq!(cpu.next(), 20);
+        assert_eq!(cpu.reg.pc, 0x0040);
+        assert_eq!(cpu.mem.borrow().get_word(cpu.reg.sp), 0x0101);
+        cpu.next();
+        cpu.next();
+        assert_eq!((cpu.reg.pc, cpu.reg.b), (0x0101, 0x01));
+        // HALT runs again, nothing is pending any more.
+        cpu.next();
+        assert!(cpu.halted);
+        for _ in 0..4 {
+            cpu.next();
+        }
+        assert_eq!((cpu.reg.b, cpu.reg.c), (0x01, 0x00));
+    }
+
+    #[test]
+    fn di_right_after_ei_cancels_the_enable() {
+        // EI; DI; NOP with VBlank requested.
+        let mut cpu = cpu_with(&[0xfb, 0xf3, 0x00, 0x00]);
+        cpu.mem.borrow_mut().set(0xffff, 0x01);
+        cpu.mem.borrow_mut().set(0xff0f, 0x01);
+        for _ in 0..4 {
+            cpu.next();
+        }
+        assert_eq!(cpu.reg.pc, 0x0104);
+        assert!(!cpu.ei);
+    }
+
+    #[test]
+    fn hl_increment_copy_loop() {
+        // LD HL,C000h; LD A,11h; loop: LD (HL+),A; INC A; CP 15h; JR NZ,loop
+        let mut cpu = cpu_with(&[0x21, 0x00, 0xc0, 0x3e, 0x11, 0x22, 0x3c, 0xfe, 0x15, 0x20, 0xfa]);
+        while cpu.reg.pc != 0x010b {
+            cpu.next();
+        }
+        let copied: Vec<u8> = (0xc000..0xc005).map(|a| cpu.mem.borrow().get(a)).collect();
+        assert_eq!(copied, [0x11, 0x12, 0x13, 0x14, 0x00]);
+        assert_eq!(cpu.reg.get_hl(), 0xc004);
+        // HL wraps both ways, after the access.
+        exec(&[0x22], &[], &[("a", 0x12), ("h", 0xff), ("l", 0xff)])
+            .changed(&[("h", 0x00), ("l", 0x00)], 8)
+            .wrote(&[(0xffff, 0x12)]);
+        exec(&[0x3a], &[(0x0000, 0x34)], &[]).changed(&[("a", 0x34), ("h", 0xff), ("l", 0xff)], 8);
+    }
+
+    #[test]
+    fn opcode_cycles_of_branches() {
+        assert_eq!(
+            opcode_cycles(0xc2),
+            Cycles {
+                taken: 16,
+                not_taken: 12
+            }
+        );
+        assert_eq!(opcode_cycles(0x00), Cycles { taken: 4, not_taken: 4 });
+        assert_eq!(
+            cb_opcode_cycles(0x7e),
+            Cycles {
+                taken: 12,
+                not_taken: 12
+            }
+        );
+        // Every conditional branch takes the cycles reported for the way it went.
+        for &op in [
+            0x20, 0x28, 0x30, 0x38, 0xc0, 0xc8, 0xd0, 0xd8, 0xc2, 0xca, 0xd2, 0xda, 0xc4, 0xcc, 0xd4, 0xdc,
+        ]
+        .iter()
+        {
+            for &f in [0x00, 0x10, 0x80, 0x90].iter() {
+                let mut cpu = cpu_with(&[op, 0x10, 0x20]);
+                cpu.reg.f = f;
+                cpu.reg.sp = 0xd000;
+                let cycles = cpu.next();
+                let c = opcode_cycles(op);
+                let want = if (0x0101..=0x0103).contains(&cpu.reg.pc) {
+                    c.not_taken
+                } else {
+                    c.taken
+                };
+                assert_eq!(cycles, u32::from(want), "opcode {:02x} f {:02x}", op, f);
+            }
+        }
+    }
+
+    #[test]
+    fn stop_calls_the_speed_switch() {
+        // STOP; INC A, with no button pressed in P1.
+        let stop = || {
+            let cpu = cpu_with(&[0x10, 0x00, 0x3c]);
+            cpu.mem.borrow_mut().set(0xff00, 0x0f);
+            cpu
+        };
+        let mut cpu = stop();
+        cpu.next();
+        assert!(cpu.is_stopped());
+        let mut cpu = stop();
+        let calls = Rc::new(RefCell::new(0));
+        let seen = calls.clone();
+        cpu.set_speed_switch(move || {
+            *seen.borrow_mut() += 1;
+            true
+        });
+        cpu.next();
+        assert!(!cpu.is_stopped());
+        cpu.next();
+        assert_eq!((cpu.reg.pc, cpu.reg.a, *calls.borrow()), (0x0103, 0x02, 1));
+    }
+}
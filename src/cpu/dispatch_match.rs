@@ -0,0 +1,526 @@
+// The opcode match that OPS and CB_OPS replaced, as plain match expressions calling the same handlers. It is only
+// built with the dispatch-match feature, to measure the two kinds of dispatch against each other in
+// examples/dispatch.rs.
+use super::Cpu;
+
+pub fn op(cpu: &mut Cpu, opcode: u8) {
+    match opcode {
+        0x00 => Cpu::nop(cpu),
+        0x01 => Cpu::ld_r16_d16::<0>(cpu),
+        0x02 => Cpu::ld_bc_a(cpu),
+        0x03 => Cpu::inc_r16::<0>(cpu),
+        0x04 => Cpu::inc_r8::<0>(cpu),
+        0x05 => Cpu::dec_r8::<0>(cpu),
+        0x06 => Cpu::ld_r8_d8::<0>(cpu),
+        0x07 => Cpu::rlca(cpu),
+        0x08 => Cpu::ld_a16_sp(cpu),
+        0x09 => Cpu::add_hl_r16::<0>(cpu),
+        0x0a => Cpu::ld_a_bc(cpu),
+        0x0b => Cpu::dec_r16::<0>(cpu),
+        0x0c => Cpu::inc_r8::<1>(cpu),
+        0x0d => Cpu::dec_r8::<1>(cpu),
+        0x0e => Cpu::ld_r8_d8::<1>(cpu),
+        0x0f => Cpu::rrca(cpu),
+        0x10 => Cpu::stop(cpu),
+        0x11 => Cpu::ld_r16_d16::<1>(cpu),
+        0x12 => Cpu::ld_de_a(cpu),
+        0x13 => Cpu::inc_r16::<1>(cpu),
+        0x14 => Cpu::inc_r8::<2>(cpu),
+        0x15 => Cpu::dec_r8::<2>(cpu),
+        0x16 => Cpu::ld_r8_d8::<2>(cpu),
+        0x17 => Cpu::rla(cpu),
+        0x18 => Cpu::jr(cpu),
+        0x19 => Cpu::add_hl_r16::<1>(cpu),
+        0x1a => Cpu::ld_a_de(cpu),
+        0x1b => Cpu::dec_r16::<1>(cpu),
+        0x1c => Cpu::inc_r8::<3>(cpu),
+        0x1d => Cpu::dec_r8::<3>(cpu),
+        0x1e => Cpu::ld_r8_d8::<3>(cpu),
+        0x1f => Cpu::rra(cpu),
+        0x20 => Cpu::jr_cc::<0>(cpu),
+        0x21 => Cpu::ld_r16_d16::<2>(cpu),
+        0x22 => Cpu::ld_hli_a(cpu),
+        0x23 => Cpu::inc_r16::<2>(cpu),
+        0x24 => Cpu::inc_r8::<4>(cpu),
+        0x25 => Cpu::dec_r8::<4>(cpu),
+        0x26 => Cpu::ld_r8_d8::<4>(cpu),
+        0x27 => Cpu::daa(cpu),
+        0x28 => Cpu::jr_cc::<1>(cpu),
+        0x29 => Cpu::add_hl_r16::<2>(cpu),
+        0x2a => Cpu::ld_a_hli(cpu),
+        0x2b => Cpu::dec_r16::<2>(cpu),
+        0x2c => Cpu::inc_r8::<5>(cpu),
+        0x2d => Cpu::dec_r8::<5>(cpu),
+        0x2e => Cpu::ld_r8_d8::<5>(cpu),
+        0x2f => Cpu::cpl(cpu),
+        0x30 => Cpu::jr_cc::<2>(cpu),
+        0x31 => Cpu::ld_r16_d16::<3>(cpu),
+        0x32 => Cpu::ld_hld_a(cpu),
+        0x33 => Cpu::inc_r16::<3>(cpu),
+        0x34 => Cpu::inc_r8::<6>(cpu),
+        0x35 => Cpu::dec_r8::<6>(cpu),
+        0x36 => Cpu::ld_r8_d8::<6>(cpu),
+        0x37 => Cpu::scf(cpu),
+        0x38 => Cpu::jr_cc::<3>(cpu),
+        0x39 => Cpu::add_hl_r16::<3>(cpu),
+        0x3a => Cpu::ld_a_hld(cpu),
+        0x3b => Cpu::dec_r16::<3>(cpu),
+        0x3c => Cpu::inc_r8::<7>(cpu),
+        0x3d => Cpu::dec_r8::<7>(cpu),
+        0x3e => Cpu::ld_r8_d8::<7>(cpu),
+        0x3f => Cpu::ccf(cpu),
+        0x40 => Cpu::ld_r8_r8::<0, 0>(cpu),
+        0x41 => Cpu::ld_r8_r8::<0, 1>(cpu),
+        0x42 => Cpu::ld_r8_r8::<0, 2>(cpu),
+        0x43 => Cpu::ld_r8_r8::<0, 3>(cpu),
+        0x44 => Cpu::ld_r8_r8::<0, 4>(cpu),
+        0x45 => Cpu::ld_r8_r8::<0, 5>(cpu),
+        0x46 => Cpu::ld_r8_r8::<0, 6>(cpu),
+        0x47 => Cpu::ld_r8_r8::<0, 7>(cpu),
+        0x48 => Cpu::ld_r8_r8::<1, 0>(cpu),
+        0x49 => Cpu::ld_r8_r8::<1, 1>(cpu),
+        0x4a => Cpu::ld_r8_r8::<1, 2>(cpu),
+        0x4b => Cpu::ld_r8_r8::<1, 3>(cpu),
+        0x4c => Cpu::ld_r8_r8::<1, 4>(cpu),
+        0x4d => Cpu::ld_r8_r8::<1, 5>(cpu),
+        0x4e => Cpu::ld_r8_r8::<1, 6>(cpu),
+        0x4f => Cpu::ld_r8_r8::<1, 7>(cpu),
+        0x50 => Cpu::ld_r8_r8::<2, 0>(cpu),
+        0x51 => Cpu::ld_r8_r8::<2, 1>(cpu),
+        0x52 => Cpu::ld_r8_r8::<2, 2>(cpu),
+        0x53 => Cpu::ld_r8_r8::<2, 3>(cpu),
+        0x54 => Cpu::ld_r8_r8::<2, 4>(cpu),
+        0x55 => Cpu::ld_r8_r8::<2, 5>(cpu),
+        0x56 => Cpu::ld_r8_r8::<2, 6>(cpu),
+        0x57 => Cpu::ld_r8_r8::<2, 7>(cpu),
+        0x58 => Cpu::ld_r8_r8::<3, 0>(cpu),
+        0x59 => Cpu::ld_r8_r8::<3, 1>(cpu),
+        0x5a => Cpu::ld_r8_r8::<3, 2>(cpu),
+        0x5b => Cpu::ld_r8_r8::<3, 3>(cpu),
+        0x5c => Cpu::ld_r8_r8::<3, 4>(cpu),
+        0x5d => Cpu::ld_r8_r8::<3, 5>(cpu),
+        0x5e => Cpu::ld_r8_r8::<3, 6>(cpu),
+        0x5f => Cpu::ld_r8_r8::<3, 7>(cpu),
+        0x60 => Cpu::ld_r8_r8::<4, 0>(cpu),
+        0x61 => Cpu::ld_r8_r8::<4, 1>(cpu),
+        0x62 => Cpu::ld_r8_r8::<4, 2>(cpu),
+        0x63 => Cpu::ld_r8_r8::<4, 3>(cpu),
+        0x64 => Cpu::ld_r8_r8::<4, 4>(cpu),
+        0x65 => Cpu::ld_r8_r8::<4, 5>(cpu),
+        0x66 => Cpu::ld_r8_r8::<4, 6>(cpu),
+        0x67 => Cpu::ld_r8_r8::<4, 7>(cpu),
+        0x68 => Cpu::ld_r8_r8::<5, 0>(cpu),
+        0x69 => Cpu::ld_r8_r8::<5, 1>(cpu),
+        0x6a => Cpu::ld_r8_r8::<5, 2>(cpu),
+        0x6b => Cpu::ld_r8_r8::<5, 3>(cpu),
+        0x6c => Cpu::ld_r8_r8::<5, 4>(cpu),
+        0x6d => Cpu::ld_r8_r8::<5, 5>(cpu),
+        0x6e => Cpu::ld_r8_r8::<5, 6>(cpu),
+        0x6f => Cpu::ld_r8_r8::<5, 7>(cpu),
+        0x70 => Cpu::ld_r8_r8::<6, 0>(cpu),
+        0x71 => Cpu::ld_r8_r8::<6, 1>(cpu),
+        0x72 => Cpu::ld_r8_r8::<6, 2>(cpu),
+        0x73 => Cpu::ld_r8_r8::<6, 3>(cpu),
+        0x74 => Cpu::ld_r8_r8::<6, 4>(cpu),
+        0x75 => Cpu::ld_r8_r8::<6, 5>(cpu),
+        0x76 => Cpu::halt(cpu),
+        0x77 => Cpu::ld_r8_r8::<6, 7>(cpu),
+        0x78 => Cpu::ld_r8_r8::<7, 0>(cpu),
+        0x79 => Cpu::ld_r8_r8::<7, 1>(cpu),
+        0x7a => Cpu::ld_r8_r8::<7, 2>(cpu),
+        0x7b => Cpu::ld_r8_r8::<7, 3>(cpu),
+        0x7c => Cpu::ld_r8_r8::<7, 4>(cpu),
+        0x7d => Cpu::ld_r8_r8::<7, 5>(cpu),
+        0x7e => Cpu::ld_r8_r8::<7, 6>(cpu),
+        0x7f => Cpu::ld_r8_r8::<7, 7>(cpu),
+        0x80 => Cpu::alu_r8::<0, 0>(cpu),
+        0x81 => Cpu::alu_r8::<0, 1>(cpu),
+        0x82 => Cpu::alu_r8::<0, 2>(cpu),
+        0x83 => Cpu::alu_r8::<0, 3>(cpu),
+        0x84 => Cpu::alu_r8::<0, 4>(cpu),
+        0x85 => Cpu::alu_r8::<0, 5>(cpu),
+        0x86 => Cpu::alu_r8::<0, 6>(cpu),
+        0x87 => Cpu::alu_r8::<0, 7>(cpu),
+        0x88 => Cpu::alu_r8::<1, 0>(cpu),
+        0x89 => Cpu::alu_r8::<1, 1>(cpu),
+        0x8a => Cpu::alu_r8::<1, 2>(cpu),
+        0x8b => Cpu::alu_r8::<1, 3>(cpu),
+        0x8c => Cpu::alu_r8::<1, 4>(cpu),
+        0x8d => Cpu::alu_r8::<1, 5>(cpu),
+        0x8e => Cpu::alu_r8::<1, 6>(cpu),
+        0x8f => Cpu::alu_r8::<1, 7>(cpu),
+        0x90 => Cpu::alu_r8::<2, 0>(cpu),
+        0x91 => Cpu::alu_r8::<2, 1>(cpu),
+        0x92 => Cpu::alu_r8::<2, 2>(cpu),
+        0x93 => Cpu::alu_r8::<2, 3>(cpu),
+        0x94 => Cpu::alu_r8::<2, 4>(cpu),
+        0x95 => Cpu::alu_r8::<2, 5>(cpu),
+        0x96 => Cpu::alu_r8::<2, 6>(cpu),
+        0x97 => Cpu::alu_r8::<2, 7>(cpu),
+        0x98 => Cpu::alu_r8::<3, 0>(cpu),
+        0x99 => Cpu::alu_r8::<3, 1>(cpu),
+        0x9a => Cpu::alu_r8::<3, 2>(cpu),
+        0x9b => Cpu::alu_r8::<3, 3>(cpu),
+        0x9c => Cpu::alu_r8::<3, 4>(cpu),
+        0x9d => Cpu::alu_r8::<3, 5>(cpu),
+        0x9e => Cpu::alu_r8::<3, 6>(cpu),
+        0x9f => Cpu::alu_r8::<3, 7>(cpu),
+        0xa0 => Cpu::alu_r8::<4, 0>(cpu),
+        0xa1 => Cpu::alu_r8::<4, 1>(cpu),
+        0xa2 => Cpu::alu_r8::<4, 2>(cpu),
+        0xa3 => Cpu::alu_r8::<4, 3>(cpu),
+        0xa4 => Cpu::alu_r8::<4, 4>(cpu),
+        0xa5 => Cpu::alu_r8::<4, 5>(cpu),
+        0xa6 => Cpu::alu_r8::<4, 6>(cpu),
+        0xa7 => Cpu::alu_r8::<4, 7>(cpu),
+        0xa8 => Cpu::alu_r8::<5, 0>(cpu),
+        0xa9 => Cpu::alu_r8::<5, 1>(cpu),
+        0xaa => Cpu::alu_r8::<5, 2>(cpu),
+        0xab => Cpu::alu_r8::<5, 3>(cpu),
+        0xac => Cpu::alu_r8::<5, 4>(cpu),
+        0xad => Cpu::alu_r8::<5, 5>(cpu),
+        0xae => Cpu::alu_r8::<5, 6>(cpu),
+        0xaf => Cpu::alu_r8::<5, 7>(cpu),
+        0xb0 => Cpu::alu_r8::<6, 0>(cpu),
+        0xb1 => Cpu::alu_r8::<6, 1>(cpu),
+        0xb2 => Cpu::alu_r8::<6, 2>(cpu),
+        0xb3 => Cpu::alu_r8::<6, 3>(cpu),
+        0xb4 => Cpu::alu_r8::<6, 4>(cpu),
+        0xb5 => Cpu::alu_r8::<6, 5>(cpu),
+        0xb6 => Cpu::alu_r8::<6, 6>(cpu),
+        0xb7 => Cpu::alu_r8::<6, 7>(cpu),
+        0xb8 => Cpu::alu_r8::<7, 0>(cpu),
+        0xb9 => Cpu::alu_r8::<7, 1>(cpu),
+        0xba => Cpu::alu_r8::<7, 2>(cpu),
+        0xbb => Cpu::alu_r8::<7, 3>(cpu),
+        0xbc => Cpu::alu_r8::<7, 4>(cpu),
+        0xbd => Cpu::alu_r8::<7, 5>(cpu),
+        0xbe => Cpu::alu_r8::<7, 6>(cpu),
+        0xbf => Cpu::alu_r8::<7, 7>(cpu),
+        0xc0 => Cpu::ret_cc::<0>(cpu),
+        0xc1 => Cpu::pop::<0>(cpu),
+        0xc2 => Cpu::jp_cc::<0>(cpu),
+        0xc3 => Cpu::jp(cpu),
+        0xc4 => Cpu::call_cc::<0>(cpu),
+        0xc5 => Cpu::push::<0>(cpu),
+        0xc6 => Cpu::alu_d8::<0>(cpu),
+        0xc7 => Cpu::rst::<0x00>(cpu),
+        0xc8 => Cpu::ret_cc::<1>(cpu),
+        0xc9 => Cpu::ret(cpu),
+        0xca => Cpu::jp_cc::<1>(cpu),
+        0xcb => Cpu::prefix_cb(cpu),
+        0xcc => Cpu::call_cc::<1>(cpu),
+        0xcd => Cpu::call(cpu),
+        0xce => Cpu::alu_d8::<1>(cpu),
+        0xcf => Cpu::rst::<0x08>(cpu),
+        0xd0 => Cpu::ret_cc::<2>(cpu),
+        0xd1 => Cpu::pop::<1>(cpu),
+        0xd2 => Cpu::jp_cc::<2>(cpu),
+        0xd3 => Cpu::undefined::<0xd3>(cpu),
+        0xd4 => Cpu::call_cc::<2>(cpu),
+        0xd5 => Cpu::push::<1>(cpu),
+        0xd6 => Cpu::alu_d8::<2>(cpu),
+        0xd7 => Cpu::rst::<0x10>(cpu),
+        0xd8 => Cpu::ret_cc::<3>(cpu),
+        0xd9 => Cpu::reti(cpu),
+        0xda => Cpu::jp_cc::<3>(cpu),
+        0xdb => Cpu::undefined::<0xdb>(cpu),
+        0xdc => Cpu::call_cc::<3>(cpu),
+        0xdd => Cpu::undefined::<0xdd>(cpu),
+        0xde => Cpu::alu_d8::<3>(cpu),
+        0xdf => Cpu::rst::<0x18>(cpu),
+        0xe0 => Cpu::ldh_a8_a(cpu),
+        0xe1 => Cpu::pop::<2>(cpu),
+        0xe2 => Cpu::ld_c_a(cpu),
+        0xe3 => Cpu::undefined::<0xe3>(cpu),
+        0xe4 => Cpu::undefined::<0xe4>(cpu),
+        0xe5 => Cpu::push::<2>(cpu),
+        0xe6 => Cpu::alu_d8::<4>(cpu),
+        0xe7 => Cpu::rst::<0x20>(cpu),
+        0xe8 => Cpu::add_sp_e8(cpu),
+        0xe9 => Cpu::jp_hl(cpu),
+        0xea => Cpu::ld_a16_a(cpu),
+        0xeb => Cpu::undefined::<0xeb>(cpu),
+        0xec => Cpu::undefined::<0xec>(cpu),
+        0xed => Cpu::undefined::<0xed>(cpu),
+        0xee => Cpu::alu_d8::<5>(cpu),
+        0xef => Cpu::rst::<0x28>(cpu),
+        0xf0 => Cpu::ldh_a_a8(cpu),
+        0xf1 => Cpu::pop::<3>(cpu),
+        0xf2 => Cpu::ld_a_c(cpu),
+        0xf3 => Cpu::di(cpu),
+        0xf4 => Cpu::undefined::<0xf4>(cpu),
+        0xf5 => Cpu::push::<3>(cpu),
+        0xf6 => Cpu::alu_d8::<6>(cpu),
+        0xf7 => Cpu::rst::<0x30>(cpu),
+        0xf8 => Cpu::ld_hl_sp_e8(cpu),
+        0xf9 => Cpu::ld_sp_hl(cpu),
+        0xfa => Cpu::ld_a_a16(cpu),
+        0xfb => Cpu::ei(cpu),
+        0xfc => Cpu::undefined::<0xfc>(cpu),
+        0xfd => Cpu::undefined::<0xfd>(cpu),
+        0xfe => Cpu::alu_d8::<7>(cpu),
+        0xff => Cpu::rst::<0x38>(cpu),
+    }
+}
+
+pub fn cb(cpu: &mut Cpu, cbcode: u8) {
+    match cbcode {
+        0x00 => Cpu::rot_r8::<0, 0>(cpu),
+        0x01 => Cpu::rot_r8::<0, 1>(cpu),
+        0x02 => Cpu::rot_r8::<0, 2>(cpu),
+        0x03 => Cpu::rot_r8::<0, 3>(cpu),
+        0x04 => Cpu::rot_r8::<0, 4>(cpu),
+        0x05 => Cpu::rot_r8::<0, 5>(cpu),
+        0x06 => Cpu::rot_r8::<0, 6>(cpu),
+        0x07 => Cpu::rot_r8::<0, 7>(cpu),
+        0x08 => Cpu::rot_r8::<1, 0>(cpu),
+        0x09 => Cpu::rot_r8::<1, 1>(cpu),
+        0x0a => Cpu::rot_r8::<1, 2>(cpu),
+        0x0b => Cpu::rot_r8::<1, 3>(cpu),
+        0x0c => Cpu::rot_r8::<1, 4>(cpu),
+        0x0d => Cpu::rot_r8::<1, 5>(cpu),
+        0x0e => Cpu::rot_r8::<1, 6>(cpu),
+        0x0f => Cpu::rot_r8::<1, 7>(cpu),
+        0x10 => Cpu::rot_r8::<2, 0>(cpu),
+        0x11 => Cpu::rot_r8::<2, 1>(cpu),
+        0x12 => Cpu::rot_r8::<2, 2>(cpu),
+        0x13 => Cpu::rot_r8::<2, 3>(cpu),
+        0x14 => Cpu::rot_r8::<2, 4>(cpu),
+        0x15 => Cpu::rot_r8::<2, 5>(cpu),
+        0x16 => Cpu::rot_r8::<2, 6>(cpu),
+        0x17 => Cpu::rot_r8::<2, 7>(cpu),
+        0x18 => Cpu::rot_r8::<3, 0>(cpu),
+        0x19 => Cpu::rot_r8::<3, 1>(cpu),
+        0x1a => Cpu::rot_r8::<3, 2>(cpu),
+        0x1b => Cpu::rot_r8::<3, 3>(cpu),
+        0x1c => Cpu::rot_r8::<3, 4>(cpu),
+        0x1d => Cpu::rot_r8::<3, 5>(cpu),
+        0x1e => Cpu::rot_r8::<3, 6>(cpu),
+        0x1f => Cpu::rot_r8::<3, 7>(cpu),
+        0x20 => Cpu::rot_r8::<4, 0>(cpu),
+        0x21 => Cpu::rot_r8::<4, 1>(cpu),
+        0x22 => Cpu::rot_r8::<4, 2>(cpu),
+        0x23 => Cpu::rot_r8::<4, 3>(cpu),
+        0x24 => Cpu::rot_r8::<4, 4>(cpu),
+        0x25 => Cpu::rot_r8::<4, 5>(cpu),
+        0x26 => Cpu::rot_r8::<4, 6>(cpu),
+        0x27 => Cpu::rot_r8::<4, 7>(cpu),
+        0x28 => Cpu::rot_r8::<5, 0>(cpu),
+        0x29 => Cpu::rot_r8::<5, 1>(cpu),
+        0x2a => Cpu::rot_r8::<5, 2>(cpu),
+        0x2b => Cpu::rot_r8::<5, 3>(cpu),
+        0x2c => Cpu::rot_r8::<5, 4>(cpu),
+        0x2d => Cpu::rot_r8::<5, 5>(cpu),
+        0x2e => Cpu::rot_r8::<5, 6>(cpu),
+        0x2f => Cpu::rot_r8::<5, 7>(cpu),
+        0x30 => Cpu::rot_r8::<6, 0>(cpu),
+        0x31 => Cpu::rot_r8::<6, 1>(cpu),
+        0x32 => Cpu::rot_r8::<6, 2>(cpu),
+        0x33 => Cpu::rot_r8::<6, 3>(cpu),
+        0x34 => Cpu::rot_r8::<6, 4>(cpu),
+        0x35 => Cpu::rot_r8::<6, 5>(cpu),
+        0x36 => Cpu::rot_r8::<6, 6>(cpu),
+        0x37 => Cpu::rot_r8::<6, 7>(cpu),
+        0x38 => Cpu::rot_r8::<7, 0>(cpu),
+        0x39 => Cpu::rot_r8::<7, 1>(cpu),
+        0x3a => Cpu::rot_r8::<7, 2>(cpu),
+        0x3b => Cpu::rot_r8::<7, 3>(cpu),
+        0x3c => Cpu::rot_r8::<7, 4>(cpu),
+        0x3d => Cpu::rot_r8::<7, 5>(cpu),
+        0x3e => Cpu::rot_r8::<7, 6>(cpu),
+        0x3f => Cpu::rot_r8::<7, 7>(cpu),
+        0x40 => Cpu::bit_r8::<0, 0>(cpu),
+        0x41 => Cpu::bit_r8::<0, 1>(cpu),
+        0x42 => Cpu::bit_r8::<0, 2>(cpu),
+        0x43 => Cpu::bit_r8::<0, 3>(cpu),
+        0x44 => Cpu::bit_r8::<0, 4>(cpu),
+        0x45 => Cpu::bit_r8::<0, 5>(cpu),
+        0x46 => Cpu::bit_r8::<0, 6>(cpu),
+        0x47 => Cpu::bit_r8::<0, 7>(cpu),
+        0x48 => Cpu::bit_r8::<1, 0>(cpu),
+        0x49 => Cpu::bit_r8::<1, 1>(cpu),
+        0x4a => Cpu::bit_r8::<1, 2>(cpu),
+        0x4b => Cpu::bit_r8::<1, 3>(cpu),
+        0x4c => Cpu::bit_r8::<1, 4>(cpu),
+        0x4d => Cpu::bit_r8::<1, 5>(cpu),
+        0x4e => Cpu::bit_r8::<1, 6>(cpu),
+        0x4f => Cpu::bit_r8::<1, 7>(cpu),
+        0x50 => Cpu::bit_r8::<2, 0>(cpu),
+        0x51 => Cpu::bit_r8::<2, 1>(cpu),
+        0x52 => Cpu::bit_r8::<2, 2>(cpu),
+        0x53 => Cpu::bit_r8::<2, 3>(cpu),
+        0x54 => Cpu::bit_r8::<2, 4>(cpu),
+        0x55 => Cpu::bit_r8::<2, 5>(cpu),
+        0x56 => Cpu::bit_r8::<2, 6>(cpu),
+        0x57 => Cpu::bit_r8::<2, 7>(cpu),
+        0x58 => Cpu::bit_r8::<3, 0>(cpu),
+        0x59 => Cpu::bit_r8::<3, 1>(cpu),
+        0x5a => Cpu::bit_r8::<3, 2>(cpu),
+        0x5b => Cpu::bit_r8::<3, 3>(cpu),
+        0x5c => Cpu::bit_r8::<3, 4>(cpu),
+        0x5d => Cpu::bit_r8::<3, 5>(cpu),
+        0x5e => Cpu::bit_r8::<3, 6>(cpu),
+        0x5f => Cpu::bit_r8::<3, 7>(cpu),
+        0x60 => Cpu::bit_r8::<4, 0>(cpu),
+        0x61 => Cpu::bit_r8::<4, 1>(cpu),
+        0x62 => Cpu::bit_r8::<4, 2>(cpu),
+        0x63 => Cpu::bit_r8::<4, 3>(cpu),
+        0x64 => Cpu::bit_r8::<4, 4>(cpu),
+        0x65 => Cpu::bit_r8::<4, 5>(cpu),
+        0x66 => Cpu::bit_r8::<4, 6>(cpu),
+        0x67 => Cpu::bit_r8::<4, 7>(cpu),
+        0x68 => Cpu::bit_r8::<5, 0>(cpu),
+        0x69 => Cpu::bit_r8::<5, 1>(cpu),
+        0x6a => Cpu::bit_r8::<5, 2>(cpu),
+        0x6b => Cpu::bit_r8::<5, 3>(cpu),
+        0x6c => Cpu::bit_r8::<5, 4>(cpu),
+        0x6d => Cpu::bit_r8::<5, 5>(cpu),
+        0x6e => Cpu::bit_r8::<5, 6>(cpu),
+        0x6f => Cpu::bit_r8::<5, 7>(cpu),
+        0x70 => Cpu::bit_r8::<6, 0>(cpu),
+        0x71 => Cpu::bit_r8::<6, 1>(cpu),
+        0x72 => Cpu::bit_r8::<6, 2>(cpu),
+        0x73 => Cpu::bit_r8::<6, 3>(cpu),
+        0x74 => Cpu::bit_r8::<6, 4>(cpu),
+        0x75 => Cpu::bit_r8::<6, 5>(cpu),
+        0x76 => Cpu::bit_r8::<6, 6>(cpu),
+        0x77 => Cpu::bit_r8::<6, 7>(cpu),
+        0x78 => Cpu::bit_r8::<7, 0>(cpu),
+        0x79 => Cpu::bit_r8::<7, 1>(cpu),
+        0x7a => Cpu::bit_r8::<7, 2>(cpu),
+        0x7b => Cpu::bit_r8::<7, 3>(cpu),
+        0x7c => Cpu::bit_r8::<7, 4>(cpu),
+        0x7d => Cpu::bit_r8::<7, 5>(cpu),
+        0x7e => Cpu::bit_r8::<7, 6>(cpu),
+        0x7f => Cpu::bit_r8::<7, 7>(cpu),
+        0x80 => Cpu::res_r8::<0, 0>(cpu),
+        0x81 => Cpu::res_r8::<0, 1>(cpu),
+        0x82 => Cpu::res_r8::<0, 2>(cpu),
+        0x83 => Cpu::res_r8::<0, 3>(cpu),
+        0x84 => Cpu::res_r8::<0, 4>(cpu),
+        0x85 => Cpu::res_r8::<0, 5>(cpu),
+        0x86 => Cpu::res_r8::<0, 6>(cpu),
+        0x87 => Cpu::res_r8::<0, 7>(cpu),
+        0x88 => Cpu::res_r8::<1, 0>(cpu),
+        0x89 => Cpu::res_r8::<1, 1>(cpu),
+        0x8a => Cpu::res_r8::<1, 2>(cpu),
+        0x8b => Cpu::res_r8::<1, 3>(cpu),
+        0x8c => Cpu::res_r8::<1, 4>(cpu),
+        0x8d => Cpu::res_r8::<1, 5>(cpu),
+        0x8e => Cpu::res_r8::<1, 6>(cpu),
+        0x8f => Cpu::res_r8::<1, 7>(cpu),
+        0x90 => Cpu::res_r8::<2, 0>(cpu),
+        0x91 => Cpu::res_r8::<2, 1>(cpu),
+        0x92 => Cpu::res_r8::<2, 2>(cpu),
+        0x93 => Cpu::res_r8::<2, 3>(cpu),
+        0x94 => Cpu::res_r8::<2, 4>(cpu),
+        0x95 => Cpu::res_r8::<2, 5>(cpu),
+        0x96 => Cpu::res_r8::<2, 6>(cpu),
+        0x97 => Cpu::res_r8::<2, 7>(cpu),
+        0x98 => Cpu::res_r8::<3, 0>(cpu),
+        0x99 => Cpu::res_r8::<3, 1>(cpu),
+        0x9a => Cpu::res_r8::<3, 2>(cpu),
+        0x9b => Cpu::res_r8::<3, 3>(cpu),
+        0x9c => Cpu::res_r8::<3, 4>(cpu),
+        0x9d => Cpu::res_r8::<3, 5>(cpu),
+        0x9e => Cpu::res_r8::<3, 6>(cpu),
+        0x9f => Cpu::res_r8::<3, 7>(cpu),
+        0xa0 => Cpu::res_r8::<4, 0>(cpu),
+        0xa1 => Cpu::res_r8::<4, 1>(cpu),
+        0xa2 => Cpu::res_r8::<4, 2>(cpu),
+        0xa3 => Cpu::res_r8::<4, 3>(cpu),
+        0xa4 => Cpu::res_r8::<4, 4>(cpu),
+        0xa5 => Cpu::res_r8::<4, 5>(cpu),
+        0xa6 => Cpu::res_r8::<4, 6>(cpu),
+        0xa7 => Cpu::res_r8::<4, 7>(cpu),
+        0xa8 => Cpu::res_r8::<5, 0>(cpu),
+        0xa9 => Cpu::res_r8::<5, 1>(cpu),
+        0xaa => Cpu::res_r8::<5, 2>(cpu),
+        0xab => Cpu::res_r8::<5, 3>(cpu),
+        0xac => Cpu::res_r8::<5, 4>(cpu),
+        0xad => Cpu::res_r8::<5, 5>(cpu),
+        0xae => Cpu::res_r8::<5, 6>(cpu),
+        0xaf => Cpu::res_r8::<5, 7>(cpu),
+        0xb0 => Cpu::res_r8::<6, 0>(cpu),
+        0xb1 => Cpu::res_r8::<6, 1>(cpu),
+        0xb2 => Cpu::res_r8::<6, 2>(cpu),
+        0xb3 => Cpu::res_r8::<6, 3>(cpu),
+        0xb4 => Cpu::res_r8::<6, 4>(cpu),
+        0xb5 => Cpu::res_r8::<6, 5>(cpu),
+        0xb6 => Cpu::res_r8::<6, 6>(cpu),
+        0xb7 => Cpu::res_r8::<6, 7>(cpu),
+        0xb8 => Cpu::res_r8::<7, 0>(cpu),
+        0xb9 => Cpu::res_r8::<7, 1>(cpu),
+        0xba => Cpu::res_r8::<7, 2>(cpu),
+        0xbb => Cpu::res_r8::<7, 3>(cpu),
+        0xbc => Cpu::res_r8::<7, 4>(cpu),
+        0xbd => Cpu::res_r8::<7, 5>(cpu),
+        0xbe => Cpu::res_r8::<7, 6>(cpu),
+        0xbf => Cpu::res_r8::<7, 7>(cpu),
+        0xc0 => Cpu::set_r8::<0, 0>(cpu),
+        0xc1 => Cpu::set_r8::<0, 1>(cpu),
+        0xc2 => Cpu::set_r8::<0, 2>(cpu),
+        0xc3 => Cpu::set_r8::<0, 3>(cpu),
+        0xc4 => Cpu::set_r8::<0, 4>(cpu),
+        0xc5 => Cpu::set_r8::<0, 5>(cpu),
+        0xc6 => Cpu::set_r8::<0, 6>(cpu),
+        0xc7 => Cpu::set_r8::<0, 7>(cpu),
+        0xc8 => Cpu::set_r8::<1, 0>(cpu),
+        0xc9 => Cpu::set_r8::<1, 1>(cpu),
+        0xca => Cpu::set_r8::<1, 2>(cpu),
+        0xcb => Cpu::set_r8::<1, 3>(cpu),
+        0xcc => Cpu::set_r8::<1, 4>(cpu),
+        0xcd => Cpu::set_r8::<1, 5>(cpu),
+        0xce => Cpu::set_r8::<1, 6>(cpu),
+        0xcf => Cpu::set_r8::<1, 7>(cpu),
+        0xd0 => Cpu::set_r8::<2, 0>(cpu),
+        0xd1 => Cpu::set_r8::<2, 1>(cpu),
+        0xd2 => Cpu::set_r8::<2, 2>(cpu),
+        0xd3 => Cpu::set_r8::<2, 3>(cpu),
+        0xd4 => Cpu::set_r8::<2, 4>(cpu),
+        0xd5 => Cpu::set_r8::<2, 5>(cpu),
+        0xd6 => Cpu::set_r8::<2, 6>(cpu),
+        0xd7 => Cpu::set_r8::<2, 7>(cpu),
+        0xd8 => Cpu::set_r8::<3, 0>(cpu),
+        0xd9 => Cpu::set_r8::<3, 1>(cpu),
+        0xda => Cpu::set_r8::<3, 2>(cpu),
+        0xdb => Cpu::set_r8::<3, 3>(cpu),
+        0xdc => Cpu::set_r8::<3, 4>(cpu),
+        0xdd => Cpu::set_r8::<3, 5>(cpu),
+        0xde => Cpu::set_r8::<3, 6>(cpu),
+        0xdf => Cpu::set_r8::<3, 7>(cpu),
+        0xe0 => Cpu::set_r8::<4, 0>(cpu),
+        0xe1 => Cpu::set_r8::<4, 1>(cpu),
+        0xe2 => Cpu::set_r8::<4, 2>(cpu),
+        0xe3 => Cpu::set_r8::<4, 3>(cpu),
+        0xe4 => Cpu::set_r8::<4, 4>(cpu),
+        0xe5 => Cpu::set_r8::<4, 5>(cpu),
+        0xe6 => Cpu::set_r8::<4, 6>(cpu),
+        0xe7 => Cpu::set_r8::<4, 7>(cpu),
+        0xe8 => Cpu::set_r8::<5, 0>(cpu),
+        0xe9 => Cpu::set_r8::<5, 1>(cpu),
+        0xea => Cpu::set_r8::<5, 2>(cpu),
+        0xeb => Cpu::set_r8::<5, 3>(cpu),
+        0xec => Cpu::set_r8::<5, 4>(cpu),
+        0xed => Cpu::set_r8::<5, 5>(cpu),
+        0xee => Cpu::set_r8::<5, 6>(cpu),
+        0xef => Cpu::set_r8::<5, 7>(cpu),
+        0xf0 => Cpu::set_r8::<6, 0>(cpu),
+        0xf1 => Cpu::set_r8::<6, 1>(cpu),
+        0xf2 => Cpu::set_r8::<6, 2>(cpu),
+        0xf3 => Cpu::set_r8::<6, 3>(cpu),
+        0xf4 => Cpu::set_r8::<6, 4>(cpu),
+        0xf5 => Cpu::set_r8::<6, 5>(cpu),
+        0xf6 => Cpu::set_r8::<6, 6>(cpu),
+        0xf7 => Cpu::set_r8::<6, 7>(cpu),
+        0xf8 => Cpu::set_r8::<7, 0>(cpu),
+        0xf9 => Cpu::set_r8::<7, 1>(cpu),
+        0xfa => Cpu::set_r8::<7, 2>(cpu),
+        0xfb => Cpu::set_r8::<7, 3>(cpu),
+        0xfc => Cpu::set_r8::<7, 4>(cpu),
+        0xfd => Cpu::set_r8::<7, 5>(cpu),
+        0xfe => Cpu::set_r8::<7, 6>(cpu),
+        0xff => Cpu::set_r8::<7, 7>(cpu),
+    }
+}
@@ -0,0 +1,276 @@
+// Disassembler for the sharp LR35902 instruction set. Opcodes are decoded from their bit fields rather than a 512 entry
+// table, the layout of an opcode byte is:
+//
+//   7 6 5 4 3 2 1 0
+//   x x y y y z z z
+//       p p q
+//
+// Immediate values are printed as $XX (8-bit) or $XXXX (16-bit), relative jumps show the resolved target address.
+// Undefined opcodes are printed as "DB $XX". A Disassembler with symbols prints the label of the addresses that have
+// one instead: the targets of jumps and calls and the memory operands, not the 16-bit immediates loaded in registers.
+//
+// See: https://gb-archive.github.io/salvage/decoding_gbz80_opcodes/Decoding%20Gamboy%20Z80%20Opcodes.html
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+
+const R8: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const RP: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const RP2: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const CC: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP "];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const MISC: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+// Labels of a symbol file, in the format written by RGBDS and most other assemblers: one "BB:AAAA Label" per line,
+// the bank and address in hex, and comments starting with a semicolon.
+pub struct Disassembler {
+    symbols: BTreeMap<(usize, u16), String>,
+    // The banks currently mapped at 4000-7FFF, 8000-9FFF, A000-BFFF and D000-DFFF, to pick the labels of the right
+    // bank, see Cartridge::bank. The other areas are always bank 0.
+    pub rom_bank: usize,
+    pub vram_bank: usize,
+    pub sram_bank: usize,
+    pub wram_bank: usize,
+}
+
+impl Disassembler {
+    pub fn new() -> Self {
+        Self {
+            symbols: BTreeMap::new(),
+            rom_bank: 1,
+            vram_bank: 0,
+            sram_bank: 0,
+            wram_bank: 1,
+        }
+    }
+
+    // Add the labels of a symbol file, returns how many were read. Lines that aren't a label are skipped, a label
+    // given twice keeps the last name.
+    pub fn load_symbols(&mut self, sym: &str) -> usize {
+        let mut n = 0;
+        for line in sym.lines() {
+            let line = line.split(';').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let (at, name) = match (words.next(), words.next()) {
+                (Some(at), Some(name)) => (at, name),
+                _ => continue,
+            };
+            let (bank, addr) = match at.split_once(':') {
+                Some(some) => some,
+                None => continue,
+            };
+            if let (Ok(bank), Ok(addr)) = (usize::from_str_radix(bank, 16), u16::from_str_radix(addr, 16)) {
+                self.symbols.insert((bank, addr), String::from(name));
+                n += 1;
+            }
+        }
+        n
+    }
+
+    // The label of the address in the bank mapped there, if any.
+    pub fn label(&self, a: u16) -> Option<&str> {
+        let bank = match a {
+            0x4000..=0x7fff => self.rom_bank,
+            0x8000..=0x9fff => self.vram_bank,
+            0xa000..=0xbfff => self.sram_bank,
+            0xd000..=0xdfff => self.wram_bank,
+            _ => 0,
+        };
+        self.symbols.get(&(bank, a)).map(String::as_str)
+    }
+
+    // Same as disassemble, with labels.
+    pub fn disassemble(&self, mem: &[u8], addr: u16) -> (String, u8) {
+        decode(mem, addr, &|a| self.label(a))
+    }
+}
+
+impl Default for Disassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Decode the instruction at addr, returns its mnemonic and length in bytes. Bytes past the end of mem read as 0x00.
+pub fn disassemble(mem: &[u8], addr: u16) -> (String, u8) {
+    decode(mem, addr, &|_| None)
+}
+
+fn decode<'a>(mem: &[u8], addr: u16, label: &dyn Fn(u16) -> Option<&'a str>) -> (String, u8) {
+    let at = |a: u16| label(a).map_or_else(|| format!("${:04X}", a), String::from);
+    let high = |n: u8| label(0xff00 | u16::from(n)).map_or_else(|| format!("${:02X}", n), String::from);
+    let get = |i: u16| mem.get(addr.wrapping_add(i) as usize).copied().unwrap_or(0x00);
+    let op = get(0);
+    let n = get(1);
+    let nn = u16::from(n) | (u16::from(get(2)) << 8);
+    let rel = addr.wrapping_add(2).wrapping_add(i16::from(n as i8) as u16);
+
+    let x = op >> 6;
+    let y = ((op >> 3) & 0x07) as usize;
+    let z = op & 0x07;
+    let p = y >> 1;
+    let q = y & 0x01;
+
+    match (x, z) {
+        (0, 0) => match y {
+            0 => (String::from("NOP"), 1),
+            1 => (format!("LD ({}),SP", at(nn)), 3),
+            2 => (String::from("STOP"), 2),
+            3 => (format!("JR {}", at(rel)), 2),
+            _ => (format!("JR {},{}", CC[y - 4], at(rel)), 2),
+        },
+        (0, 1) => {
+            if q == 0 {
+                (format!("LD {},${:04X}", RP[p], nn), 3)
+            } else {
+                (format!("ADD HL,{}", RP[p]), 1)
+            }
+        }
+        (0, 2) => {
+            let m = ["(BC)", "(DE)", "(HL+)", "(HL-)"][p];
+            if q == 0 {
+                (format!("LD {},A", m), 1)
+            } else {
+                (format!("LD A,{}", m), 1)
+            }
+        }
+        (0, 3) => {
+            if q == 0 {
+                (format!("INC {}", RP[p]), 1)
+            } else {
+                (format!("DEC {}", RP[p]), 1)
+            }
+        }
+        (0, 4) => (format!("INC {}", R8[y]), 1),
+        (0, 5) => (format!("DEC {}", R8[y]), 1),
+        (0, 6) => (format!("LD {},${:02X}", R8[y], n), 2),
+        (0, 7) => (String::from(MISC[y]), 1),
+        (1, _) => {
+            if op == 0x76 {
+                (String::from("HALT"), 1)
+            } else {
+                (format!("LD {},{}", R8[y], R8[z as usize]), 1)
+            }
+        }
+        (2, _) => (format!("{}{}", ALU[y], R8[z as usize]), 1),
+        (3, 0) => match y {
+            0..=3 => (format!("RET {}", CC[y]), 1),
+            4 => (format!("LDH ({}),A", high(n)), 2),
+            5 => (format!("ADD SP,${:02X}", n), 2),
+            6 => (format!("LDH A,({})", high(n)), 2),
+            _ => (format!("LD HL,SP+${:02X}", n), 2),
+        },
+        (3, 1) => {
+            if q == 0 {
+                (format!("POP {}", RP2[p]), 1)
+            } else {
+                (String::from(["RET", "RETI", "JP HL", "LD SP,HL"][p]), 1)
+            }
+        }
+        (3, 2) => match y {
+            0..=3 => (format!("JP {},{}", CC[y], at(nn)), 3),
+            4 => (String::from("LD (C),A"), 1),
+            5 => (format!("LD ({}),A", at(nn)), 3),
+            6 => (String::from("LD A,(C)"), 1),
+            _ => (format!("LD A,({})", at(nn)), 3),
+        },
+        (3, 3) => match y {
+            0 => (format!("JP {}", at(nn)), 3),
+            1 => (disassemble_cb(n), 2),
+            6 => (String::from("DI"), 1),
+            7 => (String::from("EI"), 1),
+            _ => (format!("DB ${:02X}", op), 1),
+        },
+        (3, 4) => match y {
+            0..=3 => (format!("CALL {},{}", CC[y], at(nn)), 3),
+            _ => (format!("DB ${:02X}", op), 1),
+        },
+        (3, 5) => {
+            if q == 0 {
+                (format!("PUSH {}", RP2[p]), 1)
+            } else if p == 0 {
+                (format!("CALL {}", at(nn)), 3)
+            } else {
+                (format!("DB ${:02X}", op), 1)
+            }
+        }
+        (3, 6) => (format!("{}${:02X}", ALU[y], n), 2),
+        _ => (format!("RST ${:02X}", y * 8), 1),
+    }
+}
+
+// Extended bit operations, the byte following the 0xcb prefix.
+fn disassemble_cb(op: u8) -> String {
+    let y = ((op >> 3) & 0x07) as usize;
+    let r = R8[(op & 0x07) as usize];
+    match op >> 6 {
+        0 => format!("{} {}", ROT[y], r),
+        1 => format!("BIT {},{}", y, r),
+        2 => format!("RES {},{}", y, r),
+        _ => format!("SET {},{}", y, r),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_encodings() {
+        let cases: [(&[u8], &str, u8); 20] = [
+            (&[0x00], "NOP", 1),
+            (&[0x7e], "LD A,(HL)", 1),
+            (&[0x76], "HALT", 1),
+            (&[0x3e, 0x12], "LD A,$12", 2),
+            (&[0x21, 0x34, 0x12], "LD HL,$1234", 3),
+            (&[0x08, 0x00, 0xc0], "LD ($C000),SP", 3),
+            (&[0x2a], "LD A,(HL+)", 1),
+            (&[0xc2, 0x34, 0x12], "JP NZ,$1234", 3),
+            (&[0xcd, 0x50, 0x01], "CALL $0150", 3),
+            (&[0xd8], "RET C", 1),
+            (&[0xf1], "POP AF", 1),
+            (&[0xe0, 0x40], "LDH ($40),A", 2),
+            (&[0xfa, 0x00, 0xd0], "LD A,($D000)", 3),
+            (&[0xfe, 0x90], "CP $90", 2),
+            (&[0x9e], "SBC A,(HL)", 1),
+            (&[0xff], "RST $38", 1),
+            (&[0xcb, 0x37], "SWAP A", 2),
+            (&[0xcb, 0x7e], "BIT 7,(HL)", 2),
+            (&[0xd3], "DB $D3", 1),
+            (&[0xdb], "DB $DB", 1),
+        ];
+        for &(bytes, text, len) in &cases {
+            assert_eq!(disassemble(bytes, 0), (String::from(text), len), "{:02x?}", bytes);
+        }
+    }
+
+    #[test]
+    fn relative_jumps_show_the_target() {
+        let mut mem = vec![0x00; 0x0200];
+        mem[0x0150..0x0152].copy_from_slice(&[0x18, 0xfe]);
+        mem[0x0152..0x0154].copy_from_slice(&[0x20, 0x10]);
+        assert_eq!(disassemble(&mem, 0x0150), (String::from("JR $0150"), 2));
+        assert_eq!(disassemble(&mem, 0x0152), (String::from("JR NZ,$0164"), 2));
+        // Operands past the end read as zero.
+        assert_eq!(disassemble(&[0xc3], 0), (String::from("JP $0000"), 3));
+    }
+
+    #[test]
+    fn symbols_label_the_operands_of_the_mapped_bank() {
+        let mut d = Disassembler::new();
+        let sym = "; File generated by rgblink\n01:4123 ResetHandler\n02:4123 Bank2Handler\n00:0150 Main\n\
+            00:FF40 rLCDC\n01:D000 wBuffer\nnot a label\n";
+        assert_eq!(d.load_symbols(sym), 5);
+        let text = |d: &Disassembler, bytes: &[u8]| d.disassemble(bytes, 0).0;
+        assert_eq!(text(&d, &[0xcd, 0x23, 0x41]), "CALL ResetHandler");
+        d.rom_bank = 2;
+        assert_eq!(text(&d, &[0xcd, 0x23, 0x41]), "CALL Bank2Handler");
+        assert_eq!(text(&d, &[0xc3, 0x50, 0x01]), "JP Main");
+        assert_eq!(text(&d, &[0xe0, 0x40]), "LDH (rLCDC),A");
+        assert_eq!(text(&d, &[0xea, 0x00, 0xd0]), "LD (wBuffer),A");
+        // No label, or an immediate that isn't an address.
+        assert_eq!(text(&d, &[0xcd, 0x00, 0x20]), "CALL $2000");
+        assert_eq!(text(&d, &[0x21, 0x23, 0x41]), "LD HL,$4123");
+    }
+}
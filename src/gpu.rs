@@ -1,8 +1,12 @@
 use super::convention::Term;
 use super::intf::{Flag, Intf};
-use super::memory::Memory;
-use std::cell::RefCell;
-use std::rc::Rc;
+use super::memory::{Memory, OamBug};
+use super::state::{Reader, StateError, Stateful, Writer};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
 
 #[derive(Eq, PartialEq)]
 pub enum HdmaMode {
@@ -54,10 +58,10 @@ impl Hdma {
 impl Memory for Hdma {
     fn get(&self, a: u16) -> u8 {
         match a {
-            0xff51 => (self.src >> 8) as u8,
-            0xff52 => self.src as u8,
-            0xff43 => (self.dst >> 8) as u8,
-            0xff54 => self.dst as u8,
+            // The source and destination registers are write only.
+            0xff51..=0xff54 => 0xff,
+            // Bit 7 reads as 0 while a transfer is active and 1 once it has completed or was cancelled, the lower 7
+            // bits hold the remaining length divided by 10h, minus 1.
             0xff55 => self.remain | if self.active { 0x00 } else { 0x80 },
             _ => panic!(""),
         }
@@ -165,11 +169,15 @@ pub struct Stat {
     //    2: During Searching OAM
     //    3: During Transferring Data to LCD Driver
     mode: u8,
+    // The interrupt sources are ORed into a single STAT interrupt line, and the interrupt is only requested when the
+    // line goes from low to high. While one source holds the line high, any other source becoming active is blocked.
+    line: bool,
 }
 
 impl Stat {
     pub fn power_up() -> Self {
         Self {
+            line: false,
             enable_ly_interrupt: false,
             enable_m2_interrupt: false,
             enable_m1_interrupt: false,
@@ -212,6 +220,44 @@ impl Bgpi {
     }
 }
 
+// The CGB palette memory holds 8 palettes of 4 colors each. Each color is defined by two bytes, little-endian:
+//     Bit 0-4   Red Intensity   (00-1F)
+//     Bit 5-9   Green Intensity (00-1F)
+//     Bit 10-14 Blue Intensity  (00-1F)
+// Much like VRAM, data in Palette Memory cannot be read/written during the time when the LCD Controller is reading
+// from it. (That is when the STAT register indicates Mode 3).
+struct PaletteRam {
+    i: Bgpi,
+    ram: [u8; 64],
+}
+
+impl PaletteRam {
+    fn power_up(v: u8) -> Self {
+        Self {
+            i: Bgpi::power_up(),
+            ram: [v; 64],
+        }
+    }
+
+    fn get(&self) -> u8 {
+        self.ram[self.i.i as usize]
+    }
+
+    fn set(&mut self, v: u8) {
+        self.ram[self.i.i as usize] = v;
+        if self.i.auto_increment {
+            self.i.i = (self.i.i + 1) & 0x3f;
+        }
+    }
+
+    // Red, green and blue intensity (00-1F) of a color.
+    fn rgb(&self, palette: usize, color: usize) -> (u8, u8, u8) {
+        let i = palette * 8 + color * 2;
+        let c = u16::from_le_bytes([self.ram[i], self.ram[i + 1]]);
+        ((c & 0x1f) as u8, ((c >> 5) & 0x1f) as u8, ((c >> 10) & 0x1f) as u8)
+    }
+}
+
 pub enum GrayShades {
     White = 0xff,
     Light = 0xc0,
@@ -219,6 +265,163 @@ pub enum GrayShades {
     Black = 0x00,
 }
 
+// The colors a monochrome gameboy shows for the four shades selected through BGP, OBP0 and OBP1, from the lightest
+// (shade 0) to the darkest (shade 3). Colors are RGBA, the framebuffer always has an alpha of FFh. Also the four colors
+// of a palette of the GameBoy Color, see Gpu::bg_palette.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Palette {
+    pub colors: [[u8; 4]; 4],
+}
+
+impl Palette {
+    pub const GRAYSCALE: Palette = Palette {
+        colors: [
+            [0xff, 0xff, 0xff, 0xff],
+            [0xc0, 0xc0, 0xc0, 0xff],
+            [0x60, 0x60, 0x60, 0xff],
+            [0x00, 0x00, 0x00, 0xff],
+        ],
+    };
+
+    // The green tinted screen of the original gameboy.
+    pub const GREEN: Palette = Palette {
+        colors: [
+            [0x9b, 0xbc, 0x0f, 0xff],
+            [0x8b, 0xac, 0x0f, 0xff],
+            [0x30, 0x62, 0x30, 0xff],
+            [0x0f, 0x38, 0x0f, 0xff],
+        ],
+    };
+
+    // The slightly olive grays of the gameboy pocket.
+    pub const POCKET: Palette = Palette {
+        colors: [
+            [0xc4, 0xcf, 0xa1, 0xff],
+            [0x8b, 0x95, 0x6d, 0xff],
+            [0x4d, 0x53, 0x3c, 0xff],
+            [0x1f, 0x1f, 0x1f, 0xff],
+        ],
+    };
+
+    // Palette 0 of the Super GameBoy until the game sends its own, warm shades that go with the default border.
+    pub const SUPER: Palette = Palette {
+        colors: [
+            [0xf8, 0xe8, 0xc8, 0xff],
+            [0xd8, 0x90, 0x48, 0xff],
+            [0xa8, 0x28, 0x20, 0xff],
+            [0x30, 0x18, 0x50, 0xff],
+        ],
+    };
+
+    // The screen of each model: the green tint of the original gameboy, the neutral grays of the pocket and the
+    // default palette of the Super GameBoy. The GameBoy Color doesn't use it, its colors come from palette memory.
+    pub fn for_term(term: Term) -> Self {
+        match term {
+            Term::GB => Palette::GREEN,
+            Term::SGB => Palette::SUPER,
+            Term::GBP | Term::GBC => Palette::GRAYSCALE,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::GRAYSCALE
+    }
+}
+
+// How the 15 bit colors of the GameBoy Color are turned into the 24 bit colors of the framebuffer.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ColorCorrection {
+    // Each 5 bit channel is scaled to 8 bits on its own, vivid but oversaturated on modern displays.
+    None,
+    // Channels are mixed to mimic the washed out colors of the CGB screen, see set_rgb.
+    #[default]
+    Cgb,
+}
+
+// Packing of the pixels returned by Gpu::framebuffer.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OutputFormat {
+    // 3 bytes (R, G, B) per pixel.
+    Rgb888,
+    // 4 bytes (R, G, B, A) per pixel, A is always FFh.
+    #[default]
+    Rgba8888,
+    // 1 byte per pixel before any coloring, for hosts applying the palettes themselves. On monochrome models it is the
+    // shade (0-3) selected by BGP, OBP0 or OBP1, see set_dmg_palette for the colors. On the GameBoy Color bits 1-0
+    // hold the color number, bits 4-2 the palette number and bit 5 is set for the object palettes, so the color is
+    // entry (bits 4-0) of the background or object palette memory.
+    Indexed,
+}
+
+impl OutputFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            OutputFormat::Rgb888 => 3,
+            OutputFormat::Rgba8888 => 4,
+            OutputFormat::Indexed => 1,
+        }
+    }
+}
+
+// The layers making up the picture, see Gpu::set_layer_enabled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Layer {
+    Background,
+    Window,
+    Sprites,
+}
+
+// The two 32x32 background maps in VRAM, either of them can be used by the background and the window.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TileMap {
+    Map9800,
+    Map9c00,
+}
+
+// A decoded OAM entry. Y and X are stored as in OAM, that is the screen position plus 16 and 8. The VRAM bank and
+// the CGB palette are only used on the GameBoy Color, but are kept on the monochrome models too.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Sprite {
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub priority: bool,
+    pub yflip: bool,
+    pub xflip: bool,
+    pub palette: u8,
+    pub bank: bool,
+    pub cgb_palette: u8,
+}
+
+impl Sprite {
+    fn from_oam(b: &[u8]) -> Self {
+        let attr = Attr::from(b[3]);
+        Self {
+            y: b[0],
+            x: b[1],
+            tile: b[2],
+            priority: attr.priority,
+            yflip: attr.yflip,
+            xflip: attr.xflip,
+            palette: attr.palette_number_0 as u8,
+            bank: attr.bank,
+            cgb_palette: attr.palette_number_1 as u8,
+        }
+    }
+
+    fn to_oam(self) -> [u8; 4] {
+        let attr = (u8::from(self.priority) << 7)
+            | (u8::from(self.yflip) << 6)
+            | (u8::from(self.xflip) << 5)
+            | ((self.palette & 0x01) << 4)
+            | (u8::from(self.bank) << 3)
+            | (self.cgb_palette & 0x07);
+        [self.y, self.x, self.tile, attr]
+    }
+}
+
 // Bit7   OBJ-to-BG Priority (0=OBJ Above BG, 1=OBJ Behind BG color 1-3)
 //     (Used for both BG and Window. BG color 0 is always behind OBJ)
 // Bit6   Y flip          (0=Normal, 1=Vertically mirrored)
@@ -241,16 +444,78 @@ impl From<u8> for Attr {
             priority: u & (1 << 7) != 0,
             yflip: u & (1 << 6) != 0,
             xflip: u & (1 << 5) != 0,
-            palette_number_0: u as usize & (1 << 4),
+            palette_number_0: (u as usize >> 4) & 0x01,
             bank: u & (1 << 3) != 0,
             palette_number_1: u as usize & 0x07,
         }
     }
 }
 
+// State of the pixel pipeline during mode 3. The fetcher fills the background FIFO 8 pixels at a time, each dot one
+// pixel is shifted out of it and mixed with the pixel at the head of the sprite FIFO on its way to the LCD.
+#[derive(Clone, Copy)]
+struct Fifo {
+    // Color number and CGB attributes of the background pixels, the next one at index 8 - bg_len.
+    bg: [(u8, u8); 8],
+    bg_len: u8,
+    // Color number, attributes and OAM index of the sprite pixels, the next one first. Color 0 is an empty slot.
+    obj: [(u8, u8, u8); 8],
+    // Fetcher step: 0 tile number, 1 low byte, 2 high byte, 3 push.
+    step: u8,
+    ticks: u8,
+    tile_x: u8,
+    tile: u8,
+    attr: u8,
+    lo: u8,
+    hi: u8,
+    // Whether the fetcher switched to the window on this line.
+    window: bool,
+    first: bool,
+    // Pixels sent to the LCD so far on this line.
+    lx: u8,
+    // Pixels still to be dropped, for the fine scroll or a window starting left of the screen.
+    discard: u8,
+    // OAM X coordinate, OAM index and OAM entry of the sprites selected for this line, in the order they are fetched.
+    sprites: [(u8, u8, [u8; 4]); 40],
+    sprite_len: u8,
+    sprite_next: u8,
+    // Dots left before the sprite fetch in progress completes.
+    obj_ticks: u8,
+}
+
+impl Default for Fifo {
+    fn default() -> Self {
+        Self {
+            bg: [(0, 0); 8],
+            bg_len: 0,
+            obj: [(0, 0, 0); 8],
+            step: 0,
+            ticks: 0,
+            tile_x: 0,
+            tile: 0,
+            attr: 0,
+            lo: 0,
+            hi: 0,
+            window: false,
+            first: false,
+            lx: 0,
+            discard: 0,
+            sprites: [(0x00, 0x00, [0x00; 4]); 40],
+            sprite_len: 0,
+            sprite_next: 0,
+            obj_ticks: 0,
+        }
+    }
+}
+
+// Sprites selected per line by the hardware, see Gpu::set_sprite_limit.
+const SPRITE_LIMIT: u8 = 10;
+
 pub const SCREEN_W: usize = 160;
 pub const SCREEN_H: usize = 144;
 
+type LineCallback = Box<dyn FnMut(u8, &[u8])>;
+
 pub struct Gpu {
     // Digital image with mode RGB. Size = 144 * 160 * 3.
     // 3---------
@@ -259,10 +524,37 @@ pub struct Gpu {
     // ---------- 160
     //        144
     pub data: [[[u8; 3]; SCREEN_W]; SCREEN_H],
+    // The same pixels as palette indices, see OutputFormat::Indexed.
+    index: [[u8; SCREEN_W]; SCREEN_H],
+    // The same pixels with an alpha channel, only kept up to date in the Rgba8888 format.
+    rgba: Vec<u8>,
+    format: OutputFormat,
     pub intf: Rc<RefCell<Intf>>,
     pub term: Term,
     pub h_blank: bool,
     pub v_blank: bool,
+    // Set when the LCD enters VBlank at the end of a frame. Unlike v_blank it isn't set by turning the LCD off, and it
+    // isn't part of the save state.
+    pub frame_ready: bool,
+    // Cleared to skip writing the pixels, the frame is still fetched and timed as usual so the STAT modes, interrupts
+    // and VRAM access stay exact. The framebuffer keeps the last frame drawn. Not part of the save state.
+    pub render: bool,
+    // Layers drawn, indexed by Layer. Not part of the save state.
+    layers: [bool; 3],
+    // Weight of the previous frame in the output out of 256, and the colors of that frame before blending, empty while
+    // motion blur is off. Not part of the save state.
+    blur: u16,
+    prev: Vec<[u8; 3]>,
+    // Brightness and contrast of the output, the level each channel value becomes when they aren't 1 and the
+    // framebuffer adjusted to them. The emulated framebuffer is kept as is for the save state. Not part of the save
+    // state.
+    brightness: f32,
+    contrast: f32,
+    levels: Option<Box<[u8; 256]>>,
+    shown: Vec<u8>,
+    on_scanline: Option<LineCallback>,
+    // Times each mode was entered since power up, indexed by mode. Not part of the save state.
+    pub mode_entries: [u64; 4],
 
     lcdc: Lcdc,
     stat: Stat,
@@ -275,6 +567,13 @@ pub struct Gpu {
     // Window Y Position (R/W), Window X Position minus 7 (R/W)
     wy: u8,
     wx: u8,
+    // The window keeps an internal line counter, which is only incremented on scanlines where the window was actually
+    // drawn. Hiding the window mid-frame therefore resumes it at the following line of the window, not at LY - WY,
+    // and a window first enabled partway down the screen starts with its top line.
+    wly: u8,
+    // Whether LY has matched WY during this frame. The window only shows up once the comparison succeeded, moving WY
+    // above LY later in the frame doesn't make it appear before the next frame.
+    wy_hit: bool,
     // The LY indicates the vertical line to which the present data is transferred to the LCD Driver. The LY can take
     // on any value between 0 through 153. The values between 144 and 153 indicate the V-Blank period. Writing will
     // reset the counter.
@@ -292,22 +591,15 @@ pub struct Gpu {
     // two bits aren't used because sprite data 00 is transparent.
     op1: u8,
 
-    cbgpi: Bgpi,
-    // This register allows to read/write data to the CGBs Background Palette Memory, addressed through Register FF68.
-    // Each color is defined by two bytes (Bit 0-7 in first byte).
-    //     Bit 0-4   Red Intensity   (00-1F)
-    //     Bit 5-9   Green Intensity (00-1F)
-    //     Bit 10-14 Blue Intensity  (00-1F)
-    // Much like VRAM, data in Palette Memory cannot be read/written during the time when the LCD Controller is
-    // reading from it. (That is when the STAT register indicates Mode 3). Note: All background colors are initialized
-    // as white by the boot ROM, but it's a good idea to initialize at least one color yourself (for example if you
-    // include a soft-reset mechanic).
-    //
-    // Note: Type [[[u8; 3]; 4]; 8] equals with [u8; 64].
-    cbgpd: [[[u8; 3]; 4]; 8],
-
-    cobpi: Bgpi,
-    cobpd: [[[u8; 3]; 4]; 8],
+    // Background palette memory, addressed through FF68/FF69.
+    cbgp: PaletteRam,
+    // Sprite palette memory, addressed through FF6A/FF6B. It works exactly as the background one, except that color 0
+    // of each palette is never shown because sprite data 00 is transparent.
+    cobp: PaletteRam,
+    // FF6C - OPRI - Object Priority Mode (R/W) ** CGB Mode Only **
+    // Bit 0 selects how overlapping sprites are prioritized (0=By OAM index, 1=By X coordinate, as on monochrome
+    // models). The CGB boot ROM sets it for monochrome games.
+    opri: bool,
 
     ram: [u8; 0x4000],
     ram_bank: usize,
@@ -342,21 +634,44 @@ pub struct Gpu {
     // Bit2-0 Palette number  **CGB Mode Only**     (OBP0-7)
     oam: [u8; 0xa0],
 
-    prio: [(bool, usize); SCREEN_W],
+    dmg_palette: Palette,
+    color_correction: ColorCorrection,
     // The LCD controller operates on a 222 Hz = 4.194 MHz dot clock. An entire frame is 154 scanlines, 70224 dots, or
     // 16.74 ms. On scanlines 0 through 143, the LCD controller cycles through modes 2, 3, and 0 once every 456 dots.
     // Scanlines 144 through 153 are mode 1.
     dots: u32,
+    // Set while the first line after turning the LCD on hasn't reached mode 3.
+    lcd_start: bool,
+    // Sprites selected so far by the OAM scan of mode 2, in OAM order, see scan_oam.
+    scan: [(u8, u8, [u8; 4]); 40],
+    scan_len: u8,
+    // Most sprites the scan selects per line. Not part of the save state.
+    sprite_limit: u8,
+    fifo: Fifo,
 }
 
 impl Gpu {
     pub fn power_up(term: Term, intf: Rc<RefCell<Intf>>) -> Self {
         Self {
             data: [[[0xffu8; 3]; SCREEN_W]; SCREEN_H],
+            index: [[0x00; SCREEN_W]; SCREEN_H],
+            rgba: vec![0xff; SCREEN_W * SCREEN_H * 4],
+            format: OutputFormat::Rgba8888,
             intf,
             term,
             h_blank: false,
             v_blank: false,
+            frame_ready: false,
+            render: true,
+            layers: [true; 3],
+            blur: 0,
+            brightness: 1.0,
+            contrast: 1.0,
+            levels: None,
+            shown: Vec::new(),
+            prev: Vec::new(),
+            on_scanline: None,
+            mode_entries: [0; 4],
 
             lcdc: Lcdc::power_up(),
             stat: Stat::power_up(),
@@ -364,21 +679,320 @@ impl Gpu {
             sx: 0x00,
             wx: 0x00,
             wy: 0x00,
+            wly: 0x00,
+            wy_hit: false,
             ly: 0x00,
             lc: 0x00,
             bgp: 0x00,
             op0: 0x00,
             op1: 0x01,
-            cbgpi: Bgpi::power_up(),
-            cbgpd: [[[0u8; 3]; 4]; 8],
-            cobpi: Bgpi::power_up(),
-            cobpd: [[[0u8; 3]; 4]; 8],
+            // All background colors are initialized as white by the boot ROM.
+            cbgp: PaletteRam::power_up(0xff),
+            cobp: PaletteRam::power_up(0x00),
+            opri: false,
             ram: [0x00; 0x4000],
             ram_bank: 0x00,
             oam: [0x00; 0xa0],
-            prio: [(true, 0); SCREEN_W],
+            dmg_palette: Palette::for_term(term),
+            color_correction: ColorCorrection::Cgb,
             dots: 0,
+            lcd_start: false,
+            scan: [(0x00, 0x00, [0x00; 4]); 40],
+            scan_len: 0,
+            sprite_limit: SPRITE_LIMIT,
+            fifo: Fifo::default(),
+        }
+    }
+
+    // The frame in row-major order, SCREEN_W * SCREEN_H pixels packed as selected by set_output_format: 4 bytes of
+    // RGBA per pixel by default, on every model. Pixels are written one by one during mode 3, so during rendering the
+    // lower part still contains the previous frame.
+    pub fn framebuffer(&self) -> &[u8] {
+        match self.format {
+            OutputFormat::Rgb888 | OutputFormat::Rgba8888 if self.levels.is_some() => &self.shown,
+            OutputFormat::Rgb888 => self.data.as_flattened().as_flattened(),
+            OutputFormat::Rgba8888 => &self.rgba,
+            OutputFormat::Indexed => self.index.as_flattened(),
+        }
+    }
+
+    // 64 bit FNV-1a hash of the framebuffer, the same on every platform and from one run to the next, to compare frames
+    // with golden images. It depends on the output format.
+    pub fn framebuffer_hash(&self) -> u64 {
+        self.framebuffer().iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+            (h ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+
+    // Number of pixels differing from another framebuffer packed the same way. Pixels missing from a shorter one count
+    // as different.
+    pub fn framebuffer_diff(&self, other: &[u8]) -> usize {
+        let n = self.format.bytes_per_pixel();
+        let fb = self.framebuffer();
+        let same = fb.chunks(n).zip(other.chunks(n)).filter(|(a, b)| a == b).count();
+        fb.len().max(other.len()).div_ceil(n) - same
+    }
+
+    // Call back at the end of every visible line, when mode 3 is over, with LY and the 160 pixels of the line packed as
+    // in the framebuffer. Lines are reported in order from 0 to 143, none during VBlank or while the LCD is off, and
+    // none in the frames skipped by the renderer. The callback runs in the middle of a cpu instruction, it can't reach
+    // the memory through the Mmunit. Replaces the previous callback.
+    pub fn on_scanline(&mut self, callback: impl FnMut(u8, &[u8]) + 'static) {
+        self.on_scanline = Some(Box::new(callback));
+    }
+
+    fn enter_mode(&mut self, mode: u8) {
+        if self.stat.mode != mode {
+            self.mode_entries[usize::from(mode)] += 1;
+        }
+        self.stat.mode = mode;
+    }
+
+    fn line_done(&mut self) {
+        if !self.render {
+            return;
+        }
+        if let Some(mut callback) = self.on_scanline.take() {
+            let n = self.framebuffer().len() / SCREEN_H;
+            let y = self.ly as usize;
+            callback(self.ly, &self.framebuffer()[y * n..(y + 1) * n]);
+            self.on_scanline = Some(callback);
+        }
+    }
+
+    // Every format is produced from the same pixels, switching takes effect immediately on the whole frame.
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.format = format;
+        self.rgba.clear();
+        if format == OutputFormat::Rgba8888 {
+            let pixels = self.data.as_flattened().iter();
+            self.rgba.extend(pixels.flat_map(|c| [c[0], c[1], c[2], 0xff]));
+        }
+        self.refresh_shown();
+    }
+
+    // Write the pixel x of the current line in every format in use.
+    fn put(&mut self, x: usize, index: u8, c: [u8; 3]) {
+        let y = self.ly as usize;
+        let c = if self.blur == 0 {
+            c
+        } else {
+            let i = y * SCREEN_W + x;
+            let prev = core::mem::replace(&mut self.prev[i], c);
+            let w = self.blur;
+            let mix = |a: u8, b: u8| ((u16::from(a) * (256 - w) + u16::from(b) * w) >> 8) as u8;
+            [mix(c[0], prev[0]), mix(c[1], prev[1]), mix(c[2], prev[2])]
+        };
+        self.data[y][x] = c;
+        self.index[y][x] = index;
+        if self.format == OutputFormat::Rgba8888 {
+            let i = (y * SCREEN_W + x) * 4;
+            self.rgba[i..i + 4].copy_from_slice(&[c[0], c[1], c[2], 0xff]);
+        }
+        if let Some(l) = self.levels.as_ref() {
+            let i = (y * SCREEN_W + x) * self.format.bytes_per_pixel();
+            if let Some(p) = self.shown.get_mut(i..i + 3) {
+                p.copy_from_slice(&[l[usize::from(c[0])], l[usize::from(c[1])], l[usize::from(c[2])]]);
+            }
+        }
+    }
+
+    // Colors used for the four shades in monochrome mode. Only the final output is recolored, BGP, OBP0 and OBP1 still
+    // select the shade of each color number. Defaults to Palette::for_term. Has no effect on the GameBoy Color.
+    pub fn set_dmg_palette(&mut self, palette: Palette) {
+        self.dmg_palette = palette;
+    }
+
+    // Colors used on the GameBoy Color, Cgb by default. Like the DMG palette this only affects the output, pixels
+    // already in the framebuffer keep their colors until they are drawn again.
+    pub fn set_color_correction(&mut self, mode: ColorCorrection) {
+        self.color_correction = mode;
+    }
+
+    // Hide a layer from the output, for debugging or for players bothered by a flashing layer. This overrides LCDC
+    // without changing it: the game still reads the value it wrote and the timing of mode 3 stays the same. The pixels
+    // of a hidden background or window are drawn as color 0 of the first background palette, hidden sprites let the
+    // background through.
+    pub fn set_layer_enabled(&mut self, layer: Layer, enabled: bool) {
+        self.layers[layer as usize] = enabled;
+    }
+
+    pub fn layer_enabled(&self, layer: Layer) -> bool {
+        self.layers[layer as usize]
+    }
+
+    // Select up to limit sprites per line instead of the ten of the hardware, None selects every sprite on the line so
+    // games that flicker sprites to get around the limit show them all. The priority rules are the same whatever the
+    // number of sprites. Past the tenth sprite of a line the sprites don't stall the LCD, so mode 3 is never longer
+    // than it can be on the hardware. This is an enhancement that breaks accuracy, the default is Some(10).
+    pub fn set_sprite_limit(&mut self, limit: Option<u8>) {
+        self.sprite_limit = limit.unwrap_or(40).min(40);
+    }
+
+    // Whether the PPU keeps the cpu off an address: VRAM while a line is drawn in mode 3, OAM while it is scanned in
+    // mode 2 and read for the sprites in mode 3. The cpu then reads FFh and its writes are lost, which is why games
+    // update them in HBlank and VBlank. With the LCD off both are always accessible.
+    pub fn blocks_cpu(&self, a: u16) -> bool {
+        match a {
+            0x8000..=0x9fff => self.stat.mode == 3,
+            0xfe00..=0xfe9f => self.stat.mode >= 2,
+            _ => false,
+        }
+    }
+
+    // Dots elapsed in the current line, 0 to 455.
+    pub fn dots(&self) -> u32 {
+        self.dots
+    }
+
+    // The VRAM bank mapped at 8000-9FFF, always 0 on the monochrome models.
+    pub fn vram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    // Overwrite both VRAM banks, byte after byte, with the values returned by next.
+    pub fn fill_vram(&mut self, mut next: impl FnMut() -> u8) {
+        self.ram.iter_mut().for_each(|b| *b = next());
+    }
+
+    // Overwrite OAM, byte after byte, with the values returned by next.
+    pub fn fill_oam(&mut self, mut next: impl FnMut() -> u8) {
+        self.oam.iter_mut().for_each(|b| *b = next());
+    }
+
+    // Mix every pixel with the same pixel of the previous frame, which takes factor of the result, 0 turns it off. The
+    // LCD of the monochrome models is slow to change, so sprites flickered every other frame to look transparent are
+    // seen as a steady blend and moving objects leave a trail. 0.5 is about as strong as the original LCD. Only the RGB
+    // formats are blended, the indexed one keeps the palette indices drawn, and the mixing is left out of the
+    // emulation: the next frame is blended with the previous one as drawn, not as shown.
+    pub fn set_motion_blur(&mut self, factor: f32) {
+        self.blur = (factor.clamp(0.0, 0.9) * 256.0) as u16;
+        if self.blur == 0 {
+            self.prev = Vec::new();
+        } else if self.prev.is_empty() {
+            self.prev = self.data.as_flattened().to_vec();
+        }
+    }
+
+    // Adjust the output as the brightness and contrast controls of a display: every RGB channel v becomes
+    // ((v - 128) * contrast + 128) * brightness, clamped to 0-255. Both default to 1.0, which leaves the colors as they
+    // are. The framebuffer is adjusted at once, the indexed format is left alone. The emulation and the save state see
+    // the colors before the adjustment.
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = brightness;
+        self.update_levels();
+    }
+
+    pub fn set_contrast(&mut self, contrast: f32) {
+        self.contrast = contrast;
+        self.update_levels();
+    }
+
+    fn update_levels(&mut self) {
+        if self.brightness == 1.0 && self.contrast == 1.0 {
+            self.levels = None;
+            return;
+        }
+        let mut levels = [0x00; 256];
+        for (v, l) in levels.iter_mut().enumerate() {
+            let x = ((v as f32 - 128.0) * self.contrast + 128.0) * self.brightness;
+            // Rounded to the nearest, the cast truncates.
+            *l = (x + 0.5).clamp(0.0, 255.0) as u8;
         }
+        self.levels = Some(Box::new(levels));
+        self.refresh_shown();
+    }
+
+    // Adjust the whole framebuffer again, after it was replaced or repacked.
+    fn refresh_shown(&mut self) {
+        let levels = match self.levels.as_ref() {
+            Some(some) => some,
+            None => return,
+        };
+        let (raw, n) = match self.format {
+            OutputFormat::Rgb888 => (self.data.as_flattened().as_flattened(), 3),
+            OutputFormat::Rgba8888 => (&self.rgba[..], 4),
+            OutputFormat::Indexed => return,
+        };
+        let shown = raw
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| if i % n == 3 { v } else { levels[usize::from(v)] });
+        self.shown = shown.collect();
+    }
+
+    // Color numbers (0-3) of the 8x8 pixels of a tile in row-major order. Tiles are numbered 0-383 by their position
+    // in 8000-97FF, the bank (0-1) selects the VRAM bank on the GameBoy Color.
+    pub fn dump_tile(&self, index: u16, bank: u8) -> [u8; 64] {
+        assert!(index < 384);
+        let base = usize::from(bank & 0x01) * 0x2000 + usize::from(index) * 16;
+        let mut r = [0x00; 64];
+        for y in 0..8 {
+            let lo = self.ram[base + y * 2];
+            let hi = self.ram[base + y * 2 + 1];
+            for x in 0..8 {
+                let bit = 7 - x;
+                r[y * 8 + x] = (((hi >> bit) & 0x01) << 1) | ((lo >> bit) & 0x01);
+            }
+        }
+        r
+    }
+
+    // One of the 8 background palettes of the GameBoy Color, as the framebuffer shows it: converted from palette
+    // memory with the current color correction, with an alpha of FFh. None past palette 7. The palette memory is read
+    // as is, even in mode 3 when the cpu can't access it.
+    pub fn bg_palette(&self, index: usize) -> Option<Palette> {
+        self.cgb_palette(&self.cbgp, index)
+    }
+
+    // Same as bg_palette for the sprite palettes, color 0 is transparent and never shown.
+    pub fn obj_palette(&self, index: usize) -> Option<Palette> {
+        self.cgb_palette(&self.cobp, index)
+    }
+
+    fn cgb_palette(&self, ram: &PaletteRam, index: usize) -> Option<Palette> {
+        if index >= 8 {
+            return None;
+        }
+        let mut r = Palette { colors: [[0xff; 4]; 4] };
+        for (color, c) in r.colors.iter_mut().enumerate() {
+            let (red, green, blue) = ram.rgb(index, color);
+            c[..3].copy_from_slice(&self.cgb_color(red, green, blue));
+        }
+        Some(r)
+    }
+
+    // The 40 entries of OAM.
+    pub fn sprites(&self) -> [Sprite; 40] {
+        let mut r = [Sprite::default(); 40];
+        for (s, b) in r.iter_mut().zip(self.oam.chunks(4)) {
+            *s = Sprite::from_oam(b);
+        }
+        r
+    }
+
+    pub fn set_sprite(&mut self, index: usize, sprite: Sprite) {
+        self.oam[index * 4..index * 4 + 4].copy_from_slice(&sprite.to_oam());
+    }
+
+    // Tiles of a background map in row-major order, numbered as in dump_tile. The tile numbers of the map are
+    // resolved with the addressing mode currently selected by LCDC bit 4: in 8800 mode they are signed and relative
+    // to 9000.
+    pub fn dump_tilemap(&self, map: TileMap) -> Vec<u16> {
+        let base = match map {
+            TileMap::Map9800 => 0x9800,
+            TileMap::Map9c00 => 0x9c00,
+        };
+        (0..0x400)
+            .map(|i| {
+                let n = self.get_ram0(base + i);
+                if self.lcdc.bit4() {
+                    u16::from(n)
+                } else {
+                    (0x100 + i16::from(n as i8)) as u16
+                }
+            })
+            .collect()
     }
 
     fn get_ram0(&self, a: u16) -> u8 {
@@ -399,18 +1013,14 @@ impl Gpu {
     // 1  Light gray
     // 2  Dark gray
     // 3  Black
-    fn get_gray_shades(v: u8, i: usize) -> GrayShades {
-        match v >> (2 * i) & 0x03 {
-            0x00 => GrayShades::White,
-            0x01 => GrayShades::Light,
-            0x02 => GrayShades::Dark,
-            _ => GrayShades::Black,
-        }
+    fn get_gray_shades(v: u8, i: usize) -> usize {
+        (v >> (2 * i) & 0x03) as usize
     }
 
-    // Grey scale.
-    fn set_gre(&mut self, x: usize, g: u8) {
-        self.data[self.ly as usize][x] = [g, g, g];
+    // Monochrome shade, recolored through the DMG palette.
+    fn set_gre(&mut self, x: usize, shade: usize) {
+        let c = self.dmg_palette.colors[shade];
+        self.put(x, shade as u8, [c[0], c[1], c[2]]);
     }
 
     // When developing graphics on PCs, note that the RGB values will have different appearance on CGB displays as on
@@ -421,17 +1031,49 @@ impl Gpu {
     // intensity of only one R,G,B color will also influence the other two R,G,B colors. For example, a color setting
     // of 03EFh (Blue=0, Green=1Fh, Red=0Fh) will appear as Neon Green on VGA displays, but on the CGB it'll produce a
     // decently washed out Yellow. See image on the right.
-    fn set_rgb(&mut self, x: usize, r: u8, g: u8, b: u8) {
+    fn set_rgb(&mut self, x: usize, index: u8, r: u8, g: u8, b: u8) {
+        let c = self.cgb_color(r, g, b);
+        self.put(x, index, c);
+    }
+
+    fn cgb_color(&self, r: u8, g: u8, b: u8) -> [u8; 3] {
         assert!(r <= 0x1f);
         assert!(g <= 0x1f);
         assert!(b <= 0x1f);
+        if self.color_correction == ColorCorrection::None {
+            return [(r << 3) | (r >> 2), (g << 3) | (g >> 2), (b << 3) | (b >> 2)];
+        }
         let r = u32::from(r);
         let g = u32::from(g);
         let b = u32::from(b);
         let lr = ((r * 13 + g * 2 + b) >> 1) as u8;
         let lg = ((g * 3 + b) << 1) as u8;
         let lb = ((r * 3 + g * 2 + b * 11) >> 1) as u8;
-        self.data[self.ly as usize][x] = [lr, lg, lb];
+        [lr, lg, lb]
+    }
+
+    // LY as seen by the cpu and the coincidence check, see dot for line 153.
+    fn ly_reg(&self) -> u8 {
+        if self.ly == 153 && self.dots >= 4 {
+            0
+        } else {
+            self.ly
+        }
+    }
+
+    // Re-evaluate the STAT interrupt line, requesting an interrupt on its rising edge only. The mode 0 reported by the
+    // first line after turning the LCD on isn't a HBlank and doesn't drive the line, the LY=LYC coincidence for line 0
+    // does.
+    fn update_stat(&mut self) {
+        let line = self.lcdc.bit7()
+            && ((self.stat.enable_ly_interrupt && self.ly_reg() == self.lc)
+                || (self.stat.enable_m2_interrupt && self.stat.mode == 2)
+                || (self.stat.enable_m1_interrupt && self.stat.mode == 1)
+                || (self.stat.enable_m0_interrupt && self.stat.mode == 0 && !self.lcd_start));
+        if line && !self.stat.line {
+            self.intf.borrow_mut().hi(Flag::LCDStat);
+        }
+        self.stat.line = line;
     }
 
     pub fn next(&mut self, cycles: u32) {
@@ -439,268 +1081,436 @@ impl Gpu {
             return;
         }
         self.h_blank = false;
+        for _ in 0..cycles {
+            self.dot();
+        }
+    }
 
-        // The LCD controller operates on a 222 Hz = 4.194 MHz dot clock. An entire frame is 154 scanlines, 70224 dots,
-        // or 16.74 ms. On scanlines 0 through 143, the LCD controller cycles through modes 2, 3, and 0 once every 456
-        // dots. Scanlines 144 through 153 are mode 1.
-        //
-        // 1 scanline = 456 dots
-        //
-        // The following are typical when the display is enabled:
-        // Mode 2  2_____2_____2_____2_____2_____2___________________2____
-        // Mode 3  _33____33____33____33____33____33__________________3___
-        // Mode 0  ___000___000___000___000___000___000________________000
-        // Mode 1  ____________________________________11111111111111_____
-        if cycles == 0 {
+    // On the monochrome models, putting an address of FE00-FEFF on the bus during mode 2 corrupts the OAM row the PPU
+    // is scanning. OAM is seen as 20 rows of four 16 bit words, the PPU reads a row every machine cycle. With a the
+    // first word of the current row, b and c the first and third words of the preceding row:
+    //   Write: the first word becomes ((a ^ c) & (b ^ c)) ^ c
+    //   Read:  the first word becomes b | (a & c)
+    // and the other three words are copied from the preceding row. A read that also increments the address first
+    // mixes the two rows before, unless the current row is one of the first four or the last. The first row is never
+    // corrupted.
+    // See: https://gbdev.io/pandocs/OAM_Corruption_Bug.html
+    pub fn oam_bug(&mut self, kind: OamBug) {
+        if self.term == Term::GBC || !self.lcdc.bit7() || self.stat.mode != 2 {
             return;
         }
-        let c = (cycles - 1) / 80 + 1;
-        for i in 0..c {
-            if i == (c - 1) {
-                self.dots += cycles % 80
-            } else {
-                self.dots += 80
-            }
-            let d = self.dots;
-            self.dots %= 456;
-            if d != self.dots {
-                self.ly = (self.ly + 1) % 154;
-                if self.stat.enable_ly_interrupt && self.ly == self.lc {
-                    self.intf.borrow_mut().hi(Flag::LCDStat);
-                }
-            }
-            if self.ly >= 144 {
-                if self.stat.mode == 1 {
-                    continue;
-                }
-                self.stat.mode = 1;
+        let row = (self.dots / 4) as usize;
+        if row == 0 || row >= 20 {
+            return;
+        }
+        if kind == OamBug::ReadIncrease && (4..19).contains(&row) {
+            let a = self.oam_word(row - 2, 0);
+            let b = self.oam_word(row - 1, 0);
+            let c = self.oam_word(row, 0);
+            let d = self.oam_word(row - 1, 2);
+            self.set_oam_word(row - 1, (b & (a | c | d)) | (a & c & d));
+            self.oam.copy_within((row - 1) * 8..row * 8, row * 8);
+            self.oam.copy_within((row - 1) * 8..row * 8, (row - 2) * 8);
+        }
+        let a = self.oam_word(row, 0);
+        let b = self.oam_word(row - 1, 0);
+        let c = self.oam_word(row - 1, 2);
+        let v = match kind {
+            OamBug::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamBug::Read | OamBug::ReadIncrease => b | (a & c),
+        };
+        self.set_oam_word(row, v);
+        self.oam.copy_within((row - 1) * 8 + 2..row * 8, row * 8 + 2);
+    }
+
+    fn oam_word(&self, row: usize, i: usize) -> u16 {
+        let a = row * 8 + i * 2;
+        u16::from_le_bytes([self.oam[a], self.oam[a + 1]])
+    }
+
+    // Set the first word of a row.
+    fn set_oam_word(&mut self, row: usize, v: u16) {
+        self.oam[row * 8..row * 8 + 2].copy_from_slice(&v.to_le_bytes());
+    }
+
+    // The LCD controller operates on a 222 Hz = 4.194 MHz dot clock. An entire frame is 154 scanlines, 70224 dots, or
+    // 16.74 ms. On scanlines 0 through 143, the LCD controller cycles through modes 2, 3, and 0 once every 456 dots.
+    // Scanlines 144 through 153 are mode 1.
+    //
+    // 1 scanline = 456 dots
+    //
+    // The following are typical when the display is enabled:
+    // Mode 2  2_____2_____2_____2_____2_____2___________________2____
+    // Mode 3  _33____33____33____33____33____33__________________3___
+    // Mode 0  ___000___000___000___000___000___000________________000
+    // Mode 1  ____________________________________11111111111111_____
+    //
+    // Mode 2 lasts 80 dots. Mode 3 lasts until the pixel FIFO has sent the 160 pixels of the line to the LCD, 172 to
+    // 289 dots depending on the fine scroll, the window and the sprites, and mode 0 takes the rest of the line.
+    //
+    // Line 153 is the odd one: LY reads 153 only for its first machine cycle and 0 for the rest of it, so the LY=LYC
+    // coincidence for line 0 is signalled a line early. LY stays 0 through line 0 that follows.
+    fn dot(&mut self) {
+        self.dots += 1;
+        if self.dots == 456 {
+            self.dots = 0;
+            self.ly = (self.ly + 1) % 154;
+            self.update_stat();
+        }
+        if self.ly == 153 && self.dots == 4 {
+            self.update_stat();
+        }
+        if self.ly >= 144 {
+            if self.stat.mode != 1 {
+                self.enter_mode(1);
+                self.wly = 0x00;
+                self.wy_hit = false;
                 self.v_blank = true;
+                self.frame_ready = true;
                 self.intf.borrow_mut().hi(Flag::VBlank);
-                if self.stat.enable_m1_interrupt {
-                    self.intf.borrow_mut().hi(Flag::LCDStat);
-                }
-            } else if self.dots <= 80 {
-                if self.stat.mode == 2 {
-                    continue;
-                }
-                self.stat.mode = 2;
-                if self.stat.enable_m2_interrupt {
-                    self.intf.borrow_mut().hi(Flag::LCDStat);
-                }
-            } else if self.dots <= (80 + 172) {
-                self.stat.mode = 3;
-            } else {
-                if self.stat.mode == 0 {
-                    continue;
-                }
-                self.stat.mode = 0;
-                self.h_blank = true;
-                if self.stat.enable_m0_interrupt {
-                    self.intf.borrow_mut().hi(Flag::LCDStat);
-                }
-                // Render scanline
-                if self.term == Term::GBC || self.lcdc.bit0() {
-                    self.draw_bg();
+                self.update_stat();
+            }
+            return;
+        }
+        match self.stat.mode {
+            2 if self.dots >= 80 => {
+                self.enter_mode(3);
+                self.start_line();
+                self.update_stat();
+            }
+            3 => {
+                self.fifo_dot();
+                if self.fifo.lx == SCREEN_W as u8 {
+                    if self.fifo.window {
+                        self.wly = self.wly.wrapping_add(1);
+                    }
+                    self.enter_mode(0);
+                    self.h_blank = true;
+                    self.update_stat();
+                    self.line_done();
                 }
-                if self.lcdc.bit1() {
-                    self.draw_sprites();
+            }
+            2 => self.scan_oam(),
+            // The first line after the LCD is turned on has no OAM search, it stays in mode 0 instead of mode 2. The
+            // sprites of the line are still selected, all at once when mode 3 starts 76 dots after the LCD is on.
+            0 if self.lcd_start && self.dots >= 80 => {
+                self.lcd_start = false;
+                self.wy_hit |= self.ly == self.wy;
+                self.scan_len = 0;
+                for i in 0..40 {
+                    self.scan_entry(i);
                 }
+                self.enter_mode(3);
+                self.start_line();
+                self.update_stat();
+            }
+            0 if self.lcd_start => {}
+            _ if self.dots < 80 => {
+                self.enter_mode(2);
+                // The comparison is made on every visible line, whether or not the window is enabled.
+                self.wy_hit |= self.ly == self.wy;
+                self.scan_len = 0;
+                self.scan_oam();
+                self.update_stat();
             }
+            _ => {}
+        }
+    }
+
+    // During OAM search the first ten sprites in OAM order whose Y range covers the current line are selected. The X
+    // coordinate plays no role here, a sprite far off-screen horizontally still takes a slot. One entry is checked
+    // every 2 dots of mode 2, and the entries selected are copied: the line is drawn from the copies in mode 3, so
+    // changing OAM then only shows from the next line.
+    fn scan_oam(&mut self) {
+        if self.dots & 0x01 == 0x00 {
+            self.scan_entry((self.dots / 2) as usize);
         }
     }
 
-    fn draw_bg(&mut self) {
-        let show_window = self.lcdc.bit5() && self.wy <= self.ly;
-        let tile_base = if self.lcdc.bit4() { 0x8000 } else { 0x8800 };
+    fn scan_entry(&mut self, i: usize) {
+        if i >= 40 || self.scan_len >= self.sprite_limit {
+            return;
+        }
+        let sprite_size = if self.lcdc.bit2() { 16 } else { 8 };
+        let mut entry = [0x00; 4];
+        entry.copy_from_slice(&self.oam[i * 4..i * 4 + 4]);
+        let py = i32::from(entry[0]) - 16;
+        let ly = i32::from(self.ly);
+        if ly < py || ly >= py + sprite_size {
+            return;
+        }
+        self.scan[usize::from(self.scan_len)] = (entry[1], i as u8, entry);
+        self.scan_len += 1;
+    }
 
-        let wx = self.wx.wrapping_sub(7);
-        let py = if show_window {
-            self.ly.wrapping_sub(self.wy)
-        } else {
-            self.sy.wrapping_add(self.ly)
+    // The sprites selected by the OAM scan are fetched in the order the LCD reaches them, from left to right, OAM order
+    // breaking ties.
+    fn start_line(&mut self) {
+        let mut fifo = Fifo {
+            first: true,
+            discard: self.sx & 0x07,
+            sprite_len: self.scan_len,
+            sprites: self.scan,
+            ..Fifo::default()
         };
-        let ty = (u16::from(py) >> 3) & 31;
+        fifo.sprites[..usize::from(fifo.sprite_len)].sort();
+        self.fifo = fifo;
+    }
 
-        for x in 0..SCREEN_W {
-            let px = if show_window && x as u8 >= wx {
-                x as u8 - wx
-            } else {
-                self.sx.wrapping_add(x as u8)
-            };
-            let tx = (u16::from(px) >> 3) & 31;
+    // Whether the LCD reached the next sprite to fetch.
+    fn sprite_due(&self) -> bool {
+        let next = usize::from(self.fifo.sprite_next);
+        self.lcdc.bit1()
+            && self.fifo.discard == 0
+            && next < usize::from(self.fifo.sprite_len)
+            && self.fifo.sprites[next].0 <= self.fifo.lx + 8
+    }
 
-            // Background memory base addr.
-            let bg_base = if show_window && x as u8 >= wx {
-                if self.lcdc.bit6() {
-                    0x9c00
-                } else {
-                    0x9800
-                }
-            } else if self.lcdc.bit3() {
-                0x9c00
-            } else {
-                0x9800
-            };
+    // One dot of mode 3.
+    fn fifo_dot(&mut self) {
+        // The window takes over from the background once the LCD reaches WX - 7. With WX below 7 its first pixels are
+        // dropped, WX=166 leaves only the last pixel to it. On monochrome models LCDC bit 0 disables the window too.
+        if !self.fifo.window
+            && self.fifo.discard == 0
+            && self.lcdc.bit5()
+            && (self.term == Term::GBC || self.lcdc.bit0())
+            && self.wy_hit
+            && self.wx <= 166
+            && self.fifo.lx + 7 >= self.wx
+        {
+            self.fifo.window = true;
+            self.fifo.step = 0;
+            self.fifo.ticks = 0;
+            self.fifo.tile_x = 0;
+            self.fifo.bg_len = 0;
+            self.fifo.discard = self.fifo.lx + 7 - self.wx;
+        }
 
-            // Tile data
-            // Each tile is sized 8x8 pixels and has a color depth of 4 colors/gray shades.
-            // Each tile occupies 16 bytes, where each 2 bytes represent a line:
-            // Byte 0-1  First Line (Upper 8 pixels)
-            // Byte 2-3  Next Line
-            // etc.
-            let tile_addr = bg_base + ty * 32 + tx;
-            let tile_number = self.get_ram0(tile_addr);
-            let tile_offset = if self.lcdc.bit4() {
-                i16::from(tile_number)
-            } else {
-                i16::from(tile_number as i8) + 128
-            } as u16
-                * 16;
-            let tile_location = tile_base + tile_offset;
-            let tile_attr = Attr::from(self.get_ram1(tile_addr));
-
-            let tile_y = if tile_attr.yflip { 7 - py % 8 } else { py % 8 };
-            let tile_y_data: [u8; 2] = if self.term == Term::GBC && tile_attr.bank {
-                let a = self.get_ram1(tile_location + u16::from(tile_y * 2));
-                let b = self.get_ram1(tile_location + u16::from(tile_y * 2) + 1);
-                [a, b]
+        // A sprite fetch stalls the LCD for 6 dots.
+        if self.fifo.obj_ticks != 0 {
+            self.fifo.obj_ticks -= 1;
+            if self.fifo.obj_ticks == 0 {
+                self.fetch_sprite();
+                self.fifo.sprite_next += 1;
+            }
+            return;
+        }
+        // Sprites past the hardware limit are fetched at once, see set_sprite_limit.
+        while self.fifo.sprite_next >= SPRITE_LIMIT && self.sprite_due() {
+            self.fetch_sprite();
+            self.fifo.sprite_next += 1;
+        }
+        if self.sprite_due() {
+            // The background fetch in progress has to be past the tile number and the low byte first.
+            if self.fifo.bg_len != 0 && self.fifo.step >= 2 {
+                self.fifo.obj_ticks = 5;
             } else {
-                let a = self.get_ram0(tile_location + u16::from(tile_y * 2));
-                let b = self.get_ram0(tile_location + u16::from(tile_y * 2) + 1);
-                [a, b]
-            };
-            let tile_x = if tile_attr.xflip { 7 - px % 8 } else { px % 8 };
-
-            // Palettes
-            let color_l = if tile_y_data[0] & (0x80 >> tile_x) != 0 { 1 } else { 0 };
-            let color_h = if tile_y_data[1] & (0x80 >> tile_x) != 0 { 2 } else { 0 };
-            let color = color_h | color_l;
+                self.fetcher_dot();
+            }
+            return;
+        }
 
-            // Priority
-            self.prio[x] = (tile_attr.priority, color);
+        self.fetcher_dot();
+        if self.fifo.bg_len == 0 {
+            return;
+        }
+        let bg = self.fifo.bg[usize::from(8 - self.fifo.bg_len)];
+        self.fifo.bg_len -= 1;
+        // Fine scrolling drops the first pixels of the line.
+        if self.fifo.discard != 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        let obj = self.fifo.obj[0];
+        self.fifo.obj.copy_within(1.., 0);
+        self.fifo.obj[7] = (0, 0, 0);
+        self.draw_pixel(bg, obj);
+        self.fifo.lx += 1;
+    }
 
-            if self.term == Term::GBC {
-                let r = self.cbgpd[tile_attr.palette_number_1][color][0];
-                let g = self.cbgpd[tile_attr.palette_number_1][color][1];
-                let b = self.cbgpd[tile_attr.palette_number_1][color][2];
-                self.set_rgb(x as usize, r, g, b);
-            } else {
-                let color = Self::get_gray_shades(self.bgp, color) as u8;
-                self.set_gre(x, color);
+    // The fetcher reads the tile number, then the low and the high byte of the row of the tile, each of them takes 2
+    // dots. The 8 pixels are pushed on a later dot, as soon as the background FIFO is empty.
+    fn fetcher_dot(&mut self) {
+        if self.fifo.step < 3 {
+            self.fifo.ticks += 1;
+            if self.fifo.ticks < 2 {
+                return;
+            }
+            self.fifo.ticks = 0;
+            match self.fifo.step {
+                0 => self.fetch_tile(),
+                1 => self.fifo.lo = self.fetch_row(0),
+                _ => self.fifo.hi = self.fetch_row(1),
+            }
+            self.fifo.step += 1;
+            // The first fetch of every line is thrown away, the same tile is fetched again.
+            if self.fifo.step == 3 && self.fifo.first {
+                self.fifo.first = false;
+                self.fifo.step = 0;
             }
+            return;
+        }
+        if self.fifo.bg_len != 0 {
+            return;
+        }
+        self.fifo.step = 0;
+        let xflip = Attr::from(self.fifo.attr).xflip;
+        for i in 0..8u8 {
+            let bit = if xflip { i } else { 7 - i };
+            let color = (((self.fifo.hi >> bit) & 0x01) << 1) | ((self.fifo.lo >> bit) & 0x01);
+            self.fifo.bg[usize::from(i)] = (color, self.fifo.attr);
         }
+        self.fifo.bg_len = 8;
+        self.fifo.tile_x = self.fifo.tile_x.wrapping_add(1);
     }
 
-    // Gameboy video controller can display up to 40 sprites either in 8x8 or in 8x16 pixels. Because of a limitation
-    // of hardware, only ten sprites can be displayed per scan line. Sprite patterns have the same format as BG tiles,
-    // but they are taken from the Sprite Pattern Table located at $8000-8FFF and have unsigned numbering.
-    //
-    // Sprite attributes reside in the Sprite Attribute Table (OAM - Object Attribute Memory) at $FE00-FE9F. Each of
-    // the 40 entries consists of four bytes with the following meanings:
-    //   Byte0 - Y Position
-    //   Specifies the sprites vertical position on the screen (minus 16). An off-screen value (for example, Y=0 or
-    //   Y>=160) hides the sprite.
-    //
-    //   Byte1 - X Position
-    //   Specifies the sprites horizontal position on the screen (minus 8). An off-screen value (X=0 or X>=168) hides
-    //   the sprite, but the sprite still affects the priority ordering - a better way to hide a sprite is to set its
-    //   Y-coordinate off-screen.
-    //
-    //   Byte2 - Tile/Pattern Number
-    //   Specifies the sprites Tile Number (00-FF). This (unsigned) value selects a tile from memory at 8000h-8FFFh. In
-    //   CGB Mode this could be either in VRAM Bank 0 or 1, depending on Bit 3 of the following byte. In 8x16 mode, the
-    //   lower bit of the tile number is ignored. IE: the upper 8x8 tile is "NN AND FEh", and the lower 8x8 tile is
-    //   "NN OR 01h".
-    //
-    //   Byte3 - Attributes/Flags:
-    //     Bit7   OBJ-to-BG Priority (0=OBJ Above BG, 1=OBJ Behind BG color 1-3)
-    //           (Used for both BG and Window. BG color 0 is always behind OBJ)
-    //     Bit6   Y flip          (0=Normal, 1=Vertically mirrored)
-    //     Bit5   X flip          (0=Normal, 1=Horizontally mirrored)
-    //     Bit4   Palette number  **Non CGB Mode Only** (0=OBP0, 1=OBP1)
-    //     Bit3   Tile VRAM-Bank  **CGB Mode Only**     (0=Bank 0, 1=Bank 1)
-    //     Bit2-0 Palette number  **CGB Mode Only**     (OBP0-7)
-    fn draw_sprites(&mut self) {
-        // Sprite tile size 8x8 or 8x16(2 stacked vertically).
+    // Tile number of the next 8 pixels in the background or window map. In CGB mode, VRAM bank 1 holds an attribute
+    // byte for each tile index of the map at the same address.
+    //  Bit 0-2  Background Palette number  (BGP0-7)
+    //  Bit 3    Tile VRAM Bank number      (0=Bank 0, 1=Bank 1)
+    //  Bit 5    Horizontal Flip            (0=Normal, 1=Mirror horizontally)
+    //  Bit 6    Vertical Flip              (0=Normal, 1=Mirror vertically)
+    //  Bit 7    BG-to-OAM Priority         (0=Use OAM priority bit, 1=BG Priority)
+    fn fetch_tile(&mut self) {
+        let (base, tx, py) = if self.fifo.window {
+            let base = if self.lcdc.bit6() { 0x9c00 } else { 0x9800 };
+            (base, self.fifo.tile_x, self.wly)
+        } else {
+            let base = if self.lcdc.bit3() { 0x9c00 } else { 0x9800 };
+            (
+                base,
+                (self.sx >> 3).wrapping_add(self.fifo.tile_x),
+                self.sy.wrapping_add(self.ly),
+            )
+        };
+        let a = base + (u16::from(py) >> 3) * 32 + (u16::from(tx) & 31);
+        self.fifo.tile = self.get_ram0(a);
+        self.fifo.attr = if self.term == Term::GBC { self.get_ram1(a) } else { 0x00 };
+    }
+
+    // Tile data
+    // Each tile is sized 8x8 pixels and has a color depth of 4 colors/gray shades.
+    // Each tile occupies 16 bytes, where each 2 bytes represent a line:
+    // Byte 0-1  First Line (Upper 8 pixels)
+    // Byte 2-3  Next Line
+    // etc.
+    fn fetch_row(&self, byte: u16) -> u8 {
+        let py = if self.fifo.window {
+            self.wly
+        } else {
+            self.sy.wrapping_add(self.ly)
+        };
+        let attr = Attr::from(self.fifo.attr);
+        let tile_y = if attr.yflip { 7 - py % 8 } else { py % 8 };
+        let tile_location = if self.lcdc.bit4() {
+            0x8000 + u16::from(self.fifo.tile) * 16
+        } else {
+            0x8800 + (i16::from(self.fifo.tile as i8) + 128) as u16 * 16
+        };
+        let a = tile_location + u16::from(tile_y) * 2 + byte;
+        if self.term == Term::GBC && attr.bank {
+            self.get_ram1(a)
+        } else {
+            self.get_ram0(a)
+        }
+    }
+
+    // Mix the pixels of the next sprite into the sprite FIFO. A pixel belongs to the first sprite with a
+    // non-transparent color on it, even if that sprite is hidden behind the background there. On monochrome models,
+    // and in CGB mode with OPRI set, the one with the smaller X coordinate wins and the OAM index breaks ties, which
+    // is the order sprites are fetched in. Otherwise in CGB mode only the OAM index matters.
+    fn fetch_sprite(&mut self) {
         let sprite_size = if self.lcdc.bit2() { 16 } else { 8 };
-        for i in 0..40 {
-            let sprite_addr = 0xfe00 + (i as u16) * 4;
-            let py = self.get(sprite_addr).wrapping_sub(16);
-            let px = self.get(sprite_addr + 1).wrapping_sub(8);
-            let tile_number = self.get(sprite_addr + 2) & if self.lcdc.bit2() { 0xfe } else { 0xff };
-            let tile_attr = Attr::from(self.get(sprite_addr + 3));
-
-            // If this is true the scanline is out of the area we care about
-            if py <= 0xff - sprite_size + 1 {
-                if self.ly < py || self.ly > py + sprite_size - 1 {
-                    continue;
-                }
-            } else {
-                if self.ly > py.wrapping_add(sprite_size) - 1 {
-                    continue;
-                }
-            }
-            if px >= (SCREEN_W as u8) && px <= (0xff - 7) {
+        let (x, i, entry) = self.fifo.sprites[usize::from(self.fifo.sprite_next)];
+        let i = usize::from(i);
+        let py = i32::from(entry[0]) - 16;
+        let tile_number = entry[2] & if self.lcdc.bit2() { 0xfe } else { 0xff };
+        let attr = entry[3];
+        let tile_attr = Attr::from(attr);
+
+        let row = (i32::from(self.ly) - py) & (sprite_size - 1);
+        let tile_y = if tile_attr.yflip { sprite_size - 1 - row } else { row } as u16;
+        let tile_y_addr = 0x8000u16 + u16::from(tile_number) * 16 + tile_y * 2;
+        let tile_y_data: [u8; 2] = if self.term == Term::GBC && tile_attr.bank {
+            [self.get_ram1(tile_y_addr), self.get_ram1(tile_y_addr + 1)]
+        } else {
+            [self.get_ram0(tile_y_addr), self.get_ram0(tile_y_addr + 1)]
+        };
+
+        // Sprites partially off the left edge of the screen lose their first columns.
+        let skip = usize::from((self.fifo.lx + 8).saturating_sub(x));
+        for k in 0..8usize.saturating_sub(skip) {
+            let c = k + skip;
+            let bit = if tile_attr.xflip { c } else { 7 - c };
+            let color = (((tile_y_data[1] >> bit) & 0x01) << 1) | ((tile_y_data[0] >> bit) & 0x01);
+            // Color 0 is always transparent.
+            if color == 0 {
                 continue;
             }
+            let slot = self.fifo.obj[k];
+            if slot.0 == 0 || (self.term == Term::GBC && !self.opri && (i as u8) < slot.2) {
+                self.fifo.obj[k] = (color, attr, i as u8);
+            }
+        }
+    }
 
-            let tile_y = if tile_attr.yflip {
-                sprite_size - 1 - self.ly.wrapping_sub(py)
-            } else {
-                self.ly.wrapping_sub(py)
-            };
-            let tile_y_addr = 0x8000u16 + u16::from(tile_number) * 16 + u16::from(tile_y) * 2;
-            let tile_y_data: [u8; 2] = if self.term == Term::GBC && tile_attr.bank {
-                let b1 = self.get_ram1(tile_y_addr);
-                let b2 = self.get_ram1(tile_y_addr + 1);
-                [b1, b2]
-            } else {
-                let b1 = self.get_ram0(tile_y_addr);
-                let b2 = self.get_ram0(tile_y_addr + 1);
-                [b1, b2]
-            };
-
-            for x in 0..8 {
-                if px.wrapping_add(x) >= (SCREEN_W as u8) {
-                    continue;
-                }
-                let tile_x = if tile_attr.xflip { 7 - x } else { x };
-
-                // Palettes
-                let color_l = if tile_y_data[0] & (0x80 >> tile_x) != 0 { 1 } else { 0 };
-                let color_h = if tile_y_data[1] & (0x80 >> tile_x) != 0 { 2 } else { 0 };
-                let color = color_h | color_l;
-                if color == 0 {
-                    continue;
-                }
+    // Send a pixel to the LCD, the background color, its attributes in CGB mode, and the sprite color, its attributes
+    // and OAM index. The palettes are applied here, as the pixel leaves the FIFO: a write to BGP, OBP0 or OBP1 between
+    // two lines, or in the middle of one, changes the shades of the pixels that follow.
+    fn draw_pixel(&mut self, bg: (u8, u8), obj: (u8, u8, u8)) {
+        if !self.render {
+            return;
+        }
+        let x = usize::from(self.fifo.lx);
+        let shown = if self.fifo.window {
+            Layer::Window
+        } else {
+            Layer::Background
+        };
+        let bg = if self.layer_enabled(shown) { bg } else { (0, 0) };
+        let obj = if self.layer_enabled(Layer::Sprites) {
+            obj
+        } else {
+            (0, 0, 0)
+        };
+        let bg_attr = Attr::from(bg.1);
+        // When LCDC.0 is cleared on monochrome models both background and window become blank (white).
+        let blank = self.term != Term::GBC && !self.lcdc.bit0();
+        let color = if blank { 0 } else { bg.0 };
 
-                // Confirm the priority of background and sprite.
-                let prio = self.prio[px.wrapping_add(x) as usize];
-                let skip = if self.term == Term::GBC && !self.lcdc.bit0() {
-                    prio.1 == 0
-                } else if prio.0 {
-                    prio.1 != 0
+        // Confirm the priority of background and sprite. Background color 0 is always behind sprites. Otherwise the
+        // BG-to-OAM priority bit of the tile attributes puts the background in front, and when it is clear the
+        // OBJ-to-BG priority bit of the sprite decides. In CGB mode LCDC.0 cleared overrides both, sprites are always
+        // in front.
+        let obj_attr = Attr::from(obj.1);
+        let hidden = if self.term == Term::GBC && !self.lcdc.bit0() {
+            false
+        } else if bg_attr.priority {
+            color != 0
+        } else {
+            obj_attr.priority && color != 0
+        };
+        if obj.0 != 0 && self.lcdc.bit1() && !hidden {
+            if self.term == Term::GBC {
+                let (r, g, b) = self.cobp.rgb(obj_attr.palette_number_1, usize::from(obj.0));
+                let index = 0x20 | ((obj_attr.palette_number_1 as u8) << 2) | obj.0;
+                self.set_rgb(x, index, r, g, b);
+            } else {
+                let palette = if obj_attr.palette_number_0 == 1 {
+                    self.op1
                 } else {
-                    tile_attr.priority && prio.1 != 0
+                    self.op0
                 };
-                if skip {
-                    continue;
-                }
-
-                if self.term == Term::GBC {
-                    let r = self.cobpd[tile_attr.palette_number_1][color][0];
-                    let g = self.cobpd[tile_attr.palette_number_1][color][1];
-                    let b = self.cobpd[tile_attr.palette_number_1][color][2];
-                    self.set_rgb(px.wrapping_add(x) as usize, r, g, b);
-                } else {
-                    let color = if tile_attr.palette_number_0 == 1 {
-                        Self::get_gray_shades(self.op1, color) as u8
-                    } else {
-                        Self::get_gray_shades(self.op0, color) as u8
-                    };
-                    self.set_gre(px.wrapping_add(x) as usize, color);
-                }
+                self.set_gre(x, Self::get_gray_shades(palette, usize::from(obj.0)));
             }
+        } else if self.term == Term::GBC {
+            let (r, g, b) = self.cbgp.rgb(bg_attr.palette_number_1, usize::from(color));
+            let index = ((bg_attr.palette_number_1 as u8) << 2) | color;
+            self.set_rgb(x, index, r, g, b);
+        } else if blank {
+            self.set_gre(x, 0);
+        } else {
+            self.set_gre(x, Self::get_gray_shades(self.bgp, usize::from(color)));
         }
     }
 }
@@ -716,45 +1526,35 @@ impl Memory for Gpu {
                 let bit5 = if self.stat.enable_m2_interrupt { 0x20 } else { 0x00 };
                 let bit4 = if self.stat.enable_m1_interrupt { 0x10 } else { 0x00 };
                 let bit3 = if self.stat.enable_m0_interrupt { 0x08 } else { 0x00 };
-                let bit2 = if self.ly == self.lc { 0x04 } else { 0x00 };
+                let bit2 = if self.ly_reg() == self.lc { 0x04 } else { 0x00 };
                 bit6 | bit5 | bit4 | bit3 | bit2 | self.stat.mode
             }
             0xff42 => self.sy,
             0xff43 => self.sx,
-            0xff44 => self.ly,
+            0xff44 => self.ly_reg(),
             0xff45 => self.lc,
             0xff47 => self.bgp,
             0xff48 => self.op0,
             0xff49 => self.op1,
             0xff4a => self.wy,
             0xff4b => self.wx,
-            0xff4f => 0xfe | self.ram_bank as u8,
-            0xff68 => self.cbgpi.get(),
-            0xff69 => {
-                let r = self.cbgpi.i as usize >> 3;
-                let c = self.cbgpi.i as usize >> 1 & 0x3;
-                if self.cbgpi.i & 0x01 == 0x00 {
-                    let a = self.cbgpd[r][c][0];
-                    let b = self.cbgpd[r][c][1] << 5;
-                    a | b
+            // VRAM bank switching only exists on the CGB.
+            0xff4f => {
+                if self.term == Term::GBC {
+                    0xfe | self.ram_bank as u8
                 } else {
-                    let a = self.cbgpd[r][c][1] >> 3;
-                    let b = self.cbgpd[r][c][2] << 2;
-                    a | b
+                    0xff
                 }
             }
-            0xff6a => self.cobpi.get(),
-            0xff6b => {
-                let r = self.cobpi.i as usize >> 3;
-                let c = self.cobpi.i as usize >> 1 & 0x3;
-                if self.cobpi.i & 0x01 == 0x00 {
-                    let a = self.cobpd[r][c][0];
-                    let b = self.cobpd[r][c][1] << 5;
-                    a | b
+            0xff68 => self.cbgp.i.get(),
+            0xff69 => self.cbgp.get(),
+            0xff6a => self.cobp.i.get(),
+            0xff6b => self.cobp.get(),
+            0xff6c => {
+                if self.term == Term::GBC {
+                    0xfe | u8::from(self.opri)
                 } else {
-                    let a = self.cobpd[r][c][1] >> 3;
-                    let b = self.cobpd[r][c][2] << 2;
-                    a | b
+                    0xff
                 }
             }
             _ => panic!(""),
@@ -765,66 +1565,1068 @@ impl Memory for Gpu {
         match a {
             0x8000..=0x9fff => self.ram[self.ram_bank * 0x2000 + a as usize - 0x8000] = v,
             0xfe00..=0xfe9f => self.oam[a as usize - 0xfe00] = v,
+            // Turning the LCD off stops the PPU on the spot: LY and the dot counter are reset and STAT reports mode 0
+            // until it is turned on again, VRAM and OAM are freely accessible meanwhile and the screen is blank.
+            // Turning it on starts a new frame at line 0.
             0xff40 => {
+                let on = self.lcdc.bit7();
                 self.lcdc.data = v;
-                if !self.lcdc.bit7() {
+                if on && !self.lcdc.bit7() {
                     self.dots = 0;
                     self.ly = 0;
-                    self.stat.mode = 0;
+                    self.enter_mode(0);
+                    self.stat.line = false;
+                    self.wly = 0x00;
+                    self.wy_hit = false;
                     // Clean screen.
                     self.data = [[[0xffu8; 3]; SCREEN_W]; SCREEN_H];
+                    self.index = [[0x00; SCREEN_W]; SCREEN_H];
+                    self.rgba.iter_mut().for_each(|b| *b = 0xff);
+                    self.refresh_shown();
                     self.v_blank = true;
                 }
+                if !on && self.lcdc.bit7() {
+                    // The first line is 4 dots short, 452 dots: it starts as if its first machine cycle had passed.
+                    self.dots = 4;
+                    self.lcd_start = true;
+                    self.update_stat();
+                }
             }
             0xff41 => {
                 self.stat.enable_ly_interrupt = v & 0x40 != 0x00;
                 self.stat.enable_m2_interrupt = v & 0x20 != 0x00;
                 self.stat.enable_m1_interrupt = v & 0x10 != 0x00;
                 self.stat.enable_m0_interrupt = v & 0x08 != 0x00;
+                self.update_stat();
             }
             0xff42 => self.sy = v,
             0xff43 => self.sx = v,
             0xff44 => {}
-            0xff45 => self.lc = v,
+            0xff45 => {
+                self.lc = v;
+                self.update_stat();
+            }
             0xff47 => self.bgp = v,
             0xff48 => self.op0 = v,
             0xff49 => self.op1 = v,
             0xff4a => self.wy = v,
             0xff4b => self.wx = v,
-            0xff4f => self.ram_bank = (v & 0x01) as usize,
-            0xff68 => self.cbgpi.set(v),
-            0xff69 => {
-                let r = self.cbgpi.i as usize >> 3;
-                let c = self.cbgpi.i as usize >> 1 & 0x03;
-                if self.cbgpi.i & 0x01 == 0x00 {
-                    self.cbgpd[r][c][0] = v & 0x1f;
-                    self.cbgpd[r][c][1] = (self.cbgpd[r][c][1] & 0x18) | (v >> 5);
-                } else {
-                    self.cbgpd[r][c][1] = (self.cbgpd[r][c][1] & 0x07) | ((v & 0x03) << 3);
-                    self.cbgpd[r][c][2] = (v >> 2) & 0x1f;
-                }
-                if self.cbgpi.auto_increment {
-                    self.cbgpi.i += 0x01;
-                    self.cbgpi.i &= 0x3f;
+            0xff4f => {
+                if self.term == Term::GBC {
+                    self.ram_bank = (v & 0x01) as usize;
                 }
             }
-            0xff6a => self.cobpi.set(v),
-            0xff6b => {
-                let r = self.cobpi.i as usize >> 3;
-                let c = self.cobpi.i as usize >> 1 & 0x03;
-                if self.cobpi.i & 0x01 == 0x00 {
-                    self.cobpd[r][c][0] = v & 0x1f;
-                    self.cobpd[r][c][1] = (self.cobpd[r][c][1] & 0x18) | (v >> 5);
-                } else {
-                    self.cobpd[r][c][1] = (self.cobpd[r][c][1] & 0x07) | ((v & 0x03) << 3);
-                    self.cobpd[r][c][2] = (v >> 2) & 0x1f;
-                }
-                if self.cobpi.auto_increment {
-                    self.cobpi.i += 0x01;
-                    self.cobpi.i &= 0x3f;
+            0xff68 => self.cbgp.i.set(v),
+            0xff69 => self.cbgp.set(v),
+            0xff6a => self.cobp.i.set(v),
+            0xff6b => self.cobp.set(v),
+            0xff6c => {
+                if self.term == Term::GBC {
+                    self.opri = v & 0x01 != 0x00;
                 }
             }
             _ => panic!(""),
         }
     }
 }
+
+impl Stateful for PaletteRam {
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.i.get());
+        w.bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.i.set(r.u8()?);
+        r.bytes_into(&mut self.ram, "palette ram")
+    }
+}
+
+impl Stateful for Fifo {
+    fn save_state(&self, w: &mut Writer) {
+        for (c, a) in self.bg.iter() {
+            w.u8(*c);
+            w.u8(*a);
+        }
+        for (c, a, i) in self.obj.iter() {
+            w.u8(*c);
+            w.u8(*a);
+            w.u8(*i);
+        }
+        for (x, i, entry) in self.sprites.iter() {
+            w.u8(*x);
+            w.u8(*i);
+            w.bytes(entry);
+        }
+        let regs = [
+            self.bg_len,
+            self.step,
+            self.ticks,
+            self.tile_x,
+            self.tile,
+            self.attr,
+            self.lo,
+            self.hi,
+            self.lx,
+            self.discard,
+            self.sprite_len,
+            self.sprite_next,
+            self.obj_ticks,
+        ];
+        for v in regs.iter() {
+            w.u8(*v);
+        }
+        w.bool(self.window);
+        w.bool(self.first);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        for p in self.bg.iter_mut() {
+            *p = (r.u8()? & 0x03, r.u8()?);
+        }
+        for p in self.obj.iter_mut() {
+            *p = (r.u8()? & 0x03, r.u8()?, r.u8()? % 40);
+        }
+        for p in self.sprites.iter_mut() {
+            p.0 = r.u8()?;
+            p.1 = r.u8()? % 40;
+            r.bytes_into(&mut p.2, "sprites")?;
+        }
+        self.bg_len = r.u8()?.min(8);
+        self.step = r.u8()?.min(3);
+        self.ticks = r.u8()?.min(1);
+        self.tile_x = r.u8()?;
+        self.tile = r.u8()?;
+        self.attr = r.u8()?;
+        self.lo = r.u8()?;
+        self.hi = r.u8()?;
+        self.lx = r.u8()?.min(SCREEN_W as u8);
+        self.discard = r.u8()?.min(7);
+        self.sprite_len = r.u8()?.min(40);
+        self.sprite_next = r.u8()?.min(self.sprite_len);
+        self.obj_ticks = r.u8()?.min(5);
+        if self.sprite_next == self.sprite_len {
+            self.obj_ticks = 0;
+        }
+        self.window = r.bool()?;
+        self.first = r.bool()?;
+        Ok(())
+    }
+}
+
+// The framebuffer is included, so a restored machine shows the same picture before the next frame is drawn.
+impl Stateful for Gpu {
+    fn save_state(&self, w: &mut Writer) {
+        w.bytes(self.data.as_flattened().as_flattened());
+        w.bytes(self.index.as_flattened());
+        w.bool(self.h_blank);
+        w.bool(self.v_blank);
+        w.u8(self.lcdc.data);
+        w.bool(self.stat.enable_ly_interrupt);
+        w.bool(self.stat.enable_m2_interrupt);
+        w.bool(self.stat.enable_m1_interrupt);
+        w.bool(self.stat.enable_m0_interrupt);
+        w.u8(self.stat.mode);
+        w.bool(self.stat.line);
+        let regs = [
+            self.sy, self.sx, self.wy, self.wx, self.wly, self.ly, self.lc, self.bgp, self.op0, self.op1,
+        ];
+        for v in regs.iter() {
+            w.u8(*v);
+        }
+        self.cbgp.save_state(w);
+        self.cobp.save_state(w);
+        w.bytes(&self.ram);
+        w.u8(self.ram_bank as u8);
+        w.bytes(&self.oam);
+        w.u32(self.dots);
+        w.bool(self.wy_hit);
+        self.fifo.save_state(w);
+        for (x, i, entry) in self.scan.iter() {
+            w.u8(*x);
+            w.u8(*i);
+            w.bytes(entry);
+        }
+        w.u8(self.scan_len);
+        w.bool(self.lcd_start);
+        w.bool(self.opri);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.bytes_into(self.data.as_flattened_mut().as_flattened_mut(), "framebuffer")?;
+        r.bytes_into(self.index.as_flattened_mut(), "framebuffer")?;
+        self.h_blank = r.bool()?;
+        self.v_blank = r.bool()?;
+        self.lcdc.data = r.u8()?;
+        self.stat.enable_ly_interrupt = r.bool()?;
+        self.stat.enable_m2_interrupt = r.bool()?;
+        self.stat.enable_m1_interrupt = r.bool()?;
+        self.stat.enable_m0_interrupt = r.bool()?;
+        self.stat.mode = r.u8()? & 0x03;
+        self.stat.line = r.bool()?;
+        self.sy = r.u8()?;
+        self.sx = r.u8()?;
+        self.wy = r.u8()?;
+        self.wx = r.u8()?;
+        self.wly = r.u8()?;
+        self.ly = r.u8()?;
+        self.lc = r.u8()?;
+        self.bgp = r.u8()?;
+        self.op0 = r.u8()?;
+        self.op1 = r.u8()?;
+        self.cbgp.load_state(r)?;
+        self.cobp.load_state(r)?;
+        r.bytes_into(&mut self.ram, "vram")?;
+        self.ram_bank = usize::from(r.u8()? & 0x01);
+        r.bytes_into(&mut self.oam, "oam")?;
+        self.dots = r.u32()?;
+        self.wy_hit = r.bool()?;
+        self.fifo.load_state(r)?;
+        for p in self.scan.iter_mut() {
+            p.0 = r.u8()?;
+            p.1 = r.u8()? % 40;
+            r.bytes_into(&mut p.2, "sprites")?;
+        }
+        self.scan_len = r.u8()?.min(40);
+        self.lcd_start = r.bool()?;
+        self.opri = r.bool()?;
+        self.set_output_format(self.format);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A monochrome gpu with the given tiles. The neutral grays of the Pocket make the shades simple to check.
+    fn gpu_with(tiles: &[(u8, [u8; 2])]) -> Gpu {
+        let mut gpu = Gpu::power_up(Term::GBP, Rc::new(RefCell::new(Intf::power_up())));
+        for &(n, row) in tiles {
+            for y in 0..8 {
+                gpu.set(0x8000 + u16::from(n) * 16 + y * 2, row[0]);
+                gpu.set(0x8000 + u16::from(n) * 16 + y * 2 + 1, row[1]);
+            }
+        }
+        gpu.set(0xff47, 0xe4);
+        gpu
+    }
+
+    fn pixel(gpu: &Gpu, x: usize, y: usize) -> u8 {
+        gpu.framebuffer()[(y * SCREEN_W + x) * 4]
+    }
+
+    #[test]
+    fn scrolled_tilemap() {
+        let mut gpu = gpu_with(&[(0, [0x00, 0x00]), (1, [0xff, 0xff])]);
+        for ty in 0..32 {
+            for tx in 0..32 {
+                gpu.set(0x9800 + ty * 32 + tx, ((tx + ty) & 1) as u8);
+            }
+        }
+        gpu.set(0xff42, 5);
+        gpu.set(0xff43, 3);
+        gpu.set(0xff40, 0x91);
+        gpu.next(70224);
+
+        let mut want = Vec::new();
+        for y in 0..SCREEN_H {
+            for x in 0..SCREEN_W {
+                let g = [0xff, 0x00][((x + 3) / 8 + (y + 5) / 8) & 1];
+                want.extend_from_slice(&[g, g, g, 0xff]);
+            }
+        }
+        assert!(gpu.framebuffer() == &want[..]);
+    }
+
+    #[test]
+    fn window_line_counter() {
+        let mut gpu = gpu_with(&[(0, [0x00, 0x00]), (1, [0xff, 0xff]), (2, [0xff, 0x00])]);
+        for i in 0..32 * 32 {
+            gpu.set(0x9800 + i, 2);
+            gpu.set(0x9c00 + i, if i < 32 { 1 } else { 0 });
+        }
+        gpu.set(0xff4a, 0);
+        gpu.set(0xff4b, 7);
+        gpu.set(0xff40, 0xf1);
+        // Hide the window on lines 4-7. It resumes with its own line 4, not with LY - WY.
+        for ly in 0..16 {
+            gpu.set(0xff40, if (4..8).contains(&ly) { 0xd1 } else { 0xf1 });
+            gpu.next(456);
+        }
+        for x in [0, SCREEN_W - 1].iter().copied() {
+            for y in 0..16 {
+                let want = match y {
+                    0..=3 | 8..=11 => 0x00,
+                    4..=7 => 0xc0,
+                    _ => 0xff,
+                };
+                assert_eq!(pixel(&gpu, x, y), want, "{} {}", x, y);
+            }
+        }
+    }
+
+    fn sprite(gpu: &mut Gpu, i: u16, x: u8, y: u8, tile: u8) {
+        gpu.set(0xfe00 + i * 4, y + 16);
+        gpu.set(0xfe00 + i * 4 + 1, x + 8);
+        gpu.set(0xfe00 + i * 4 + 2, tile);
+        gpu.set(0xfe00 + i * 4 + 3, 0x00);
+    }
+
+    #[test]
+    fn ten_sprites_per_line() {
+        let mut gpu = gpu_with(&[(0, [0x00, 0x00]), (1, [0xff, 0xff])]);
+        gpu.set(0xff48, 0xe4);
+        for i in 0..11 {
+            sprite(&mut gpu, i, i as u8 * 10, 0, 1);
+        }
+        gpu.set(0xff40, 0x93);
+        gpu.next(456);
+        for i in 0..11 {
+            let want = if i < 10 { 0x00 } else { 0xff };
+            assert_eq!(pixel(&gpu, i * 10, 0), want, "sprite {}", i);
+        }
+    }
+
+    #[test]
+    fn sprite_limit_none_draws_the_whole_line() {
+        // Twelve sprites, the eleventh overlapping the twelfth so the smaller X still wins between them.
+        let line = |limit: Option<u8>| {
+            let mut gpu = gpu_with(&[(0, [0x00, 0x00]), (1, [0xff, 0xff]), (2, [0xff, 0x00])]);
+            gpu.set(0xff48, 0xe4);
+            for i in 0..10 {
+                sprite(&mut gpu, i, i as u8 * 10, 0, 1);
+            }
+            sprite(&mut gpu, 10, 100, 0, 2);
+            sprite(&mut gpu, 11, 104, 0, 1);
+            gpu.set_sprite_limit(limit);
+            gpu.set(0xff40, 0x93);
+            gpu.next(456);
+            [0, 90, 100, 104, 108]
+                .iter()
+                .map(|&x| pixel(&gpu, x, 0))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(line(Some(10)), [0x00, 0x00, 0xff, 0xff, 0xff]);
+        assert_eq!(line(None), [0x00, 0x00, 0xc0, 0xc0, 0x00]);
+    }
+
+    #[test]
+    fn smaller_x_wins_on_dmg() {
+        let mut gpu = gpu_with(&[(0, [0x00, 0x00]), (1, [0xff, 0xff]), (2, [0xff, 0x00])]);
+        gpu.set(0xff48, 0xe4);
+        sprite(&mut gpu, 0, 20, 0, 2);
+        sprite(&mut gpu, 1, 16, 0, 1);
+        gpu.set(0xff40, 0x93);
+        gpu.next(456);
+        assert_eq!(pixel(&gpu, 19, 0), 0x00);
+        assert_eq!(pixel(&gpu, 23, 0), 0x00);
+        assert_eq!(pixel(&gpu, 24, 0), 0xc0);
+    }
+
+    #[test]
+    fn stat_line_rises_once() {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        let mut gpu = Gpu::power_up(Term::GB, intf.clone());
+        gpu.set(0xff45, 0x05);
+        gpu.set(0xff41, 0x48);
+        gpu.set(0xff40, 0x91);
+        gpu.next(100);
+        assert_eq!(gpu.get(0xff41) & 0x03, 3);
+        intf.borrow_mut().data = 0x00;
+
+        gpu.set(0xff45, 0x00);
+        assert_eq!(intf.borrow().data, 0x02);
+        intf.borrow_mut().data = 0x00;
+        gpu.set(0xff45, 0x00);
+        // The HBlank source becomes active while LYC=LY still holds the line high.
+        gpu.next(200);
+        assert_eq!(gpu.get(0xff41) & 0x03, 0);
+        assert_eq!(intf.borrow().data, 0x00);
+    }
+
+    #[test]
+    fn palette_index_auto_increment() {
+        let mut gpu = Gpu::power_up(Term::GBC, Rc::new(RefCell::new(Intf::power_up())));
+        gpu.set(0xff68, 0x86);
+        gpu.set(0xff69, 0x1f);
+        gpu.set(0xff69, 0x00);
+        assert_eq!(gpu.get(0xff68), 0x88);
+        gpu.set(0xff68, 0xbf);
+        gpu.set(0xff69, 0x7c);
+        assert_eq!(gpu.get(0xff68), 0x80);
+        // Without the auto-increment bit the index stays.
+        gpu.set(0xff68, 0x06);
+        assert_eq!(gpu.get(0xff69), 0x1f);
+        assert_eq!(gpu.get(0xff69), 0x1f);
+        assert_eq!(gpu.get(0xff68), 0x06);
+        gpu.set(0xff68, 0x3f);
+        assert_eq!(gpu.get(0xff69), 0x7c);
+    }
+
+    #[test]
+    fn bgr555_to_rgb888() {
+        let mut gpu = Gpu::power_up(Term::GBC, Rc::new(RefCell::new(Intf::power_up())));
+        for y in 0..8 {
+            gpu.set(0x8010 + y * 2, 0xff);
+        }
+        gpu.set(0x9800, 0x01);
+        // Palette 0: color 1 pure red, color 2 pure blue.
+        gpu.set(0xff68, 0x82);
+        for &b in &[0x1f, 0x00, 0x00, 0x7c] {
+            gpu.set(0xff69, b);
+        }
+        gpu.set(0xff40, 0x91);
+        gpu.next(456);
+        assert_eq!(&gpu.framebuffer()[..3], &[201, 0, 46]);
+        for y in 0..8 {
+            gpu.set(0x8010 + y * 2, 0x00);
+            gpu.set(0x8010 + y * 2 + 1, 0xff);
+        }
+        gpu.next(456);
+        assert_eq!(&gpu.framebuffer()[SCREEN_W * 4..SCREEN_W * 4 + 3], &[15, 62, 170]);
+    }
+
+    #[test]
+    fn vram_banks_and_tile_attributes() {
+        let mut gpu = Gpu::power_up(Term::GBC, Rc::new(RefCell::new(Intf::power_up())));
+        for a in 0x8010..0x8020 {
+            gpu.set(a, 0xff);
+        }
+        gpu.set(0x9800, 0x01);
+        gpu.set(0xff4f, 0x01);
+        gpu.set(0x8010, 0x80);
+        for a in 0x8011..0x8020 {
+            gpu.set(a, 0x00);
+        }
+        // Tile 1 from bank 1, both flips, palette 3.
+        gpu.set(0x9800, 0x6b);
+        gpu.set(0xff4f, 0x00);
+        assert_eq!(gpu.get(0x8010), 0xff);
+        assert_eq!(gpu.get(0x9800), 0x01);
+
+        gpu.set(0xff68, 0x98);
+        for &b in &[0x00, 0x00, 0x1f, 0x00] {
+            gpu.set(0xff69, b);
+        }
+        gpu.set(0xff40, 0x91);
+        gpu.next(456 * 8);
+        let rgb = |x: usize, y: usize| &gpu.framebuffer()[(y * SCREEN_W + x) * 4..][..3];
+        for y in 0..8 {
+            for x in 0..8 {
+                let want: &[u8] = if (x, y) == (7, 7) { &[201, 0, 46] } else { &[0, 0, 0] };
+                assert_eq!(rgb(x, y), want, "{} {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn dmg_palette_recolors_the_shades() {
+        for &palette in &[Palette::GREEN, Palette::POCKET, Palette::GRAYSCALE] {
+            let mut gpu = gpu_with(&[(0, [0xff, 0xff])]);
+            gpu.set_dmg_palette(palette);
+            gpu.set(0xff40, 0x91);
+            gpu.next(456);
+            assert_eq!(&gpu.framebuffer()[..3], &palette.colors[3][..3]);
+            // BGP still picks the shade, color 3 mapped to shade 1.
+            gpu.set(0xff47, 0x64);
+            gpu.next(456);
+            assert_eq!(&gpu.framebuffer()[SCREEN_W * 4..][..3], &palette.colors[1][..3]);
+        }
+    }
+
+    // Background of tile 2 (light gray), window rows of tile 1 (black) then tile 0 (white).
+    fn window_gpu() -> Gpu {
+        let mut gpu = gpu_with(&[(0, [0x00, 0x00]), (1, [0xff, 0xff]), (2, [0xff, 0x00])]);
+        for i in 0..32 * 32 {
+            gpu.set(0x9800 + i, 2);
+            gpu.set(0x9c00 + i, if i < 32 { 1 } else { 0 });
+        }
+        gpu
+    }
+
+    #[test]
+    fn window_enabled_mid_frame_starts_at_its_top_line() {
+        let mut gpu = window_gpu();
+        gpu.set(0xff4a, 0);
+        gpu.set(0xff4b, 7);
+        for ly in 0..24 {
+            gpu.set(0xff40, if ly < 10 { 0xd1 } else { 0xf1 });
+            gpu.next(456);
+        }
+        for y in 0..24 {
+            let want = match y {
+                0..=9 => 0xc0,
+                10..=17 => 0x00,
+                _ => 0xff,
+            };
+            assert_eq!(pixel(&gpu, 0, y), want, "{}", y);
+        }
+    }
+
+    #[test]
+    fn window_waits_for_ly_to_match_wy() {
+        let mut gpu = window_gpu();
+        gpu.set(0xff4a, 200);
+        gpu.set(0xff4b, 7);
+        gpu.set(0xff40, 0xf1);
+        for ly in 0..154 {
+            // WY moved above LY after the line went by, no window this frame.
+            if ly == 10 {
+                gpu.set(0xff4a, 3);
+            }
+            gpu.next(456);
+        }
+        assert_eq!(pixel(&gpu, 0, 3), 0xc0);
+        assert_eq!(pixel(&gpu, 0, 20), 0xc0);
+        gpu.next(456 * 154);
+        assert_eq!(pixel(&gpu, 0, 2), 0xc0);
+        assert_eq!(pixel(&gpu, 0, 3), 0x00);
+        assert_eq!(pixel(&gpu, 0, 11), 0xff);
+    }
+
+    #[test]
+    fn window_wx_edges() {
+        let mut gpu = window_gpu();
+        for y in 0..8 {
+            gpu.set(0x8010 + y * 2, 0x80);
+            gpu.set(0x8010 + y * 2 + 1, 0x80);
+        }
+        gpu.set(0xff4a, 0);
+        // The first four columns of the window are off-screen.
+        gpu.set(0xff4b, 3);
+        gpu.set(0xff40, 0xf1);
+        gpu.next(456);
+        for x in 0..16 {
+            let want = if x == 4 || x == 12 { 0x00 } else { 0xff };
+            assert_eq!(pixel(&gpu, x, 0), want, "{}", x);
+        }
+        // Only the last column is window.
+        gpu.set(0xff4b, 166);
+        gpu.next(456);
+        assert_eq!(pixel(&gpu, SCREEN_W - 2, 1), 0xc0);
+        assert_eq!(pixel(&gpu, SCREEN_W - 1, 1), 0x00);
+    }
+
+    // Steps through scanline 1 a dot at a time and returns how many dots it spent in mode 3 and in mode 0.
+    fn mode3_and_hblank(gpu: &mut Gpu) -> (u32, u32) {
+        gpu.set(0xff40, 0x93);
+        for _ in 0..456 {
+            gpu.next(1);
+        }
+        let mut dots = [0; 4];
+        for _ in 0..456 {
+            gpu.next(1);
+            dots[usize::from(gpu.get(0xff41) & 0x03)] += 1;
+        }
+        (dots[3], dots[0])
+    }
+
+    #[test]
+    fn scroll_and_sprites_lengthen_mode3() {
+        let mut gpu = gpu_with(&[]);
+        let (base, base_hblank) = mode3_and_hblank(&mut gpu);
+        assert_eq!(base, 172);
+
+        let mut gpu = gpu_with(&[]);
+        gpu.set(0xff43, 3);
+        sprite(&mut gpu, 0, 0, 0, 0);
+        sprite(&mut gpu, 1, 42, 0, 0);
+        sprite(&mut gpu, 2, 43, 0, 0);
+        // 3 dots of fine scroll, 2 + 6 for the first sprite, 6 for each sprite on the already fetched tile 5.
+        let (mode3, hblank) = mode3_and_hblank(&mut gpu);
+        assert_eq!((mode3, hblank), (base + 3 + 8 + 6 + 6, base_hblank - 23));
+    }
+
+    #[test]
+    fn dump_tile_deinterleaves_the_bit_planes() {
+        let mut gpu = gpu_with(&[]);
+        // Row 0 has colors 0 1 2 3 0 1 2 3, every other row is color 3 in the first column only.
+        gpu.set(0x8010, 0x55);
+        gpu.set(0x8011, 0x33);
+        for y in 1..8 {
+            gpu.set(0x8010 + y * 2, 0x80);
+            gpu.set(0x8011 + y * 2, 0x80);
+        }
+        let tile = gpu.dump_tile(1, 0);
+        assert_eq!(tile[..8], [0, 1, 2, 3, 0, 1, 2, 3]);
+        for y in 1..8 {
+            assert_eq!(tile[y * 8..y * 8 + 8], [3, 0, 0, 0, 0, 0, 0, 0]);
+        }
+        assert_eq!(gpu.dump_tile(0, 0), [0; 64]);
+    }
+
+    #[test]
+    fn dump_tilemap_follows_the_addressing_mode() {
+        let mut gpu = gpu_with(&[]);
+        gpu.set(0x9c00, 0x01);
+        gpu.set(0x9c01, 0xff);
+        gpu.set(0xff40, 0x91);
+        let map = gpu.dump_tilemap(TileMap::Map9c00);
+        assert_eq!(map.len(), 32 * 32);
+        assert_eq!(map[..3], [1, 255, 0]);
+        gpu.set(0xff40, 0x81);
+        assert_eq!(gpu.dump_tilemap(TileMap::Map9c00)[..3], [257, 255, 256]);
+        assert_eq!(gpu.dump_tilemap(TileMap::Map9800)[0], 256);
+    }
+
+    #[test]
+    fn sprite_round_trip() {
+        let mut gpu = gpu_with(&[]);
+        for (i, &b) in [0x20, 0x18, 0x42, 0xbd].iter().enumerate() {
+            gpu.set(0xfe08 + i as u16, b);
+        }
+        let s = gpu.sprites()[2];
+        let want = Sprite {
+            y: 0x20,
+            x: 0x18,
+            tile: 0x42,
+            priority: true,
+            yflip: false,
+            xflip: true,
+            palette: 1,
+            bank: true,
+            cgb_palette: 5,
+        };
+        assert_eq!(s, want);
+        gpu.set_sprite(7, s);
+        let oam: Vec<u8> = (0..4).map(|i| gpu.get(0xfe1c + i)).collect();
+        assert_eq!(oam, [0x20, 0x18, 0x42, 0xbd]);
+        assert_eq!(gpu.sprites()[7], want);
+    }
+
+    #[test]
+    fn scx_written_mid_line_splits_the_line() {
+        // Black in the first column of the even tiles only.
+        let mut gpu = gpu_with(&[(0, [0x00, 0x00]), (1, [0x80, 0x80])]);
+        for i in 0..32 * 32 {
+            gpu.set(0x9800 + i, ((i + 1) & 0x01) as u8);
+        }
+        gpu.set(0xff40, 0x91);
+        gpu.next(452);
+        // The LCD is 80 pixels into line 1: mode 2 and the two fetches of the first tile took 92 dots.
+        gpu.next(92 + 80);
+        gpu.set(0xff43, 8);
+        gpu.next(456 - 92 - 80);
+        // The tile of pixels 80-87 was already fetched, the next ones come from one tile further right in the map.
+        let black: Vec<usize> = (0..SCREEN_W).filter(|&x| pixel(&gpu, x, 1) == 0x00).collect();
+        assert_eq!(black, [0, 16, 32, 48, 64, 80, 88, 104, 120, 136, 152]);
+        let black: Vec<usize> = (0..SCREEN_W).filter(|&x| pixel(&gpu, x, 0) == 0x00).collect();
+        assert_eq!(black, (0..SCREEN_W).step_by(16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn color_correction_modes() {
+        let mut gpu = Gpu::power_up(Term::GBC, Rc::new(RefCell::new(Intf::power_up())));
+        // Background palette 0, color 0: 03EFh, a yellow.
+        gpu.set(0xff68, 0x80);
+        gpu.set(0xff69, 0xef);
+        gpu.set(0xff69, 0x03);
+        gpu.set(0xff40, 0x91);
+        gpu.set_color_correction(ColorCorrection::None);
+        gpu.next(456);
+        assert_eq!(&gpu.framebuffer()[..3], &[0x7b, 0xff, 0x00]);
+        gpu.set_color_correction(ColorCorrection::Cgb);
+        gpu.next(456);
+        assert_eq!(&gpu.framebuffer()[SCREEN_W * 4..SCREEN_W * 4 + 3], &[0x80, 0xba, 0x35]);
+        // Lines already drawn keep their colors.
+        assert_eq!(&gpu.framebuffer()[..3], &[0x7b, 0xff, 0x00]);
+    }
+
+    #[test]
+    fn lcd_off_blanks_the_screen_and_resets_ly() {
+        let mut gpu = gpu_with(&[(0, [0xff, 0xff])]);
+        gpu.set(0xff40, 0x91);
+        gpu.next(456 * 50 + 100);
+        assert_eq!(gpu.get(0xff44), 50);
+        assert_eq!(pixel(&gpu, 0, 0), 0x00);
+
+        gpu.set(0xff40, 0x11);
+        assert_eq!(gpu.get(0xff44), 0);
+        assert_eq!(gpu.get(0xff41) & 0x03, 0);
+        assert!(gpu.framebuffer().iter().all(|&b| b == 0xff));
+        gpu.next(456 * 10);
+        assert_eq!(gpu.get(0xff44), 0);
+        assert_eq!(gpu.get(0xff41) & 0x03, 0);
+    }
+
+    #[test]
+    fn lcd_on_skips_the_first_oam_search() {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        let mut gpu = Gpu::power_up(Term::GB, intf.clone());
+        // Interrupt on mode 2.
+        gpu.set(0xff41, 0x20);
+        gpu.set(0xff40, 0x91);
+        intf.borrow_mut().data = 0x00;
+        for _ in 0..76 {
+            assert_eq!(gpu.get(0xff41) & 0x03, 0);
+            gpu.next(1);
+        }
+        assert_eq!(gpu.get(0xff41) & 0x03, 3);
+        assert_eq!(intf.borrow().data, 0x00);
+        // Line 1 has its OAM search again.
+        gpu.next(452 - 76);
+        assert_eq!(gpu.get(0xff44), 1);
+        assert_eq!(gpu.get(0xff41) & 0x03, 2);
+        assert_eq!(intf.borrow().data, 0x02);
+    }
+
+    #[test]
+    fn first_line_after_lcd_on_is_short_and_not_a_hblank() {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        let mut gpu = Gpu::power_up(Term::GB, intf.clone());
+        // Interrupt on mode 0: the first line starts in mode 0 without raising it, its real HBlank does.
+        gpu.set(0xff41, 0x08);
+        intf.borrow_mut().data = 0x00;
+        gpu.set(0xff40, 0x91);
+        assert_eq!(intf.borrow().data, 0x00);
+        let mut dot = 0;
+        while intf.borrow().data == 0x00 {
+            gpu.next(1);
+            dot += 1;
+        }
+        assert_eq!(gpu.get(0xff44), 0);
+        assert!((76 + 172..452).contains(&dot), "{}", dot);
+        gpu.next(452 - dot - 1);
+        assert_eq!(gpu.get(0xff44), 0);
+        gpu.next(1);
+        assert_eq!(gpu.get(0xff44), 1);
+
+        // The LY=LYC coincidence of line 0 still raises the interrupt.
+        let mut gpu = Gpu::power_up(Term::GB, intf.clone());
+        gpu.set(0xff45, 0x00);
+        gpu.set(0xff41, 0x40);
+        intf.borrow_mut().data = 0x00;
+        gpu.set(0xff40, 0x91);
+        assert_eq!(intf.borrow().data, 0x02);
+    }
+
+    #[test]
+    fn opri_selects_the_sprite_priority_rule() {
+        let mut gpu = Gpu::power_up(Term::GBC, Rc::new(RefCell::new(Intf::power_up())));
+        gpu.set_color_correction(ColorCorrection::None);
+        for y in 0..8 {
+            gpu.set(0x8010 + y * 2, 0xff);
+            gpu.set(0x8010 + y * 2 + 1, 0xff);
+            gpu.set(0x8020 + y * 2, 0xff);
+        }
+        // Object palette 0: color 1 pure red, color 3 pure blue.
+        gpu.set(0xff6a, 0x82);
+        for &b in &[0x1f, 0x00, 0x00, 0x00, 0x00, 0x7c] {
+            gpu.set(0xff6b, b);
+        }
+        // The blue sprite is first in OAM, the red one is further left.
+        sprite(&mut gpu, 0, 20, 0, 1);
+        sprite(&mut gpu, 1, 16, 0, 2);
+        gpu.set(0xff6c, 0x00);
+        gpu.set(0xff40, 0x93);
+        gpu.next(456);
+        gpu.set(0xff6c, 0x01);
+        gpu.next(456);
+        let at = |y: usize| &gpu.framebuffer()[(y * SCREEN_W + 20) * 4..][..3];
+        assert_eq!(at(0), &[0x00, 0x00, 0xff]);
+        assert_eq!(at(1), &[0xff, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn output_formats_show_the_same_pixels() {
+        // Shades 0, 0, 1, 1, 2, 2, 3, 3 in every tile.
+        let mut gpu = gpu_with(&[(0, [0x33, 0x0f])]);
+        gpu.set(0xff40, 0x91);
+        gpu.next(70224);
+        gpu.set_output_format(OutputFormat::Rgb888);
+        let rgb = gpu.framebuffer().to_vec();
+        gpu.set_output_format(OutputFormat::Indexed);
+        let index = gpu.framebuffer().to_vec();
+        gpu.set_output_format(OutputFormat::Rgba8888);
+        let rgba = gpu.framebuffer().to_vec();
+        assert_eq!(&index[..8], &[0, 0, 1, 1, 2, 2, 3, 3]);
+        for i in 0..SCREEN_W * SCREEN_H {
+            let c = Palette::GRAYSCALE.colors[usize::from(index[i])];
+            assert_eq!(&rgb[i * 3..i * 3 + 3], &c[..3]);
+            assert_eq!(&rgba[i * 4..i * 4 + 4], &[c[0], c[1], c[2], 0xff]);
+        }
+        // Drawing in the RGBA format gives the same frame.
+        gpu.next(70224);
+        assert!(gpu.framebuffer() == &rgba[..]);
+    }
+
+    #[test]
+    fn cgb_indices_hold_the_palette_and_color() {
+        let mut gpu = Gpu::power_up(Term::GBC, Rc::new(RefCell::new(Intf::power_up())));
+        gpu.set_output_format(OutputFormat::Indexed);
+        for y in 0..8 {
+            gpu.set(0x8010 + y * 2, 0xff);
+        }
+        gpu.set(0x9800, 0x01);
+        // Background palette 2 for the first tile, one sprite with object palette 5 over the second.
+        gpu.set(0xff4f, 0x01);
+        gpu.set(0x9800, 0x02);
+        gpu.set(0xff4f, 0x00);
+        sprite(&mut gpu, 0, 8, 0, 1);
+        gpu.set(0xfe03, 0x05);
+        gpu.set(0xff40, 0x93);
+        gpu.next(456);
+        assert_eq!(gpu.framebuffer()[0], (2 << 2) | 1);
+        assert_eq!(gpu.framebuffer()[8], 0x20 | (5 << 2) | 1);
+        assert_eq!(gpu.framebuffer()[16], 0);
+    }
+
+    // A gpu at line 1 with every OAM word holding its row and index, ready to scan row 5.
+    fn oam_bug_gpu(term: Term) -> Gpu {
+        let mut gpu = Gpu::power_up(term, Rc::new(RefCell::new(Intf::power_up())));
+        for row in 0..20u16 {
+            for i in 0..4 {
+                let [lo, hi] = (0x1000 * (i + 1) + row).to_le_bytes();
+                gpu.set(0xfe00 + row * 8 + i * 2, lo);
+                gpu.set(0xfe00 + row * 8 + i * 2 + 1, hi);
+            }
+        }
+        gpu.set(0xff40, 0x91);
+        // The first line after turning the LCD on has no OAM search and is 452 dots.
+        gpu.next(452 + 20);
+        assert_eq!(gpu.stat.mode, 2);
+        gpu
+    }
+
+    #[test]
+    fn oam_bug_write_corrupts_the_scanned_row() {
+        let mut gpu = oam_bug_gpu(Term::GB);
+        let before = gpu.oam;
+        gpu.oam_bug(OamBug::Write);
+        let (a, b, c) = (0x1005u16, 0x1004u16, 0x3004u16);
+        assert_eq!(&gpu.oam[40..42], &(((a ^ c) & (b ^ c)) ^ c).to_le_bytes());
+        assert_eq!(&gpu.oam[42..48], &before[34..40]);
+        assert_eq!(&gpu.oam[..40], &before[..40]);
+        assert_eq!(&gpu.oam[48..], &before[48..]);
+
+        let mut gpu = oam_bug_gpu(Term::GB);
+        gpu.oam_bug(OamBug::Read);
+        assert_eq!(&gpu.oam[40..42], &(b | (a & c)).to_le_bytes());
+        // The color models and the other modes aren't affected.
+        let mut gpu = oam_bug_gpu(Term::GBC);
+        let before = gpu.oam;
+        gpu.oam_bug(OamBug::Write);
+        assert_eq!(gpu.oam, before);
+        let mut gpu = oam_bug_gpu(Term::GB);
+        gpu.next(80);
+        let before = gpu.oam;
+        gpu.oam_bug(OamBug::Write);
+        assert_eq!(gpu.oam, before);
+    }
+
+    #[test]
+    fn one_vblank_interrupt_per_frame() {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        let mut gpu = Gpu::power_up(Term::GB, intf.clone());
+        gpu.set(0xff40, 0x91);
+        let mut vblanks = Vec::new();
+        let mut ly = Vec::new();
+        // Counted from the end of the first line, which is 4 dots short.
+        gpu.next(452);
+        for dot in 456 + 1..=70224 * 2 {
+            gpu.next(1);
+            if intf.borrow().data & 0x01 != 0x00 {
+                intf.borrow_mut().data = 0x00;
+                vblanks.push(dot);
+            }
+            if (152 * 456..154 * 456).contains(&dot) {
+                ly.push(gpu.get(0xff44));
+            }
+        }
+        assert_eq!(vblanks, [144 * 456, 70224 + 144 * 456]);
+        // Line 152, then 153 for one machine cycle followed by 0 up to line 1.
+        assert!(ly[..456].iter().all(|&v| v == 152));
+        assert!(ly[456..460].iter().all(|&v| v == 153));
+        assert!(ly[460..].iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn lyc_0_matches_during_line_153() {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        let mut gpu = Gpu::power_up(Term::GB, intf.clone());
+        gpu.set(0xff45, 0x00);
+        gpu.set(0xff41, 0x40);
+        gpu.set(0xff40, 0x91);
+        gpu.next(153 * 456 - 4 + 1);
+        intf.borrow_mut().data = 0x00;
+        assert_eq!(gpu.get(0xff41) & 0x04, 0x00);
+        gpu.next(4);
+        assert_eq!(gpu.get(0xff41) & 0x04, 0x04);
+        assert_eq!(intf.borrow().data, 0x02);
+    }
+
+    #[test]
+    fn hidden_sprites_let_the_background_through() {
+        let render = |with_sprite: bool, sprites: bool| {
+            let mut gpu = gpu_with(&[(1, [0xff, 0xff]), (2, [0xff, 0x00])]);
+            gpu.set(0xff48, 0xe4);
+            for i in 0..32 * 32 {
+                gpu.set(0x9800 + i, 2);
+            }
+            if with_sprite {
+                sprite(&mut gpu, 0, 40, 0, 1);
+            }
+            gpu.set_layer_enabled(Layer::Sprites, sprites);
+            gpu.set(0xff40, 0x93);
+            gpu.next(456);
+            gpu
+        };
+        let shown = render(true, true);
+        assert_eq!(pixel(&shown, 40, 0), 0x00);
+        assert_eq!(pixel(&shown, 48, 0), 0xc0);
+        // The hidden sprite leaves the background line as if it wasn't there, and LCDC keeps what was written.
+        let hidden = render(true, false);
+        let line = SCREEN_W * 4;
+        assert!(hidden.framebuffer()[..line] == render(false, true).framebuffer()[..line]);
+        assert_eq!(pixel(&hidden, 40, 0), 0xc0);
+        assert_eq!(hidden.get(0xff40), 0x93);
+    }
+
+    #[test]
+    fn oam_written_in_mode_3_shows_from_the_next_line() {
+        let mut gpu = gpu_with(&[(0, [0x00, 0x00]), (1, [0xff, 0xff])]);
+        gpu.set(0xff48, 0xe4);
+        sprite(&mut gpu, 0, 40, 48, 1);
+        gpu.set(0xff40, 0x93);
+        gpu.next(456 * 50 + 100);
+        assert_eq!((gpu.get(0xff44), gpu.get(0xff41) & 0x03), (50, 3));
+        // The line keeps the sprite copied during mode 2.
+        gpu.set(0xfe01, 80 + 8);
+        gpu.next(456 * 2);
+        assert_eq!(pixel(&gpu, 40, 50), 0x00);
+        assert_eq!(pixel(&gpu, 80, 50), 0xff);
+        assert_eq!(pixel(&gpu, 40, 51), 0xff);
+        assert_eq!(pixel(&gpu, 80, 51), 0x00);
+    }
+
+    #[test]
+    fn motion_blur_mixes_the_previous_frame() {
+        let mut gpu = gpu_with(&[]);
+        gpu.set(0xff40, 0x91);
+        gpu.set_motion_blur(0.5);
+        // Tile 0 is blank, so BGP alone makes the screen black or white.
+        let mut seen = Vec::new();
+        for bgp in [0xff, 0x00, 0xff, 0x00].iter() {
+            gpu.set(0xff47, *bgp);
+            gpu.next(70224);
+            seen.push(pixel(&gpu, 80, 72));
+        }
+        assert_eq!(seen[0], 0x7f);
+        assert!(seen[1..].iter().all(|&p| (0x7f..=0x80).contains(&p)), "{:?}", seen);
+        // Off, the raw frame again.
+        gpu.set_motion_blur(0.0);
+        gpu.set(0xff47, 0xff);
+        gpu.next(70224);
+        assert_eq!(pixel(&gpu, 80, 72), 0x00);
+    }
+
+    #[test]
+    fn the_default_palette_follows_the_model() {
+        let shade_1 = |term: Term| {
+            let mut gpu = Gpu::power_up(term, Rc::new(RefCell::new(Intf::power_up())));
+            for a in 0x8000..0x8010 {
+                gpu.set(a, if a & 1 == 0 { 0xff } else { 0x00 });
+            }
+            gpu.set(0xff47, 0xe4);
+            gpu.set(0xff40, 0x91);
+            gpu.next(70224);
+            gpu.framebuffer()[..3].to_vec()
+        };
+        assert_eq!(shade_1(Term::GB), [0x8b, 0xac, 0x0f]);
+        assert_eq!(shade_1(Term::GBP), [0xc0, 0xc0, 0xc0]);
+        assert_eq!(shade_1(Term::SGB), [0xd8, 0x90, 0x48]);
+    }
+
+    #[test]
+    fn on_scanline_fires_for_each_visible_line() {
+        let mut gpu = gpu_with(&[(1, [0xff, 0x00])]);
+        for i in 0..32 * 32 {
+            gpu.set(0x9800 + i, (i & 1) as u8);
+        }
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let seen = lines.clone();
+        gpu.on_scanline(move |ly, pixels| seen.borrow_mut().push((ly, pixels.to_vec())));
+        gpu.set(0xff40, 0x91);
+        gpu.next(70224);
+        let lines = lines.borrow();
+        assert_eq!(lines.len(), 144);
+        for (i, (ly, pixels)) in lines.iter().enumerate() {
+            assert_eq!(usize::from(*ly), i);
+            let row = SCREEN_W * 4;
+            assert!(pixels[..] == gpu.framebuffer()[i * row..(i + 1) * row], "line {}", ly);
+        }
+    }
+
+    #[test]
+    fn framebuffer_hash_and_diff() {
+        let frame = || {
+            let mut gpu = gpu_with(&[(0, [0x00, 0x00]), (1, [0xaa, 0x55])]);
+            for i in 0..32 * 32 {
+                gpu.set(0x9800 + i, (i & 0x01) as u8);
+            }
+            gpu.set(0xff40, 0x91);
+            gpu.next(70224);
+            gpu
+        };
+        let mut a = frame();
+        let b = frame();
+        assert_eq!(a.framebuffer_hash(), b.framebuffer_hash());
+        assert_eq!(a.framebuffer_diff(b.framebuffer()), 0);
+
+        let mut other = b.framebuffer().to_vec();
+        other[(10 * SCREEN_W + 20) * 4 + 1] ^= 0x01;
+        assert_eq!(a.framebuffer_diff(&other), 1);
+        // The last pixel is cut short.
+        assert_eq!(a.framebuffer_diff(&other[..other.len() - 2]), 2);
+        a.rgba[(10 * SCREEN_W + 20) * 4 + 1] ^= 0x01;
+        assert_ne!(a.framebuffer_hash(), b.framebuffer_hash());
+        assert_eq!(a.framebuffer_diff(&other), 0);
+
+        // The packing follows the output format.
+        let mut a = frame();
+        a.set_output_format(OutputFormat::Indexed);
+        let n = SCREEN_W * SCREEN_H;
+        assert_eq!(a.framebuffer_diff(&vec![0xff; n]), n);
+    }
+
+    #[test]
+    fn brightness_and_contrast_adjust_the_output_only() {
+        let frame = || {
+            let mut gpu = gpu_with(&[(0, [0x00, 0x00]), (1, [0xff, 0x00])]);
+            for i in 0..32 * 32 {
+                gpu.set(0x9800 + i, (i & 0x01) as u8);
+            }
+            gpu.set(0xff40, 0x91);
+            gpu.next(70224);
+            gpu
+        };
+        let rgb = |gpu: &Gpu, x: usize| {
+            let i = (10 * SCREEN_W + x) * 4;
+            [gpu.framebuffer()[i], gpu.framebuffer()[i + 1], gpu.framebuffer()[i + 2]]
+        };
+        let mut plain = frame();
+        let mut gpu = frame();
+        // White and the light gray of color 1.
+        let (white, gray) = (rgb(&gpu, 0), rgb(&gpu, 8));
+        assert_eq!(white, [0xff; 3]);
+        assert!(gray[0] > 0x20 && gray[0] < 0xff);
+        let half = |c: [u8; 3]| c.map(|v| (f32::from(v) / 2.0).round() as u8);
+        gpu.set_brightness(0.5);
+        assert_eq!((rgb(&gpu, 0), rgb(&gpu, 8)), (half(white), half(gray)));
+        // The next frame is adjusted as it is drawn, the emulated pixels are not.
+        gpu.next(70224);
+        plain.next(70224);
+        assert_eq!((rgb(&gpu, 0), rgb(&gpu, 8)), (half(white), half(gray)));
+        assert!(gpu.data == plain.data);
+        // Out of range values clamp instead of wrapping.
+        gpu.set_brightness(6.0);
+        assert_eq!((rgb(&gpu, 0), rgb(&gpu, 8)), ([0xff; 3], [0xff; 3]));
+        gpu.set_contrast(4.0);
+        gpu.set_brightness(-1.0);
+        assert_eq!((rgb(&gpu, 0), rgb(&gpu, 8)), ([0x00; 3], [0x00; 3]));
+        gpu.set_contrast(1.0);
+        gpu.set_brightness(1.0);
+        assert!(gpu.framebuffer() == plain.framebuffer());
+    }
+}
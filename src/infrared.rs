@@ -0,0 +1,60 @@
+// FF56 - RP - CGB Mode Only - Infrared Communications Port
+//  Bit 0   Write Data   (0=LED Off, 1=LED On)             (Read/Write)
+//  Bit 1   Read Data    (0=Receiving IR Signal, 1=Normal) (Read Only)
+//  Bit 6-7 Data Read Enable (0=Disable, 3=Enable)         (Read/Write)
+//
+// With reading disabled bit 1 reads 1 whatever the light received. The unused bits 2-5 read 1.
+//
+// See: https://gbdev.io/pandocs/IR.html
+use super::state::{Reader, StateError, Stateful, Writer};
+use alloc::boxed::Box;
+
+// The light in front of the port, for example another console or a remote. The peer is told every write of the LED
+// and asked whether light is received when RP is read with reading enabled.
+pub trait IrPeer {
+    fn led(&mut self, _: bool) {}
+
+    fn light(&self) -> bool;
+}
+
+pub struct Infrared {
+    // Bits 0 and 6-7 as last written.
+    data: u8,
+    // Without a peer no light is received.
+    peer: Option<Box<dyn IrPeer>>,
+}
+
+impl Infrared {
+    pub fn power_up() -> Self {
+        Self { data: 0x00, peer: None }
+    }
+
+    pub fn set_peer(&mut self, peer: Box<dyn IrPeer>) {
+        self.peer = Some(peer);
+    }
+
+    pub fn get(&self) -> u8 {
+        let light = self.data & 0xc0 == 0xc0 && self.peer.as_ref().is_some_and(|p| p.light());
+        let read = if light { 0x00 } else { 0x02 };
+        0x3c | self.data | read
+    }
+
+    pub fn set(&mut self, v: u8) {
+        self.data = v & 0xc1;
+        if let Some(peer) = self.peer.as_mut() {
+            peer.led(v & 0x01 != 0x00);
+        }
+    }
+}
+
+// The peer is not part of the state, it stays across loads.
+impl Stateful for Infrared {
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.data);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.data = r.u8()? & 0xc1;
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@
 // Bit 3: Serial   Interrupt Request (INT 58h)  (1=Request)
 // Bit 4: Joypad   Interrupt Request (INT 60h)  (1=Request)
 #[rustfmt::skip]
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Flag {
     VBlank  = 0,
     LCDStat = 1,
@@ -14,6 +14,20 @@ pub enum Flag {
     Joypad  = 4,
 }
 
+impl Flag {
+    // The interrupt of bit n of IF.
+    pub fn from_bit(n: u32) -> Self {
+        match n {
+            0 => Flag::VBlank,
+            1 => Flag::LCDStat,
+            2 => Flag::Timer,
+            3 => Flag::Serial,
+            4 => Flag::Joypad,
+            _ => panic!("Unsupported interrupt"),
+        }
+    }
+}
+
 pub struct Intf {
     pub data: u8,
 }
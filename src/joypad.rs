@@ -16,8 +16,10 @@
 // allowing the inputs to stabilize, and only the value from the last read actually used).
 use super::intf::{Flag, Intf};
 use super::memory::Memory;
-use std::cell::RefCell;
-use std::rc::Rc;
+use super::pacing::REFRESH_RATE;
+use super::state::{Reader, StateError, Stateful, Writer};
+use alloc::rc::Rc;
+use core::cell::RefCell;
 
 #[rustfmt::skip]
 #[derive(Clone)]
@@ -36,6 +38,15 @@ pub struct Joypad {
     intf: Rc<RefCell<Intf>>,
     matrix: u8,
     select: u8,
+    // Keys held by the host, in the same layout as matrix. While deferred they don't reach the matrix until
+    // set_buttons is called, which lets input recording and playback apply inputs on frame boundaries only.
+    live: u8,
+    deferred: bool,
+    // The button each host key presses, by bit index of JoypadKey. Not part of the save state.
+    remap: [u8; 8],
+    // Toggle rate in Hz of the turbo keys, by bit index of JoypadKey, and the frame count the phase is taken from.
+    turbo: [Option<f32>; 8],
+    pub frame: u64,
 }
 
 impl Joypad {
@@ -44,35 +55,254 @@ impl Joypad {
             intf,
             matrix: 0xff,
             select: 0x00,
+            live: 0xff,
+            deferred: false,
+            remap: [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80],
+            turbo: [None; 8],
+            frame: 0,
         }
     }
 }
 
 impl Joypad {
+    // The input lines P10-P13 as seen through the selected groups, 0 means pressed. If both groups are selected the
+    // lines are the AND of both, if none is selected all lines read high.
+    fn lines(&self) -> u8 {
+        let mut r = 0x0f;
+        if (self.select & 0b0001_0000) == 0x00 {
+            r &= self.matrix & 0x0f;
+        }
+        if (self.select & 0b0010_0000) == 0x00 {
+            r &= self.matrix >> 4;
+        }
+        r
+    }
+
+    // The joypad interrupt is requested when any of the input lines goes from high to low. Only keys of a selected
+    // group can drive a line, opposite directions may be held at the same time.
+    fn update(&mut self, prev: u8) {
+        if prev & !self.lines() != 0x00 {
+            self.intf.borrow_mut().hi(Flag::Joypad);
+        }
+    }
+
+    // The host keys with the turbo keys in their released half period let go. The phase follows the frame count
+    // rather than the moment the key went down, so it is replayed exactly from a save state.
+    fn held(&self) -> u8 {
+        let mut r = 0xff;
+        for (i, to) in self.remap.iter().enumerate() {
+            if self.live & (1 << i) == 0x00 {
+                r &= !to;
+            }
+        }
+        for (i, rate) in self.turbo.iter().enumerate() {
+            if let Some(rate) = rate {
+                let n = (self.frame as f64 * 2.0 * f64::from(*rate) / REFRESH_RATE) as u64;
+                if n & 0x01 != 0x00 {
+                    r |= 1 << i;
+                }
+            }
+        }
+        r
+    }
+
+    fn apply(&mut self, matrix: u8) {
+        let prev = self.lines();
+        self.matrix = matrix;
+        self.update(prev);
+    }
+
     pub fn keydown(&mut self, key: JoypadKey) {
-        self.matrix &= !(key as u8);
-        self.intf.borrow_mut().hi(Flag::Joypad);
+        self.live &= !(key as u8);
+        if !self.deferred {
+            self.apply(self.held());
+        }
     }
 
     pub fn keyup(&mut self, key: JoypadKey) {
-        self.matrix |= key as u8;
+        self.live |= key as u8;
+        if !self.deferred {
+            self.apply(self.held());
+        }
+    }
+
+    // Pressed keys as a mask of JoypadKey bits, as seen by the game.
+    pub fn buttons(&self) -> u8 {
+        !self.matrix
+    }
+
+    // Pressed keys as a mask of JoypadKey bits, as held by the host after the remapping and the turbo keys toggled.
+    pub fn live_buttons(&self) -> u8 {
+        !self.held()
+    }
+
+    // Make the host key from press the button to, keydown(from) and keyup(from) then act on to. Several keys may press
+    // the same button, it is released once none of them is held. Recorded inputs are the buttons after the remapping,
+    // they replay the same on a machine without it.
+    pub fn set_remap(&mut self, from: JoypadKey, to: JoypadKey) {
+        self.remap[(from as u8).trailing_zeros() as usize] = to as u8;
+        if !self.deferred {
+            self.apply(self.held());
+        }
+    }
+
+    // Make every host key press its own button again.
+    pub fn clear_remap(&mut self) {
+        for (i, to) in self.remap.iter_mut().enumerate() {
+            *to = 1 << i;
+        }
+        if !self.deferred {
+            self.apply(self.held());
+        }
+    }
+
+    // Make a held key alternate between pressed and released, rate_hz times per second, None makes it a plain key
+    // again. The key changes on frame boundaries only: at 15 Hz it is pressed for 2 frames and released for 2, a rate
+    // above half the refresh rate of 59.73 Hz can't be followed.
+    pub fn set_turbo(&mut self, key: JoypadKey, rate_hz: Option<f32>) {
+        self.turbo[(key as u8).trailing_zeros() as usize] = rate_hz;
+        if !self.deferred {
+            self.apply(self.held());
+        }
+    }
+
+    // Called at the start of every frame with the number of frames completed, to toggle the turbo keys.
+    pub fn next_frame(&mut self, frame: u64) {
+        self.frame = frame;
+        if !self.deferred {
+            self.apply(self.held());
+        }
+    }
+
+    pub fn set_buttons(&mut self, mask: u8) {
+        self.apply(!mask);
+    }
+
+    // Stop host keys from reaching the game until set_buttons is called. Leaving deferred mode applies the keys held
+    // by the host.
+    pub fn set_deferred(&mut self, deferred: bool) {
+        self.deferred = deferred;
+        if !deferred {
+            self.apply(self.held());
+        }
     }
 }
 
 impl Memory for Joypad {
     fn get(&self, a: u16) -> u8 {
         assert_eq!(a, 0xff00);
-        if (self.select & 0b0001_0000) == 0x00 {
-            return self.select | (self.matrix & 0x0f);
-        }
-        if (self.select & 0b0010_0000) == 0x00 {
-            return self.select | (self.matrix >> 4);
-        }
-        self.select
+        0b1100_0000 | (self.select & 0b0011_0000) | self.lines()
     }
 
     fn set(&mut self, a: u16, v: u8) {
         assert_eq!(a, 0xff00);
-        self.select = v;
+        let prev = self.lines();
+        self.select = v & 0b0011_0000;
+        self.update(prev);
+    }
+}
+
+impl Stateful for Joypad {
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.matrix);
+        w.u8(self.select);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.matrix = r.u8()?;
+        self.select = r.u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joypad() -> (Joypad, Rc<RefCell<Intf>>) {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        (Joypad::power_up(intf.clone()), intf)
+    }
+
+    #[test]
+    fn pressing_a_with_the_action_group_selected() {
+        let (mut joypad, intf) = joypad();
+        joypad.set(0xff00, 0x10);
+        assert_eq!(joypad.get(0xff00), 0xdf);
+        joypad.keydown(JoypadKey::A);
+        assert_eq!(joypad.get(0xff00), 0xde);
+        assert_eq!(intf.borrow().data, 0x10);
+        joypad.keyup(JoypadKey::A);
+        assert_eq!(joypad.get(0xff00), 0xdf);
+    }
+
+    #[test]
+    fn remapped_a_presses_b() {
+        let (mut joypad, _) = joypad();
+        joypad.set(0xff00, 0x10);
+        joypad.set_remap(JoypadKey::A, JoypadKey::B);
+        joypad.keydown(JoypadKey::A);
+        assert_eq!(joypad.get(0xff00), 0xdd);
+        // B is held while either key is.
+        joypad.keydown(JoypadKey::B);
+        joypad.keyup(JoypadKey::A);
+        assert_eq!(joypad.get(0xff00), 0xdd);
+        joypad.keyup(JoypadKey::B);
+        assert_eq!(joypad.get(0xff00), 0xdf);
+        // Clearing the remap with A held moves the press back to A.
+        joypad.keydown(JoypadKey::A);
+        joypad.clear_remap();
+        assert_eq!(joypad.get(0xff00), 0xde);
+    }
+
+    #[test]
+    fn unselected_keys_do_not_interrupt() {
+        let (mut joypad, intf) = joypad();
+        joypad.set(0xff00, 0x30);
+        joypad.keydown(JoypadKey::A);
+        joypad.keydown(JoypadKey::Left);
+        // Neither group selected, the low nibble reads all high.
+        assert_eq!(joypad.get(0xff00), 0xff);
+        assert_eq!(intf.borrow().data, 0x00);
+        // Selecting a group with a held key pulls a line low.
+        joypad.set(0xff00, 0x20);
+        assert_eq!(joypad.get(0xff00), 0xed);
+        assert_eq!(intf.borrow().data, 0x10);
+    }
+
+    #[test]
+    fn opposite_directions() {
+        let (mut joypad, _) = joypad();
+        joypad.set(0xff00, 0x20);
+        joypad.keydown(JoypadKey::Left);
+        joypad.keydown(JoypadKey::Right);
+        assert_eq!(joypad.get(0xff00) & 0x0f, 0x0c);
+    }
+
+    #[test]
+    fn turbo_a_toggles_with_the_frames() {
+        let (mut joypad, _) = joypad();
+        joypad.set(0xff00, 0x10);
+        joypad.set_turbo(JoypadKey::A, Some(15.0));
+        joypad.keydown(JoypadKey::A);
+        let mut seen = String::new();
+        let mut toggles = 0;
+        let mut last = joypad.get(0xff00) & 0x01;
+        for frame in 1..=60 {
+            joypad.next_frame(frame);
+            let a = joypad.get(0xff00) & 0x01;
+            toggles += u32::from(a != last);
+            last = a;
+            seen.push(if a == 0x00 { '#' } else { '.' });
+        }
+        // Two frames pressed, two released, for one second of frames.
+        assert!(seen.starts_with("#..##..##"), "{}", seen);
+        assert_eq!(toggles, 30);
+        // Back to a plain key, it stays pressed.
+        joypad.set_turbo(JoypadKey::A, None);
+        for frame in 61..65 {
+            joypad.next_frame(frame);
+            assert_eq!(joypad.get(0xff00) & 0x01, 0x00);
+        }
     }
 }
@@ -1,14 +1,36 @@
+// Without the std feature the crate is no_std and only needs an allocator: the cpu, the memory, the LCD, the timer and
+// the rest of the hardware run on a ROM image handed over by the host, which reads the picture from the framebuffer.
+// Files, the wall clock, the real time speed limit and the APU come with std.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
 pub mod apu;
+#[cfg(feature = "compression")]
+pub mod archive;
 pub mod cartridge;
+pub mod cheats;
 pub mod clock;
 pub mod convention;
 pub mod cpu;
+pub mod disasm;
 pub mod gpu;
+pub mod infrared;
 pub mod intf;
 pub mod joypad;
+pub mod link;
 pub mod memory;
 pub mod mmunit;
 pub mod motherboard;
+pub mod movie;
+pub mod pacing;
+#[cfg(feature = "image")]
+pub mod png;
+pub mod printer;
 pub mod register;
+pub mod rewind;
 pub mod serial;
+pub mod sgb;
+pub mod state;
 pub mod timer;
@@ -0,0 +1,86 @@
+// Two machines joined by a link cable. The cable crosses the lines of the serial ports: each machine shifts its SB out
+// to the other and shifts in the byte of the other, on the clock of the side that selected the internal one.
+//
+// The machines run in the same thread, one after the other in lockstep: each runs a slice of SYNC_CYCLES cycles of
+// the 4194304 Hz clock before the other catches up, so they never drift apart by more than a slice. A transfer is
+// exchanged at once when the clock of the master completes it, 4096 cycles after it started, with the SB of the other
+// machine as it is at that point of its own slice. The slice is a fraction of a byte, so a side that prepared its
+// byte and requested a transfer on the external clock before the master started is always ready in time, the same as
+// on the hardware. See Serial::clock_in for a side that isn't.
+use super::mmunit::Mmunit;
+use super::motherboard::MotherBoard;
+use super::serial::SerialPeer;
+use alloc::boxed::Box;
+use alloc::rc::{Rc, Weak};
+use core::cell::RefCell;
+
+// Cycles of the 4194304 Hz clock run by a machine before the other one catches up.
+pub const SYNC_CYCLES: u32 = 64;
+
+// One end of the cable, plugged into the serial port of a machine and leading to the other one. The other machine is
+// held weakly so the two ends don't keep each other alive, a cable leading to a dropped machine floats high.
+pub struct LinkPort {
+    other: Weak<RefCell<Mmunit>>,
+}
+
+impl LinkPort {
+    pub fn new(other: &Rc<RefCell<Mmunit>>) -> Self {
+        Self {
+            other: Rc::downgrade(other),
+        }
+    }
+}
+
+impl SerialPeer for LinkPort {
+    fn exchange(&mut self, v: u8) -> u8 {
+        match self.other.upgrade() {
+            Some(mmu) => mmu.borrow_mut().serial.clock_in(v),
+            None => 0xff,
+        }
+    }
+}
+
+pub struct LinkCable {
+    pub a: MotherBoard,
+    pub b: MotherBoard,
+    // Cycles each machine ran past the end of the last slice, taken out of the next one.
+    carry: [u32; 2],
+}
+
+impl LinkCable {
+    // Plug the cable into both machines, replacing their serial peers.
+    pub fn connect(a: MotherBoard, b: MotherBoard) -> Self {
+        a.mmu.borrow_mut().serial.set_peer(Box::new(LinkPort::new(&b.mmu)));
+        b.mmu.borrow_mut().serial.set_peer(Box::new(LinkPort::new(&a.mmu)));
+        Self { a, b, carry: [0; 2] }
+    }
+
+    // The synchronization point: run both machines through the next slice of SYNC_CYCLES cycles, the first one then
+    // the second one. A machine in double speed mode runs twice as many cpu cycles in the same time.
+    pub fn sync(&mut self) {
+        run_slice(&mut self.a, &mut self.carry[0]);
+        run_slice(&mut self.b, &mut self.carry[1]);
+    }
+
+    // Run both machines for at least the given number of cycles of the 4194304 Hz clock, bypassing the real time
+    // speed limit.
+    pub fn run_cycles(&mut self, cycles: u32) {
+        for _ in 0..cycles.div_ceil(SYNC_CYCLES) {
+            self.sync();
+        }
+    }
+
+    // Run both machines until the first one completes a frame, the second one is at most a slice away from it.
+    pub fn run_frame(&mut self) {
+        let frames = self.a.frame_count();
+        while self.a.frame_count() == frames {
+            self.sync();
+        }
+    }
+}
+
+fn run_slice(m: &mut MotherBoard, carry: &mut u32) {
+    let budget = SYNC_CYCLES * m.mmu.borrow().speed as u32;
+    let n = m.run_cycles(budget.saturating_sub(*carry));
+    *carry = (*carry + n).saturating_sub(budget);
+}
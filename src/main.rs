@@ -31,14 +31,16 @@ fn main() {
     let mut mbrd = MotherBoard::power_up(rom);
     let rom_name = mbrd.mmu.borrow().cartridge.title();
 
-    let mut option = minifb::WindowOptions::default();
-    option.resize = true;
-    option.scale = match c_scale {
-        1 => minifb::Scale::X1,
-        2 => minifb::Scale::X2,
-        4 => minifb::Scale::X4,
-        8 => minifb::Scale::X8,
-        _ => panic!("Supported scale: 1, 2, 4 or 8"),
+    let option = minifb::WindowOptions {
+        resize: true,
+        scale: match c_scale {
+            1 => minifb::Scale::X1,
+            2 => minifb::Scale::X2,
+            4 => minifb::Scale::X4,
+            8 => minifb::Scale::X8,
+            _ => panic!("Supported scale: 1, 2, 4 or 8"),
+        },
+        ..minifb::WindowOptions::default()
     };
     let mut window =
         minifb::Window::new(format!("Gameboy - {}", rom_name).as_str(), SCREEN_W, SCREEN_H, option).unwrap();
@@ -62,7 +64,7 @@ fn main() {
 
         let apu = Apu::power_up(format.sample_rate.0);
         let apu_data = apu.buffer.clone();
-        mbrd.mmu.borrow_mut().apu = Some(apu);
+        mbrd.mmu.borrow_mut().set_apu(apu);
 
         thread::spawn(move || {
             event_loop.run(move |_, stream_data| {
@@ -79,15 +81,15 @@ fn main() {
                         cpal::UnknownTypeOutputBuffer::U16(mut buffer) => {
                             for (i, (data_l, data_r)) in apu_data.drain(..len).enumerate() {
                                 buffer[i * 2] =
-                                    (data_l * f32::from(std::i16::MAX) + f32::from(std::u16::MAX) / 2.0) as u16;
+                                    (data_l * f32::from(i16::MAX) + f32::from(u16::MAX) / 2.0) as u16;
                                 buffer[i * 2 + 1] =
-                                    (data_r * f32::from(std::i16::MAX) + f32::from(std::u16::MAX) / 2.0) as u16;
+                                    (data_r * f32::from(i16::MAX) + f32::from(u16::MAX) / 2.0) as u16;
                             }
                         }
                         cpal::UnknownTypeOutputBuffer::I16(mut buffer) => {
                             for (i, (data_l, data_r)) in apu_data.drain(..len).enumerate() {
-                                buffer[i * 2] = (data_l * f32::from(std::i16::MAX)) as i16;
-                                buffer[i * 2 + 1] = (data_r * f32::from(std::i16::MAX)) as i16;
+                                buffer[i * 2] = (data_l * f32::from(i16::MAX)) as i16;
+                                buffer[i * 2 + 1] = (data_r * f32::from(i16::MAX)) as i16;
                             }
                         }
                     }
@@ -13,6 +13,9 @@
 // FFFF        Interrupt Enable Register
 //
 // See: http://bgb.bircd.org/pandocs.htm#cgbregisters
+//
+// Memory is also the bus seen by the cpu: every opcode fetch, read and write it makes goes through the Memory it was
+// powered up with. A host can intercept the reads and writes with a Bus, see MotherBoard::set_bus.
 pub trait Memory {
     fn get(&self, a: u16) -> u8;
 
@@ -26,4 +29,32 @@ pub trait Memory {
         self.set(a, (v & 0xFF) as u8);
         self.set(a + 1, (v >> 8) as u8)
     }
+
+    // Called by the cpu with the clock cycles elapsed, once before each of its memory accesses and once for the rest
+    // of every instruction, so the hardware behind the bus can run in step with the cpu.
+    fn tick(&mut self, _: u32) {}
+
+    // Called by the cpu for every machine cycle it puts an address of FE00-FEFF on the bus, before the access if there
+    // is one. This includes the cycles where the 16 bit incrementer alone drives the bus: INC rr, DEC rr and the
+    // stack pointer decrement of PUSH, CALL, RST and interrupts. On the monochrome models it corrupts OAM in mode 2,
+    // see Gpu::oam_bug.
+    fn oam_bug(&mut self, _: u16, _: OamBug) {}
+}
+
+// How the bus was driven when triggering the OAM bug. The incrementer counts as a write, reads that also increment
+// the address are LD A, (HL+), LD A, (HL-) and the reads of POP, RET and RETI.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OamBug {
+    Read,
+    Write,
+    ReadIncrease,
+}
+
+// The reads and writes of the cpu as seen by a host, see MotherBoard::set_bus. A Bus sits in front of the Mmunit,
+// usually forwarding to it to log or rewrite the accesses. The clock and the OAM bug don't go through it and always
+// reach the Mmunit, so the rest of the hardware runs whatever the Bus does.
+pub trait Bus {
+    fn read(&self, a: u16) -> u8;
+
+    fn write(&mut self, a: u16, v: u8);
 }
@@ -1,18 +1,156 @@
 // A memory management unit (MMU), sometimes called paged memory management unit (PMMU), is a computer hardware unit
 // having all memory references passed through itself, primarily performing the translation of virtual memory addresses
 // to physical addresses.
+#[cfg(feature = "std")]
 use super::apu::Apu;
-use super::cartridge::{self, Cartridge};
+#[cfg(feature = "compression")]
+use super::cartridge::OpenError;
+use super::cartridge::{self, Cartridge, CartridgeError, CartridgeHeader, CartridgeOptions};
+use super::cheats::Cheats;
 use super::convention::Term;
 use super::gpu::{Gpu, Hdma, HdmaMode};
-use super::intf::Intf;
+use super::infrared::Infrared;
+use super::intf::{Flag, Intf};
 use super::joypad::Joypad;
-use super::memory::Memory;
+use super::memory::{Memory, OamBug};
 use super::serial::Serial;
+use super::sgb::Sgb;
+use super::state::{Reader, StateError, Stateful, Writer};
 use super::timer::Timer;
-use std::cell::RefCell;
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+#[cfg(feature = "std")]
 use std::path::Path;
-use std::rc::Rc;
+
+// The OAM DMA copies 160 bytes from XX00-XX9F into OAM, one byte per machine cycle. It runs alongside the CPU for 160
+// machine cycles (640 clock cycles). Every byte is read through the same path as the CPU reads, with the ROM, VRAM, RAM
+// and WRAM banks selected at the time of the read, so a source in a switchable bank copies whatever bank is mapped.
+struct OamDma {
+    src: u16,
+    n: u16,
+    cycles: u32,
+}
+
+// The areas of the memory map, see memory.rs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Region {
+    RomBank0,
+    RomBankN,
+    Vram,
+    ExternalRam,
+    WramBank0,
+    WramBankN,
+    EchoRam,
+    Oam,
+    Unusable,
+    IoRegisters,
+    Hram,
+    InterruptEnable,
+}
+
+// The I/O registers FF00-FF7F as the boot ROM leaves them, which is what a game started without it finds at 0100. Only
+// the registers of the monochrome models set by the boot ROM are given, the rest hold FFh. Values the documentation
+// leaves open are the ones this emulation starts with.
+// STAT and LY depend on how far the PPU got: the boot ROM hands over during line 153, where LY already reads 0 and
+// STAT 85h, while the PPU here starts at the top of line 0.
+//
+// See: https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+pub fn io_power_up(term: Term) -> [u8; 0x80] {
+    #[rustfmt::skip]
+    let regs = [
+        (0xff00, 0xcf), (0xff01, 0x00), (0xff02, 0x7e), (0xff04, 0xab), (0xff05, 0x00), (0xff06, 0x00),
+        (0xff07, 0xf8), (0xff0f, 0xe1),
+        (0xff10, 0x80), (0xff11, 0xbf), (0xff12, 0xf3), (0xff13, 0xff), (0xff14, 0xbf), (0xff16, 0x3f),
+        (0xff17, 0x00), (0xff18, 0xff), (0xff19, 0xbf), (0xff1a, 0x7f), (0xff1b, 0xff), (0xff1c, 0x9f),
+        (0xff1d, 0xff), (0xff1e, 0xbf), (0xff20, 0xff), (0xff21, 0x00), (0xff22, 0x00), (0xff23, 0xbf),
+        (0xff24, 0x77), (0xff25, 0xf3), (0xff26, 0xf1),
+        (0xff40, 0x91), (0xff41, 0x85), (0xff42, 0x00), (0xff43, 0x00), (0xff44, 0x00), (0xff45, 0x00),
+        (0xff46, 0xff), (0xff47, 0xfc), (0xff48, 0xff), (0xff49, 0xff), (0xff4a, 0x00), (0xff4b, 0x00),
+    ];
+    let mut r = [0xff; 0x80];
+    for &(a, v) in regs.iter() {
+        r[a - 0xff00] = v;
+    }
+    match term {
+        Term::GB | Term::GBP => {}
+        // The Super GameBoy boot ROM turns sound off again. Its DIV isn't documented.
+        Term::SGB => {
+            r[0x04] = 0x00;
+            r[0x26] = 0xf0;
+        }
+        // The CGB boot ROM runs for longer and depends on the cartridge, DIV isn't documented either.
+        Term::GBC => {
+            r[0x02] = 0x7f;
+            r[0x04] = 0x00;
+            r[0x46] = 0x00;
+        }
+    }
+    r
+}
+
+// Seeds the garbage of Mmunit::fill_power_on.
+const POWER_ON_SEED: u64 = 0x0147_0143_0104_0100;
+
+// SplitMix64, see https://prng.di.unimi.it/splitmix64.c
+fn splitmix(seed: u64) -> impl FnMut() -> u8 {
+    let mut state = seed;
+    move || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as u8
+    }
+}
+
+// The registers of io_power_up written at power up, NR52 first so the APU is on for the sound registers. DIV and IF
+// are set directly, STAT and LY can't be written, and writing DMA would start a transfer.
+const POWER_UP_WRITES: [u16; 36] = [
+    0xff00, 0xff01, 0xff02, 0xff05, 0xff06, 0xff07, 0xff26, 0xff10, 0xff11, 0xff12, 0xff13, 0xff14, 0xff16, 0xff17,
+    0xff18, 0xff19, 0xff1a, 0xff1b, 0xff1c, 0xff1d, 0xff1e, 0xff20, 0xff21, 0xff22, 0xff23, 0xff24, 0xff25, 0xff40,
+    0xff42, 0xff43, 0xff45, 0xff47, 0xff48, 0xff49, 0xff4a, 0xff4b,
+];
+
+// A write of the cpu to an I/O register, FF00-FF7F or IE at FFFF, see Mmunit::set_io_trace. old is the register as
+// the cpu read it back right before the write, unused bits included, and cycles the clock cycles since power up at
+// the time of the write, as counted by Cpu::cycles.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IoWrite {
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+    pub cycles: u64,
+}
+
+type IoTrace = Box<dyn FnMut(IoWrite)>;
+
+// The banking state a write to the cartridge bank controller can change, see BankSwitch.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BankRegister {
+    // The banks mapped at 4000-7FFF and A000-BFFF, see Cartridge::bank.
+    RomBank,
+    RamBank,
+    // The MBC1 banking mode, see Cartridge::bank_mode.
+    Mode,
+    // 1 when A000-BFFF is enabled, 0 when it isn't.
+    RamEnable,
+}
+
+// A write of the cpu to 0000-7FFF that changed the banking of the cartridge, see Mmunit::set_bank_switch_hook. A
+// write that changes several registers at once, the MBC1 upper bank bits in mode 1 for instance, is reported once per
+// register, in the order of BankRegister. cycles is counted as in IoWrite.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BankSwitch {
+    pub kind: BankRegister,
+    pub old: usize,
+    pub new: usize,
+    pub cycles: u64,
+}
+
+type BankHook = Box<dyn FnMut(BankSwitch)>;
 
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub enum Speed {
@@ -22,103 +160,349 @@ pub enum Speed {
 
 pub struct Mmunit {
     pub cartridge: Box<dyn Cartridge>,
+    pub cheats: Cheats,
+    #[cfg(feature = "std")]
     pub apu: Option<Apu>,
     pub gpu: Gpu,
+    // The infrared port, GBC only.
+    pub infrared: Infrared,
     pub joypad: Joypad,
     pub serial: Serial,
+    // Command packets sent through the joypad register, only decoded on the Super GameBoy.
+    pub sgb: Sgb,
     pub shift: bool,
     pub speed: Speed,
     pub term: Term,
     pub timer: Timer,
+    // The boot ROM overlays the cartridge until FF50 is written.
+    boot_rom: Option<Vec<u8>>,
     inte: u8,
     intf: Rc<RefCell<Intf>>,
     hdma: Hdma,
+    oam_dma: Option<OamDma>,
     hram: [u8; 0x7f],
     wram: [u8; 0x8000],
     wram_bank: usize,
+    // Dots run through Memory::tick and not yet collected with take_dots.
+    dots: u32,
+    // Clock cycles ticked by the cpu since power up, including the ones of the instruction in progress.
+    cycles: u64,
+    io_trace: Option<IoTrace>,
+    bank_hook: Option<BankHook>,
 }
 
 impl Mmunit {
+    #[cfg(feature = "std")]
     pub fn power_up(path: impl AsRef<Path>) -> Self {
-        let cart = cartridge::power_up(path);
+        Self::with_cartridge(cartridge::power_up(path))
+    }
+
+    // See cartridge::power_up_with.
+    #[cfg(feature = "std")]
+    pub fn power_up_with(path: impl AsRef<Path>, options: CartridgeOptions) -> Self {
+        Self::with_cartridge(cartridge::power_up_with(path, options))
+    }
+
+    // See cartridge::from_rom.
+    pub fn from_rom(rom: impl Into<Cow<'static, [u8]>>) -> Self {
+        Self::with_cartridge(cartridge::from_rom(rom))
+    }
+
+    // See cartridge::from_rom_with.
+    pub fn from_rom_with(rom: impl Into<Cow<'static, [u8]>>, options: CartridgeOptions) -> Self {
+        Self::with_cartridge(cartridge::from_rom_with(rom, options))
+    }
+
+    // See cartridge::from_bytes.
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        Ok(Self::with_cartridge(cartridge::from_bytes(rom)?))
+    }
+
+    // See cartridge::from_bytes_with.
+    pub fn from_bytes_with(rom: Vec<u8>, options: CartridgeOptions) -> Result<Self, CartridgeError> {
+        Ok(Self::with_cartridge(cartridge::from_bytes_with(rom, options)?))
+    }
+
+    // See cartridge::from_path.
+    #[cfg(feature = "compression")]
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, OpenError> {
+        Ok(Self::with_cartridge(cartridge::from_path(path)?))
+    }
+
+    // An independent copy of the memory and the hardware behind it, see MotherBoard::fork.
+    pub fn fork(&self) -> Self {
+        let mut r = Self::with_cartridge_as(self.cartridge.fork(), self.term);
+        r.cheats = self.cheats.clone();
+        #[cfg(feature = "std")]
+        {
+            r.apu = self.apu.as_ref().map(|apu| {
+                let mut r = Apu::power_up(apu.sample_rate());
+                r.set_term(self.term);
+                r
+            });
+        }
+        r.boot_rom = self.boot_rom.clone();
+        let mut w = Writer::new();
+        self.save_state(&mut w);
+        // The state was saved by the same cartridge on the same term, it always loads.
+        r.load_state(&mut Reader::new(&w.into_inner())).unwrap();
+        r
+    }
+
+    // Power up the console the ROM is meant for, see Term::detect. The other constructors only choose between the
+    // GameBoy and the GameBoy Color.
+    pub fn auto(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        let cart = cartridge::from_bytes(rom)?;
+        // The cartridge already checked the header area is there.
+        let term = Term::detect(&CartridgeHeader::parse(cart.rom()).unwrap());
+        Ok(Self::with_cartridge_as(cart, term))
+    }
+
+    // Power up around a cartridge built by the host: a GameBoy Color when bit 7 of the CGB flag at 0143h is set, the
+    // original GameBoy otherwise.
+    pub fn with_cartridge(cart: Box<dyn Cartridge>) -> Self {
         let term = match cart.get(0x0143) & 0x80 {
             0x80 => Term::GBC,
             _ => Term::GB,
         };
+        Self::with_cartridge_as(cart, term)
+    }
+
+    fn with_cartridge_as(cart: Box<dyn Cartridge>, term: Term) -> Self {
         let intf = Rc::new(RefCell::new(Intf::power_up()));
         let mut r = Self {
             cartridge: cart,
+            cheats: Cheats::power_up(),
+            #[cfg(feature = "std")]
             apu: None,
             gpu: Gpu::power_up(term, intf.clone()),
+            infrared: Infrared::power_up(),
             joypad: Joypad::power_up(intf.clone()),
             serial: Serial::power_up(intf.clone()),
+            sgb: Sgb::power_up(),
             shift: false,
             speed: Speed::Normal,
             term,
             timer: Timer::power_up(intf.clone()),
+            boot_rom: None,
             inte: 0x00,
             intf: intf.clone(),
             hdma: Hdma::power_up(),
+            oam_dma: None,
             hram: [0x00; 0x7f],
             wram: [0x00; 0x8000],
             wram_bank: 0x01,
+            dots: 0,
+            cycles: 0,
+            io_trace: None,
+            bank_hook: None,
         };
-        r.set(0xff05, 0x00);
-        r.set(0xff06, 0x00);
-        r.set(0xff07, 0x00);
-        r.set(0xff10, 0x80);
-        r.set(0xff11, 0xbf);
-        r.set(0xff12, 0xf3);
-        r.set(0xff14, 0xbf);
-        r.set(0xff16, 0x3f);
-        r.set(0xff16, 0x3f);
-        r.set(0xff17, 0x00);
-        r.set(0xff19, 0xbf);
-        r.set(0xff1a, 0x7f);
-        r.set(0xff1b, 0xff);
-        r.set(0xff1c, 0x9f);
-        r.set(0xff1e, 0xff);
-        r.set(0xff20, 0xff);
-        r.set(0xff21, 0x00);
-        r.set(0xff22, 0x00);
-        r.set(0xff23, 0xbf);
-        r.set(0xff24, 0x77);
-        r.set(0xff25, 0xf3);
-        r.set(0xff26, 0xf1);
-        r.set(0xff40, 0x91);
-        r.set(0xff42, 0x00);
-        r.set(0xff43, 0x00);
-        r.set(0xff45, 0x00);
-        r.set(0xff47, 0xfc);
-        r.set(0xff48, 0xff);
-        r.set(0xff49, 0xff);
-        r.set(0xff4a, 0x00);
-        r.set(0xff4b, 0x00);
+        let io = io_power_up(term);
+        for &a in POWER_UP_WRITES.iter() {
+            r.set(a, io[usize::from(a - 0xff00)]);
+        }
+        r.intf.borrow_mut().data = io[0x0f] & 0x1f;
+        r.timer.set_div(u16::from(io[0x04]) << 8);
+        r.fill_power_on(false);
         r
     }
+
+    // Fill OAM, HRAM and VRAM with the garbage the console powers up with, and that its boot ROM leaves. The garbage
+    // varies from unit to unit but isn't uniform: on the monochrome models the bits of every other byte of VRAM and
+    // HRAM lean towards 1 and the others towards 0, and the first 8 bytes of OAM lean the same way two by two and repeat
+    // over the whole of it. A byte leaning towards 1 is drawn here as the OR of three random ones, the other way as
+    // their AND. The color models show no such bias, and their boot ROM clears OAM and VRAM. The monochrome boot ROMs
+    // clear VRAM too, the GameBoy and GameBoy Pocket ones then draw the logo, see draw_boot_logo. The same term always
+    // gives the same bytes, fill_ram draws other ones.
+    //
+    // See: https://gbdev.io/pandocs/Power_Up_Sequence.html
+    fn fill_power_on(&mut self, boot: bool) {
+        let mut next = splitmix(POWER_ON_SEED);
+        if self.term == Term::GBC {
+            self.hram.iter_mut().for_each(|b| *b = next());
+            self.gpu.fill_oam(|| 0x00);
+            if boot {
+                self.gpu.fill_vram(next);
+            } else {
+                self.gpu.fill_vram(|| 0x00);
+            }
+            return;
+        }
+        let mut lean = move |one: bool| {
+            let (a, b, c) = (next(), next(), next());
+            if one {
+                a | b | c
+            } else {
+                a & b & c
+            }
+        };
+        // HRAM starts at an even address, FF80.
+        for (i, b) in self.hram.iter_mut().enumerate() {
+            *b = lean(i & 1 != 0);
+        }
+        let mut oam = [0x00; 8];
+        for (i, b) in oam.iter_mut().enumerate() {
+            *b = lean(i & 2 == 0);
+        }
+        let mut i = 0;
+        self.gpu.fill_oam(|| {
+            i += 1;
+            oam[(i - 1) % 8]
+        });
+        if boot {
+            let mut i = 0;
+            self.gpu.fill_vram(|| {
+                i += 1;
+                lean((i - 1) & 1 != 0)
+            });
+            return;
+        }
+        self.gpu.fill_vram(|| 0x00);
+        if self.term != Term::SGB {
+            self.draw_boot_logo();
+        }
+    }
+
+    // Leave VRAM as the monochrome boot ROM does after scrolling the logo in. The logo at 0104-0133 of the cartridge
+    // is enlarged to twice its size into tiles 01h-18h at 8010-818F, each bit doubled horizontally and each row
+    // vertically, in the low bit plane only. Tile 19h at 8190 is the registered mark. The tile map shows tile 19h at
+    // 9910, tiles 01h-0Ch at 9904-990F and tiles 0Dh-18h at 9924-992F, the rest of VRAM is cleared.
+    //
+    // See: https://gbdev.gg8.se/wiki/articles/Gameboy_Bootstrap_ROM
+    fn draw_boot_logo(&mut self) {
+        let mut a = 0x8010;
+        for i in 0x0104..0x0134 {
+            let v = self.cartridge.rom()[i];
+            for nibble in [v >> 4, v & 0x0f] {
+                let mut row = 0x00;
+                for bit in (0..4).rev() {
+                    if nibble & (1 << bit) != 0x00 {
+                        row |= 0b11 << (bit * 2);
+                    }
+                }
+                self.gpu.set(a, row);
+                self.gpu.set(a + 2, row);
+                a += 4;
+            }
+        }
+        for (i, &v) in [0x3c, 0x42, 0xb9, 0xa5, 0xb9, 0xa5, 0x42, 0x3c].iter().enumerate() {
+            self.gpu.set(0x8190 + 2 * i as u16, v);
+        }
+        self.gpu.set(0x9910, 0x19);
+        for i in 0..0x0c {
+            self.gpu.set(0x9904 + i, 0x01 + i as u8);
+            self.gpu.set(0x9924 + i, 0x0d + i as u8);
+        }
+    }
 }
 
 impl Mmunit {
+    // Request an interrupt the way the peripherals do, by setting its bit in IF.
+    pub fn request_interrupt(&mut self, flag: Flag) {
+        self.intf.borrow_mut().hi(flag);
+    }
+
+    // FFFF - IE - Interrupt Enable, one bit per interrupt as in IF.
+    pub fn interrupt_enable(&self) -> u8 {
+        self.inte
+    }
+
+    pub fn set_interrupt_enable(&mut self, v: u8) {
+        self.inte = v;
+    }
+
+    // FF0F - IF - Interrupt Flag. Only the lower five bits exist, the upper three read as 1.
+    pub fn interrupt_flag(&self) -> u8 {
+        self.get(0xff0f)
+    }
+
+    pub fn set_interrupt_flag(&mut self, v: u8) {
+        self.set(0xff0f, v);
+    }
+
+    // cycles are cpu clock cycles. The timer, the serial port and the OAM DMA are clocked by the cpu and run them
+    // all, so they keep their rate relative to the cpu in double speed mode. The LCD and the APU run at a fixed rate
+    // and get half of them in double speed mode, as dots. An HDMA transfer in progress stalls the cpu, its cycles
+    // are added to both.
     pub fn next(&mut self, cycles: u32) -> u32 {
         let cpu_divider = self.speed as u32;
         let vram_cycles = self.run_dma();
         let gpu_cycles = cycles / cpu_divider + vram_cycles;
         let cpu_cycles = cycles + vram_cycles * cpu_divider;
+        self.run_oam_dma(cpu_cycles);
+        self.serial.next(cpu_cycles);
         self.timer.next(cpu_cycles);
         self.gpu.next(gpu_cycles);
-        self.apu.as_mut().map_or((), |s| s.next(gpu_cycles));
+        #[cfg(feature = "std")]
+        if let Some(s) = self.apu.as_mut() {
+            s.next(gpu_cycles);
+        }
         gpu_cycles
     }
 
-    pub fn switch_speed(&mut self) {
-        if self.shift {
-            if self.speed == Speed::Double {
-                self.speed = Speed::Normal;
-            } else {
-                self.speed = Speed::Double;
-            }
+    // The dots the LCD ran while the cpu ticked the hardware, since the last call.
+    pub fn take_dots(&mut self) -> u32 {
+        core::mem::take(&mut self.dots)
+    }
+
+    // Performs the speed switch prepared through KEY1, returns true if the speed changed. Only the GameBoy Color
+    // supports double speed, on the other terms the call is a no-op that returns false.
+    pub fn switch_speed(&mut self) -> bool {
+        if self.term != Term::GBC {
+            self.shift = false;
+            return false;
+        }
+        if !self.shift {
+            return false;
+        }
+        if self.speed == Speed::Double {
+            self.speed = Speed::Normal;
+        } else {
+            self.speed = Speed::Double;
         }
         self.shift = false;
+        true
+    }
+
+    // Apply the GameShark codes, once per frame. The bank of a code selects the external RAM bank for A000-BFFF and
+    // the WRAM bank for D000-DFFF on the GameBoy Color.
+    pub fn apply_cheats(&mut self) {
+        for i in 0..self.cheats.gameshark().len() {
+            let c = self.cheats.gameshark()[i];
+            match c.addr {
+                0xa000..=0xbfff => self.cartridge.poke_ram(usize::from(c.bank), c.addr, c.value),
+                0xd000..=0xdfff if self.term == Term::GBC => {
+                    let bank = usize::from(c.bank & 0x07).max(1);
+                    self.wram[c.addr as usize - 0xd000 + 0x1000 * bank] = c.value;
+                }
+                _ => self.write(c.addr, c.value),
+            }
+        }
+    }
+
+    fn run_oam_dma(&mut self, cycles: u32) {
+        let mut dma = match self.oam_dma.take() {
+            Some(some) => some,
+            None => return,
+        };
+        dma.cycles += cycles;
+        while dma.cycles >= 4 && dma.n < 0xa0 {
+            dma.cycles -= 4;
+            let b = self.oam_dma_byte(&dma);
+            self.gpu.set(0xfe00 + dma.n, b);
+            dma.n += 1;
+        }
+        if dma.n < 0xa0 {
+            self.oam_dma = Some(dma);
+        }
+    }
+
+    // The byte the OAM DMA is reading from the bus. Sources E000-FFFF are not wired to OAM, IO or HRAM but read the
+    // work RAM behind the echo area instead.
+    fn oam_dma_byte(&self, dma: &OamDma) -> u8 {
+        let a = dma.src + dma.n.min(0x9f);
+        let a = if a >= 0xe000 { a - 0x2000 } else { a };
+        self.read(a)
     }
 
     fn run_dma(&mut self) -> u32 {
@@ -150,7 +534,7 @@ impl Mmunit {
     fn run_dma_hrampart(&mut self) {
         let mmu_src = self.hdma.src;
         for i in 0..0x10 {
-            let b: u8 = self.get(mmu_src + i);
+            let b: u8 = self.read(mmu_src + i);
             self.gpu.set(self.hdma.dst + i, b);
         }
         self.hdma.src += 0x10;
@@ -163,10 +547,165 @@ impl Mmunit {
     }
 }
 
-impl Memory for Mmunit {
-    fn get(&self, a: u16) -> u8 {
+impl Mmunit {
+    // Call sink with every write of the cpu to an I/O register, made through Memory::set: "LCDC changed from 91h to
+    // 11h at cycle N". The writes of the DMA and of the power up sequence aren't seen. Replaces the previous sink.
+    pub fn set_io_trace(&mut self, sink: impl FnMut(IoWrite) + 'static) {
+        self.io_trace = Some(Box::new(sink));
+    }
+
+    pub fn clear_io_trace(&mut self) {
+        self.io_trace = None;
+    }
+
+    // Call hook with every change of the cartridge banking made by the cpu, "ROM bank changed from 1 to 5 at cycle
+    // N". Writes that leave the banking as it was don't call it. Replaces the previous hook.
+    pub fn set_bank_switch_hook(&mut self, hook: impl FnMut(BankSwitch) + 'static) {
+        self.bank_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_bank_switch_hook(&mut self) {
+        self.bank_hook = None;
+    }
+
+    fn banking(&self) -> [usize; 4] {
+        [
+            self.cartridge.bank(0x4000),
+            self.cartridge.bank(0xa000),
+            usize::from(self.cartridge.bank_mode()),
+            usize::from(self.cartridge.ram_enabled()),
+        ]
+    }
+
+    fn write_bank_controller(&mut self, a: u16, v: u8) {
+        let old = self.banking();
+        self.cartridge.set(a, v);
+        let new = self.banking();
+        let kinds = [
+            BankRegister::RomBank,
+            BankRegister::RamBank,
+            BankRegister::Mode,
+            BankRegister::RamEnable,
+        ];
+        let cycles = self.cycles;
+        if let Some(hook) = self.bank_hook.as_mut() {
+            for (i, kind) in kinds.iter().enumerate() {
+                if old[i] != new[i] {
+                    hook(BankSwitch {
+                        kind: *kind,
+                        old: old[i],
+                        new: new[i],
+                        cycles,
+                    });
+                }
+            }
+        }
+    }
+
+    // Attach the APU. Without a boot ROM mapped to initialise it, the sound registers are set as the boot ROM leaves
+    // them, see io_power_up.
+    #[cfg(feature = "std")]
+    pub fn set_apu(&mut self, mut apu: Apu) {
+        apu.set_term(self.term);
+        self.apu = Some(apu);
+        self.reset_apu();
+    }
+
+    // Put the APU back to its power up state, see set_apu.
+    #[cfg(feature = "std")]
+    pub fn reset_apu(&mut self) {
+        match self.apu.as_mut() {
+            Some(apu) => apu.reset(),
+            None => return,
+        }
+        if self.boot_rom.is_none() {
+            let io = io_power_up(self.term);
+            for &a in POWER_UP_WRITES.iter().filter(|a| (0xff10..=0xff26).contains(*a)) {
+                self.write(a, io[usize::from(a - 0xff00)]);
+            }
+        }
+    }
+
+    // Map a boot ROM over the cartridge. The DMG boot ROM is 256 bytes and covers 0000-00FF, the CGB one is 2304 bytes
+    // and also covers 0200-08FF, leaving the cartridge header at 0100-01FF visible.
+    //
+    // The I/O registers go back to their state at reset, before any boot ROM ran: the LCD and the APU are off, the
+    // timer, palettes and scroll registers are cleared and DIV starts from 0. VRAM holds the garbage of power up again
+    // instead of the logo of draw_boot_logo, the boot ROM draws its own, see fill_power_on.
+    pub fn set_boot_rom(&mut self, rom: Vec<u8>) {
+        self.boot_rom = Some(rom);
+        for &a in POWER_UP_WRITES.iter() {
+            self.set(a, 0x00);
+        }
+        self.intf.borrow_mut().data = 0x00;
+        self.timer.set_div(0x0000);
+        self.fill_power_on(true);
+    }
+
+    // Fill WRAM, HRAM and VRAM with a pattern derived from seed. The RAM of a real console powers up with garbage that
+    // varies from unit to unit and from one power up to the next, the same seed always gives the same garbage. On the
+    // real hardware the boot ROM clears VRAM before the cartridge runs, see MotherBoard::with_boot_rom.
+    pub fn fill_ram(&mut self, seed: u64) {
+        let mut next = splitmix(seed);
+        self.wram.iter_mut().for_each(|b| *b = next());
+        self.hram.iter_mut().for_each(|b| *b = next());
+        self.gpu.fill_vram(next);
+    }
+
+    fn boot_rom_get(&self, a: u16) -> Option<u8> {
+        let rom = self.boot_rom.as_ref()?;
+        if (0x0100..0x0200).contains(&a) {
+            return None;
+        }
+        rom.get(a as usize).copied()
+    }
+
+    // The region an address belongs to, for tools labelling addresses.
+    pub fn region(a: u16) -> Region {
+        match a {
+            0x0000..=0x3fff => Region::RomBank0,
+            0x4000..=0x7fff => Region::RomBankN,
+            0x8000..=0x9fff => Region::Vram,
+            0xa000..=0xbfff => Region::ExternalRam,
+            0xc000..=0xcfff => Region::WramBank0,
+            0xd000..=0xdfff => Region::WramBankN,
+            0xe000..=0xfdff => Region::EchoRam,
+            0xfe00..=0xfe9f => Region::Oam,
+            0xfea0..=0xfeff => Region::Unusable,
+            0xff00..=0xff7f => Region::IoRegisters,
+            0xff80..=0xfffe => Region::Hram,
+            0xffff => Region::InterruptEnable,
+        }
+    }
+
+    // The bank currently mapped in a region, None for the regions that aren't banked. The echo RAM mirrors both work
+    // RAM regions, so it has no bank of its own. The ROM and RAM banks are the ones of the bank controller, see
+    // Cartridge::bank, the boot ROM overlaying bank 0 isn't taken into account.
+    pub fn current_bank(&self, region: Region) -> Option<usize> {
+        match region {
+            Region::RomBank0 => Some(self.cartridge.bank(0x0000)),
+            Region::RomBankN => Some(self.cartridge.bank(0x4000)),
+            Region::Vram => Some(self.gpu.vram_bank()),
+            Region::ExternalRam => Some(self.cartridge.bank(0xa000)),
+            Region::WramBank0 => Some(0),
+            Region::WramBankN => Some(self.wram_bank),
+            _ => None,
+        }
+    }
+
+    // Read the address as it is mapped, without the bus conflicts of a running OAM DMA or of the PPU modes. Reads in
+    // this emulation never have side effects, so this is safe on any register.
+    pub fn peek(&self, a: u16) -> u8 {
+        self.read(a)
+    }
+
+    fn read(&self, a: u16) -> u8 {
         match a {
-            0x0000..=0x7fff => self.cartridge.get(a),
+            0x0000..=0x08ff if self.boot_rom.is_some() => match self.boot_rom_get(a) {
+                Some(v) => v,
+                None => self.cheats.rom(a, self.cartridge.get(a)),
+            },
+            0x0000..=0x7fff => self.cheats.rom(a, self.cartridge.get(a)),
             0x8000..=0x9fff => self.gpu.get(a),
             0xa000..=0xbfff => self.cartridge.get(a),
             0xc000..=0xcfff => self.wram[a as usize - 0xc000],
@@ -174,31 +713,54 @@ impl Memory for Mmunit {
             0xe000..=0xefff => self.wram[a as usize - 0xe000],
             0xf000..=0xfdff => self.wram[a as usize - 0xf000 + 0x1000 * self.wram_bank],
             0xfe00..=0xfe9f => self.gpu.get(a),
-            0xfea0..=0xfeff => 0x00,
+            // The unused area after OAM reads as FFh on monochrome models. Later revisions of the CGB repeat the upper
+            // nibble of the low address byte instead, FEB0-FEBF reads as BBh. Writes are ignored.
+            0xfea0..=0xfeff => match self.term {
+                Term::GBC => (a as u8 & 0xf0) | ((a as u8) >> 4),
+                _ => 0xff,
+            },
             0xff00 => self.joypad.get(a),
             0xff01..=0xff02 => self.serial.get(a),
             0xff04..=0xff07 => self.timer.get(a),
-            0xff0f => self.intf.borrow().data,
+            0xff0f => 0xe0 | self.intf.borrow().data,
+            #[cfg(feature = "std")]
             0xff10..=0xff3f => match &self.apu {
                 Some(some) => some.get(a),
                 None => 0x00,
             },
-            0xff4d => {
+            // FF4D - KEY1 - Prepare Speed Switch ** CGB Mode Only **
+            // Bit 7 - Current Speed     (0=Normal, 1=Double) (Read Only)
+            // Bit 0 - Prepare Switch    (0=No, 1=Prepare) (Read/Write)
+            // The switch takes place on the next STOP. In double speed the cpu, the timer, the serial port and the OAM
+            // DMA run twice as fast, the LCD, the HDMA and the APU keep their speed. The other bits read as 1.
+            0xff4d if self.term == Term::GBC => {
                 let a = if self.speed == Speed::Double { 0x80 } else { 0x00 };
                 let b = if self.shift { 0x01 } else { 0x00 };
-                a | b
+                0x7e | a | b
             }
+            0xff4d => 0xff,
+            0xff56 if self.term == Term::GBC => self.infrared.get(),
             0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f => self.gpu.get(a),
-            0xff51..=0xff55 => self.hdma.get(a),
-            0xff68..=0xff6b => self.gpu.get(a),
-            0xff70 => self.wram_bank as u8,
+            0xff51..=0xff55 => {
+                if self.term == Term::GBC {
+                    self.hdma.get(a)
+                } else {
+                    0xff
+                }
+            }
+            0xff68..=0xff6c => self.gpu.get(a),
+            // SVBK only exists on the GameBoy Color, the unused upper bits read as 1s.
+            0xff70 => match self.term {
+                Term::GBC => 0xf8 | self.wram_bank as u8,
+                _ => 0xff,
+            },
             0xff80..=0xfffe => self.hram[a as usize - 0xff80],
             0xffff => self.inte,
             _ => 0x00,
         }
     }
 
-    fn set(&mut self, a: u16, v: u8) {
+    fn write(&mut self, a: u16, v: u8) {
         match a {
             0x0000..=0x7fff => self.cartridge.set(a, v),
             0x8000..=0x9fff => self.gpu.set(a, v),
@@ -209,27 +771,36 @@ impl Memory for Mmunit {
             0xf000..=0xfdff => self.wram[a as usize - 0xf000 + 0x1000 * self.wram_bank] = v,
             0xfe00..=0xfe9f => self.gpu.set(a, v),
             0xfea0..=0xfeff => {}
-            0xff00 => self.joypad.set(a, v),
+            0xff00 => {
+                if self.term == Term::SGB {
+                    self.sgb.write(v);
+                }
+                self.joypad.set(a, v)
+            }
             0xff01..=0xff02 => self.serial.set(a, v),
             0xff04..=0xff07 => self.timer.set(a, v),
+            #[cfg(feature = "std")]
             0xff10..=0xff3f => self.apu.as_mut().map_or((), |s| s.set(a, v)),
             0xff46 => {
                 // Writing to this register launches a DMA transfer from ROM or RAM to OAM memory (sprite attribute
                 // table).
                 // See: http://gbdev.gg8.se/wiki/articles/Video_Display#FF46_-_DMA_-_DMA_Transfer_and_Start_Address_.28R.2FW.29
-                assert!(v <= 0xf1);
-                let base = u16::from(v) << 8;
-                for i in 0..0xa0 {
-                    let b = self.get(base + i);
-                    self.set(0xfe00 + i, b);
-                }
+                self.oam_dma = Some(OamDma {
+                    src: u16::from(v) << 8,
+                    n: 0,
+                    cycles: 0,
+                });
             }
-            0xff4d => self.shift = (v & 0x01) == 0x01,
+            0xff4d if self.term == Term::GBC => self.shift = (v & 0x01) == 0x01,
+            0xff56 if self.term == Term::GBC => self.infrared.set(v),
             0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f => self.gpu.set(a, v),
-            0xff51..=0xff55 => self.hdma.set(a, v),
-            0xff68..=0xff6b => self.gpu.set(a, v),
-            0xff0f => self.intf.borrow_mut().data = v,
-            0xff70 => {
+            // Writing a nonzero value unmaps the boot ROM, it can't be mapped back in until reset.
+            0xff50 if v != 0x00 => self.boot_rom = None,
+            0xff51..=0xff55 if self.term == Term::GBC => self.hdma.set(a, v),
+            0xff68..=0xff6c => self.gpu.set(a, v),
+            0xff0f => self.intf.borrow_mut().data = v & 0x1f,
+            // The monochrome models have a single fixed bank at D000-DFFF.
+            0xff70 if self.term == Term::GBC => {
                 self.wram_bank = match v & 0x7 {
                     0 => 1,
                     n => n as usize,
@@ -241,3 +812,156 @@ impl Memory for Mmunit {
         }
     }
 }
+
+impl Memory for Mmunit {
+    // While an OAM DMA transfer is running the external bus is busy, the CPU only sees the I/O registers and HRAM.
+    // Reads from anywhere else conflict with the DMA and return the byte it is copying, writes are ignored. This is why
+    // games wait for the end of the transfer in a loop running from HRAM. VRAM and OAM are also out of reach while the
+    // PPU uses them, see Gpu::blocks_cpu. The DMA transfers themselves aren't blocked.
+    fn get(&self, a: u16) -> u8 {
+        if let Some(dma) = self.oam_dma.as_ref() {
+            if a < 0xff00 {
+                return self.oam_dma_byte(dma);
+            }
+        }
+        if self.gpu.blocks_cpu(a) {
+            return 0xff;
+        }
+        self.read(a)
+    }
+
+    fn set(&mut self, a: u16, v: u8) {
+        if self.oam_dma.is_some() && a < 0xff00 {
+            return;
+        }
+        if self.gpu.blocks_cpu(a) {
+            return;
+        }
+        if a < 0x8000 && self.bank_hook.is_some() {
+            return self.write_bank_controller(a, v);
+        }
+        if self.io_trace.is_none() || !(0xff00..=0xff7f).contains(&a) && a != 0xffff {
+            return self.write(a, v);
+        }
+        let old = self.read(a);
+        self.write(a, v);
+        let w = IoWrite {
+            addr: a,
+            old,
+            new: v,
+            cycles: self.cycles,
+        };
+        if let Some(trace) = self.io_trace.as_mut() {
+            trace(w);
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += u64::from(cycles);
+        let dots = self.next(cycles);
+        self.dots = self.dots.wrapping_add(dots);
+    }
+
+    fn oam_bug(&mut self, _: u16, kind: OamBug) {
+        self.gpu.oam_bug(kind);
+    }
+}
+
+// The header and global checksums identify the cartridge, a state is only loaded into the game that produced it. The
+// APU state is nested so machines with and without sound may exchange states, the audio is simply left as is.
+impl Stateful for Mmunit {
+    fn save_state(&self, w: &mut Writer) {
+        w.bytes(&self.cartridge.rom()[0x014d..0x0150]);
+        w.bool(self.term == Term::GBC);
+        self.cartridge.save_state(w);
+        #[cfg(feature = "std")]
+        match self.apu.as_ref() {
+            Some(apu) => {
+                let mut a = Writer::new();
+                apu.save_state(&mut a);
+                w.bytes(&a.into_inner());
+            }
+            None => w.bytes(&[]),
+        }
+        #[cfg(not(feature = "std"))]
+        w.bytes(&[]);
+        self.gpu.save_state(w);
+        self.joypad.save_state(w);
+        self.serial.save_state(w);
+        self.infrared.save_state(w);
+        self.timer.save_state(w);
+        w.bool(self.shift);
+        w.bool(self.speed == Speed::Double);
+        w.bool(self.boot_rom.is_some());
+        w.u8(self.inte);
+        w.u8(self.intf.borrow().data);
+        w.u16(self.hdma.src);
+        w.u16(self.hdma.dst);
+        w.bool(self.hdma.active);
+        w.bool(self.hdma.mode == HdmaMode::Hdma);
+        w.u8(self.hdma.remain);
+        w.bool(self.oam_dma.is_some());
+        if let Some(dma) = self.oam_dma.as_ref() {
+            w.u16(dma.src);
+            w.u16(dma.n);
+            w.u32(dma.cycles);
+        }
+        w.bytes(&self.hram);
+        w.bytes(&self.wram);
+        w.u8(self.wram_bank as u8);
+        w.u64(self.cycles);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        if r.bytes()? != &self.cartridge.rom()[0x014d..0x0150] {
+            return Err(StateError::Mismatch("cartridge"));
+        }
+        if r.bool()? != (self.term == Term::GBC) {
+            return Err(StateError::Mismatch("term"));
+        }
+        self.cartridge.load_state(r)?;
+        let apu = r.bytes()?;
+        #[cfg(feature = "std")]
+        if let Some(s) = self.apu.as_mut() {
+            if !apu.is_empty() {
+                s.load_state(&mut Reader::new(apu))?;
+            }
+        }
+        // Without std there is no APU, its state is skipped.
+        #[cfg(not(feature = "std"))]
+        let _ = apu;
+        self.gpu.load_state(r)?;
+        self.joypad.load_state(r)?;
+        self.serial.load_state(r)?;
+        self.infrared.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.shift = r.bool()?;
+        self.speed = if r.bool()? { Speed::Double } else { Speed::Normal };
+        if !r.bool()? {
+            self.boot_rom = None;
+        } else if self.boot_rom.is_none() {
+            return Err(StateError::Mismatch("boot rom"));
+        }
+        self.inte = r.u8()?;
+        self.intf.borrow_mut().data = r.u8()? & 0x1f;
+        self.hdma.src = r.u16()?;
+        self.hdma.dst = r.u16()?;
+        self.hdma.active = r.bool()?;
+        self.hdma.mode = if r.bool()? { HdmaMode::Hdma } else { HdmaMode::Gdma };
+        self.hdma.remain = r.u8()?;
+        self.oam_dma = if r.bool()? {
+            Some(OamDma {
+                src: r.u16()?,
+                n: r.u16()?.min(0xa0),
+                cycles: r.u32()?,
+            })
+        } else {
+            None
+        };
+        r.bytes_into(&mut self.hram, "hram")?;
+        r.bytes_into(&mut self.wram, "wram")?;
+        self.wram_bank = usize::from(r.u8()? & 0x07).max(1);
+        self.cycles = r.u64()?;
+        Ok(())
+    }
+}
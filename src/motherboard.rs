@@ -1,31 +1,944 @@
-use super::cpu::Rtc;
-use super::memory::Memory;
+#[cfg(feature = "compression")]
+use super::cartridge::OpenError;
+use super::cartridge::{Cartridge, CartridgeError, CartridgeOptions};
+use super::convention::Term;
+use super::cpu::{BreakReason, InterruptEvent, Rtc, StepError, CLOCK_FREQUENCY};
+#[cfg(feature = "image")]
+use super::gpu;
+use super::memory::{Bus, Memory, OamBug};
 use super::mmunit::Mmunit;
-use std::cell::RefCell;
+use super::movie::InputMovie;
+use super::pacing::PacingClock;
+use super::register::Register;
+use super::rewind::RewindBuffer;
+use super::state::{self, Reader, StateError, Stateful, Writer};
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{Ref, RefCell};
+#[cfg(feature = "std")]
 use std::path::Path;
-use std::rc::Rc;
+
+type FrameCallback = Box<dyn FnMut(&[u8])>;
+
+// The cpu side of set_bus: the reads and writes go to the host bus, the clock and the OAM bug to the Mmunit.
+struct HostBus {
+    bus: Rc<RefCell<dyn Bus>>,
+    mmu: Rc<RefCell<Mmunit>>,
+}
+
+impl Memory for HostBus {
+    fn get(&self, a: u16) -> u8 {
+        self.bus.borrow().read(a)
+    }
+
+    fn set(&mut self, a: u16, v: u8) {
+        self.bus.borrow_mut().write(a, v)
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.mmu.borrow_mut().tick(cycles)
+    }
+
+    fn oam_bug(&mut self, a: u16, kind: OamBug) {
+        self.mmu.borrow_mut().oam_bug(a, kind)
+    }
+}
 
 pub struct MotherBoard {
     pub mmu: Rc<RefCell<Mmunit>>,
     pub cpu: Rtc,
+    frames: u64,
+    // Dots run past the end of the last frame, carried over so frames stay aligned to 70224 dot boundaries.
+    dots: u32,
+    frame: Vec<u8>,
+    rewind: Option<RewindBuffer>,
+    // Set at the end of a frame, the next step starts a new frame.
+    frame_start: bool,
+    recording: Option<InputMovie>,
+    playback: Option<(InputMovie, usize)>,
+    on_frame: Option<FrameCallback>,
+    // How often on_frame is called, and the line it was last called on at Scanline granularity.
+    granularity: Granularity,
+    observed_ly: u8,
+    // State right after power up and the boot ROM it started from, reset returns to them.
+    power_on: Vec<u8>,
+    boot_rom: Option<Vec<u8>>,
+    // Emulated frames per host frame, and the fraction of a frame owed by the previous host frames.
+    speed: f32,
+    speed_carry: f32,
+    // Frames left undrawn after every frame drawn, and how many were skipped since the last one drawn.
+    frame_skip: u32,
+    skipped: u32,
+    pacer: PacingClock,
+    // The counters of the last frame completed, and their totals when the current frame started.
+    stats: FrameStats,
+    stats_start: FrameStats,
+    // The most dots a call of run_frame runs, see set_frame_cycle_ceiling.
+    frame_ceiling: u32,
+}
+
+// Dots in a frame: 154 lines of 456 dots. Dots are counted at normal speed, so in double speed mode a frame takes
+// twice as many cpu cycles.
+pub const FRAME_DOTS: u32 = 70224;
+
+// The test ROMs of the mooneye-test-suite end by loading the Fibonacci numbers 3, 5, 8, 13, 21 and 34 into B, C, D, E,
+// H and L when they pass, 42h into all of them when they fail, and executing LD B,B.
+const TEST_PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+// What the machine did during a frame, see MotherBoard::last_frame_stats.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FrameStats {
+    // Instructions executed, halted and stopped steps aside.
+    pub instructions: u64,
+    // Cpu clock cycles. A frame is FRAME_DOTS dots, the cycles are twice as many in double speed mode and may differ
+    // by the length of an instruction, frames end between instructions.
+    pub cycles: u64,
+    // Interrupts dispatched.
+    pub interrupts: u64,
+    // Times the PPU entered each mode, indexed by mode: 144 lines of modes 2, 3 and 0 and one VBlank with the LCD on.
+    pub mode_entries: [u64; 4],
+}
+
+// How a call of run_frame ended.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameResult<'a> {
+    // See Gpu::framebuffer.
+    pub framebuffer: &'a [u8],
+    // The call reached the ceiling of set_frame_cycle_ceiling before the end of the frame. The frame isn't counted
+    // and the next call goes on with it.
+    pub short: bool,
+}
+
+impl FrameStats {
+    fn since(&self, start: &FrameStats) -> FrameStats {
+        let mut mode_entries = [0; 4];
+        for (i, n) in mode_entries.iter_mut().enumerate() {
+            *n = self.mode_entries[i].saturating_sub(start.mode_entries[i]);
+        }
+        FrameStats {
+            instructions: self.instructions.saturating_sub(start.instructions),
+            cycles: self.cycles.saturating_sub(start.cycles),
+            interrupts: self.interrupts.saturating_sub(start.interrupts),
+            mode_entries,
+        }
+    }
+}
+
+// A decoded copy of the machine state, see MotherBoard::capture. Two captures compare equal when the machine is in
+// the same state, and a debugger can compare the fields one by one where a save state is opaque bytes. The fields
+// are decoded from a save state kept along with them, it is what restore loads: changing them doesn't change the state
+// restored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MachineState {
+    pub reg: Register,
+    // IME, the interrupt master enable.
+    pub ime: bool,
+    pub halted: bool,
+    pub stopped: bool,
+    // FFFF - IE and FF0F - IF.
+    pub ie: u8,
+    pub intf: u8,
+    // Cpu clock cycles since power up.
+    pub cycles: u64,
+    pub timer: TimerState,
+    pub ppu: PpuState,
+    // The banks mapped at 4000-7FFF and A000-BFFF, see Cartridge::bank.
+    pub rom_bank: usize,
+    pub ram_bank: usize,
+    data: Vec<u8>,
+}
+
+impl MachineState {
+    // The save state the fields were decoded from, as returned by MotherBoard::save_state.
+    pub fn save_state(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TimerState {
+    // The 16 bit counter DIV is the upper byte of.
+    pub div: u16,
+    pub tima: u8,
+    pub tma: u8,
+    pub tac: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PpuState {
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scy: u8,
+    pub scx: u8,
+    // LY as the cpu reads it, and the dots elapsed in the line.
+    pub ly: u8,
+    pub dots: u32,
+    pub lyc: u8,
+    pub wy: u8,
+    pub wx: u8,
+}
+
+// How often the on_frame callback is called, see MotherBoard::set_observer_granularity.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Granularity {
+    // Every frame drawn, when the LCD enters VBlank.
+    Frame,
+    // Every time LY changes, 154 times a frame with the LCD on, VBlank lines included. Never while the LCD is off.
+    Scanline,
+    // After every step of the cpu.
+    Instruction,
+}
+
+// Outcome of a test ROM run by MotherBoard::run_test.
+#[derive(Clone, Debug)]
+pub enum TestResult {
+    Passed,
+    // LD B,B was executed with any other values in the registers.
+    Failed { registers: Register },
+    // The ROM didn't execute LD B,B in time.
+    Timeout,
+}
+
+// Why MotherBoard::load_cartridge refused a cartridge.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SwapError {
+    // The boot ROM installed is for the other family of consoles: a CGB cartridge needs the 2304 bytes CGB boot ROM,
+    // the others the 256 bytes monochrome one.
+    BootRom { cgb: bool, size: usize },
+}
+
+impl core::fmt::Display for SwapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            SwapError::BootRom { cgb, size } => {
+                let kind = if *cgb { "CGB" } else { "monochrome" };
+                write!(f, "The {} bytes boot ROM can't start a {} cartridge", size, kind)
+            }
+        }
+    }
+}
+
+impl core::error::Error for SwapError {}
+
+// The RAM searched by MotherBoard::scan_memory.
+fn scanned() -> impl Iterator<Item = u16> {
+    (0xa000..=0xdfff).chain(0xff80..=0xfffe)
 }
 
 impl MotherBoard {
+    #[cfg(feature = "std")]
     pub fn power_up(path: impl AsRef<Path>) -> Self {
-        let mmu = Rc::new(RefCell::new(Mmunit::power_up(path)));
-        let cpu = Rtc::power_up(mmu.borrow().term, mmu.clone());
-        Self { mmu, cpu }
+        Self::with_mmu(Mmunit::power_up(path))
     }
 
-    pub fn next(&mut self) -> u32 {
-        if self.mmu.borrow().get(self.cpu.cpu.reg.pc) == 0x10 {
-            self.mmu.borrow_mut().switch_speed();
+    // Power up with the cartridge wiring overridden by options, see cartridge::power_up_with.
+    #[cfg(feature = "std")]
+    pub fn power_up_with(path: impl AsRef<Path>, options: CartridgeOptions) -> Self {
+        Self::with_mmu(Mmunit::power_up_with(path, options))
+    }
+
+    // Power up with a ROM image in memory, see cartridge::from_rom.
+    pub fn from_rom(rom: impl Into<Cow<'static, [u8]>>) -> Self {
+        Self::with_mmu(Mmunit::from_rom(rom))
+    }
+
+    // Same as from_rom, with the cartridge wiring overridden by options.
+    pub fn from_rom_with(rom: impl Into<Cow<'static, [u8]>>, options: CartridgeOptions) -> Self {
+        Self::with_mmu(Mmunit::from_rom_with(rom, options))
+    }
+
+    // Power up with a ROM image in memory, or report why it can't be loaded. See cartridge::from_bytes.
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        Ok(Self::with_mmu(Mmunit::from_bytes(rom)?))
+    }
+
+    // Same as from_bytes, with the cartridge wiring detected from the header overridden by options.
+    pub fn from_bytes_with(rom: Vec<u8>, options: CartridgeOptions) -> Result<Self, CartridgeError> {
+        Ok(Self::with_mmu(Mmunit::from_bytes_with(rom, options)?))
+    }
+
+    // Power up with a ROM file that may be compressed, or report why it can't be loaded. See cartridge::from_path.
+    #[cfg(feature = "compression")]
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, OpenError> {
+        Ok(Self::with_mmu(Mmunit::from_path(path)?))
+    }
+
+    // Power up the console the ROM is meant for, the Super GameBoy included, or report why the ROM can't be loaded. See
+    // Term::detect and cartridge::from_bytes.
+    pub fn auto(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        Ok(Self::with_mmu(Mmunit::auto(rom)?))
+    }
+
+    fn with_mmu(mmu: Mmunit) -> Self {
+        let mmu = Rc::new(RefCell::new(mmu));
+        let mut cpu = Rtc::power_up(mmu.borrow().term, mmu.clone());
+        let m = mmu.clone();
+        cpu.cpu.set_speed_switch(move || m.borrow_mut().switch_speed());
+        let mut r = Self {
+            mmu,
+            cpu,
+            frames: 0,
+            dots: 0,
+            frame: Vec::new(),
+            rewind: None,
+            frame_start: true,
+            recording: None,
+            playback: None,
+            on_frame: None,
+            granularity: Granularity::Frame,
+            observed_ly: 0,
+            power_on: Vec::new(),
+            boot_rom: None,
+            speed: 1.0,
+            speed_carry: 0.0,
+            frame_skip: 0,
+            skipped: 0,
+            pacer: PacingClock::power_up(),
+            stats: FrameStats::default(),
+            stats_start: FrameStats::default(),
+            frame_ceiling: 2 * FRAME_DOTS,
+        };
+        r.power_on = r.save_state();
+        r
+    }
+
+    // An independent copy of the machine for lookahead, such as trying inputs and keeping the best branch: running
+    // either one has no effect on the other. The copy shares the ROM image instead of copying it, see cartridge::Rom,
+    // and holds the whole emulated state, the cheats and the boot ROM. A machine with an APU forks with an APU of its
+    // own, whose samples go to its own buffer. Host side settings start from their defaults: the callbacks, bus, serial
+    // peer, breakpoints, rewind, recording, speed and the gpu output settings. Both write the same save files when
+    // asked to save.
+    pub fn fork(&self) -> Self {
+        let mut r = Self::with_mmu(self.mmu.borrow().fork());
+        r.power_on = self.power_on.clone();
+        r.boot_rom = self.boot_rom.clone();
+        // The state was saved by a machine of the same cartridge, it always loads.
+        r.load_state(&self.save_state()).unwrap();
+        r
+    }
+
+    // Power up with the RAM filled from seed instead of cleared or filled with the pattern of Mmunit::fill_power_on,
+    // see Mmunit::fill_ram. Everything else is deterministic already: two machines with the same seed, ROM and inputs
+    // stay identical. reset and reset_with_ram_clear power up with the same pattern again.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.mmu.borrow_mut().fill_ram(seed);
+        self.power_on = self.save_state();
+        self
+    }
+
+    // Start from a boot ROM instead of the post boot state. The cpu and I/O registers are cleared, see
+    // Mmunit::set_boot_rom, and execution begins at 0x0000, the boot ROM is responsible for the rest of the
+    // initialisation before handing over to the cartridge at 0x0100.
+    pub fn with_boot_rom(mut self, rom: Vec<u8>) -> Self {
+        self.mmu.borrow_mut().set_boot_rom(rom.clone());
+        self.cpu.cpu.reg = Register::default();
+        self.cpu.cpu.ei = false;
+        self.power_on = self.save_state();
+        self.boot_rom = Some(rom);
+        self
+    }
+
+    // Press the reset button: the cpu, memories, PPU, APU, timer and cartridge bank registers go back to their power
+    // up state, from the boot ROM again if there is one. The ROM stays loaded and the battery backed RAM keeps its
+    // contents, cartridge RAM without a battery goes back to what it held at power up. Host side settings such as the
+    // callbacks, cheats or breakpoints are kept.
+    pub fn reset(&mut self) {
+        self.power_cycle(false);
+    }
+
+    // Like reset, but the battery backed RAM is cleared as well, as on a cold boot with a fresh save.
+    pub fn reset_with_ram_clear(&mut self) {
+        self.power_cycle(true);
+    }
+
+    fn power_cycle(&mut self, clear: bool) {
+        let ram = self.mmu.borrow().cartridge.save_ram();
+        if let Some(rom) = self.boot_rom.clone() {
+            self.mmu.borrow_mut().set_boot_rom(rom);
+        }
+        // The state was saved by this very machine, it always loads.
+        let power_on = core::mem::take(&mut self.power_on);
+        self.load_state(&power_on).unwrap();
+        self.power_on = power_on;
+        let mut mmu = self.mmu.borrow_mut();
+        if let Some(mut ram) = ram {
+            if clear {
+                ram.iter_mut().for_each(|b| *b = 0x00);
+            }
+            mmu.cartridge.load_ram(&ram).unwrap();
+        }
+        // The APU is attached by the host after power up, so it isn't part of the power up state.
+        #[cfg(feature = "std")]
+        mmu.reset_apu();
+        self.frame_start = true;
+    }
+
+    // Swap in another game, as from a game selection menu: the machine powers up again around the new cartridge, from
+    // the boot ROM if there is one, running for the console the cartridge asks for. Nothing of the previous game is
+    // kept, including its cheats, rewind history and recording. The APU, the bus and the cpu side host settings such
+    // as the speed limit and breakpoints stay, the machine isn't built again. On error the machine is left as it was.
+    pub fn load_cartridge(&mut self, cart: Box<dyn Cartridge>) -> Result<(), SwapError> {
+        let mut mmu = Mmunit::with_cartridge(cart);
+        let term = mmu.term;
+        if let Some(rom) = self.boot_rom.as_ref() {
+            let cgb = term == Term::GBC;
+            if cgb != (rom.len() > 0x0100) {
+                return Err(SwapError::BootRom { cgb, size: rom.len() });
+            }
+            mmu.set_boot_rom(rom.clone());
+        }
+        #[cfg(feature = "std")]
+        if let Some(apu) = self.mmu.borrow_mut().apu.take() {
+            mmu.set_apu(apu);
+        }
+        *self.mmu.borrow_mut() = mmu;
+
+        let mut cpu = Rtc::power_up(term, self.mmu.clone());
+        if self.boot_rom.is_some() {
+            cpu.cpu.reg = Register::default();
+            cpu.cpu.ei = false;
+        }
+        let mut w = Writer::new();
+        cpu.cpu.save_state(&mut w);
+        // A cpu state saved by the same version always loads.
+        self.cpu.cpu.load_state(&mut Reader::new(&w.into_inner())).unwrap();
+
+        self.frames = 0;
+        self.dots = 0;
+        self.frame_start = true;
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.clear();
+        }
+        self.recording = None;
+        self.playback = None;
+        self.mmu.borrow_mut().joypad.frame = 0;
+        self.stats = FrameStats::default();
+        self.stats_start = self.stats_total();
+        self.power_on = self.save_state();
+        Ok(())
+    }
+
+    // Route the reads and writes of the cpu through another bus, typically a wrapper around self.mmu that logs or
+    // rewrites accesses. The clock and the rest of the hardware keep using the Mmunit directly.
+    pub fn set_bus(&mut self, bus: Rc<RefCell<dyn Bus>>) {
+        self.cpu.cpu.mem = Rc::new(RefCell::new(HostBus {
+            bus,
+            mmu: self.mmu.clone(),
+        }));
+    }
+
+    // Read memory as the cpu does, through the current banks and with the restrictions of a running OAM DMA and of the
+    // PPU modes.
+    pub fn read_mem(&self, a: u16) -> u8 {
+        self.mmu.borrow().get(a)
+    }
+
+    // Write memory as the cpu does, with all the side effects: a write to 2000-3FFF switches the ROM bank, one to FF46
+    // starts an OAM DMA and so on.
+    pub fn write_mem(&mut self, a: u16, v: u8) {
+        self.mmu.borrow_mut().set(a, v);
+    }
+
+    // Read memory for inspection, see Mmunit::peek. The machine state is left untouched.
+    pub fn peek_mem(&self, a: u16) -> u8 {
+        self.mmu.borrow().peek(a)
+    }
+
+    // The addresses of the RAM in the banks currently mapped for which predicate(address, byte) holds, in the order of
+    // memory_snapshot: external RAM at A000-BFFF, WRAM at C000-DFFF and HRAM at FF80-FFFE. External RAM reads as FFh
+    // while the game keeps it disabled. Typically the first step of looking for a variable, such as the one holding
+    // the health, by value.
+    pub fn scan_memory(&self, predicate: impl Fn(u16, u8) -> bool) -> Vec<u16> {
+        let mmu = self.mmu.borrow();
+        scanned().filter(|&a| predicate(a, mmu.peek(a))).collect()
+    }
+
+    // The bytes of the RAM scanned by scan_memory, in the same order.
+    pub fn memory_snapshot(&self) -> Vec<u8> {
+        let mmu = self.mmu.borrow();
+        scanned().map(|a| mmu.peek(a)).collect()
+    }
+
+    // The addresses scanned by scan_memory whose byte differs from a previous memory_snapshot. The next steps of the
+    // search compare with the snapshot taken when the value was known, after it changed in the game.
+    pub fn scan_changed(&self, previous: &[u8]) -> Vec<u16> {
+        let mmu = self.mmu.borrow();
+        scanned()
+            .zip(previous.iter())
+            .filter(|&(a, &v)| mmu.peek(a) != v)
+            .map(|(a, _)| a)
+            .collect()
+    }
+
+    // Keep the bytes the game sends over the serial port, see Serial::capture.
+    pub fn capture_serial(&mut self, enable: bool) {
+        self.mmu.borrow_mut().serial.capture(enable);
+    }
+
+    // Keep the interrupts the cpu dispatches, see Cpu::capture_interrupts.
+    pub fn capture_interrupts(&mut self, enable: bool) {
+        self.cpu.cpu.capture_interrupts(enable);
+    }
+
+    // The interrupts dispatched since the last drain, with the cpu cycles they were dispatched at.
+    pub fn drain_interrupt_events(&mut self) -> Vec<InterruptEvent> {
+        self.cpu.cpu.drain_interrupts()
+    }
+
+    // Text sent over the serial port since capture_serial was enabled.
+    pub fn serial_output(&self) -> Ref<'_, str> {
+        Ref::map(self.mmu.borrow(), |mmu| mmu.serial.output())
+    }
+
+    // Call back with the framebuffer every time the LCD enters VBlank, whichever of next, run_frame or run_cycles is
+    // driving the emulation. See Gpu::framebuffer for the layout. Replaces the previous callback.
+    pub fn on_frame(&mut self, callback: impl FnMut(&[u8]) + 'static) {
+        self.on_frame = Some(Box::new(callback));
+    }
+
+    // Call on_frame more or less often than once a frame, trading overhead for resolution in tools that follow the
+    // picture as it is drawn. Below Frame granularity the framebuffer is handed over partly drawn: the lines up to LY
+    // are the current frame, the rest the last one. Frame skipping only applies at Frame granularity.
+    pub fn set_observer_granularity(&mut self, granularity: Granularity) {
+        self.granularity = granularity;
+        self.observed_ly = self.mmu.borrow().get(0xff44);
+    }
+
+    // Advance emulation to the end of the frame as fast as possible and return the completed framebuffer, or stop
+    // short at the ceiling of set_frame_cycle_ceiling. See Gpu::framebuffer for the layout. The real time speed limit
+    // is bypassed, so the result depends only on the ROM, the inputs and the machine state, except for MBC3
+    // cartridges keeping their clock in wall-clock mode.
+    //
+    // A frame is FRAME_DOTS dots of time, not the interval between two VBlanks: it ends whether or not the LCD is on,
+    // and a cpu halted with every interrupt disabled, stopped or locked up by an illegal opcode still lets the time
+    // pass. A buggy ROM can't keep run_frame from returning, the framebuffer then holds the last picture drawn.
+    pub fn run_frame(&mut self) -> FrameResult<'_> {
+        let start = self.dots;
+        let mut short = false;
+        loop {
+            self.begin_step();
+            let stopped = self.cpu.cpu.stopped;
+            let cycles = self.cpu.cpu.next();
+            if self.advance(stopped, cycles) {
+                break;
+            }
+            if self.dots - start >= self.frame_ceiling {
+                short = true;
+                break;
+            }
+        }
+        self.frame.clear();
+        self.frame.extend_from_slice(self.mmu.borrow().gpu.framebuffer());
+        FrameResult {
+            framebuffer: &self.frame,
+            short,
+        }
+    }
+
+    // End run_frame after at most dots dots, plus the length of the instruction that crosses them, even in the middle
+    // of a frame. The default of twice FRAME_DOTS never cuts a frame short, tools stepping through a ROM that keeps the
+    // LCD off can ask for less to get control back sooner. Not part of the save state.
+    pub fn set_frame_cycle_ceiling(&mut self, dots: u32) {
+        self.frame_ceiling = dots.max(1);
+    }
+
+    // Advance emulation by at least the given number of cpu clock cycles, bypassing the real time speed limit, and
+    // return the cycles actually run. Instructions are never split, so the budget may be exceeded by the length of the
+    // last one. The rest of the hardware follows every instruction, so splitting a budget over several calls reaches
+    // the same state as running it at once, as long as every call ends on the same instruction.
+    pub fn run_cycles(&mut self, cycles: u32) -> u32 {
+        let mut n = 0;
+        while n < cycles {
+            self.begin_step();
+            let stopped = self.cpu.cpu.stopped;
+            let c = self.cpu.cpu.next();
+            self.advance(stopped, c);
+            n += c;
+        }
+        n
+    }
+
+    // Run mult times faster than the real hardware, or slower below 1. This scales both the real time speed limit of
+    // next and the number of frames run by run_host_frame. The APU keeps running in step with the cpu, so fast-forward
+    // produces mult times as many samples as the host plays: once a second of audio is waiting in Apu::buffer the
+    // newer samples are dropped, the sound keeps its pitch and plays in pieces.
+    pub fn set_speed_multiplier(&mut self, mult: f32) {
+        self.speed = mult.max(0.0);
+        self.speed_carry = 0.0;
+        self.cpu.set_speed(self.speed);
+    }
+
+    // Pace the emulation as if the cpu ran at hz instead of the 4194304 Hz of the hardware, see set_speed_multiplier.
+    // Only the relation to wall time changes, the hardware still counts the same cycles, so at 1 Hz every instruction
+    // takes seconds but has the same effects.
+    pub fn set_clock_hz(&mut self, hz: u32) {
+        self.set_speed_multiplier(hz as f32 / CLOCK_FREQUENCY as f32);
+    }
+
+    // Run the frames due in one host frame at the speed set by set_speed_multiplier, and return the framebuffer. At
+    // 2.0 every call runs two frames, at 0.5 every other call runs one.
+    pub fn run_host_frame(&mut self) -> &[u8] {
+        self.speed_carry += self.speed;
+        while self.speed_carry >= 1.0 {
+            self.speed_carry -= 1.0;
+            self.run_frame();
+        }
+        self.frame.clear();
+        self.frame.extend_from_slice(self.mmu.borrow().gpu.framebuffer());
+        &self.frame
+    }
+
+    // Real time pacing for frontends driving run_cycles from their own clock, see PacingClock. It follows the double
+    // speed mode and the speed multiplier.
+    pub fn pacer(&mut self) -> &mut PacingClock {
+        let speed = self.mmu.borrow().speed as u32;
+        self.pacer.set_scale(f64::from(self.speed) * f64::from(speed));
+        &mut self.pacer
+    }
+
+    // Only draw one frame out of n + 1, the PPU still runs through the skipped frames but doesn't write their pixels,
+    // see Gpu::render. The framebuffer keeps the last frame drawn and on_frame is only called for the frames drawn.
+    pub fn set_frame_skip(&mut self, n: u32) {
+        self.frame_skip = n;
+    }
+
+    // The last frame as a PNG file, colored as shown: with the DMG palette on the monochrome models and the color
+    // correction on the GameBoy Color.
+    #[cfg(feature = "image")]
+    pub fn screenshot_png(&self) -> Vec<u8> {
+        let mmu = self.mmu.borrow();
+        let pixels = mmu.gpu.data.as_flattened().iter();
+        let rgba: Vec<u8> = pixels.flat_map(|c| [c[0], c[1], c[2], 0xff]).collect();
+        super::png::encode_rgba(gpu::SCREEN_W, gpu::SCREEN_H, &rgba)
+    }
+
+    // The counters of the last frame completed, all zero before the first one. Loading a state starts the current
+    // frame over.
+    pub fn last_frame_stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    fn stats_total(&self) -> FrameStats {
+        let cpu = &self.cpu.cpu;
+        FrameStats {
+            instructions: cpu.instructions(),
+            cycles: cpu.cycles(),
+            interrupts: cpu.interrupts(),
+            mode_entries: self.mmu.borrow().gpu.mode_entries,
         }
+    }
+
+    // Number of frames completed since power up.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> u32 {
+        self.begin_step();
+        let stopped = self.cpu.cpu.stopped;
         let cycles = self.cpu.next();
-        self.mmu.borrow_mut().next(cycles);
+        self.advance(stopped, cycles);
         cycles
     }
 
+    // Same as next, but fails while the cpu is locked up by an illegal opcode, see Cpu::fault. Clearing the fault
+    // resumes execution.
+    pub fn try_next(&mut self) -> Result<u32, StepError> {
+        let cycles = self.next();
+        match self.cpu.cpu.fault {
+            Some(e) => Err(e),
+            None => Ok(cycles),
+        }
+    }
+
+    // Account for the cycles of the last cpu step, the cpu ticked the rest of the hardware through them already.
+    // Returns true if a frame was completed. In STOP mode the timer and LCD are frozen, only the cpu keeps polling the
+    // joypad, but frames are still counted.
+    fn advance(&mut self, stopped: bool, cycles: u32) -> bool {
+        self.dots += if stopped {
+            cycles / self.mmu.borrow().speed as u32
+        } else {
+            self.mmu.borrow_mut().take_dots()
+        };
+        self.frame_ready();
+        self.observe();
+        if self.dots < FRAME_DOTS {
+            return false;
+        }
+        self.dots -= FRAME_DOTS;
+        self.frames += 1;
+        let total = self.stats_total();
+        self.stats = total.since(&self.stats_start);
+        self.stats_start = total;
+        self.frame_start = true;
+        self.mmu.borrow_mut().joypad.next_frame(self.frames);
+        self.mmu.borrow_mut().apply_cheats();
+        if self.rewind.as_mut().is_some_and(|r| r.frame()) {
+            let state = self.save_state();
+            self.rewind.as_mut().unwrap().push(state);
+        }
+        true
+    }
+
+    fn frame_ready(&mut self) {
+        let mut mmu = self.mmu.borrow_mut();
+        if !mmu.gpu.frame_ready {
+            return;
+        }
+        mmu.gpu.frame_ready = false;
+        let drawn = mmu.gpu.render;
+        self.skipped = if drawn { 0 } else { self.skipped + 1 };
+        mmu.gpu.render = self.skipped >= self.frame_skip;
+        if !drawn || self.granularity != Granularity::Frame {
+            return;
+        }
+        if let Some(callback) = self.on_frame.as_mut() {
+            callback(mmu.gpu.framebuffer());
+        }
+    }
+
+    // Call on_frame at the granularities finer than a frame.
+    fn observe(&mut self) {
+        let callback = match self.on_frame.as_mut() {
+            Some(some) => some,
+            None => return,
+        };
+        let mmu = self.mmu.borrow();
+        match self.granularity {
+            Granularity::Frame => return,
+            Granularity::Scanline => {
+                let ly = mmu.get(0xff44);
+                if ly == self.observed_ly {
+                    return;
+                }
+                self.observed_ly = ly;
+            }
+            Granularity::Instruction => {}
+        }
+        callback(mmu.gpu.framebuffer());
+    }
+
+    // Apply the movie inputs at the start of every frame.
+    fn begin_step(&mut self) {
+        if !self.frame_start {
+            return;
+        }
+        self.frame_start = false;
+        if self.recording.is_none() && self.playback.is_none() {
+            return;
+        }
+        let mut mmu = self.mmu.borrow_mut();
+        if let Some((movie, i)) = self.playback.as_mut() {
+            if let Some(&m) = movie.frames.get(*i) {
+                mmu.joypad.set_buttons(m);
+                *i += 1;
+            } else {
+                self.playback = None;
+            }
+        }
+        if let Some(movie) = self.recording.as_mut() {
+            let m = if self.playback.is_some() {
+                mmu.joypad.buttons()
+            } else {
+                mmu.joypad.live_buttons()
+            };
+            mmu.joypad.set_buttons(m);
+            movie.frames.push(m);
+        }
+        let deferred = self.recording.is_some() || self.playback.is_some();
+        mmu.joypad.set_deferred(deferred);
+    }
+
+    // Start recording the inputs from the current state. A recording in progress is discarded.
+    pub fn start_recording(&mut self) {
+        self.recording = Some(InputMovie {
+            start: self.save_state(),
+            frames: Vec::new(),
+        });
+        self.frame_start = true;
+        self.mmu.borrow_mut().joypad.set_deferred(true);
+    }
+
+    // The inputs recorded so far, recording goes on.
+    pub fn export_recording(&self) -> InputMovie {
+        self.recording.clone().unwrap_or_default()
+    }
+
+    pub fn stop_recording(&mut self) -> Option<InputMovie> {
+        let movie = self.recording.take();
+        self.mmu.borrow_mut().joypad.set_deferred(self.playback.is_some());
+        movie
+    }
+
+    // Restore the state the movie starts from and replay its inputs, one mask per frame. Host keys are ignored until
+    // the movie ends.
+    pub fn play_movie(&mut self, movie: InputMovie) -> Result<(), StateError> {
+        self.load_state(&movie.start)?;
+        self.playback = Some((movie, 0));
+        self.frame_start = true;
+        self.mmu.borrow_mut().joypad.set_deferred(true);
+        Ok(())
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    // Record a state every interval frames, keeping enough of them to rewind by capacity_frames frames. Enabling
+    // rewind again starts over with an empty buffer.
+    pub fn enable_rewind(&mut self, capacity_frames: usize, interval: u8) {
+        let n = capacity_frames / usize::from(interval.max(1));
+        self.rewind = Some(RewindBuffer::power_up(n, interval));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    // Go back to the previous recorded state, returns false if there is none or rewind is disabled.
+    pub fn rewind_step_back(&mut self) -> bool {
+        let state = match self.rewind.as_mut().and_then(|r| r.step_back()) {
+            Some(some) => some.to_vec(),
+            None => return false,
+        };
+        self.load_state(&state).is_ok()
+    }
+
+    // Undo a rewind_step_back, returns false if emulation went on since or nothing was rewound.
+    pub fn rewind_step_forward(&mut self) -> bool {
+        let state = match self.rewind.as_mut().and_then(|r| r.step_forward()) {
+            Some(some) => some.to_vec(),
+            None => return false,
+        };
+        self.load_state(&state).is_ok()
+    }
+
+    // Run a test ROM following the mooneye-test-suite convention, without the real time speed limit, until it reports
+    // its result by executing LD B,B or max_frames frames have run.
+    pub fn run_test(&mut self, max_frames: u64) -> TestResult {
+        self.cpu.cpu.set_break_on_ld_b_b(true);
+        let end = self.frames + max_frames;
+        let _ = self.cpu.cpu.take_break();
+        let r = loop {
+            if self.frames >= end {
+                break TestResult::Timeout;
+            }
+            self.begin_step();
+            let stopped = self.cpu.cpu.stopped;
+            let cycles = self.cpu.cpu.next();
+            self.advance(stopped, cycles);
+            if let Some(BreakReason::Software(_)) = self.cpu.cpu.take_break() {
+                let reg = self.cpu.cpu.reg.clone();
+                if [reg.b, reg.c, reg.d, reg.e, reg.h, reg.l] == TEST_PASSED {
+                    break TestResult::Passed;
+                }
+                break TestResult::Failed { registers: reg };
+            }
+        };
+        self.cpu.cpu.set_break_on_ld_b_b(false);
+        r
+    }
+
+    // Run the whole machine until a cpu breakpoint or watchpoint is hit or the cpu locks up, without the real time
+    // speed limit.
+    pub fn run_until_break(&mut self) -> BreakReason {
+        let r = self.cpu.cpu.run_until_break();
+        self.mmu.borrow_mut().take_dots();
+        r
+    }
+
+    // Snapshot of the whole machine: cpu, memories, cartridge bank registers and RAM, RTC, PPU, APU, timer and the
+    // frame counters. Host side settings such as the real time speed limit, breakpoints or a serial peer aren't saved.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        for b in state::MAGIC.iter() {
+            w.u8(*b);
+        }
+        w.u8(state::VERSION);
+        self.cpu.cpu.save_state(&mut w);
+        self.mmu.borrow().save_state(&mut w);
+        w.u64(self.frames);
+        w.u32(self.dots);
+        w.into_inner()
+    }
+
+    // Decode the state of the machine, see MachineState.
+    pub fn capture(&self) -> MachineState {
+        let cpu = &self.cpu.cpu;
+        let mmu = self.mmu.borrow();
+        MachineState {
+            reg: cpu.reg.clone(),
+            ime: cpu.ei,
+            halted: cpu.halted,
+            stopped: cpu.stopped,
+            ie: mmu.interrupt_enable(),
+            intf: mmu.interrupt_flag(),
+            cycles: cpu.cycles(),
+            timer: TimerState {
+                div: mmu.timer.div(),
+                tima: mmu.get(0xff05),
+                tma: mmu.get(0xff06),
+                tac: mmu.get(0xff07),
+            },
+            ppu: PpuState {
+                lcdc: mmu.get(0xff40),
+                stat: mmu.get(0xff41),
+                scy: mmu.get(0xff42),
+                scx: mmu.get(0xff43),
+                ly: mmu.get(0xff44),
+                dots: mmu.gpu.dots(),
+                lyc: mmu.get(0xff45),
+                wy: mmu.get(0xff4a),
+                wx: mmu.get(0xff4b),
+            },
+            rom_bank: mmu.cartridge.bank(0x4000),
+            ram_bank: mmu.cartridge.bank(0xa000),
+            data: self.save_state(),
+        }
+    }
+
+    // Return to a state returned by capture. On error the machine is left exactly as it was.
+    pub fn restore(&mut self, state: &MachineState) -> Result<(), StateError> {
+        self.load_state(&state.data)
+    }
+
+    // Restore a state returned by save_state. On error the machine is left exactly as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = Reader::new(data);
+        for b in state::MAGIC.iter() {
+            if r.u8().map_err(|_| StateError::BadMagic)? != *b {
+                return Err(StateError::BadMagic);
+            }
+        }
+        let v = r.u8()?;
+        if v != state::VERSION {
+            return Err(StateError::Version(v));
+        }
+        let backup = self.save_state();
+        let rs = self.load_machine(&mut r);
+        if rs.is_err() {
+            let header = state::MAGIC.len() + 1;
+            self.load_machine(&mut Reader::new(&backup[header..])).unwrap();
+        }
+        rs
+    }
+
+    fn load_machine(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.cpu.cpu.load_state(r)?;
+        self.mmu.borrow_mut().load_state(r)?;
+        self.frames = r.u64()?;
+        self.dots = r.u32()?;
+        self.mmu.borrow_mut().joypad.frame = self.frames;
+        self.stats_start = self.stats_total();
+        if !r.is_empty() {
+            return Err(StateError::Mismatch("trailing data"));
+        }
+        Ok(())
+    }
+
     pub fn check_and_reset_gpu_updated(&mut self) -> bool {
         let result = self.mmu.borrow().gpu.v_blank;
         self.mmu.borrow_mut().gpu.v_blank = false;
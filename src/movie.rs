@@ -0,0 +1,21 @@
+// Input movies record the joypad once per frame, so together with the state they start from they replay a session
+// exactly. While a movie is recorded or played the keys only change on frame boundaries.
+use alloc::vec::Vec;
+
+#[derive(Clone, Default)]
+pub struct InputMovie {
+    // Save state of the machine when the recording started.
+    pub start: Vec<u8>,
+    // Pressed keys for every frame, as a mask of JoypadKey bits.
+    pub frames: Vec<u8>,
+}
+
+impl InputMovie {
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
@@ -0,0 +1,109 @@
+// Pacing turns the real time elapsed on the host into cpu cycles to run, so a frontend driven by its own display or
+// audio callback stays in step with the hardware without tuning constants. The LCD refreshes every 70224 dots, that
+// is 4194304 / 70224 = 59.7275 Hz, which is neither the 60 Hz of most displays nor a whole number of audio samples per
+// frame. The fractions are carried over from call to call, so jitter in the host ticks averages out.
+use super::cpu::CLOCK_FREQUENCY;
+use super::motherboard::FRAME_DOTS;
+use core::time::Duration;
+
+pub const REFRESH_RATE: f64 = CLOCK_FREQUENCY as f64 / FRAME_DOTS as f64;
+
+// At most this many frames are run for one host tick. A longer pause, after a breakpoint or while the window was
+// dragged, is dropped instead of being caught up in a burst.
+pub const MAX_FRAMES: u32 = 4;
+
+// Largest correction of the rate done by sync_audio.
+const MAX_SKEW: f64 = 0.005;
+
+pub struct PacingClock {
+    // Cycles per second of emulated time relative to the hardware: 2 in double speed mode, times the speed multiplier.
+    scale: f64,
+    // Correction of the rate by sync_audio, around 1.
+    skew: f64,
+    // Fraction of a cycle owed to the next call.
+    carry: f64,
+}
+
+impl PacingClock {
+    pub fn power_up() -> Self {
+        Self {
+            scale: 1.0,
+            skew: 1.0,
+            carry: 0.0,
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = scale;
+    }
+
+    // Cpu cycles to run for dt of host time, MotherBoard::run_cycles runs them. Steady ticks of one refresh period give
+    // FRAME_DOTS cycles each at normal speed.
+    pub fn cycles_for_elapsed(&mut self, dt: Duration) -> u32 {
+        let limit = f64::from(FRAME_DOTS * MAX_FRAMES) * self.scale;
+        let cycles = dt.as_secs_f64() * f64::from(CLOCK_FREQUENCY) * self.scale * self.skew;
+        let cycles = if cycles > limit {
+            self.carry = 0.0;
+            limit
+        } else {
+            cycles + self.carry
+        };
+        // Never negative, the cast rounds down.
+        let n = cycles as u32;
+        self.carry = cycles - f64::from(n);
+        n
+    }
+
+    // Keep the audio latency steady when the host plays the samples from its own clock. With queued samples waiting
+    // to be played at sample_rate, the rate is nudged by up to 0.5% so the queue tends to two frames of audio: a
+    // fuller queue slows the emulation down, an emptier one speeds it up. The pitch change is too small to be heard.
+    pub fn sync_audio(&mut self, queued: usize, sample_rate: u32) {
+        let target = 2.0 * f64::from(sample_rate) / REFRESH_RATE;
+        let d = ((target - queued as f64) / target).clamp(-1.0, 1.0);
+        self.skew = 1.0 + d * MAX_SKEW;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_refresh_period_is_one_frame() {
+        let mut clock = PacingClock::power_up();
+        let dt = Duration::from_secs_f64(1.0 / REFRESH_RATE);
+        let mut total = 0;
+        for _ in 0..600 {
+            let n = clock.cycles_for_elapsed(dt);
+            assert!((FRAME_DOTS - 1..=FRAME_DOTS).contains(&n), "{}", n);
+            total += n;
+        }
+        // The fractions carried over don't drift.
+        assert!((FRAME_DOTS * 600 - total) <= 1);
+        clock.set_scale(2.0);
+        assert!((FRAME_DOTS * 2 - 1..=FRAME_DOTS * 2 + 1).contains(&clock.cycles_for_elapsed(dt)));
+    }
+
+    #[test]
+    fn long_pauses_are_clamped() {
+        let mut clock = PacingClock::power_up();
+        assert_eq!(
+            clock.cycles_for_elapsed(Duration::from_secs(5)),
+            FRAME_DOTS * MAX_FRAMES
+        );
+        // About one frame at 16.7ms, the host doesn't pay back the pause.
+        let n = clock.cycles_for_elapsed(Duration::from_micros(16_700));
+        assert!((70_000..70_100).contains(&n), "{}", n);
+    }
+
+    #[test]
+    fn an_empty_audio_queue_speeds_up() {
+        let mut clock = PacingClock::power_up();
+        let dt = Duration::from_secs_f64(1.0 / REFRESH_RATE);
+        clock.sync_audio(0, 48000);
+        let n = clock.cycles_for_elapsed(dt);
+        assert!(n > FRAME_DOTS && n <= FRAME_DOTS + FRAME_DOTS / 200 + 1, "{}", n);
+        clock.sync_audio(2 * 48000 * 70224 / CLOCK_FREQUENCY as usize, 48000);
+        assert!(clock.cycles_for_elapsed(dt) - FRAME_DOTS <= 2);
+    }
+}
@@ -0,0 +1,87 @@
+// A minimal PNG encoder for screenshots, so a frontend doesn't need an image library to save one. The pixels are
+// stored without compression, in deflate "stored" blocks: a 160x144 RGBA screenshot takes about 90KB.
+//
+// A PNG file is the 8 byte signature followed by chunks, each made of:
+// Bytes  Content
+// 4      Length of the data (BE)
+// 4      Chunk type
+// n      Data
+// 4      CRC-32 of the type and data (BE)
+//
+// IHDR holds the size and pixel format, IDAT the zlib stream of the rows, each row starting with its filter type, and
+// IEND closes the file.
+//
+// See: https://www.w3.org/TR/png/
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// Encode w * h pixels of 4 bytes (R, G, B, A) in row-major order.
+pub fn encode_rgba(w: usize, h: usize, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(rgba.len(), w * h * 4);
+    let mut r = SIGNATURE.to_vec();
+
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&(w as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(h as u32).to_be_bytes());
+    // 8 bits per channel, color type 6 (RGBA), deflate, adaptive filtering, no interlace.
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+    chunk(&mut r, b"IHDR", &ihdr);
+
+    let mut raw = Vec::with_capacity(h * (w * 4 + 1));
+    for row in rgba.chunks(w * 4) {
+        // Filter type 0, the row as is.
+        raw.push(0x00);
+        raw.extend_from_slice(row);
+    }
+    chunk(&mut r, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut r, b"IEND", &[]);
+    r
+}
+
+fn chunk(w: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    w.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = w.len();
+    w.extend_from_slice(kind);
+    w.extend_from_slice(data);
+    let crc = crc32(&w[start..]);
+    w.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream of stored blocks of up to 65535 bytes: a header byte with BFINAL, the length and its complement (LE),
+// then the bytes. The stream ends with the Adler-32 of the data (BE).
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut r = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        r.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        r.push(u8::from(blocks.peek().is_none()));
+        let n = block.len() as u16;
+        r.extend_from_slice(&n.to_le_bytes());
+        r.extend_from_slice(&(!n).to_le_bytes());
+        r.extend_from_slice(block);
+    }
+    r.extend_from_slice(&adler32(data).to_be_bytes());
+    r
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut c = 0xffff_ffff_u32;
+    for &b in data {
+        c ^= u32::from(b);
+        for _ in 0..8 {
+            let poly = if c & 0x01 != 0x00 { 0xedb8_8320 } else { 0x0000_0000 };
+            c = poly ^ (c >> 1);
+        }
+    }
+    !c
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &v in data {
+        a = (a + u32::from(v)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,309 @@
+// The Game Boy Printer is a thermal printer attached to the link cable. The game is always the master and sends
+// packets, the printer answers every byte with 0x00 except the last two of a packet:
+//
+// Byte     Sent by the game      Answered by the printer
+// 0        0x88                  0x00
+// 1        0x33                  0x00
+// 2        Command               0x00
+// 3        Compression flag      0x00
+// 4-5      Data length (LE)      0x00
+// 6-       Data                  0x00
+// 2 bytes  Checksum (LE)         0x00
+// 1 byte   0x00                  0x81 (Alive)
+// 1 byte   0x00                  Status
+//
+// The checksum is the 16 bit sum of the command, compression flag, length and data bytes.
+//
+// Commands: 0x01 INIT clears the image buffer, 0x02 PRINT prints the buffer, 0x04 DATA appends up to 0x280 bytes of
+// tile data (two rows of 20 tiles, 2bpp like VRAM) and 0x0f STATUS only queries the status.
+//
+// Status bits:
+// Bit7 Low battery
+// Bit6 Other error
+// Bit5 Paper jam
+// Bit4 Packet error
+// Bit3 Unprocessed data in the buffer
+// Bit2 Image data full
+// Bit1 Printer busy
+// Bit0 Checksum error
+//
+// See: https://gbdev.io/pandocs/Gameboy_Printer.html
+use super::serial::SerialPeer;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+// Width in pixels of a print.
+pub const PRINTER_W: usize = 160;
+
+// Bytes of tile data in one band of 20x2 tiles, the most a DATA packet holds.
+const BAND: usize = 0x280;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Step {
+    Magic0,
+    Magic1,
+    Command,
+    Compression,
+    LengthLo,
+    LengthHi,
+    Data,
+    ChecksumLo,
+    ChecksumHi,
+    Alive,
+    Status,
+}
+
+pub struct Printer {
+    step: Step,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    sum: u16,
+    checksum: u16,
+    packet: Vec<u8>,
+    // Decompressed tile data received since the last INIT or PRINT.
+    buffer: Vec<u8>,
+    status: u8,
+    // STATUS packets left to report the printer busy after a PRINT.
+    busy: u8,
+    // Shades (0-3, 0 is white) of the printed pixels not taken yet, PRINTER_W pixels per line.
+    image: Vec<u8>,
+}
+
+impl Printer {
+    pub fn power_up() -> Self {
+        Self {
+            step: Step::Magic0,
+            command: 0x00,
+            compressed: false,
+            length: 0,
+            sum: 0,
+            checksum: 0,
+            packet: Vec::new(),
+            buffer: Vec::new(),
+            status: 0x00,
+            busy: 0,
+            image: Vec::new(),
+        }
+    }
+
+    // The pages printed since the last call, one shade (0-3, 0 is white) per pixel in row-major order, PRINTER_W
+    // pixels per line. Successive prints are joined vertically, the way games print long pictures in several parts.
+    pub fn take_image(&mut self) -> Option<Vec<u8>> {
+        if self.image.is_empty() {
+            return None;
+        }
+        Some(core::mem::take(&mut self.image))
+    }
+
+    fn run(&mut self) {
+        if self.sum != self.checksum {
+            self.status |= 0x01;
+            return;
+        }
+        self.status &= !0x01;
+        let data = if self.compressed {
+            decompress(&self.packet)
+        } else {
+            core::mem::take(&mut self.packet)
+        };
+        match self.command {
+            0x01 => {
+                self.buffer.clear();
+                self.status = 0x00;
+                self.busy = 0;
+            }
+            0x02 => {
+                // Bytes: number of sheets, margins, palette, exposure. No sheets only feeds paper.
+                if data.len() == 4 && data[0] != 0 {
+                    self.print(data[2]);
+                }
+                self.buffer.clear();
+                self.status = 0x06;
+                self.busy = 2;
+            }
+            0x04 => {
+                // The 8KB of RAM in the printer hold 9 bands, a whole screen.
+                let room = (BAND * 9).saturating_sub(self.buffer.len());
+                self.buffer.extend_from_slice(&data[..data.len().min(room)]);
+                self.status |= 0x08;
+                if self.buffer.len() >= BAND * 9 {
+                    self.status |= 0x04;
+                }
+            }
+            0x0f => {
+                if self.busy != 0 {
+                    self.busy -= 1;
+                    self.status = if self.busy == 0 { 0x04 } else { 0x06 };
+                } else {
+                    self.status &= !0x06;
+                }
+            }
+            _ => self.status |= 0x10,
+        }
+    }
+
+    // Turn the tile data into lines of shades. The buffer holds bands of 20x2 tiles, every tile is 16 bytes as in VRAM.
+    // The palette maps the color numbers to shades like BGP, 0x00 is taken as the usual 0xe4.
+    fn print(&mut self, palette: u8) {
+        let palette = if palette == 0x00 { 0xe4 } else { palette };
+        let rows = self.buffer.len() / (20 * 16);
+        for row in 0..rows {
+            for y in 0..8 {
+                for tx in 0..20 {
+                    let i = (row * 20 + tx) * 16 + y * 2;
+                    let (lo, hi) = (self.buffer[i], self.buffer[i + 1]);
+                    for bit in (0..8).rev() {
+                        let color = (((hi >> bit) & 0x01) << 1) | ((lo >> bit) & 0x01);
+                        self.image.push((palette >> (2 * color)) & 0x03);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Run length encoding of DATA packets: a control byte with bit 7 set repeats the next byte (control & 0x7f) + 2
+// times, otherwise the next control + 1 bytes are copied as is.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut r = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let c = data[i];
+        i += 1;
+        if c & 0x80 != 0x00 {
+            if let Some(&v) = data.get(i) {
+                r.extend(core::iter::repeat_n(v, usize::from(c & 0x7f) + 2));
+            }
+            i += 1;
+        } else {
+            let n = usize::from(c) + 1;
+            r.extend_from_slice(&data[i..(i + n).min(data.len())]);
+            i += n;
+        }
+    }
+    r
+}
+
+impl SerialPeer for Printer {
+    fn exchange(&mut self, v: u8) -> u8 {
+        let r = match self.step {
+            Step::Alive => 0x81,
+            Step::Status => self.status,
+            _ => 0x00,
+        };
+        self.step = match self.step {
+            Step::Magic0 if v == 0x88 => Step::Magic1,
+            Step::Magic0 => Step::Magic0,
+            Step::Magic1 if v == 0x33 => Step::Command,
+            // Out of sync, wait for the next packet. A new 0x88 may already start it.
+            Step::Magic1 if v == 0x88 => Step::Magic1,
+            Step::Magic1 => Step::Magic0,
+            Step::Command => {
+                self.command = v;
+                self.sum = u16::from(v);
+                Step::Compression
+            }
+            Step::Compression => {
+                self.compressed = v & 0x01 != 0x00;
+                self.sum = self.sum.wrapping_add(u16::from(v));
+                Step::LengthLo
+            }
+            Step::LengthLo => {
+                self.length = u16::from(v);
+                self.sum = self.sum.wrapping_add(u16::from(v));
+                Step::LengthHi
+            }
+            Step::LengthHi => {
+                self.length |= u16::from(v) << 8;
+                self.sum = self.sum.wrapping_add(u16::from(v));
+                self.packet.clear();
+                if self.length == 0 {
+                    Step::ChecksumLo
+                } else {
+                    Step::Data
+                }
+            }
+            Step::Data => {
+                self.packet.push(v);
+                self.sum = self.sum.wrapping_add(u16::from(v));
+                if self.packet.len() == usize::from(self.length) {
+                    Step::ChecksumLo
+                } else {
+                    Step::Data
+                }
+            }
+            Step::ChecksumLo => {
+                self.checksum = u16::from(v);
+                Step::ChecksumHi
+            }
+            Step::ChecksumHi => {
+                self.checksum |= u16::from(v) << 8;
+                self.run();
+                Step::Alive
+            }
+            Step::Alive => Step::Status,
+            Step::Status => Step::Magic0,
+        };
+        r
+    }
+}
+
+// Lets the host keep a handle on the printer to take the images while the serial port owns the peer.
+impl SerialPeer for Rc<RefCell<Printer>> {
+    fn exchange(&mut self, v: u8) -> u8 {
+        self.borrow_mut().exchange(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Send a whole packet, returns the alive and status bytes the printer answered with.
+    fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+        packet.extend_from_slice(data);
+        let sum = packet.iter().fold(0u16, |s, &b| s.wrapping_add(u16::from(b)));
+        let mut bytes = vec![0x88, 0x33];
+        bytes.extend_from_slice(&packet);
+        bytes.extend_from_slice(&sum.to_le_bytes());
+        for &b in &bytes {
+            assert_eq!(printer.exchange(b), 0x00);
+        }
+        (printer.exchange(0x00), printer.exchange(0x00))
+    }
+
+    #[test]
+    fn prints_two_bands() {
+        let mut printer = Printer::power_up();
+        assert_eq!(send(&mut printer, 0x01, false, &[]), (0x81, 0x00));
+        // A black band, then a white one compressed into runs of 129 bytes and one of 124.
+        assert_eq!(send(&mut printer, 0x04, false, &[0xff; BAND]), (0x81, 0x08));
+        let rle = [0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xfa, 0x00];
+        assert_eq!(send(&mut printer, 0x04, true, &rle), (0x81, 0x08));
+        assert_eq!(printer.take_image(), None);
+        // One sheet, margins, palette and exposure.
+        send(&mut printer, 0x02, false, &[0x01, 0x13, 0xe4, 0x40]);
+        assert_eq!(send(&mut printer, 0x0f, false, &[]), (0x81, 0x06));
+        assert_eq!(send(&mut printer, 0x0f, false, &[]), (0x81, 0x04));
+        assert_eq!(send(&mut printer, 0x0f, false, &[]), (0x81, 0x00));
+
+        let image = printer.take_image().unwrap();
+        assert_eq!(image.len(), PRINTER_W * 32);
+        assert!(image[..PRINTER_W * 16].iter().all(|&s| s == 3));
+        assert!(image[PRINTER_W * 16..].iter().all(|&s| s == 0));
+        assert_eq!(printer.take_image(), None);
+    }
+
+    #[test]
+    fn bad_checksum_sets_the_error_bit() {
+        let mut printer = Printer::power_up();
+        for &b in &[0x88, 0x33, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00] {
+            printer.exchange(b);
+        }
+        assert_eq!((printer.exchange(0x00), printer.exchange(0x00)), (0x81, 0x01));
+        assert_eq!(send(&mut printer, 0x01, false, &[]), (0x81, 0x00));
+    }
+}
@@ -1,4 +1,7 @@
 use super::convention::Term;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
 
 // The GameBoy has instructions & registers similar to the Intel 8080, Intel 8085, & Zilog Z80 microprocessors. It has
 // eight 8-bit registers A,B,C,D,E,F,H,L and two 16-bit registers SP & PC
@@ -11,7 +14,8 @@ use super::convention::Term;
 // |    SP     |  ---> Stack Pointer
 // |    PC     |  ---> Program Counter
 // -------------
-#[derive(Clone, Default)]
+#[derive(Clone, Debug, Default, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Register {
     pub a: u8,
     pub f: u8, // The F register is indirectly accessible by the programer.
@@ -49,6 +53,13 @@ impl Register {
         self.f = (v & 0x00f0) as u8;
     }
 
+    // Same as set_af, but returns false if any of the low 4 bits of F were set in v. The hardware discards those bits,
+    // so a false return means the program expected flag bits to persist that never will.
+    pub fn set_af_checked(&mut self, v: u16) -> bool {
+        self.set_af(v);
+        v & 0x000f == 0x0000
+    }
+
     pub fn set_bc(&mut self, v: u16) {
         self.b = (v >> 8) as u8;
         self.c = (v & 0x00ff) as u8;
@@ -65,6 +76,148 @@ impl Register {
     }
 }
 
+// Fixed layout used by save states: a, f, b, c, d, e, h, l, sp, pc. The 16-bit registers are stored in little-endian.
+// The layout is independent of the struct definition, so states stay portable even if the struct gains new fields.
+impl Register {
+    pub fn to_bytes(&self) -> [u8; 12] {
+        let mut r = [0x00; 12];
+        r[0] = self.a;
+        r[1] = self.f;
+        r[2] = self.b;
+        r[3] = self.c;
+        r[4] = self.d;
+        r[5] = self.e;
+        r[6] = self.h;
+        r[7] = self.l;
+        r[8..10].copy_from_slice(&self.sp.to_le_bytes());
+        r[10..12].copy_from_slice(&self.pc.to_le_bytes());
+        r
+    }
+
+    pub fn from_bytes(b: &[u8; 12]) -> Self {
+        Self {
+            a: b[0],
+            // The low 4 bits of F are wired to zero, a state with them set would leak them through PUSH AF.
+            f: b[1] & 0xf0,
+            b: b[2],
+            c: b[3],
+            d: b[4],
+            e: b[5],
+            h: b[6],
+            l: b[7],
+            sp: u16::from_le_bytes([b[8], b[9]]),
+            pc: u16::from_le_bytes([b[10], b[11]]),
+        }
+    }
+}
+
+// Access by name for debuggers and other tools that treat the registers generically. Names are case-insensitive, 8-bit
+// registers are widened to u16 when read and truncated when written.
+impl Register {
+    pub fn get_named(&self, name: &str) -> Option<u16> {
+        match name.to_ascii_uppercase().as_str() {
+            "A" => Some(u16::from(self.a)),
+            "F" => Some(u16::from(self.f)),
+            "B" => Some(u16::from(self.b)),
+            "C" => Some(u16::from(self.c)),
+            "D" => Some(u16::from(self.d)),
+            "E" => Some(u16::from(self.e)),
+            "H" => Some(u16::from(self.h)),
+            "L" => Some(u16::from(self.l)),
+            "SP" => Some(self.sp),
+            "PC" => Some(self.pc),
+            "AF" => Some(self.get_af()),
+            "BC" => Some(self.get_bc()),
+            "DE" => Some(self.get_de()),
+            "HL" => Some(self.get_hl()),
+            _ => None,
+        }
+    }
+
+    // Returns false if the name is unknown, the registers are left untouched in that case.
+    pub fn set_named(&mut self, name: &str, value: u16) -> bool {
+        match name.to_ascii_uppercase().as_str() {
+            "A" => self.a = value as u8,
+            "F" => self.f = value as u8 & 0xf0,
+            "B" => self.b = value as u8,
+            "C" => self.c = value as u8,
+            "D" => self.d = value as u8,
+            "E" => self.e = value as u8,
+            "H" => self.h = value as u8,
+            "L" => self.l = value as u8,
+            "SP" => self.sp = value,
+            "PC" => self.pc = value,
+            "AF" => self.set_af(value),
+            "BC" => self.set_bc(value),
+            "DE" => self.set_de(value),
+            "HL" => self.set_hl(value),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn iter_8bit(&self) -> impl Iterator<Item = (&'static str, u8)> {
+        let r = [
+            ("A", self.a),
+            ("F", self.f),
+            ("B", self.b),
+            ("C", self.c),
+            ("D", self.d),
+            ("E", self.e),
+            ("H", self.h),
+            ("L", self.l),
+        ];
+        IntoIterator::into_iter(r)
+    }
+
+    // The registers that differ from other, in the order of the save state layout. Only the top nibble of F counts, as
+    // in the comparison of two registers.
+    pub fn diff(&self, other: &Register) -> Vec<RegisterDiff> {
+        let r = [
+            ("A", u16::from(self.a), u16::from(other.a)),
+            ("F", u16::from(self.f & 0xf0), u16::from(other.f & 0xf0)),
+            ("B", u16::from(self.b), u16::from(other.b)),
+            ("C", u16::from(self.c), u16::from(other.c)),
+            ("D", u16::from(self.d), u16::from(other.d)),
+            ("E", u16::from(self.e), u16::from(other.e)),
+            ("H", u16::from(self.h), u16::from(other.h)),
+            ("L", u16::from(self.l), u16::from(other.l)),
+            ("SP", self.sp, other.sp),
+            ("PC", self.pc, other.pc),
+        ];
+        IntoIterator::into_iter(r)
+            .filter(|(_, old, new)| old != new)
+            .map(|(name, old, new)| RegisterDiff { name, old, new })
+            .collect()
+    }
+}
+
+// A register that differs between two register files, as reported by Register::diff. 8-bit registers are widened to
+// u16, names are the ones of get_named.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegisterDiff {
+    pub name: &'static str,
+    pub old: u16,
+    pub new: u16,
+}
+
+// The low 4 bits of F are wired to zero, they are left out so a register built by hand compares equal to the one the
+// cpu would hold.
+impl PartialEq for Register {
+    fn eq(&self, other: &Self) -> bool {
+        self.a == other.a
+            && self.f & 0xf0 == other.f & 0xf0
+            && self.b == other.b
+            && self.c == other.c
+            && self.d == other.d
+            && self.e == other.e
+            && self.h == other.h
+            && self.l == other.l
+            && self.sp == other.sp
+            && self.pc == other.pc
+    }
+}
+
 // The Fleg Register consists of the following bits: Z, N, H, C, 0, 0, 0, 0.
 pub enum Flag {
     // Zero Flag. This bit is set when the result of a math operationis zero or two values match when using the CP
@@ -101,10 +254,232 @@ impl Register {
             self.f &= f.bw();
         }
     }
+
+    // Writes all four flags in one pass, the low nibble of F is always zero.
+    pub fn set_flags(&mut self, z: bool, n: bool, h: bool, c: bool) {
+        let mut f = 0x00;
+        if z {
+            f |= Flag::Z.og();
+        }
+        if n {
+            f |= Flag::N.og();
+        }
+        if h {
+            f |= Flag::H.og();
+        }
+        if c {
+            f |= Flag::C.og();
+        }
+        self.f = f;
+    }
+
+    pub fn flags_raw(&self) -> u8 {
+        self.f
+    }
+
+    pub fn set_flags_raw(&mut self, f: u8) {
+        self.f = f & 0xf0;
+    }
+
+    // The four flags as letters separated by spaces, uppercase when set and lowercase when clear: "Z n h C".
+    pub fn flags_string(&self) -> String {
+        let r = [
+            (Flag::Z, 'Z', 'z'),
+            (Flag::N, 'N', 'n'),
+            (Flag::H, 'H', 'h'),
+            (Flag::C, 'C', 'c'),
+        ];
+        let letters: Vec<String> = IntoIterator::into_iter(r)
+            .map(|(f, set, clear)| if self.get_flag(f) { set } else { clear }.to_string())
+            .collect();
+        letters.join(" ")
+    }
+}
+
+// One line dump for logs: AF=01B0 BC=0013 DE=00D8 HL=014D SP=FFFE PC=0100 [Z n H C].
+impl core::fmt::Display for Register {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} [{}]",
+            self.get_af(),
+            self.get_bc(),
+            self.get_de(),
+            self.get_hl(),
+            self.sp,
+            self.pc,
+            self.flags_string()
+        )
+    }
+}
+
+// The flags of the 8 bit addition or subtraction of n and the carry to or from a, given the result s computed on 16
+// bits, without a branch so the hot ALU opcodes stay friendly to the branch predictor. Bit 4 of a ^ n ^ r is the carry
+// or borrow into bit 4 of the result, and bit 8 of s the carry or borrow out of bit 7: a borrow sets all the upper
+// bits of s.
+fn arith_flags(a: u8, n: u8, s: u16, sub: bool) -> u8 {
+    let r = s as u8;
+    let z = u8::from(r == 0x00) << 7;
+    let h = ((a ^ n ^ r) & 0x10) << 1;
+    let c = ((s >> 4) as u8) & 0x10;
+    z | (u8::from(sub) << 6) | h | c
+}
+
+impl Register {
+    // Add n to A.
+    // n = A,B,C,D,E,H,L,(HL),#
+    //
+    // Flags affected:
+    // Z - Set if result is zero.
+    // N - Reset.
+    // H - Set if carry from bit 3.
+    // C - Set if carry from bit 7.
+    pub fn alu_add(&mut self, n: u8) {
+        let a = self.a;
+        let s = u16::from(a) + u16::from(n);
+        self.f = arith_flags(a, n, s, false);
+        self.a = s as u8;
+    }
+
+    // Add n + Carry flag to A.
+    // n = A,B,C,D,E,H,L,(HL),#
+    //
+    // Flags affected:
+    // Z - Set if result is zero.
+    // N - Reset.
+    // H - Set if carry from bit 3.
+    // C - Set if carry from bit 7.
+    pub fn alu_adc(&mut self, n: u8) {
+        let a = self.a;
+        let c = u16::from(self.f >> 4) & 0x01;
+        let s = u16::from(a) + u16::from(n) + c;
+        self.f = arith_flags(a, n, s, false);
+        self.a = s as u8;
+    }
+
+    // Subtract n from A.
+    // n = A,B,C,D,E,H,L,(HL),#
+    //
+    // Flags affected:
+    // Z - Set if result is zero.
+    // N - Set.
+    // H - Set if no borrow from bit 4.
+    // C - Set if no borrow
+    pub fn alu_sub(&mut self, n: u8) {
+        let a = self.a;
+        let s = u16::from(a).wrapping_sub(u16::from(n));
+        self.f = arith_flags(a, n, s, true);
+        self.a = s as u8;
+    }
+
+    // Subtract n + Carry flag from A.
+    // n = A,B,C,D,E,H,L,(HL),#
+    //
+    // Flags affected:
+    // Z - Set if result is zero.
+    // N - Set.
+    // H - Set if no borrow from bit 4.
+    // C - Set if no borrow.
+    pub fn alu_sbc(&mut self, n: u8) {
+        let a = self.a;
+        let c = u16::from(self.f >> 4) & 0x01;
+        let s = u16::from(a).wrapping_sub(u16::from(n)).wrapping_sub(c);
+        self.f = arith_flags(a, n, s, true);
+        self.a = s as u8;
+    }
+
+    // Add n to HL
+    // n = BC,DE,HL,SP
+    //
+    // Flags affected:
+    // Z - Not affected.
+    // N - Reset.
+    // H - Set if carry from bit 11.
+    // C - Set if carry from bit 15.
+    pub fn add_hl(&mut self, n: u16) {
+        let a = self.get_hl();
+        let r = a.wrapping_add(n);
+        self.set_flag(Flag::C, a > 0xffff - n);
+        self.set_flag(Flag::H, (a & 0x0fff) + (n & 0x0fff) > 0x0fff);
+        self.set_flag(Flag::N, false);
+        self.set_hl(r);
+    }
+
+    // Add n to Stack Pointer (SP) and return the result without committing it, it backs both ADD SP, e8 and
+    // LD HL, SP+e8.
+    // n = one byte signed immediate value (#).
+    //
+    // Flags affected:
+    // Z - Reset.
+    // N - Reset.
+    // H - Set if carry from bit 3 of the unsigned low byte addition.
+    // C - Set if carry from bit 7 of the unsigned low byte addition.
+    pub fn add_sp_e8(&mut self, n: i8) -> u16 {
+        let a = self.sp;
+        let b = i16::from(n) as u16;
+        self.set_flags(
+            false,
+            false,
+            (a & 0x000f) + (b & 0x000f) > 0x000f,
+            (a & 0x00ff) + (b & 0x00ff) > 0x00ff,
+        );
+        a.wrapping_add(b)
+    }
+
+    // Decimal adjust register A. This instruction adjusts register A so that the correct representation of Binary
+    // Coded Decimal (BCD) is obtained.
+    //
+    // Flags affected:
+    // Z - Set if register A is zero.
+    // N - Not affected.
+    // H - Reset.
+    // C - Set or reset according to operation
+    pub fn daa(&mut self) {
+        let mut a = self.a;
+        let mut adjust = if self.get_flag(Flag::C) { 0x60 } else { 0x00 };
+        if self.get_flag(Flag::H) {
+            adjust |= 0x06;
+        };
+        if !self.get_flag(Flag::N) {
+            if a & 0x0f > 0x09 {
+                adjust |= 0x06;
+            };
+            if a > 0x99 {
+                adjust |= 0x60;
+            };
+            a = a.wrapping_add(adjust);
+        } else {
+            a = a.wrapping_sub(adjust);
+        }
+        self.set_flag(Flag::C, adjust >= 0x60);
+        self.set_flag(Flag::H, false);
+        self.set_flag(Flag::Z, a == 0x00);
+        self.a = a;
+    }
+}
+
+// Values that replace the power up defaults, used to start the cpu from an arbitrary state such as a hardware test
+// vector or the boot ROM at 0x0000. A None field keeps the default of the selected term.
+#[derive(Clone, Copy, Default)]
+pub struct RegisterOverrides {
+    pub a: Option<u8>,
+    pub f: Option<u8>,
+    pub b: Option<u8>,
+    pub c: Option<u8>,
+    pub d: Option<u8>,
+    pub e: Option<u8>,
+    pub h: Option<u8>,
+    pub l: Option<u8>,
+    pub sp: Option<u16>,
+    pub pc: Option<u16>,
 }
 
 impl Register {
     pub fn power_up(term: Term) -> Self {
+        Self::power_up_with(term, RegisterOverrides::default())
+    }
+
+    pub fn power_up_with(term: Term, overrides: RegisterOverrides) -> Self {
         let mut r = Self::default();
         match term {
             Term::GB => {
@@ -134,6 +509,347 @@ impl Register {
         // in ROM is executed. The Program Counter from this point on is controlled, indirectly, by the program
         // instructions themselves that were generated by the programmer of the ROM cart.
         r.pc = 0x0100;
+        if let Some(v) = overrides.a {
+            r.a = v;
+        }
+        if let Some(v) = overrides.f {
+            r.f = v & 0xf0;
+        }
+        if let Some(v) = overrides.b {
+            r.b = v;
+        }
+        if let Some(v) = overrides.c {
+            r.c = v;
+        }
+        if let Some(v) = overrides.d {
+            r.d = v;
+        }
+        if let Some(v) = overrides.e {
+            r.e = v;
+        }
+        if let Some(v) = overrides.h {
+            r.h = v;
+        }
+        if let Some(v) = overrides.l {
+            r.l = v;
+        }
+        if let Some(v) = overrides.sp {
+            r.sp = v;
+        }
+        if let Some(v) = overrides.pc {
+            r.pc = v;
+        }
         r
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Xorshift, so every run sees the same registers.
+    fn xorshift(x: &mut u32) -> u32 {
+        *x ^= *x << 13;
+        *x ^= *x >> 17;
+        *x ^= *x << 5;
+        *x
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut x = 0x2545_f491;
+        for _ in 0..1000 {
+            let mut b = [0x00; 12];
+            for v in b.iter_mut() {
+                *v = xorshift(&mut x) as u8;
+            }
+            let r = Register::from_bytes(&b);
+            assert_eq!(Register::from_bytes(&r.to_bytes()), r);
+            // All but the low nibble of F, which is always zero.
+            b[1] &= 0xf0;
+            assert_eq!(r.to_bytes(), b);
+        }
+    }
+
+    #[test]
+    fn daa() {
+        // A and F before, A and F after.
+        let cases: [(u8, u8, u8, u8); 14] = [
+            // After an addition.
+            (0x45, 0x00, 0x45, 0x00),
+            (0x00, 0x00, 0x00, 0x80),
+            (0x15, 0x80, 0x15, 0x00),
+            (0x0a, 0x00, 0x10, 0x00),
+            (0x3f, 0x00, 0x45, 0x00),
+            (0x10, 0x20, 0x16, 0x00),
+            (0x20, 0x10, 0x80, 0x10),
+            (0x9f, 0x00, 0x05, 0x10),
+            // The low nibble adjustment carries into the high nibble, which then needs one of its own.
+            (0x8a, 0x00, 0x90, 0x00),
+            (0x9a, 0x00, 0x00, 0x90),
+            // After a subtraction, C is only kept, never set.
+            (0x0f, 0x60, 0x09, 0x40),
+            (0x06, 0x60, 0x00, 0xc0),
+            (0xa0, 0x50, 0x40, 0x50),
+            (0x9a, 0x40, 0x9a, 0x40),
+        ];
+        for &(a, f, want_a, want_f) in cases.iter() {
+            let mut r = Register {
+                a,
+                f,
+                ..Register::default()
+            };
+            r.daa();
+            assert_eq!((r.a, r.f), (want_a, want_f), "a {:02x} f {:02x}", a, f);
+        }
+    }
+
+    #[test]
+    fn alu_flags() {
+        let add: fn(&mut Register, u8) = Register::alu_add;
+        let adc: fn(&mut Register, u8) = Register::alu_adc;
+        let sub: fn(&mut Register, u8) = Register::alu_sub;
+        let sbc: fn(&mut Register, u8) = Register::alu_sbc;
+        // The operation, A and F before, the operand, A and F after.
+        let cases = [
+            ("add", add, 0x0f, 0x00, 0x01, 0x10, 0x20),
+            ("add", add, 0xff, 0x00, 0x01, 0x00, 0xb0),
+            ("add", add, 0xf0, 0x00, 0x10, 0x00, 0x90),
+            ("add", add, 0x12, 0xf0, 0x34, 0x46, 0x00),
+            ("adc", adc, 0x0e, 0x10, 0x01, 0x10, 0x20),
+            ("adc", adc, 0xff, 0x10, 0x00, 0x00, 0xb0),
+            ("adc", adc, 0x0f, 0x00, 0x00, 0x0f, 0x00),
+            ("sub", sub, 0x00, 0x00, 0x01, 0xff, 0x70),
+            ("sub", sub, 0x10, 0x00, 0x01, 0x0f, 0x60),
+            ("sub", sub, 0x3e, 0x00, 0x3e, 0x00, 0xc0),
+            ("sub", sub, 0x3e, 0x00, 0x40, 0xfe, 0x50),
+            ("sbc", sbc, 0x00, 0x10, 0x00, 0xff, 0x70),
+            ("sbc", sbc, 0x10, 0x10, 0x0f, 0x00, 0xe0),
+            ("sbc", sbc, 0x05, 0x00, 0x05, 0x00, 0xc0),
+        ];
+        for &(name, op, a, f, n, want_a, want_f) in cases.iter() {
+            let mut r = Register {
+                a,
+                f,
+                ..Register::default()
+            };
+            op(&mut r, n);
+            assert_eq!((r.a, r.f), (want_a, want_f), "{} {:02x} {:02x} f {:02x}", name, a, n, f);
+        }
+    }
+
+    #[test]
+    fn add_hl() {
+        // HL and F before, the operand, HL and F after. Z is kept either way, even when the result is zero.
+        let cases: [(u16, u8, u16, u16, u8); 6] = [
+            (0x8800, 0x00, 0x8800, 0x1000, 0x30),
+            (0x8800, 0xc0, 0x8800, 0x1000, 0xb0),
+            (0x0fff, 0x00, 0x0001, 0x1000, 0x20),
+            (0xffff, 0x00, 0x0001, 0x0000, 0x30),
+            (0x1234, 0x80, 0x0000, 0x1234, 0x80),
+            (0x7000, 0x70, 0x1000, 0x8000, 0x00),
+        ];
+        for &(hl, f, n, want_hl, want_f) in cases.iter() {
+            let mut r = Register {
+                f,
+                ..Register::default()
+            };
+            r.set_hl(hl);
+            r.add_hl(n);
+            assert_eq!((r.get_hl(), r.f), (want_hl, want_f), "{:04x} {:04x} {:02x}", hl, n, f);
+        }
+    }
+
+    #[test]
+    fn add_sp_e8() {
+        // SP and F before, the offset, the result and F after. H and C come from the unsigned low byte addition, also
+        // for a negative offset.
+        let cases: [(u16, u8, i8, u16, u8); 6] = [
+            (0xfff8, 0x00, 8, 0x0000, 0x30),
+            (0xfff8, 0x00, -8, 0xfff0, 0x30),
+            (0x0005, 0x00, -1, 0x0004, 0x30),
+            (0x0000, 0x00, -1, 0xffff, 0x00),
+            (0x0000, 0xc0, 1, 0x0001, 0x00),
+            (0x00f0, 0x00, 0x10, 0x0100, 0x10),
+        ];
+        for &(sp, f, n, want, want_f) in cases.iter() {
+            let mut r = Register {
+                sp,
+                f,
+                ..Register::default()
+            };
+            assert_eq!((r.add_sp_e8(n), r.f), (want, want_f), "{:04x} {} {:02x}", sp, n, f);
+            assert_eq!(r.sp, sp);
+        }
+    }
+
+    #[test]
+    fn power_up_overrides() {
+        let overrides = RegisterOverrides {
+            pc: Some(0x0000),
+            sp: Some(0xfffe),
+            ..RegisterOverrides::default()
+        };
+        let r = Register::power_up_with(Term::GBC, overrides);
+        let want = Register {
+            pc: 0x0000,
+            ..Register::power_up(Term::GBC)
+        };
+        assert_eq!(r, want);
+    }
+
+    #[test]
+    fn named_registers() {
+        let mut r = Register::default();
+        assert!(r.set_named("af", 0x12ff));
+        assert_eq!(r.get_named("AF"), Some(0x12f0));
+        assert_eq!(r.get_named("a"), Some(0x0012));
+        assert!(r.set_named("Hl", 0xc0de));
+        assert_eq!((r.h, r.l), (0xc0, 0xde));
+        assert!(r.set_named("SP", 0xfffe));
+        assert_eq!(r.get_named("sp"), Some(0xfffe));
+        assert!(!r.set_named("IX", 0x0000));
+        assert_eq!(r.get_named("IX"), None);
+        let names: Vec<&str> = r.iter_8bit().map(|(name, _)| name).collect();
+        assert_eq!(names, ["A", "F", "B", "C", "D", "E", "H", "L"]);
+        assert_eq!(r.iter_8bit().nth(6), Some(("H", 0xc0)));
+    }
+
+    #[test]
+    fn set_af_checked() {
+        let mut r = Register::default();
+        assert!(r.set_af_checked(0x01b0));
+        assert_eq!(r.get_af(), 0x01b0);
+        assert!(!r.set_af_checked(0x01bf));
+        assert_eq!(r.get_af(), 0x01b0);
+    }
+
+    #[test]
+    fn set_flags() {
+        let mut r = Register::default();
+        r.set_flags(true, false, true, false);
+        assert_eq!(r.flags_raw(), 0xa0);
+        r.set_flags_raw(0x5f);
+        assert_eq!(r.f, 0x50);
+        assert!(r.get_flag(Flag::N) && r.get_flag(Flag::C));
+    }
+
+    // ADD, ADC, SUB and SBC as they were written before arith_flags, comparing nibbles and bytes with branches.
+    fn branchy(op: usize, r: &mut Register, n: u8) {
+        let a = r.a;
+        let c = u8::from(r.get_flag(Flag::C));
+        match op {
+            0 => {
+                let v = a.wrapping_add(n);
+                r.set_flags(
+                    v == 0x00,
+                    false,
+                    (a & 0x0f) + (n & 0x0f) > 0x0f,
+                    u16::from(a) + u16::from(n) > 0xff,
+                );
+                r.a = v;
+            }
+            1 => {
+                let v = a.wrapping_add(n).wrapping_add(c);
+                r.set_flags(
+                    v == 0x00,
+                    false,
+                    (a & 0x0f) + (n & 0x0f) + c > 0x0f,
+                    u16::from(a) + u16::from(n) + u16::from(c) > 0xff,
+                );
+                r.a = v;
+            }
+            2 => {
+                let v = a.wrapping_sub(n);
+                r.set_flags(v == 0x00, true, (a & 0x0f) < (n & 0x0f), u16::from(a) < u16::from(n));
+                r.a = v;
+            }
+            _ => {
+                let v = a.wrapping_sub(n).wrapping_sub(c);
+                r.set_flags(
+                    v == 0x00,
+                    true,
+                    (a & 0x0f) < (n & 0x0f) + c,
+                    u16::from(a) < u16::from(n) + u16::from(c),
+                );
+                r.a = v;
+            }
+        }
+    }
+
+    #[test]
+    fn arith_flags_match_the_branchy_alu() {
+        let ops: [fn(&mut Register, u8); 4] = [
+            Register::alu_add,
+            Register::alu_adc,
+            Register::alu_sub,
+            Register::alu_sbc,
+        ];
+        for (op, alu) in ops.iter().enumerate() {
+            // Carry clear and set, with the other flags low and high.
+            for &f in [0x00, 0x10, 0xe0, 0xf0].iter() {
+                for a in 0..=0xff {
+                    for n in 0..=0xff {
+                        let mut want = Register {
+                            a,
+                            f,
+                            ..Register::default()
+                        };
+                        branchy(op, &mut want, n);
+                        let mut got = Register {
+                            a,
+                            f,
+                            ..Register::default()
+                        };
+                        alu(&mut got, n);
+                        assert_eq!(
+                            (got.a, got.f),
+                            (want.a, want.f),
+                            "op {} a {:02x} n {:02x} f {:02x}",
+                            op,
+                            a,
+                            n,
+                            f
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn diff_lists_the_registers_that_differ() {
+        let old = Register::power_up(Term::GB);
+        let mut new = old.clone();
+        new.a = 0x42;
+        new.pc = 0x0150;
+        let diff = old.diff(&new);
+        assert_eq!(diff.len(), 2);
+        assert_eq!((diff[0].name, diff[0].old, diff[0].new), ("A", 0x01, 0x42));
+        assert_eq!((diff[1].name, diff[1].old, diff[1].new), ("PC", 0x0100, 0x0150));
+        assert!(old != new);
+        // The low bits of F are not part of the register.
+        let mut new = old.clone();
+        new.f |= 0x0f;
+        assert!(old == new);
+        assert!(old.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn flags_string_and_display() {
+        let r = Register {
+            f: 0x90,
+            ..Register::default()
+        };
+        assert_eq!(r.flags_string(), "Z n h C");
+        let r = Register {
+            f: 0x60,
+            ..Register::default()
+        };
+        assert_eq!(r.flags_string(), "z N H c");
+        assert_eq!(
+            Register::power_up(Term::GB).to_string(),
+            "AF=01B0 BC=0013 DE=00D8 HL=014D SP=FFFE PC=0100 [Z n H C]"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+// Rewind keeps a ring of save states taken every few frames. Consecutive states differ in little more than a few
+// registers and the bytes the game touched, so only the newest state is kept whole and every older one is stored as
+// the XOR with its successor, run-length encoded. Dropping the oldest state therefore never breaks the chain, and no
+// state has to be decoded to take a new one.
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+pub struct RewindBuffer {
+    capacity: usize,
+    interval: u8,
+    countdown: u8,
+    // The state at the rewind position, deltas in past lead to older states and deltas in future to newer ones.
+    head: Vec<u8>,
+    past: VecDeque<Vec<u8>>,
+    future: Vec<Vec<u8>>,
+    // Whether the machine is still in the head state, set when it was just loaded.
+    loaded: bool,
+}
+
+impl RewindBuffer {
+    // Keep at most capacity states, one every interval frames.
+    pub fn power_up(capacity: usize, interval: u8) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            countdown: interval.max(1),
+            head: Vec::new(),
+            past: VecDeque::new(),
+            future: Vec::new(),
+            loaded: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        if self.head.is_empty() {
+            0
+        } else {
+            self.past.len() + 1
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_empty()
+    }
+
+    // Drop every state, the capacity and interval stay.
+    pub fn clear(&mut self) {
+        self.countdown = self.interval;
+        self.head.clear();
+        self.past.clear();
+        self.future.clear();
+        self.loaded = false;
+    }
+
+    // Called at the end of every frame, returns true when a state should be pushed.
+    pub fn frame(&mut self) -> bool {
+        self.loaded = false;
+        self.countdown -= 1;
+        if self.countdown != 0 {
+            return false;
+        }
+        self.countdown = self.interval;
+        true
+    }
+
+    // Take a new state. States newer than the rewind position are discarded, the oldest state is evicted when the
+    // buffer is full.
+    pub fn push(&mut self, state: Vec<u8>) {
+        self.future.clear();
+        if self.head.len() != state.len() {
+            self.past.clear();
+        } else {
+            self.past.push_back(rle_encode(&xor(&self.head, &state)));
+            // The head is one of the states, past holds the others.
+            if self.past.len() + 1 > self.capacity {
+                self.past.pop_front();
+            }
+        }
+        self.head = state;
+    }
+
+    // Move to the previous state and return it. The first call after emulation went on returns the newest state.
+    pub fn step_back(&mut self) -> Option<&[u8]> {
+        if self.head.is_empty() {
+            return None;
+        }
+        if self.loaded {
+            let d = self.past.pop_back()?;
+            rle_apply(&mut self.head, &d);
+            self.future.push(d);
+        }
+        self.loaded = true;
+        Some(&self.head)
+    }
+
+    // Undo a step back and return the state that follows.
+    pub fn step_forward(&mut self) -> Option<&[u8]> {
+        let d = self.future.pop()?;
+        rle_apply(&mut self.head, &d);
+        self.past.push_back(d);
+        self.loaded = true;
+        Some(&self.head)
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(x, y)| x ^ y).collect()
+}
+
+// A control byte c below 0x80 is followed by c + 1 literal bytes, otherwise it stands for c - 0x7f zero bytes.
+fn rle_encode(data: &[u8]) -> Vec<u8> {
+    let mut r = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = data[i..].iter().take(0x80).take_while(|&&b| b == 0x00).count();
+        if zeros > 0 {
+            r.push(0x7f + zeros as u8);
+            i += zeros;
+            continue;
+        }
+        let n = data[i..].iter().take(0x80).take_while(|&&b| b != 0x00).count();
+        r.push(n as u8 - 1);
+        r.extend_from_slice(&data[i..i + n]);
+        i += n;
+    }
+    r
+}
+
+// XOR the decoded delta into data.
+fn rle_apply(data: &mut [u8], rle: &[u8]) {
+    let mut i = 0;
+    let mut j = 0;
+    while j < rle.len() {
+        let c = rle[j];
+        j += 1;
+        if c >= 0x80 {
+            i += usize::from(c - 0x7f);
+            continue;
+        }
+        let n = usize::from(c) + 1;
+        for (d, s) in data[i..i + n].iter_mut().zip(&rle[j..j + n]) {
+            *d ^= s;
+        }
+        i += n;
+        j += n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A state of a few hundred bytes that differs from its neighbours in a handful of them.
+    fn state(i: u8) -> Vec<u8> {
+        let mut r = vec![0x00; 0x0300];
+        r[0x0000] = i;
+        r[0x0100] = i.wrapping_mul(3);
+        r[0x02ff] = !i;
+        r
+    }
+
+    #[test]
+    fn rewinds_to_the_oldest_state_kept_and_forward_again() {
+        let mut r = RewindBuffer::power_up(4, 1);
+        for i in 0..10 {
+            r.push(state(i));
+        }
+        assert_eq!(r.len(), 4);
+        let mut frames = 0;
+        let mut last = None;
+        while let Some(s) = r.step_back() {
+            last = Some(s.to_vec());
+            frames += 1;
+        }
+        assert_eq!(frames, 4);
+        assert_eq!(last, Some(state(6)));
+        for i in 7..10 {
+            assert_eq!(r.step_forward(), Some(&state(i)[..]));
+        }
+        assert_eq!(r.step_forward(), None);
+    }
+
+    #[test]
+    fn a_capacity_of_one_keeps_the_newest_state() {
+        let mut r = RewindBuffer::power_up(1, 1);
+        r.push(state(1));
+        r.push(state(2));
+        assert_eq!(r.len(), 1);
+        assert_eq!(r.step_back(), Some(&state(2)[..]));
+        assert_eq!(r.step_back(), None);
+    }
+
+    #[test]
+    fn rle_round_trips_at_the_run_limit() {
+        for n in [0x7f, 0x80, 0x81, 0x100, 0x101] {
+            for fill in [0x00, 0x5a] {
+                let mut data = vec![fill; n];
+                data.push(0x01);
+                data.extend(vec![fill; n]);
+                let rle = rle_encode(&data);
+                let mut out = vec![0x00; data.len()];
+                rle_apply(&mut out, &rle);
+                assert_eq!(out, data, "run of {}x{:02x}", n, fill);
+            }
+        }
+        // A run of 80h zero bytes is one control byte, one more starts another run.
+        assert_eq!(rle_encode(&[0x00; 0x80]), [0xff]);
+        assert_eq!(rle_encode(&[0x00; 0x81]), [0xff, 0x80]);
+        assert_eq!(rle_encode(&[0x01; 0x81])[0], 0x7f);
+        assert_eq!(rle_encode(&[0x01; 0x81])[0x81], 0x00);
+    }
+}
@@ -5,12 +5,28 @@
 // next byte but the last one hasn't gone out yet, it has no choice but to wait.
 //
 // See: http://gbdev.gg8.se/wiki/articles/Serial_Data_Transfer_(Link_Cable)
-use super::intf::Intf;
-use std::cell::RefCell;
-use std::rc::Rc;
+use super::intf::{Flag, Intf};
+use super::state::{Reader, StateError, Stateful, Writer};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use core::cell::RefCell;
+
+// The other end of the link cable, for example a second emulator instance or a Game Boy Printer. The peer receives the
+// byte shifted out and returns the byte that it shifts in at the same time.
+pub trait SerialPeer {
+    fn exchange(&mut self, v: u8) -> u8;
+
+    // Polled while a transfer waits for the external clock, with the byte that would be shifted out. A peer driving
+    // the clock returns the byte it shifts in, the transfer then completes as with Serial::clock_in. Peers that never
+    // drive the clock, like the printer, keep the transfer waiting.
+    fn drive_clock(&mut self, _: u8) -> Option<u8> {
+        None
+    }
+}
 
 pub struct Serial {
-    _intf: Rc<RefCell<Intf>>,
+    intf: Rc<RefCell<Intf>>,
 
     // Before a transfer, it holds the next byte that will go out.
     // During a transfer, it has a blend of the outgoing and incoming bytes. Each cycle, the leftmost bit is shifted
@@ -20,21 +36,43 @@ pub struct Serial {
     // Bit 1 - Clock Speed (0=Normal, 1=Fast) ** CGB Mode Only **
     // Bit 0 - Shift Clock (0=External Clock, 1=Internal Clock)
     control: u8,
+    // With the internal clock the bits are shifted at 8192Hz, a whole byte takes 8 * 512 = 4096 clock cycles. Without
+    // a cable attached the line floats high, so 0xff is shifted in.
+    peer: Option<Box<dyn SerialPeer>>,
+    n: u32,
+    // Bytes sent so far, when capturing. Test ROMs such as Blargg's print their results over the serial port.
+    output: Option<String>,
 }
 
 impl Serial {
     pub fn power_up(intf: Rc<RefCell<Intf>>) -> Self {
         Self {
-            _intf: intf,
+            intf,
             data: 0x00,
             control: 0x00,
+            peer: None,
+            n: 0,
+            output: None,
         }
     }
 
+    // Start or stop keeping the bytes sent, each byte becomes a char of the output. Starting again clears it.
+    pub fn capture(&mut self, enable: bool) {
+        self.output = if enable { Some(String::new()) } else { None };
+    }
+
+    pub fn output(&self) -> &str {
+        self.output.as_deref().unwrap_or("")
+    }
+
+    pub fn set_peer(&mut self, peer: Box<dyn SerialPeer>) {
+        self.peer = Some(peer);
+    }
+
     pub fn get(&self, a: u16) -> u8 {
         match a {
             0xff01 => self.data,
-            0xff02 => self.control,
+            0xff02 => self.control | 0x7e,
             _ => panic!("Only supports addresses 0xff01, 0xff02"),
         }
     }
@@ -42,8 +80,175 @@ impl Serial {
     pub fn set(&mut self, a: u16, v: u8) {
         match a {
             0xff01 => self.data = v,
-            0xff02 => self.control = v,
+            0xff02 => {
+                self.control = v & 0x81;
+                self.n = 0;
+            }
             _ => panic!("Only supports addresses 0xff01, 0xff02"),
         };
     }
+
+    // Only transfers driven by the internal clock make progress, with an external clock the transfer waits for the
+    // other side to drive the clock, see clock_in and SerialPeer::drive_clock. Without a peer it waits forever.
+    pub fn next(&mut self, cycles: u32) {
+        if self.control & 0x81 == 0x80 {
+            let out = self.data;
+            if let Some(v) = self.peer.as_mut().and_then(|p| p.drive_clock(out)) {
+                self.clock_in(v);
+            }
+            return;
+        }
+        if self.control & 0x81 != 0x81 {
+            return;
+        }
+        self.n += cycles;
+        if self.n < 4096 {
+            return;
+        }
+        self.n = 0;
+        if let Some(s) = self.output.as_mut() {
+            s.push(char::from(self.data));
+        }
+        self.data = match self.peer.as_mut() {
+            Some(some) => some.exchange(self.data),
+            None => 0xff,
+        };
+        self.control &= 0x7f;
+        self.intf.borrow_mut().hi(Flag::Serial);
+    }
+
+    // The other end of the cable shifted a whole byte with its internal clock: take v and return the byte shifted out.
+    // The transfer only completes if one was requested with the external clock. Otherwise the shift register doesn't
+    // follow the clock, SB stays as is and goes out again, as when the game hasn't loaded the next byte in time.
+    pub fn clock_in(&mut self, v: u8) -> u8 {
+        let out = self.data;
+        if self.control & 0x81 != 0x80 {
+            return out;
+        }
+        if let Some(s) = self.output.as_mut() {
+            s.push(char::from(out));
+        }
+        self.data = v;
+        self.control &= 0x7f;
+        self.intf.borrow_mut().hi(Flag::Serial);
+        out
+    }
+}
+
+// The peer and the captured output are not part of the state, they stay across loads.
+impl Stateful for Serial {
+    fn save_state(&self, w: &mut Writer) {
+        w.u8(self.data);
+        w.u8(self.control);
+        w.u32(self.n);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.data = r.u8()?;
+        self.control = r.u8()?;
+        self.n = r.u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo(Rc<RefCell<Vec<u8>>>);
+
+    impl SerialPeer for Echo {
+        fn exchange(&mut self, v: u8) -> u8 {
+            self.0.borrow_mut().push(v);
+            0x5a
+        }
+    }
+
+    #[test]
+    fn internal_clock_transfer() {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        let mut serial = Serial::power_up(intf.clone());
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        serial.set_peer(Box::new(Echo(sent.clone())));
+        serial.set(0xff01, 0x42);
+        serial.set(0xff02, 0x81);
+        serial.next(4095);
+        assert_eq!(serial.get(0xff02), 0xff);
+        assert_eq!(intf.borrow().data, 0x00);
+        serial.next(1);
+        assert_eq!(serial.get(0xff02), 0x7f);
+        assert_eq!(serial.get(0xff01), 0x5a);
+        assert_eq!(intf.borrow().data, 0x08);
+        assert_eq!(*sent.borrow(), vec![0x42]);
+    }
+
+    #[test]
+    fn no_peer_shifts_in_ff() {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        let mut serial = Serial::power_up(intf.clone());
+        serial.set(0xff01, 0x42);
+        serial.set(0xff02, 0x81);
+        serial.next(4096);
+        assert_eq!(serial.get(0xff01), 0xff);
+        assert_eq!(intf.borrow().data, 0x08);
+    }
+
+    #[test]
+    fn external_clock_waits() {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        let mut serial = Serial::power_up(intf.clone());
+        serial.set(0xff01, 0x42);
+        serial.set(0xff02, 0x80);
+        serial.next(4096 * 4);
+        assert_eq!(serial.get(0xff01), 0x42);
+        assert_eq!(serial.get(0xff02), 0xfe);
+        assert_eq!(intf.borrow().data, 0x00);
+    }
+
+    // A master clocking the transfer on its nth poll, shifting in A5h.
+    struct Master {
+        polls: u32,
+        clock_at: u32,
+        got: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl SerialPeer for Master {
+        fn exchange(&mut self, v: u8) -> u8 {
+            v
+        }
+
+        fn drive_clock(&mut self, v: u8) -> Option<u8> {
+            self.polls += 1;
+            if self.polls < self.clock_at {
+                return None;
+            }
+            self.got.borrow_mut().push(v);
+            Some(0xa5)
+        }
+    }
+
+    #[test]
+    fn the_peer_drives_the_external_clock() {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        let mut serial = Serial::power_up(intf.clone());
+        let got = Rc::new(RefCell::new(Vec::new()));
+        serial.set_peer(Box::new(Master {
+            polls: 0,
+            clock_at: 1000,
+            got: got.clone(),
+        }));
+        serial.set(0xff01, 0x5a);
+        serial.set(0xff02, 0x80);
+        for _ in 0..999 {
+            serial.next(4);
+        }
+        assert_eq!(serial.get(0xff02), 0xfe);
+        assert_eq!(intf.borrow().data, 0x00);
+        assert!(got.borrow().is_empty());
+        serial.next(4);
+        assert_eq!(*got.borrow(), [0x5a]);
+        assert_eq!(serial.get(0xff01), 0xa5);
+        assert_eq!(serial.get(0xff02), 0x7e);
+        assert_eq!(intf.borrow().data, 0x08);
+    }
 }
@@ -0,0 +1,128 @@
+// The Super GameBoy receives commands from the game through the two select lines of the joypad register, P14 and P15.
+// A transfer starts with a reset pulse, then sends 128 bits and a stop bit, each one a pulse followed by both lines
+// high:
+//
+// Write to FF00   P15 P14
+// 00h             0   0     Reset
+// 10h             0   1     Bit 1
+// 20h             1   0     Bit 0
+// 30h             1   1     End of the pulse
+//
+// The bits make 16 bytes, least significant bit first, and the stop bit is a 0. The first byte holds the command in
+// bits 7-3 and the number of packets (1-7) in bits 2-0, every packet of a longer command starts with its own reset
+// pulse. Packets are delivered as they arrive, it is up to the receiver to gather the packets of a command.
+//
+// See: https://gbdev.io/pandocs/SGB_Command_Packet.html
+use alloc::boxed::Box;
+
+pub type CommandCallback = Box<dyn FnMut(&[u8; 16])>;
+
+pub struct Sgb {
+    // The P14/P15 lines as last written.
+    lines: u8,
+    // Bits received for the packet in progress, None between packets.
+    bits: Option<usize>,
+    packet: [u8; 16],
+    on_command: Option<CommandCallback>,
+}
+
+impl Sgb {
+    pub fn power_up() -> Self {
+        Self {
+            lines: 0x30,
+            bits: None,
+            packet: [0x00; 16],
+            on_command: None,
+        }
+    }
+
+    // Call back with every packet received. Replaces the previous callback.
+    pub fn on_command(&mut self, callback: impl FnMut(&[u8; 16]) + 'static) {
+        self.on_command = Some(Box::new(callback));
+    }
+
+    // Follow a write to FF00.
+    pub fn write(&mut self, v: u8) {
+        let lines = v & 0x30;
+        let prev = self.lines;
+        self.lines = lines;
+        if lines == 0x00 {
+            self.bits = Some(0);
+            self.packet = [0x00; 16];
+            return;
+        }
+        if prev != 0x30 || lines == 0x30 {
+            return;
+        }
+        let n = match self.bits {
+            Some(some) => some,
+            None => return,
+        };
+        let bit = lines == 0x10;
+        if n < 128 {
+            if bit {
+                self.packet[n / 8] |= 1 << (n % 8);
+            }
+            self.bits = Some(n + 1);
+            return;
+        }
+        // A 1 in place of the stop bit makes the packet invalid.
+        self.bits = None;
+        if bit {
+            return;
+        }
+        if let Some(callback) = self.on_command.as_mut() {
+            callback(&self.packet);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    // The FF00 writes sending a packet, with stop as the stop bit.
+    fn pulses(packet: &[u8; 16], stop: bool) -> Vec<u8> {
+        let mut r = vec![0x00, 0x30];
+        for i in 0..129 {
+            let bit = if i < 128 {
+                packet[i / 8] >> (i % 8) & 0x01 != 0x00
+            } else {
+                stop
+            };
+            r.push(if bit { 0x10 } else { 0x20 });
+            r.push(0x30);
+        }
+        r
+    }
+
+    fn received(writes: &[u8]) -> Vec<[u8; 16]> {
+        let mut sgb = Sgb::power_up();
+        let r = Rc::new(RefCell::new(Vec::new()));
+        let c = r.clone();
+        sgb.on_command(move |packet| c.borrow_mut().push(*packet));
+        for &v in writes {
+            sgb.write(v);
+        }
+        let r = r.borrow().clone();
+        r
+    }
+
+    #[test]
+    fn packets_are_assembled_from_the_pulses() {
+        // MLT_REQ with two players.
+        let mut packet = [0x00; 16];
+        packet[0] = 0x89;
+        packet[1] = 0x01;
+        packet[15] = 0xa5;
+        assert_eq!(received(&pulses(&packet, false)), [packet]);
+        // A 1 as the stop bit drops the packet, the next reset pulse starts over.
+        let mut writes = pulses(&packet, true);
+        writes.extend(pulses(&[0xff; 16], false));
+        assert_eq!(received(&writes), [[0xff; 16]]);
+        // Nothing is sent without a reset pulse first.
+        assert!(received(&pulses(&packet, false)[2..]).is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+// Save states are a flat little-endian byte stream: a magic header, a version byte and then the state of every
+// component in a fixed order. There is no per-field tagging, so any change to the layout must bump VERSION, loading a
+// state with another version is refused rather than misread.
+use alloc::vec::Vec;
+
+pub const MAGIC: [u8; 4] = *b"GBSS";
+pub const VERSION: u8 = 15;
+
+#[derive(Debug)]
+pub enum StateError {
+    // The data doesn't start with the save state magic.
+    BadMagic,
+    // The state was written by an incompatible version of the format.
+    Version(u8),
+    // The data ended before the whole machine was restored.
+    Truncated,
+    // The state doesn't belong to the machine it is loaded into, such as a state of another cartridge.
+    Mismatch(&'static str),
+}
+
+impl core::fmt::Display for StateError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "Not a save state"),
+            StateError::Version(v) => write!(f, "Unsupported save state version {}, expected {}", v, VERSION),
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::Mismatch(s) => write!(f, "Save state mismatch: {}", s),
+        }
+    }
+}
+
+impl core::error::Error for StateError {}
+
+pub trait Stateful {
+    fn save_state(&self, w: &mut Writer);
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError>;
+}
+
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.buf.push(v as u8);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    // Arbitrary length data, prefixed by its length.
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.u32(v.len() as u32);
+        self.buf.extend_from_slice(v);
+    }
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.buf.len() < n {
+            return Err(StateError::Truncated);
+        }
+        let (a, b) = self.buf.split_at(n);
+        self.buf = b;
+        Ok(a)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0x00)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let mut b = [0x00; 2];
+        b.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(b))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let mut b = [0x00; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut b = [0x00; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let n = self.u32()? as usize;
+        self.take(n)
+    }
+
+    // Read data written by Writer::bytes into a buffer of the same length, used for memories of a fixed size.
+    pub fn bytes_into(&mut self, v: &mut [u8], what: &'static str) -> Result<(), StateError> {
+        let b = self.bytes()?;
+        if b.len() != v.len() {
+            return Err(StateError::Mismatch(what));
+        }
+        v.copy_from_slice(b);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
@@ -4,17 +4,20 @@
 // with the contents of Timer Modulo (TMA).
 //
 // See: http://gbdev.gg8.se/wiki/articles/Timer_and_Divider_Registers
-use super::clock::Clock;
 use super::intf::{Flag, Intf};
-use std::cell::RefCell;
-use std::rc::Rc;
+use super::state::{Reader, StateError, Stateful, Writer};
+use alloc::rc::Rc;
+use core::cell::RefCell;
 
 #[derive(Default)]
 struct Register {
     // This register is incremented at rate of 16384Hz (~16779Hz on SGB). Writing any value to this register resets it
     // to 00h.
     // Note: The divider is affected by CGB double speed mode, and will increment at 32768Hz in double speed.
-    div: u8,
+    //
+    // DIV is the upper 8 bits of a 16 bit counter which is incremented every clock cycle. TIMA is clocked by the
+    // falling edge of one of the counter bits, as selected by TAC.
+    div: u16,
     // This timer is incremented by a clock frequency specified by the TAC register ($FF07). When the value overflows
     // (gets bigger than FFh) then it will be reset to the value specified in TMA (FF06), and an interrupt will be
     // requested, as described below.
@@ -33,11 +36,20 @@ struct Register {
 // Each time when the timer overflows (ie. when TIMA gets bigger than FFh), then an interrupt is requested by
 // setting Bit 2 in the IF Register (FF0F). When that interrupt is enabled, then the CPU will execute it by calling
 // the timer interrupt vector at 0050h.
+//
+// The reload is delayed by 4 clock cycles: right after the overflow TIMA reads 00h, and only on the following machine
+// cycle TMA is loaded and the interrupt requested. Writing TIMA during the delay cancels the reload, while a value
+// written to TMA during the delay is the one that gets loaded. TMA is also copied to TIMA through the machine cycle of
+// the reload: a write to TIMA in that cycle is ignored, and a write to TMA lands in TIMA as well.
+//
+// See: https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
 pub struct Timer {
     intf: Rc<RefCell<Intf>>,
     reg: Register,
-    div_clock: Clock,
-    tma_clock: Clock,
+    reload: bool,
+    // Set through the machine cycle TIMA is reloaded in.
+    reloading: bool,
+    n: u32,
 }
 
 impl Timer {
@@ -45,62 +57,252 @@ impl Timer {
         Timer {
             intf,
             reg: Register::default(),
-            div_clock: Clock::power_up(256),
-            tma_clock: Clock::power_up(1024),
+            reload: false,
+            reloading: false,
+            n: 0,
         }
     }
 
+    // The 16 bit counter DIV is the upper byte of.
+    pub fn div(&self) -> u16 {
+        self.reg.div
+    }
+
+    // Set the 16 bit counter DIV is the upper byte of. The cpu can only reset it to 0 by writing DIV.
+    pub fn set_div(&mut self, v: u16) {
+        self.reg.div = v;
+    }
+
     pub fn get(&self, a: u16) -> u8 {
         match a {
-            0xff04 => self.reg.div,
+            0xff04 => (self.reg.div >> 8) as u8,
             0xff05 => self.reg.tima,
             0xff06 => self.reg.tma,
-            0xff07 => self.reg.tac,
+            0xff07 => 0xf8 | self.reg.tac,
             _ => panic!("Unsupported address"),
         }
     }
 
     pub fn set(&mut self, a: u16, v: u8) {
         match a {
+            // Resetting the counter, or switching to another bit or disabling the timer through TAC, may turn the
+            // selected signal from high to low. The falling edge detector sees this like a regular tick, so TIMA is
+            // incremented once.
             0xff04 => {
-                self.reg.div = 0x00;
-                self.div_clock.n = 0x00;
+                let prev = self.signal();
+                self.reg.div = 0x0000;
+                if prev && !self.signal() {
+                    self.incr_tima();
+                }
+            }
+            0xff05 if self.reloading => {}
+            0xff05 => {
+                self.reg.tima = v;
+                self.reload = false;
+            }
+            0xff06 => {
+                self.reg.tma = v;
+                if self.reloading {
+                    self.reg.tima = v;
+                }
             }
-            0xff05 => self.reg.tima = v,
-            0xff06 => self.reg.tma = v,
             0xff07 => {
-                if (self.reg.tac & 0x03) != (v & 0x03) {
-                    self.tma_clock.n = 0x00;
-                    self.tma_clock.period = match v & 0x03 {
-                        0x00 => 1024,
-                        0x01 => 16,
-                        0x02 => 64,
-                        0x03 => 256,
-                        _ => panic!(""),
-                    };
-                    self.reg.tima = self.reg.tma;
+                let prev = self.signal();
+                self.reg.tac = v & 0x07;
+                if prev && !self.signal() {
+                    self.incr_tima();
                 }
-                self.reg.tac = v;
             }
             _ => panic!("Unsupported address"),
         }
     }
 
+    // The state of the counter bit selected by TAC, ANDed with the timer enable bit.
+    fn signal(&self) -> bool {
+        let b = match self.reg.tac & 0x03 {
+            0x00 => 9,
+            0x01 => 3,
+            0x02 => 5,
+            _ => 7,
+        };
+        self.reg.tac & 0x04 != 0x00 && (self.reg.div >> b) & 0x01 != 0x00
+    }
+
+    fn incr_tima(&mut self) {
+        self.reg.tima = self.reg.tima.wrapping_add(1);
+        if self.reg.tima == 0x00 {
+            self.reload = true;
+        }
+    }
+
+    // Advance the timer by one machine cycle.
+    fn tick(&mut self) {
+        self.reloading = self.reload;
+        if self.reload {
+            self.reload = false;
+            self.reg.tima = self.reg.tma;
+            self.intf.borrow_mut().hi(Flag::Timer);
+        }
+        let prev = self.signal();
+        self.reg.div = self.reg.div.wrapping_add(4);
+        if prev && !self.signal() {
+            self.incr_tima();
+        }
+    }
+
     pub fn next(&mut self, cycles: u32) {
-        // Increment div at rate of 16384Hz. Because the clock cycles is 4194304, so div increment every 256 cycles.
-        self.reg.div = self.reg.div.wrapping_add(self.div_clock.next(cycles) as u8);
-
-        // Increment tima at rate of Clock / freq
-        // Timer Enable
-        if (self.reg.tac & 0x04) != 0x00 {
-            let n = self.tma_clock.next(cycles);
-            for _ in 0..n {
-                self.reg.tima = self.reg.tima.wrapping_add(1);
-                if self.reg.tima == 0x00 {
-                    self.reg.tima = self.reg.tma;
-                    self.intf.borrow_mut().hi(Flag::Timer);
-                }
-            }
+        self.n += cycles;
+        while self.n >= 4 {
+            self.n -= 4;
+            self.tick();
         }
     }
 }
+
+impl Stateful for Timer {
+    fn save_state(&self, w: &mut Writer) {
+        w.u16(self.reg.div);
+        w.u8(self.reg.tima);
+        w.u8(self.reg.tma);
+        w.u8(self.reg.tac);
+        w.bool(self.reload);
+        w.bool(self.reloading);
+        w.u32(self.n);
+    }
+
+    fn load_state(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.reg.div = r.u16()?;
+        self.reg.tima = r.u8()?;
+        self.reg.tma = r.u8()?;
+        self.reg.tac = r.u8()?;
+        self.reload = r.bool()?;
+        self.reloading = r.bool()?;
+        self.n = r.u32()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(tac: u8) -> (Timer, Rc<RefCell<Intf>>) {
+        let intf = Rc::new(RefCell::new(Intf::power_up()));
+        let mut timer = Timer::power_up(intf.clone());
+        timer.set(0xff07, tac);
+        (timer, intf)
+    }
+
+    #[test]
+    fn tima_rates() {
+        for &(tac, period) in &[(0x04, 1024), (0x05, 16), (0x06, 64), (0x07, 256)] {
+            let (mut timer, _) = timer(tac);
+            timer.next(period * 10 - 4);
+            assert_eq!(timer.get(0xff05), 9, "tac {:02x}", tac);
+            timer.next(4);
+            assert_eq!(timer.get(0xff05), 10, "tac {:02x}", tac);
+        }
+        let (mut timer, _) = timer(0x01);
+        timer.next(1024);
+        assert_eq!(timer.get(0xff05), 0);
+        assert_eq!(timer.get(0xff04), 4);
+    }
+
+    // TIMA at FFh, overflowing 16 clocks later.
+    fn overflow() -> (Timer, Rc<RefCell<Intf>>) {
+        let (mut timer, intf) = timer(0x05);
+        timer.set(0xff05, 0xff);
+        timer.set(0xff06, 0x80);
+        timer.next(16);
+        assert_eq!(timer.get(0xff05), 0x00);
+        assert_eq!(intf.borrow().data & 0x04, 0x00);
+        (timer, intf)
+    }
+
+    #[test]
+    fn reload_is_delayed_one_machine_cycle() {
+        let (mut timer, intf) = overflow();
+        timer.next(4);
+        assert_eq!(timer.get(0xff05), 0x80);
+        assert_eq!(intf.borrow().data & 0x04, 0x04);
+    }
+
+    #[test]
+    fn tima_write_during_the_delay_cancels_the_reload() {
+        let (mut timer, intf) = overflow();
+        timer.set(0xff05, 0x10);
+        timer.next(4);
+        assert_eq!(timer.get(0xff05), 0x10);
+        assert_eq!(intf.borrow().data & 0x04, 0x00);
+    }
+
+    #[test]
+    fn tma_write_during_the_delay_is_loaded() {
+        let (mut timer, intf) = overflow();
+        timer.set(0xff06, 0x33);
+        timer.next(4);
+        assert_eq!(timer.get(0xff05), 0x33);
+        assert_eq!(intf.borrow().data & 0x04, 0x04);
+    }
+
+    #[test]
+    fn writes_in_the_reload_cycle() {
+        // TMA lands in TIMA, as the reload copies it.
+        let (mut timer, _) = overflow();
+        timer.next(4);
+        timer.set(0xff06, 0x44);
+        assert_eq!(timer.get(0xff05), 0x44);
+        // TIMA is written over by the reload, the write is lost.
+        let (mut timer, _) = overflow();
+        timer.next(4);
+        timer.set(0xff05, 0x10);
+        assert_eq!(timer.get(0xff05), 0x80);
+        // One cycle later both are plain writes again.
+        let (mut timer, _) = overflow();
+        timer.next(8);
+        timer.set(0xff06, 0x44);
+        assert_eq!(timer.get(0xff05), 0x80);
+        timer.set(0xff05, 0x10);
+        assert_eq!(timer.get(0xff05), 0x10);
+    }
+
+    #[test]
+    fn div_write_on_a_high_bit_ticks_once() {
+        let (mut timer, _) = timer(0x04);
+        // Counter at 03FCh, bit 9 is high for 4 more clocks.
+        timer.next(0x03fc);
+        assert_eq!(timer.get(0xff05), 0);
+        timer.set(0xff04, 0x00);
+        assert_eq!(timer.get(0xff05), 1);
+        assert_eq!(timer.get(0xff04), 0);
+        // With the bit low the reset does nothing.
+        timer.next(0x01fc);
+        timer.set(0xff04, 0x00);
+        assert_eq!(timer.get(0xff05), 1);
+    }
+
+    #[test]
+    fn tac_write_on_a_high_bit_ticks_once() {
+        let (mut timer, _) = timer(0x05);
+        timer.next(8);
+        // Bit 3 high, bit 9 low.
+        timer.set(0xff07, 0x04);
+        assert_eq!(timer.get(0xff05), 1);
+        timer.set(0xff07, 0x05);
+        timer.set(0xff07, 0x01);
+        assert_eq!(timer.get(0xff05), 2);
+    }
+
+    #[test]
+    fn div_write_overflow_reloads_after_the_delay() {
+        let (mut timer, intf) = timer(0x05);
+        timer.set(0xff05, 0xff);
+        timer.set(0xff06, 0x42);
+        timer.next(8);
+        timer.set(0xff04, 0x00);
+        assert_eq!(timer.get(0xff05), 0x00);
+        timer.next(4);
+        assert_eq!(timer.get(0xff05), 0x42);
+        assert_eq!(intf.borrow().data & 0x04, 0x04);
+    }
+}
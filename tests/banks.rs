@@ -0,0 +1,67 @@
+mod common;
+
+use common::Header;
+use gameboy::memory::Memory;
+use gameboy::motherboard::MotherBoard;
+
+const CART_TYPES: [u8; 20] = [
+    0x00, 0x01, 0x02, 0x03, 0x05, 0x06, 0x08, 0x09, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e,
+    0xff,
+];
+
+struct XorShift(u32);
+
+impl XorShift {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+// Random bank register values, whatever the cartridge, ROM or image size, never read out of the ROM or RAM.
+#[test]
+fn random_bank_switches_never_read_out_of_bounds() {
+    let mut rng = XorShift(0x2545_f491);
+    for &cart_type in CART_TYPES.iter() {
+        for &rom_size in [0x00, 0x03].iter() {
+            for &ram_size in [0x00, 0x03].iter() {
+                // from_rom accepts images shorter than their header declares.
+                for &short in [false, true].iter() {
+                    let header = Header {
+                        cgb: 0x80,
+                        cart_type,
+                        rom_size,
+                        ram_size,
+                        ..Header::default()
+                    };
+                    let mut rom = common::rom_with(header, &common::IDLE);
+                    for (i, b) in rom.iter_mut().enumerate().skip(0x4000) {
+                        *b = (i >> 14) as u8;
+                    }
+                    if short {
+                        rom.truncate(0x4000 + 0x0150);
+                    }
+                    let m = MotherBoard::from_rom(rom);
+                    let mut mmu = m.mmu.borrow_mut();
+                    for round in 0..2000 {
+                        let v = rng.next();
+                        let a = match v % 7 {
+                            0..=3 => (v % 4) as u16 * 0x2000 + (v >> 8) as u16 % 0x2000,
+                            4 => 0xa000 + (v >> 8) as u16 % 0x2000,
+                            5 => 0xff4f,
+                            _ => 0xff70,
+                        };
+                        mmu.set(a, (v >> 24) as u8);
+                        if round % 500 == 499 {
+                            for a in 0x0000..=0xffff {
+                                std::hint::black_box(mmu.get(a));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
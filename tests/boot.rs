@@ -0,0 +1,87 @@
+mod common;
+
+use common::Header;
+use gameboy::memory::Memory;
+
+// LDH A,(40h); LD B,A; LD A,(8010h); LD C,A; LDH A,(47h); LD D,A; LD A,01h; LDH (50h),A
+const BOOT: [u8; 14] = [
+    0xf0, 0x40, 0x47, 0xfa, 0x10, 0x80, 0x4f, 0xf0, 0x47, 0x57, 0x3e, 0x01, 0xe0, 0x50,
+];
+
+fn boot_rom() -> Vec<u8> {
+    let mut rom = vec![0x00; 0x0100];
+    rom[..BOOT.len()].copy_from_slice(&BOOT);
+    rom
+}
+
+#[test]
+fn boot_rom_overlays_the_cartridge_until_ff50() {
+    // LD A,01h; LDH (50h),A, then filler up to 0100h.
+    let mut boot = vec![0xdd; 0x100];
+    boot[..4].copy_from_slice(&[0x3e, 0x01, 0xe0, 0x50]);
+    let mut m = common::machine(Header::default(), &common::IDLE).with_boot_rom(boot);
+    assert_eq!(m.cpu.cpu.reg.pc, 0x0000);
+    assert_eq!(m.cpu.cpu.reg.a, 0x00);
+    assert_eq!(m.mmu.borrow().get(0x0000), 0x3e);
+    assert_eq!(m.mmu.borrow().get(0x00ff), 0xdd);
+    assert_eq!(m.mmu.borrow().get(0x0104), 0xce);
+
+    m.next();
+    assert_eq!(m.mmu.borrow().get(0x0000), 0x3e);
+    m.next();
+    assert_eq!(m.mmu.borrow().get(0x0000), 0x00);
+    assert_eq!(m.mmu.borrow().get(0x00ff), 0x00);
+    // Writing FF50 again does not bring it back.
+    m.mmu.borrow_mut().set(0xff50, 0x00);
+    assert_eq!(m.mmu.borrow().get(0x0000), 0x00);
+}
+
+#[test]
+fn without_a_boot_rom_the_machine_starts_as_left_by_it() {
+    let m = common::machine(Header::default(), &[0x18, 0xfe]);
+    let mmu = m.mmu.borrow();
+    assert_eq!(mmu.get(0xff40), 0x91);
+    assert_eq!(mmu.get(0xff47), 0xfc);
+    assert_ne!(mmu.get(0x8010), 0x00);
+}
+
+#[test]
+fn the_boot_rom_starts_from_the_reset_state() {
+    // JR -2
+    let mut m = common::machine(Header::default(), &[0x18, 0xfe]).with_boot_rom(boot_rom());
+    let garbage = {
+        let mmu = m.mmu.borrow();
+        assert_eq!(mmu.get(0x0000), 0xf0);
+        assert_eq!(mmu.get(0xff40), 0x00);
+        assert_eq!(mmu.get(0xff04), 0x00);
+        assert_eq!(mmu.get(0xff26) & 0x80, 0x00);
+        // The logo isn't drawn yet, VRAM holds garbage.
+        assert_ne!(mmu.get(0x9910), 0x19);
+        assert_ne!(
+            (0x8010..0x8020).map(|a| mmu.get(a)).collect::<Vec<_>>(),
+            [0xf0, 0x00, 0xf0, 0x00, 0xfc, 0x00, 0xfc, 0x00, 0xfc, 0x00, 0xfc, 0x00, 0xf3, 0x00, 0xf3, 0x00]
+        );
+        mmu.get(0x8010)
+    };
+    assert_eq!(m.cpu.cpu.reg.pc, 0x0000);
+    while m.cpu.cpu.reg.pc != 0x0150 {
+        m.next();
+    }
+    assert_eq!(m.cpu.cpu.reg.b, 0x00);
+    assert_eq!(m.cpu.cpu.reg.c, garbage);
+    assert_eq!(m.cpu.cpu.reg.d, 0x00);
+    // FF50 unmapped the boot ROM, the cartridge shows through.
+    assert_eq!(m.mmu.borrow().get(0x0000), 0x00);
+}
+
+#[test]
+fn reset_goes_back_to_the_boot_rom() {
+    let mut m = common::machine(Header::default(), &[0x18, 0xfe]).with_boot_rom(boot_rom());
+    while m.cpu.cpu.reg.pc != 0x0150 {
+        m.next();
+    }
+    m.reset();
+    assert_eq!(m.cpu.cpu.reg.pc, 0x0000);
+    assert_eq!(m.mmu.borrow().get(0x0000), 0xf0);
+    assert_eq!(m.mmu.borrow().get(0xff40), 0x00);
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::Header;
+use gameboy::memory::{Bus, Memory};
+use gameboy::mmunit::Mmunit;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Debug, Eq, PartialEq)]
+enum Access {
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+// Forwards to the Mmunit and records every access. Reads are recorded through a RefCell as Bus::read takes &self.
+struct Recorder {
+    inner: Rc<RefCell<Mmunit>>,
+    log: RefCell<Vec<Access>>,
+    // Reads of this address see 42h instead of the memory.
+    patch: Option<u16>,
+}
+
+impl Bus for Recorder {
+    fn read(&self, a: u16) -> u8 {
+        let v = if self.patch == Some(a) {
+            0x42
+        } else {
+            self.inner.borrow().get(a)
+        };
+        self.log.borrow_mut().push(Access::Read(a, v));
+        v
+    }
+
+    fn write(&mut self, a: u16, v: u8) {
+        self.log.borrow_mut().push(Access::Write(a, v));
+        self.inner.borrow_mut().set(a, v);
+    }
+}
+
+// DI; LD A,(C000h); LD (C001h),A; JR -2
+const PROGRAM: [u8; 9] = [0xf3, 0xfa, 0x00, 0xc0, 0xea, 0x01, 0xc0, 0x18, 0xfe];
+
+fn recorded(patch: Option<u16>) -> (gameboy::motherboard::MotherBoard, Rc<RefCell<Recorder>>) {
+    let mut m = common::machine(Header::default(), &PROGRAM);
+    m.mmu.borrow_mut().set(0xc000, 0x99);
+    let bus = Rc::new(RefCell::new(Recorder {
+        inner: m.mmu.clone(),
+        log: RefCell::new(Vec::new()),
+        patch,
+    }));
+    m.set_bus(bus.clone());
+    (m, bus)
+}
+
+#[test]
+fn the_bus_sees_every_access_in_order() {
+    let (mut m, bus) = recorded(None);
+    // NOP; JP 0150h at the entry point, then the program.
+    for _ in 0..4 {
+        m.next();
+    }
+    use Access::*;
+    // The cpu also reads IF and IE before every instruction to check for interrupts.
+    let log: Vec<_> = bus
+        .borrow()
+        .log
+        .borrow_mut()
+        .drain(..)
+        .filter(|a| !matches!(a, Read(0xff0f, _) | Read(0xffff, _)))
+        .collect();
+    assert_eq!(
+        log,
+        [
+            Read(0x0100, 0x00),
+            Read(0x0101, 0xc3),
+            Read(0x0102, 0x50),
+            Read(0x0103, 0x01),
+            Read(0x0150, 0xf3),
+            Read(0x0151, 0xfa),
+            Read(0x0152, 0x00),
+            Read(0x0153, 0xc0),
+            Read(0xc000, 0x99),
+        ]
+    );
+    m.next();
+    assert_eq!(bus.borrow().log.borrow().last(), Some(&Write(0xc001, 0x99)));
+    assert_eq!(m.mmu.borrow().get(0xc001), 0x99);
+}
+
+#[test]
+fn the_bus_can_rewrite_reads() {
+    let (mut m, _) = recorded(Some(0xc000));
+    for _ in 0..5 {
+        m.next();
+    }
+    assert_eq!(m.cpu.cpu.reg.a, 0x42);
+    assert_eq!(m.mmu.borrow().get(0xc001), 0x42);
+}
+
+#[test]
+fn the_hardware_runs_behind_the_bus() {
+    let (mut m, _) = recorded(None);
+    let div = m.mmu.borrow().get(0xff04);
+    m.run_frame();
+    assert_eq!(m.frame_count(), 1);
+    assert_ne!(m.mmu.borrow().get(0xff04), div);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::Header;
+use gameboy::memory::Memory;
+
+#[test]
+fn gameshark_pokes_every_frame() {
+    let mut m = common::machine(Header::default(), &common::IDLE);
+    m.mmu.borrow_mut().cheats.add_gameshark("016300C0").unwrap();
+    assert_eq!(m.mmu.borrow().get(0xc000), 0x00);
+    m.run_frame();
+    assert_eq!(m.mmu.borrow().get(0xc000), 0x63);
+    // The game overwriting the byte only lasts until the end of the frame.
+    m.mmu.borrow_mut().set(0xc000, 0x00);
+    m.run_frame();
+    assert_eq!(m.mmu.borrow().get(0xc000), 0x63);
+    m.mmu.borrow_mut().cheats.clear();
+    m.mmu.borrow_mut().set(0xc000, 0x00);
+    m.run_frame();
+    assert_eq!(m.mmu.borrow().get(0xc000), 0x00);
+}
+
+#[test]
+fn gameshark_pokes_the_wram_bank_of_the_code() {
+    let cgb = Header {
+        cgb: 0x80,
+        ..Header::default()
+    };
+    let mut m = common::machine(cgb, &common::IDLE);
+    m.mmu.borrow_mut().cheats.add_gameshark("026300D0").unwrap();
+    m.run_frame();
+    let mut mmu = m.mmu.borrow_mut();
+    assert_eq!(mmu.get(0xd000), 0x00);
+    mmu.set(0xff70, 0x02);
+    assert_eq!(mmu.get(0xd000), 0x63);
+}
+
+#[test]
+fn game_genie_patches_rom_reads() {
+    let m = common::machine(Header::default(), &common::IDLE);
+    // 0150h holds F3h, the code only applies while it does.
+    m.mmu.borrow_mut().cheats.add_game_genie("001-50F-205").unwrap();
+    assert_eq!(m.mmu.borrow().get(0x0150), 0x00);
+    m.mmu.borrow_mut().cheats.clear();
+    m.mmu.borrow_mut().cheats.add_game_genie("001-50F-206").unwrap();
+    assert_eq!(m.mmu.borrow().get(0x0150), 0xf3);
+}
+
+#[test]
+fn scan_memory_finds_a_value_and_its_changes() {
+    let m = common::machine(Header::default(), &common::IDLE);
+    let at = [0xc123, 0xd000, 0xdfff, 0xff90];
+    for &a in &at {
+        m.mmu.borrow_mut().set(a, 100);
+    }
+    assert_eq!(m.scan_memory(|_, v| v == 100), at);
+    assert_eq!(m.scan_memory(|a, v| a >= 0xff80 && v == 100), [0xff90]);
+
+    let before = m.memory_snapshot();
+    m.mmu.borrow_mut().set(0xd000, 99);
+    m.mmu.borrow_mut().set(0xc800, 1);
+    assert_eq!(m.scan_changed(&before), [0xc800, 0xd000]);
+    assert_eq!(m.scan_memory(|_, v| v == 99), [0xd000]);
+}
@@ -0,0 +1,97 @@
+// Helpers shared by the integration tests, not all of them use every one.
+#![allow(dead_code)]
+
+use gameboy::motherboard::MotherBoard;
+#[cfg(feature = "std")]
+use std::path::PathBuf;
+#[cfg(feature = "std")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d, 0x00, 0x08, 0x11,
+    0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99, 0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e,
+    0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+// The header bytes a test cares about, the rest is filled in by rom.
+#[derive(Clone, Copy, Default)]
+pub struct Header {
+    pub cgb: u8,
+    pub sgb: u8,
+    pub cart_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+}
+
+// A ROM with a valid header that jumps to program at 0150h.
+pub fn rom_with(header: Header, program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0x00; 0x8000 << header.rom_size];
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+    rom[0x0104..0x0134].copy_from_slice(&LOGO);
+    rom[0x0134..0x0139].copy_from_slice(b"TESTS");
+    rom[0x0143] = header.cgb;
+    rom[0x0146] = header.sgb;
+    rom[0x0147] = header.cart_type;
+    rom[0x0148] = header.rom_size;
+    rom[0x0149] = header.ram_size;
+    rom[0x014d] = rom[0x0134..0x014d]
+        .iter()
+        .fold(0u8, |c, &b| c.wrapping_sub(b).wrapping_sub(1));
+    rom[0x0150..0x0150 + program.len()].copy_from_slice(program);
+    rom
+}
+
+pub fn rom(program: &[u8]) -> Vec<u8> {
+    rom_with(Header::default(), program)
+}
+
+// Write rom to a file of its own, cartridges are loaded from a path. The battery files go next to it.
+#[cfg(feature = "std")]
+pub fn rom_file(rom: &[u8]) -> PathBuf {
+    static N: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!("gameboy-tests-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.gb", N.fetch_add(1, Ordering::Relaxed)));
+    std::fs::write(&path, rom).unwrap();
+    path
+}
+
+// A machine running a ROM from memory, so it has no battery files. The hardware is the same without std.
+pub fn machine(header: Header, program: &[u8]) -> MotherBoard {
+    MotherBoard::from_rom(rom_with(header, program))
+}
+
+// Same as machine, with the ROM loaded from a file so a cartridge with a battery keeps its RAM.
+#[cfg(feature = "std")]
+pub fn machine_from_file(header: Header, program: &[u8]) -> MotherBoard {
+    MotherBoard::power_up(rom_file(&rom_with(header, program)))
+}
+
+// DI; JR -2, for tests that drive the hardware from outside.
+pub const IDLE: [u8; 3] = [0xf3, 0x18, 0xfe];
+
+// Fill the tile data with DIV values forever and scroll by the joypad lines.
+// DI; LD HL,8000h; loop: LDH A,(04h); LD (HL+),A; LDH A,(00h); LDH (42h),A; LD A,H; CP 88h; JR NZ,loop; LD H,80h;
+// JR loop
+pub const NOISE: [u8; 20] = [
+    0xf3, 0x21, 0x00, 0x80, 0xf0, 0x04, 0x22, 0xf0, 0x00, 0xe0, 0x42, 0x7c, 0xfe, 0x88, 0x20, 0xf4, 0x26, 0x80, 0x18,
+    0xf0,
+];
+
+// The color at x, y of a framebuffer in the default RGBA format.
+pub fn pixel(frame: &[u8], x: usize, y: usize) -> [u8; 3] {
+    let i = (y * 160 + x) * 4;
+    [frame[i], frame[i + 1], frame[i + 2]]
+}
+
+// The CRC-32 of gzip, zip and PNG.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut c = !0u32;
+    for &b in data {
+        c ^= u32::from(b);
+        for _ in 0..8 {
+            c = if c & 1 == 1 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+    }
+    !c
+}
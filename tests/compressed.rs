@@ -0,0 +1,109 @@
+mod common;
+
+use gameboy::archive::{self, ArchiveError};
+use gameboy::cartridge::{self, OpenError};
+use gameboy::motherboard::MotherBoard;
+use std::path::PathBuf;
+
+// Deflate data as stored blocks, the bytes as they are.
+fn stored(data: &[u8]) -> Vec<u8> {
+    let mut r = Vec::new();
+    let mut blocks = data.chunks(0xffff).peekable();
+    while let Some(block) = blocks.next() {
+        r.push(u8::from(blocks.peek().is_none()));
+        r.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        r.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        r.extend_from_slice(block);
+    }
+    r
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut r = vec![0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff];
+    r.extend_from_slice(&stored(data));
+    r.extend_from_slice(&common::crc32(data).to_le_bytes());
+    r.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    r
+}
+
+// A zip archive of entries deflated into stored blocks, each after a local header, and the central directory.
+fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut r = Vec::new();
+    let mut dir = Vec::new();
+    for &(name, data) in entries {
+        let body = stored(data);
+        let mut fields = Vec::new();
+        fields.extend_from_slice(&[0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        fields.extend_from_slice(&common::crc32(data).to_le_bytes());
+        fields.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&[0x00, 0x00]);
+
+        dir.extend_from_slice(&[0x50, 0x4b, 0x01, 0x02, 0x14, 0x00]);
+        dir.extend_from_slice(&fields);
+        // No comment, disk 0 and no file attributes.
+        dir.extend_from_slice(&[0x00; 10]);
+        dir.extend_from_slice(&(r.len() as u32).to_le_bytes());
+        dir.extend_from_slice(name.as_bytes());
+
+        r.extend_from_slice(&[0x50, 0x4b, 0x03, 0x04]);
+        r.extend_from_slice(&fields);
+        r.extend_from_slice(name.as_bytes());
+        r.extend_from_slice(&body);
+    }
+    let n = entries.len() as u16;
+    let at = r.len() as u32;
+    r.extend_from_slice(&dir);
+    r.extend_from_slice(&[0x50, 0x4b, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00]);
+    r.extend_from_slice(&n.to_le_bytes());
+    r.extend_from_slice(&n.to_le_bytes());
+    r.extend_from_slice(&(dir.len() as u32).to_le_bytes());
+    r.extend_from_slice(&at.to_le_bytes());
+    r.extend_from_slice(&[0x00, 0x00]);
+    r
+}
+
+// Write data next to the ROM files of the other tests, under a name ending with ext.
+fn file(data: &[u8], ext: &str) -> PathBuf {
+    let path = common::rom_file(&[]).with_extension(ext);
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[test]
+fn a_gzipped_rom_loads_like_the_plain_one() {
+    let rom = common::rom(&common::NOISE);
+    let plain = cartridge::from_bytes(rom.clone()).unwrap();
+    let gz = cartridge::from_path(file(&gzip(&rom), "gb.gz")).unwrap();
+    assert!(gz.rom() == plain.rom());
+    assert!(cartridge::from_path(file(&rom, "gb")).unwrap().rom() == plain.rom());
+
+    let mut a = MotherBoard::from_path(file(&gzip(&rom), "gb.gz")).unwrap();
+    let mut b = MotherBoard::from_bytes(rom).unwrap();
+    for _ in 0..3 {
+        assert!(a.run_frame().framebuffer == b.run_frame().framebuffer);
+    }
+}
+
+#[test]
+fn a_zip_archive_must_hold_one_rom() {
+    let rom = common::rom(&common::NOISE);
+    let one = zip(&[("readme.txt", b"hello"), ("game.gb", &rom)]);
+    assert!(cartridge::from_path(file(&one, "zip")).unwrap().rom() == &rom[..]);
+
+    let other = common::rom(&common::IDLE);
+    let two = zip(&[("a.gb", &rom), ("b.gbc", &other)]);
+    match cartridge::from_path(file(&two, "zip")) {
+        Err(OpenError::Archive(ArchiveError::SeveralRoms(names))) => assert_eq!(names, ["a.gb", "b.gbc"]),
+        _ => panic!("two roms loaded"),
+    }
+    assert_eq!(archive::zip_roms(&two).unwrap(), ["a.gb", "b.gbc"]);
+    assert!(archive::unzip(&two, Some("b.gbc")).unwrap() == other);
+
+    // A damaged byte is caught by the CRC.
+    let mut bad = one;
+    let at = bad.windows(4).position(|w| w == b"TEST").unwrap();
+    bad[at] ^= 0x01;
+    assert!(matches!(archive::unzip(&bad, None), Err(ArchiveError::Corrupt(_))));
+}
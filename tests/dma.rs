@@ -0,0 +1,175 @@
+mod common;
+
+use common::Header;
+use gameboy::memory::Memory;
+
+#[test]
+fn oam_dma_takes_160_machine_cycles() {
+    let m = common::machine(Header::default(), &common::IDLE);
+    let mut mmu = m.mmu.borrow_mut();
+    for i in 0..0xa0 {
+        mmu.set(0xc000 + i, i as u8 ^ 0x5a);
+    }
+    mmu.set(0xff80, 0x42);
+    mmu.set(0xff46, 0xc0);
+    mmu.next(159 * 4);
+    // Only HRAM and the I/O registers are reachable during the transfer, elsewhere reads see the last source byte.
+    assert_eq!(mmu.get(0xfe00), 0x9f ^ 0x5a);
+    assert_eq!(mmu.get(0xc000), 0x9f ^ 0x5a);
+    assert_eq!(mmu.get(0xff80), 0x42);
+    mmu.next(4);
+    // Peek past the PPU, which keeps OAM from the cpu during modes 2 and 3.
+    for i in 0..0xa0 {
+        assert_eq!(mmu.peek(0xfe00 + i), i as u8 ^ 0x5a);
+    }
+}
+
+#[test]
+fn cpu_reads_conflict_with_oam_dma() {
+    let m = common::machine(Header::default(), &common::IDLE);
+    let mut mmu = m.mmu.borrow_mut();
+    mmu.set(0xc000, 0x11);
+    for i in 0..0xa0 {
+        mmu.set(0xc100 + i, i as u8 ^ 0x80);
+    }
+    mmu.set(0xff80, 0x42);
+    mmu.set(0xff46, 0xc1);
+    mmu.next(10 * 4);
+    assert_eq!(mmu.get(0xc000), 0x8a);
+    mmu.set(0xc000, 0x22);
+    mmu.set(0xff81, 0x43);
+    assert_eq!(mmu.get(0xff80), 0x42);
+    assert_eq!(mmu.get(0xff81), 0x43);
+    mmu.next(150 * 4);
+    assert_eq!(mmu.get(0xc000), 0x11);
+}
+
+#[test]
+fn oam_dma_from_the_echo_area_reads_work_ram() {
+    let m = common::machine(Header::default(), &common::IDLE);
+    let mut mmu = m.mmu.borrow_mut();
+    for i in 0..0xa0 {
+        mmu.set(0xdf00 + i, i as u8);
+    }
+    mmu.set(0xff46, 0xff);
+    mmu.next(160 * 4);
+    for i in 0..0xa0 {
+        assert_eq!(mmu.peek(0xfe00 + i), i as u8);
+    }
+}
+
+const CGB: Header = Header {
+    cgb: 0x80,
+    sgb: 0x00,
+    cart_type: 0x00,
+    rom_size: 0x00,
+    ram_size: 0x00,
+};
+
+// Start an HBlank DMA of four blocks from C000 to 8000.
+fn hdma(mmu: &mut gameboy::mmunit::Mmunit) {
+    for i in 0..0x40 {
+        mmu.set(0xc000 + i, i as u8 + 1);
+    }
+    mmu.set(0xff51, 0xc0);
+    mmu.set(0xff52, 0x00);
+    mmu.set(0xff53, 0x00);
+    mmu.set(0xff54, 0x00);
+    mmu.set(0xff55, 0x83);
+}
+
+// Run until FF55 changes, returns the new value and the line it changed on.
+fn next_block(mmu: &mut gameboy::mmunit::Mmunit) -> (u8, u8) {
+    let v = mmu.get(0xff55);
+    for _ in 0..456 * 2 {
+        mmu.next(4);
+        if mmu.get(0xff55) != v {
+            return (mmu.get(0xff55), mmu.get(0xff44));
+        }
+    }
+    panic!("no block within two lines");
+}
+
+#[test]
+fn hdma_copies_one_block_per_hblank() {
+    let m = common::machine(CGB, &common::IDLE);
+    let mut mmu = m.mmu.borrow_mut();
+    hdma(&mut mmu);
+    assert_eq!(mmu.get(0xff55), 0x03);
+    let mut lines = Vec::new();
+    for &want in &[0x02, 0x01, 0x00, 0xff] {
+        let (v, ly) = next_block(&mut mmu);
+        assert_eq!(v, want);
+        lines.push(ly);
+    }
+    for w in lines.windows(2) {
+        assert_eq!(w[1], w[0] + 1);
+    }
+    for i in 0..0x40 {
+        assert_eq!(mmu.get(0x8000 + i), i as u8 + 1);
+    }
+}
+
+#[test]
+fn hdma_cancel() {
+    let m = common::machine(CGB, &common::IDLE);
+    let mut mmu = m.mmu.borrow_mut();
+    hdma(&mut mmu);
+    assert_eq!(next_block(&mut mmu).0, 0x02);
+    mmu.set(0xff55, 0x00);
+    assert_eq!(mmu.get(0xff55), 0x82);
+    mmu.next(456 * 4);
+    assert_eq!(mmu.get(0xff55), 0x82);
+    assert_eq!(mmu.get(0x800f), 0x10);
+    assert_eq!(mmu.get(0x8010), 0x00);
+}
+
+fn byte(bank: usize, i: usize) -> u8 {
+    ((bank << 5) ^ i) as u8
+}
+
+#[test]
+fn oam_dma_copies_the_switched_rom_bank() {
+    let mbc1 = Header {
+        cart_type: 0x01,
+        rom_size: 0x02,
+        ..Header::default()
+    };
+    // DI; HALT. The cpu sleeps with no interrupt enabled: running from ROM during the transfer, it would fetch the
+    // bytes being copied instead of its own, see Mmunit::get.
+    let mut rom = common::rom_with(mbc1, &[0xf3, 0x76]);
+    for bank in 1..8 {
+        for i in 0..0xa0 {
+            rom[bank * 0x4000 + i] = byte(bank, i);
+        }
+    }
+    let mut m = gameboy::motherboard::MotherBoard::from_bytes(rom).unwrap();
+    m.run_cycles(100);
+    assert!(m.cpu.cpu.halted);
+    for &bank in [1, 3, 5, 7].iter() {
+        m.mmu.borrow_mut().set(0x2000, bank as u8);
+        m.mmu.borrow_mut().set(0xff46, 0x40);
+        m.run_cycles(800);
+        let mmu = m.mmu.borrow();
+        for i in 0..0xa0 {
+            assert_eq!(
+                mmu.peek(0xfe00 + i as u16),
+                byte(bank, i),
+                "bank {} byte {:02x}",
+                bank,
+                i
+            );
+        }
+    }
+    // VRAM is a source as well, written with the LCD off so the PPU doesn't keep it from the cpu.
+    let mut mmu = m.mmu.borrow_mut();
+    mmu.set(0xff40, 0x00);
+    for i in 0..0xa0 {
+        mmu.set(0x8000 + i, !i as u8);
+    }
+    mmu.set(0xff46, 0x80);
+    mmu.next(160 * 4);
+    for i in 0..0xa0 {
+        assert_eq!(mmu.get(0xfe00 + i), !i as u8);
+    }
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::Header;
+use gameboy::joypad::JoypadKey;
+use gameboy::memory::Memory;
+
+#[test]
+fn running_the_fork_leaves_the_parent_alone() {
+    let mut parent = common::machine(Header::default(), &common::NOISE);
+    let mut twin = common::machine(Header::default(), &common::NOISE);
+    for _ in 0..5 {
+        parent.run_frame();
+        twin.run_frame();
+    }
+    let before = parent.save_state();
+    let frame = parent.mmu.borrow().gpu.framebuffer().to_vec();
+
+    let mut fork = parent.fork();
+    assert!(fork.save_state() == before);
+    // The ROM is shared, not copied.
+    assert_eq!(
+        fork.mmu.borrow().cartridge.rom().as_ptr(),
+        parent.mmu.borrow().cartridge.rom().as_ptr()
+    );
+    fork.mmu.borrow_mut().joypad.keydown(JoypadKey::Start);
+    fork.mmu.borrow_mut().set(0xc100, 0x5a);
+    fork.run_cycles(10_000_000);
+    assert!(fork.save_state() != before);
+
+    assert!(parent.save_state() == before);
+    assert!(parent.mmu.borrow().gpu.framebuffer() == &frame[..]);
+    assert_eq!(parent.mmu.borrow().get(0xc100), 0x00);
+    for _ in 0..60 {
+        assert!(parent.run_frame().framebuffer == twin.run_frame().framebuffer);
+    }
+}
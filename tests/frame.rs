@@ -0,0 +1,246 @@
+mod common;
+
+use common::Header;
+use gameboy::joypad::JoypadKey;
+use gameboy::memory::Memory;
+use gameboy::motherboard::{Granularity, MotherBoard, FRAME_DOTS};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+// DI; XOR A; LDH (40h),A; JR -2: no VBlank ever comes, spinning with IME clear.
+const LCD_OFF: [u8; 6] = [0xf3, 0xaf, 0xe0, 0x40, 0x18, 0xfe];
+
+fn run(m: &mut MotherBoard, frames: u64) -> Vec<Vec<u8>> {
+    let mut r = Vec::new();
+    for i in 0..frames {
+        match i {
+            10 => m.mmu.borrow_mut().joypad.keydown(JoypadKey::Right),
+            20 => m.mmu.borrow_mut().joypad.keydown(JoypadKey::Start),
+            25 => m.mmu.borrow_mut().joypad.keyup(JoypadKey::Right),
+            _ => {}
+        }
+        r.push(m.run_frame().framebuffer.to_vec());
+    }
+    r
+}
+
+#[test]
+fn run_frame_is_deterministic() {
+    let mut a = common::machine(Header::default(), &common::NOISE);
+    let mut b = common::machine(Header::default(), &common::NOISE);
+    let fa = run(&mut a, 40);
+    let fb = run(&mut b, 40);
+    assert_eq!(a.frame_count(), 40);
+    assert_eq!(b.frame_count(), 40);
+    assert!(fa == fb);
+    // The frames do change, so the comparison means something.
+    assert!(fa.windows(2).any(|w| w[0] != w[1]));
+}
+
+// Hold Right for frames 5-14 and A for frames 10-19, through the host keys.
+fn press(m: &mut MotherBoard, i: u64) {
+    let mut mmu = m.mmu.borrow_mut();
+    match i {
+        5 => mmu.joypad.keydown(JoypadKey::Right),
+        10 => mmu.joypad.keydown(JoypadKey::A),
+        15 => mmu.joypad.keyup(JoypadKey::Right),
+        20 => mmu.joypad.keyup(JoypadKey::A),
+        _ => {}
+    }
+}
+
+#[test]
+fn movie_playback_matches_the_recording() {
+    let mut m = common::machine(Header::default(), &common::NOISE);
+    m.run_frame();
+    m.start_recording();
+    let mut recorded = Vec::new();
+    for i in 0..30 {
+        press(&mut m, i);
+        recorded.push(m.run_frame().framebuffer.to_vec());
+    }
+    let movie = m.stop_recording().unwrap();
+    assert_eq!(movie.len(), 30);
+    assert_eq!(movie.frames[12], (JoypadKey::Right as u8) | (JoypadKey::A as u8));
+
+    // Without the inputs the session looks different.
+    let mut idle = common::machine(Header::default(), &common::NOISE);
+    let frames: Vec<Vec<u8>> = (0..31).map(|_| idle.run_frame().framebuffer.to_vec()).collect();
+    assert!(frames[1..] != recorded[..]);
+
+    // Replay on a fresh machine, the host keys are ignored meanwhile.
+    let mut other = common::machine(Header::default(), &common::NOISE);
+    other.play_movie(movie.clone()).unwrap();
+    other.mmu.borrow_mut().joypad.keydown(JoypadKey::Down);
+    for (i, want) in recorded.iter().enumerate() {
+        assert!(other.is_playing());
+        assert!(other.run_frame().framebuffer == &want[..], "frame {}", i);
+    }
+    // And on the recording machine itself.
+    m.play_movie(movie).unwrap();
+    for want in &recorded {
+        assert!(m.run_frame().framebuffer == &want[..]);
+    }
+}
+
+#[test]
+fn run_cycles_in_chunks_matches_one_call() {
+    let mut a = common::machine(Header::default(), &common::NOISE);
+    let mut b = common::machine(Header::default(), &common::NOISE);
+    let n = a.run_cycles(70224);
+    assert!((70224..70224 + 24).contains(&n));
+    // Each call ends on the first instruction that reaches its budget, so the last one ends where a did.
+    let mut total = 0;
+    while total < 70224 {
+        total += b.run_cycles((70224 - total).min(7));
+    }
+    assert_eq!(total, n);
+    assert_eq!(a.save_state(), b.save_state());
+}
+
+#[test]
+fn from_rom_runs_a_static_image() {
+    let rom: &'static [u8] = Box::leak(common::rom(&common::NOISE).into_boxed_slice());
+    let mut a = MotherBoard::from_rom(rom);
+    let mut b = common::machine(Header::default(), &common::NOISE);
+    for _ in 0..3 {
+        assert!(a.run_frame().framebuffer == b.run_frame().framebuffer);
+    }
+    assert_eq!(a.save_state(), b.save_state());
+}
+
+#[test]
+fn on_frame_fires_once_per_frame() {
+    let mut m = common::machine(Header::default(), &common::NOISE);
+    let sizes = Rc::new(RefCell::new(Vec::new()));
+    let seen = sizes.clone();
+    m.on_frame(move |frame| seen.borrow_mut().push(frame.len()));
+    m.run_cycles(2 * 70224);
+    assert_eq!(*sizes.borrow(), [160 * 144 * 4; 2]);
+    m.run_frame();
+    assert_eq!(sizes.borrow().len(), 3);
+}
+
+// Count the on_frame calls over the same ten frames at a granularity.
+fn observed(granularity: Granularity) -> usize {
+    let mut m = common::machine(Header::default(), &common::IDLE);
+    m.run_frame();
+    let calls = Rc::new(RefCell::new(0));
+    let seen = calls.clone();
+    m.on_frame(move |_| *seen.borrow_mut() += 1);
+    m.set_observer_granularity(granularity);
+    m.run_cycles(10 * 70224);
+    let n = *calls.borrow();
+    n
+}
+
+#[test]
+fn scanline_granularity_observes_every_line() {
+    let (frame, scanline, instruction) = (
+        observed(Granularity::Frame),
+        observed(Granularity::Scanline),
+        observed(Granularity::Instruction),
+    );
+    assert_eq!(frame, 10);
+    assert!((1530..=1550).contains(&scanline), "{} calls at Scanline", scanline);
+    assert!(instruction > scanline);
+}
+
+#[test]
+fn last_frame_stats_count_a_frame() {
+    // LD A,01h; LDH (FFh),A; EI; loop: HALT; JR loop, with RETI at the VBlank vector.
+    let mut rom = common::rom(&[0x3e, 0x01, 0xe0, 0xff, 0xfb, 0x76, 0x18, 0xfd]);
+    rom[0x0040] = 0xd9;
+    let mut m = MotherBoard::from_bytes(rom).unwrap();
+    assert_eq!(m.last_frame_stats(), Default::default());
+    // The boot ROM leaves a VBlank request in IF, dispatched as soon as the first frame enables interrupts.
+    m.run_frame();
+    assert_eq!(m.last_frame_stats().interrupts, 2);
+    for _ in 0..3 {
+        m.run_frame();
+        let stats = m.last_frame_stats();
+        assert!((70220..=70228).contains(&stats.cycles), "{:?}", stats);
+        assert!(stats.instructions > 0);
+        assert_eq!(stats.interrupts, 1);
+        assert_eq!(stats.mode_entries, [144, 1, 144, 144]);
+    }
+}
+
+#[test]
+fn speed_multiplier_scales_the_frames_per_host_frame() {
+    for &(mult, frames) in &[(1.0, 10), (2.0, 20), (0.5, 5)] {
+        let mut m = common::machine(Header::default(), &common::IDLE);
+        m.set_speed_multiplier(mult);
+        for _ in 0..10 {
+            m.run_host_frame();
+        }
+        assert_eq!(m.frame_count(), frames, "{}x", mult);
+    }
+}
+
+#[test]
+fn skipped_frames_keep_the_timing() {
+    let mut a = common::machine(Header::default(), &common::NOISE);
+    let mut b = common::machine(Header::default(), &common::NOISE);
+    let drawn = Rc::new(RefCell::new(Vec::new()));
+    let d = drawn.clone();
+    b.on_frame(move |frame| d.borrow_mut().push(frame.to_vec()));
+    b.set_frame_skip(2);
+    let mut frames = Vec::new();
+    for _ in 0..10 {
+        frames.push(a.run_frame().framebuffer.to_vec());
+        b.run_frame();
+    }
+    // The last frame was drawn, so even the framebuffer in the state matches.
+    assert!(a.save_state() == b.save_state());
+    // Only frames 0, 3, 6 and 9 are drawn, exactly as without skipping.
+    let drawn = drawn.borrow();
+    assert_eq!(drawn.len(), 4);
+    for (i, frame) in drawn.iter().enumerate() {
+        assert!(*frame == frames[i * 3], "frame {}", i * 3);
+    }
+}
+
+#[test]
+fn pacing_follows_the_clock_rate() {
+    let mut m = common::machine(Header::default(), &common::IDLE);
+    for &hz in &[1, 1000, 1_000_000, 4_194_304] {
+        m.set_clock_hz(hz);
+        // One second in ticks of 10ms.
+        let cycles: u32 = (0..100)
+            .map(|_| m.pacer().cycles_for_elapsed(Duration::from_millis(10)))
+            .sum();
+        assert!((hz - 1..=hz + 1).contains(&cycles), "{} Hz: {}", hz, cycles);
+    }
+}
+
+#[test]
+fn frames_end_with_the_lcd_off() {
+    let mut m = common::machine(Header::default(), &LCD_OFF);
+    for _ in 0..3 {
+        assert!(!m.run_frame().short);
+    }
+    assert_eq!(m.mmu.borrow().get(0xff40), 0x00);
+    assert!(!m.cpu.cpu.ei);
+    assert!((u64::from(FRAME_DOTS)..u64::from(FRAME_DOTS) + 24).contains(&m.last_frame_stats().cycles));
+}
+
+#[test]
+fn the_ceiling_cuts_frames_short() {
+    let mut m = common::machine(Header::default(), &LCD_OFF);
+    m.run_frame();
+    m.set_frame_cycle_ceiling(10000);
+    let stats = m.last_frame_stats();
+    let mut shorts = 0;
+    while m.run_frame().short {
+        shorts += 1;
+        // The frame isn't over, its counters aren't updated.
+        assert_eq!(m.last_frame_stats(), stats);
+    }
+    assert_eq!(shorts, FRAME_DOTS / 10000);
+    // The short calls add up to one whole frame.
+    assert!((u64::from(FRAME_DOTS)..u64::from(FRAME_DOTS) + 24).contains(&m.last_frame_stats().cycles));
+    m.set_frame_cycle_ceiling(2 * FRAME_DOTS);
+    assert!(!m.run_frame().short);
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use gameboy::intf::Flag;
+use gameboy::motherboard::MotherBoard;
+
+// Start the timer at 4096 Hz 32 counts before TIMA overflows, enable the VBlank and timer interrupts and halt between
+// them: LD A,04h; LDH (07h),A; LD A,E0h; LDH (05h),A; XOR A; LDH (0Fh),A; LD A,05h; LDH (FFh),A; EI; loop: HALT;
+// JR loop. Both handlers are a RETI.
+fn machine() -> MotherBoard {
+    let mut rom = common::rom(&[
+        0x3e, 0x04, 0xe0, 0x07, 0x3e, 0xe0, 0xe0, 0x05, 0xaf, 0xe0, 0x0f, 0x3e, 0x05, 0xe0, 0xff, 0xfb, 0x76, 0x18,
+        0xfd,
+    ]);
+    rom[0x0040] = 0xd9;
+    rom[0x0050] = 0xd9;
+    MotherBoard::from_bytes(rom).unwrap()
+}
+
+#[test]
+fn drain_the_dispatched_interrupts_in_order() {
+    let mut m = machine();
+    m.capture_interrupts(true);
+    // TIMA overflows about 32768 cycles in, before the VBlank of line 144.
+    m.run_frame();
+    let first = m.drain_interrupt_events();
+    let flags: Vec<Flag> = first.iter().map(|e| e.flag).collect();
+    assert_eq!(flags, [Flag::Timer, Flag::VBlank]);
+    assert!(first[0].cycles < first[1].cycles);
+    assert!(m.drain_interrupt_events().is_empty());
+    // The next overflow is 256 counts away, the next frame only has its VBlank.
+    m.run_frame();
+    let events = m.drain_interrupt_events();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].flag, Flag::VBlank);
+    // One frame after the last, give or take the instruction the dispatch waited for.
+    let delta = events[0].cycles - first[1].cycles;
+    assert!((70224 - 24..70224 + 24).contains(&delta), "{}", delta);
+
+    // Nothing is kept before capturing starts.
+    let mut m = machine();
+    m.run_frame();
+    assert!(m.drain_interrupt_events().is_empty());
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use gameboy::link::LinkCable;
+use gameboy::memory::Memory;
+use gameboy::motherboard::MotherBoard;
+
+// DI; LD A,b; LDH (01h),A; LD A,sc; LDH (02h),A; JR -2: start a transfer of b, on the internal clock with SC 81h or
+// waiting for the other side with 80h.
+fn sender(b: u8, sc: u8) -> MotherBoard {
+    let program = [0xf3, 0x3e, b, 0xe0, 0x01, 0x3e, sc, 0xe0, 0x02, 0x18, 0xfe];
+    MotherBoard::from_bytes(common::rom(&program)).unwrap()
+}
+
+fn sent(m: &MotherBoard) -> (u8, u8, u8) {
+    let mmu = m.mmu.borrow();
+    (mmu.get(0xff01), mmu.get(0xff02) & 0x80, mmu.get(0xff0f) & 0x08)
+}
+
+#[test]
+fn linked_machines_swap_their_bytes() {
+    let mut link = LinkCable::connect(sender(0x42, 0x81), sender(0x99, 0x80));
+    link.run_frame();
+    assert_eq!(sent(&link.a), (0x99, 0x00, 0x08));
+    assert_eq!(sent(&link.b), (0x42, 0x00, 0x08));
+}
+
+#[test]
+fn nothing_plugged_in_shifts_in_ff() {
+    let mut m = sender(0x42, 0x81);
+    m.run_frame();
+    assert_eq!(sent(&m), (0xff, 0x00, 0x08));
+    // The external clock never comes.
+    let mut m = sender(0x99, 0x80);
+    m.run_frame();
+    assert_eq!(sent(&m), (0x99, 0x80, 0x00));
+}
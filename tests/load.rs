@@ -0,0 +1,92 @@
+mod common;
+
+use common::Header;
+use gameboy::cartridge::{self, CartridgeError};
+
+fn error(rom: Vec<u8>) -> CartridgeError {
+    match cartridge::from_bytes(rom) {
+        Ok(_) => panic!("the rom loaded"),
+        Err(e) => e,
+    }
+}
+
+fn with_type(cart_type: u8) -> Vec<u8> {
+    let header = Header {
+        cart_type,
+        ..Header::default()
+    };
+    common::rom_with(header, &common::IDLE)
+}
+
+#[test]
+fn good_roms_load() {
+    assert!(cartridge::from_bytes(common::rom(&common::IDLE)).is_ok());
+    assert!(cartridge::from_bytes(with_type(0x1b)).is_ok());
+}
+
+#[test]
+fn too_small() {
+    assert!(matches!(error(vec![0x00; 0x014f]), CartridgeError::TooSmall(0x014f)));
+    assert!(matches!(error(vec![]), CartridgeError::TooSmall(0)));
+}
+
+#[test]
+fn unsupported_mapper() {
+    // MBC6 and the Pocket Camera.
+    assert!(matches!(
+        error(with_type(0x20)),
+        CartridgeError::UnsupportedMapper(0x20)
+    ));
+    assert!(matches!(
+        error(with_type(0xfc)),
+        CartridgeError::UnsupportedMapper(0xfc)
+    ));
+}
+
+#[test]
+fn size_mismatch() {
+    let mut rom = common::rom(&common::IDLE);
+    rom.truncate(0x4000);
+    let e = error(rom);
+    assert!(matches!(
+        e,
+        CartridgeError::SizeMismatch {
+            declared: 0x8000,
+            actual: 0x4000
+        }
+    ));
+    let mut rom = common::rom(&common::IDLE);
+    rom.resize(0x10000, 0x00);
+    let e = error(rom);
+    assert!(matches!(
+        e,
+        CartridgeError::SizeMismatch {
+            declared: 0x8000,
+            actual: 0x10000
+        }
+    ));
+}
+
+#[test]
+fn unsupported_sizes() {
+    let mut rom = common::rom(&common::IDLE);
+    rom[0x0148] = 0x20;
+    assert!(matches!(error(rom), CartridgeError::UnsupportedRomSize(0x20)));
+    let header = Header {
+        cart_type: 0x03,
+        ram_size: 0x07,
+        ..Header::default()
+    };
+    let rom = common::rom_with(header, &common::IDLE);
+    assert!(matches!(error(rom), CartridgeError::UnsupportedRamSize(0x07)));
+}
+
+#[test]
+fn damaged_header() {
+    let mut rom = common::rom(&common::IDLE);
+    rom[0x0110] ^= 0x01;
+    assert!(matches!(error(rom), CartridgeError::BadLogo));
+    let mut rom = common::rom(&common::IDLE);
+    rom[0x014d] ^= 0x01;
+    assert!(matches!(error(rom), CartridgeError::BadHeaderChecksum));
+}
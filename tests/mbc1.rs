@@ -0,0 +1,59 @@
+mod common;
+
+use common::Header;
+use gameboy::memory::Memory;
+use gameboy::motherboard::MotherBoard;
+
+// MBC1+RAM with 32KB of RAM, the most a cartridge without a large ROM can bank.
+fn machine() -> MotherBoard {
+    let mbc1 = Header {
+        cart_type: 0x02,
+        rom_size: 0x00,
+        ram_size: 0x03,
+        ..Header::default()
+    };
+    let m = common::machine(mbc1, &common::IDLE);
+    m.mmu.borrow_mut().set(0x0000, 0x0a);
+    m
+}
+
+#[test]
+fn mode_1_banks_four_distinct_ram_banks() {
+    let m = machine();
+    let mut mmu = m.mmu.borrow_mut();
+    mmu.set(0x6000, 0x01);
+    for bank in 0..4 {
+        mmu.set(0x4000, bank);
+        mmu.set(0xa000, 0x10 + bank);
+        mmu.set(0xbfff, 0x20 + bank);
+    }
+    for bank in 0..4 {
+        mmu.set(0x4000, bank);
+        assert_eq!(mmu.get(0xa000), 0x10 + bank);
+        assert_eq!(mmu.get(0xbfff), 0x20 + bank);
+    }
+}
+
+#[test]
+fn mode_0_locks_the_ram_to_bank_0() {
+    let m = machine();
+    let mut mmu = m.mmu.borrow_mut();
+    mmu.set(0x6000, 0x01);
+    for bank in 0..4 {
+        mmu.set(0x4000, bank);
+        mmu.set(0xa000, 0x10 + bank);
+    }
+    mmu.set(0x6000, 0x00);
+    for bank in 0..4 {
+        mmu.set(0x4000, bank);
+        assert_eq!(mmu.get(0xa000), 0x10);
+    }
+    // Writes in mode 0 land in bank 0 too.
+    mmu.set(0x4000, 0x02);
+    mmu.set(0xa000, 0x55);
+    mmu.set(0x6000, 0x01);
+    mmu.set(0x4000, 0x00);
+    assert_eq!(mmu.get(0xa000), 0x55);
+    mmu.set(0x4000, 0x02);
+    assert_eq!(mmu.get(0xa000), 0x12);
+}
@@ -0,0 +1,301 @@
+mod common;
+
+use common::Header;
+#[cfg(feature = "std")]
+use gameboy::apu::Apu;
+use gameboy::convention::Term;
+use gameboy::infrared::IrPeer;
+use gameboy::intf::Flag;
+use gameboy::memory::Memory;
+use gameboy::mmunit::{self, BankRegister, BankSwitch, IoWrite, Mmunit, Region};
+use gameboy::motherboard::MotherBoard;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[test]
+fn echo_ram_mirrors_work_ram() {
+    let mut mmu = Mmunit::from_rom(common::rom(&common::IDLE));
+    mmu.set(0xc100, 0x12);
+    assert_eq!(mmu.get(0xe100), 0x12);
+    mmu.set(0xfdff, 0x34);
+    assert_eq!(mmu.get(0xddff), 0x34);
+}
+
+#[test]
+fn unusable_area_reads() {
+    let mut mmu = Mmunit::from_rom(common::rom(&common::IDLE));
+    mmu.set(0xfeb0, 0x12);
+    assert_eq!(mmu.get(0xfeb0), 0xff);
+    assert_eq!(mmu.get(0xfeff), 0xff);
+
+    let header = Header {
+        cgb: 0x80,
+        ..Header::default()
+    };
+    let mut mmu = Mmunit::from_rom(common::rom_with(header, &common::IDLE));
+    mmu.set(0xfeb0, 0x12);
+    assert_eq!(mmu.get(0xfeb0), 0xbb);
+    assert_eq!(mmu.get(0xfee5), 0xee);
+}
+
+#[test]
+fn svbk_switches_the_d000_bank_on_cgb_only() {
+    let header = Header {
+        cgb: 0x80,
+        ..Header::default()
+    };
+    let mut mmu = Mmunit::from_rom(common::rom_with(header, &common::IDLE));
+    for bank in 1..8 {
+        mmu.set(0xff70, bank);
+        mmu.set(0xd000, 0x10 + bank);
+    }
+    // Bank 0 selects bank 1. C000-CFFF stays fixed, the echo of D000-DFFF follows the bank.
+    mmu.set(0xff70, 0);
+    assert_eq!((mmu.get(0xff70), mmu.get(0xd000)), (0xf9, 0x11));
+    mmu.set(0xc000, 0x01);
+    for bank in 1..8 {
+        mmu.set(0xff70, bank);
+        assert_eq!(mmu.get(0xd000), 0x10 + bank);
+        assert_eq!(mmu.get(0xf000), 0x10 + bank);
+        assert_eq!(mmu.get(0xc000), 0x01);
+    }
+
+    let mut mmu = Mmunit::from_rom(common::rom(&common::IDLE));
+    mmu.set(0xd000, 0x11);
+    mmu.set(0xff70, 2);
+    mmu.set(0xd000, 0x12);
+    mmu.set(0xff70, 1);
+    assert_eq!((mmu.get(0xff70), mmu.get(0xd000)), (0xff, 0x12));
+}
+
+#[test]
+fn interrupt_registers() {
+    let mut mmu = Mmunit::from_rom(common::rom(&common::IDLE));
+    mmu.set_interrupt_flag(0x00);
+    mmu.request_interrupt(Flag::Timer);
+    assert_eq!(mmu.interrupt_flag(), 0xe4);
+    assert_eq!(mmu.get(0xff0f), 0xe4);
+    mmu.request_interrupt(Flag::VBlank);
+    assert_eq!(mmu.interrupt_flag(), 0xe5);
+    // The upper three bits always read back as 1, whatever was written.
+    mmu.set_interrupt_flag(0x00);
+    assert_eq!(mmu.interrupt_flag(), 0xe0);
+    mmu.set(0xff0f, 0xff);
+    assert_eq!(mmu.interrupt_flag(), 0xff);
+    mmu.set(0xff0f, 0x1f);
+    assert_eq!(mmu.get(0xff0f), 0xff);
+
+    mmu.set_interrupt_enable(0x15);
+    assert_eq!((mmu.interrupt_enable(), mmu.get(0xffff)), (0x15, 0x15));
+}
+
+#[test]
+fn write_mem_switches_the_rom_bank() {
+    // MBC1 with 8 banks, each marked with its number.
+    let header = Header {
+        cart_type: 0x01,
+        rom_size: 0x02,
+        ..Header::default()
+    };
+    let mut rom = common::rom_with(header, &common::IDLE);
+    for bank in 1..8 {
+        rom[bank * 0x4000] = bank as u8;
+    }
+    let mut m = MotherBoard::from_bytes(rom).unwrap();
+    assert_eq!(m.read_mem(0x4000), 1);
+    m.write_mem(0x2000, 3);
+    assert_eq!(m.read_mem(0x4000), 3);
+    assert_eq!(m.peek_mem(0x4000), 3);
+}
+
+#[test]
+fn peek_mem_leaves_the_machine_alone() {
+    let mut m = common::machine(Header::default(), &common::IDLE);
+    m.run_cycles(1000);
+    let state = m.save_state();
+    let ly = m.peek_mem(0xff44);
+    m.peek_mem(0xff41);
+    m.peek_mem(0xff00);
+    assert!(m.save_state() == state);
+    assert_eq!(m.read_mem(0xff44), ly);
+    // A running OAM DMA hides C000 from the cpu, not from peek_mem.
+    m.write_mem(0xc000, 0x12);
+    for a in 0xc100..0xc1a0 {
+        m.write_mem(a, 0x34);
+    }
+    m.write_mem(0xff46, 0xc1);
+    m.run_cycles(40);
+    assert_eq!(m.read_mem(0xc000), 0x34);
+    assert_eq!(m.peek_mem(0xc000), 0x12);
+}
+
+#[test]
+fn regions_and_their_banks() {
+    let header = Header {
+        cart_type: 0x01,
+        rom_size: 0x02,
+        ..Header::default()
+    };
+    let mut mmu = Mmunit::from_rom(common::rom_with(header, &common::IDLE));
+    mmu.set(0x2000, 5);
+    assert_eq!(Mmunit::region(0x4000), Region::RomBankN);
+    assert_eq!(mmu.current_bank(Region::RomBankN), Some(5));
+    assert_eq!(mmu.current_bank(Region::RomBank0), Some(0));
+    assert_eq!(Mmunit::region(0xe000), Region::EchoRam);
+    assert_eq!(mmu.current_bank(Region::EchoRam), None);
+    assert_eq!(Mmunit::region(0xd000), Region::WramBankN);
+    assert_eq!(mmu.current_bank(Region::WramBankN), Some(1));
+    assert_eq!(Mmunit::region(0xfea0), Region::Unusable);
+    assert_eq!(Mmunit::region(0xff80), Region::Hram);
+    assert_eq!(Mmunit::region(0xffff), Region::InterruptEnable);
+}
+
+#[test]
+fn the_ppu_mode_blocks_vram_and_oam() {
+    let mut mmu = Mmunit::from_rom(common::rom(&common::IDLE));
+    let until = |mmu: &mut Mmunit, mode: u8| {
+        while mmu.get(0xff41) & 0x03 != mode {
+            mmu.next(4);
+        }
+    };
+    until(&mut mmu, 2);
+    mmu.set(0x8000, 0x11);
+    mmu.set(0xfe00, 0x22);
+    assert_eq!(mmu.get(0x8000), 0x11);
+    assert_eq!(mmu.get(0xfe00), 0xff);
+    until(&mut mmu, 3);
+    mmu.set(0x8000, 0x33);
+    assert_eq!(mmu.get(0x8000), 0xff);
+    assert_eq!(mmu.get(0xfe00), 0xff);
+    // The write was dropped, the PPU side still sees the byte written in mode 2.
+    assert_eq!(mmu.peek(0x8000), 0x11);
+    until(&mut mmu, 0);
+    mmu.set(0x8000, 0x44);
+    mmu.set(0xfe00, 0x55);
+    assert_eq!(mmu.get(0x8000), 0x44);
+    assert_eq!(mmu.get(0xfe00), 0x55);
+}
+
+#[test]
+fn io_registers_power_up_as_the_boot_rom_leaves_them() {
+    let gb = mmunit::io_power_up(Term::GB);
+    let gbc = mmunit::io_power_up(Term::GBC);
+    for io in [gb, gbc].iter() {
+        assert_eq!([io[0x40], io[0x41], io[0x47], io[0x0f]], [0x91, 0x85, 0xfc, 0xe1]);
+        assert_eq!([io[0x10], io[0x24], io[0x25], io[0x26]], [0x80, 0x77, 0xf3, 0xf1]);
+    }
+    assert_eq!((gb[0x04], gb[0x02], gb[0x46]), (0xab, 0x7e, 0xff));
+    assert_eq!((gbc[0x04], gbc[0x02], gbc[0x46]), (0x00, 0x7f, 0x00));
+
+    // The machine reads them back, the sound registers once the APU is attached.
+    #[cfg(feature = "std")]
+    {
+        let mut mmu = Mmunit::from_rom(common::rom(&common::IDLE));
+        mmu.set_apu(Apu::power_up(48000));
+        for &a in &[0xff04, 0xff0f, 0xff40, 0xff47, 0xff24, 0xff25, 0xff26] {
+            assert_eq!(mmu.get(a), gb[usize::from(a - 0xff00)], "{:04x}", a);
+        }
+    }
+}
+
+#[test]
+fn io_trace_reports_lcdc_toggles() {
+    // DI; LD A,11h; LDH (40h),A; LD A,91h; LDH (40h),A; LD (C000h),A; JR -2.
+    let program = [
+        0xf3, 0x3e, 0x11, 0xe0, 0x40, 0x3e, 0x91, 0xe0, 0x40, 0xea, 0x00, 0xc0, 0x18, 0xfe,
+    ];
+    let mut m = MotherBoard::from_bytes(common::rom(&program)).unwrap();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let sink = log.clone();
+    m.mmu.borrow_mut().set_io_trace(move |w| sink.borrow_mut().push(w));
+    let mut spans = Vec::new();
+    // NOP; JP 0150h at the entry point first.
+    for _ in 0..8 {
+        let before = m.cpu.cpu.cycles();
+        m.next();
+        spans.push(before..=m.cpu.cpu.cycles());
+    }
+    // The work RAM write isn't an I/O register.
+    let log = log.borrow();
+    let changes: Vec<(u16, u8, u8)> = log.iter().map(|w: &IoWrite| (w.addr, w.old, w.new)).collect();
+    assert_eq!(changes, [(0xff40, 0x91, 0x11), (0xff40, 0x11, 0x91)]);
+    // Both writes land during their LDH.
+    assert!(spans[4].contains(&log[0].cycles), "{:?} {}", spans[4], log[0].cycles);
+    assert!(spans[6].contains(&log[1].cycles), "{:?} {}", spans[6], log[1].cycles);
+}
+
+#[test]
+fn bank_switch_hook_reports_the_changes() {
+    // A 1MB MBC1 with RAM.
+    let mbc1 = Header {
+        cart_type: 0x02,
+        rom_size: 0x05,
+        ram_size: 0x03,
+        ..Header::default()
+    };
+    let mut mmu = Mmunit::from_rom(common::rom_with(mbc1, &common::IDLE));
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let sink = log.clone();
+    mmu.set_bank_switch_hook(move |s: BankSwitch| sink.borrow_mut().push((s.kind, s.old, s.new)));
+    mmu.set(0x2000, 0x05);
+    // The same bank again changes nothing.
+    mmu.set(0x2000, 0x05);
+    mmu.set(0x0000, 0x0a);
+    mmu.set(0x4000, 0x01);
+    // In mode 1 the upper bits bank the RAM as well.
+    mmu.set(0x6000, 0x01);
+    assert_eq!(
+        *log.borrow(),
+        [
+            (BankRegister::RomBank, 1, 5),
+            (BankRegister::RamEnable, 0, 1),
+            (BankRegister::RomBank, 5, 37),
+            (BankRegister::RamBank, 0, 1),
+            (BankRegister::Mode, 0, 1),
+        ]
+    );
+    // Without the hook the writes still switch the banks.
+    mmu.clear_bank_switch_hook();
+    mmu.set(0x2000, 0x02);
+    assert_eq!(log.borrow().len(), 5);
+    assert_eq!(mmu.cartridge.bank(0x4000), 0x22);
+}
+
+// An infrared peer shining back what the LED sends.
+struct Mirror(Rc<RefCell<bool>>);
+
+impl IrPeer for Mirror {
+    fn led(&mut self, on: bool) {
+        *self.0.borrow_mut() = on;
+    }
+
+    fn light(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+#[test]
+fn infrared_port_on_the_gbc() {
+    let header = Header {
+        cgb: 0x80,
+        ..Header::default()
+    };
+    let mut mmu = Mmunit::from_rom(common::rom_with(header, &common::IDLE));
+    // The LED is off and without a peer no light is received, also with reading enabled.
+    assert_eq!(mmu.get(0xff56), 0x3e);
+    mmu.set(0xff56, 0xc0);
+    assert_eq!(mmu.get(0xff56), 0xfe);
+    mmu.set(0xff56, 0xc1);
+    assert_eq!(mmu.get(0xff56), 0xff);
+    // A peer receiving the LED clears bit 1, only while reading is enabled.
+    mmu.infrared.set_peer(Box::new(Mirror(Rc::new(RefCell::new(false)))));
+    mmu.set(0xff56, 0xc1);
+    assert_eq!(mmu.get(0xff56), 0xfd);
+    mmu.set(0xff56, 0x01);
+    assert_eq!(mmu.get(0xff56), 0x3f);
+
+    let mut mmu = Mmunit::from_rom(common::rom(&common::IDLE));
+    let before = mmu.get(0xff56);
+    mmu.set(0xff56, 0xc1);
+    assert_eq!(mmu.get(0xff56), before);
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use gameboy::motherboard::{MotherBoard, TestResult};
+
+// The end of a mooneye test ROM: LD B,b; LD C,c; LD D,d; LD E,e; LD H,h; LD L,l; LD B,B; JR -2. The suite itself
+// isn't in the repository, the ROM only reports the registers given.
+fn report(r: [u8; 6]) -> MotherBoard {
+    let program = [
+        0x06, r[0], 0x0e, r[1], 0x16, r[2], 0x1e, r[3], 0x26, r[4], 0x2e, r[5], 0x40, 0x18, 0xfe,
+    ];
+    MotherBoard::from_bytes(common::rom(&program)).unwrap()
+}
+
+#[test]
+fn the_fibonacci_signature_passes() {
+    let mut m = report([3, 5, 8, 13, 21, 34]);
+    assert!(matches!(m.run_test(10), TestResult::Passed));
+    assert_eq!(m.cpu.cpu.reg.pc, 0x015d);
+}
+
+#[test]
+fn other_registers_fail() {
+    match report([0x42; 6]).run_test(10) {
+        TestResult::Failed { registers } => {
+            assert_eq!([registers.b, registers.c, registers.h, registers.l], [0x42; 4]);
+        }
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn no_ld_b_b_times_out() {
+    let mut m = MotherBoard::from_bytes(common::rom(&common::IDLE)).unwrap();
+    assert!(matches!(m.run_test(3), TestResult::Timeout));
+    assert_eq!(m.frame_count(), 3);
+}
@@ -0,0 +1,40 @@
+// The hardware kept without the std feature, run from a static ROM image. cargo test --no-default-features runs it
+// against the no_std build of the library.
+mod common;
+
+use common::Header;
+use gameboy::memory::Memory;
+use gameboy::motherboard::MotherBoard;
+
+// DI; XOR A; LDH (40h),A; LD A,FFh; LD HL,8010h; LD B,10h; loop: LD (HL+),A; DEC B; JR NZ,loop; LD A,01h;
+// LD (9800h),A; LD A,04h; LDH (07h),A; LD A,91h; LDH (40h),A; JR -2: tile 1 black in the top left corner of the
+// background, the timer at 4096 Hz and the LCD on.
+const PROGRAM: [u8; 30] = [
+    0xf3, 0xaf, 0xe0, 0x40, 0x3e, 0xff, 0x21, 0x10, 0x80, 0x06, 0x10, 0x22, 0x05, 0x20, 0xfc, 0x3e, 0x01, 0xea, 0x00,
+    0x98, 0x3e, 0x04, 0xe0, 0x07, 0x3e, 0x91, 0xe0, 0x40, 0x18, 0xfe,
+];
+
+#[test]
+fn static_rom_runs_the_cpu_lcd_and_timer() {
+    let rom: &'static [u8] = Box::leak(common::rom_with(Header::default(), &PROGRAM).into_boxed_slice());
+    let mut m = MotherBoard::from_rom(rom);
+    let div = m.mmu.borrow().get(0xff04);
+    let cycles = m.run_cycles(6000);
+    assert_eq!(m.cpu.cpu.reg.pc, 0x016c);
+
+    let mmu = m.mmu.borrow();
+    assert_eq!(mmu.get(0x8010), 0xff);
+    assert_eq!(mmu.get(0x9800), 0x01);
+    // DIV counts every 256 cycles from power up, TIMA every 1024 from when the program started the timer.
+    assert_eq!(mmu.get(0xff04).wrapping_sub(div), (cycles / 256) as u8);
+    assert_eq!(mmu.get(0xff05), 0x05);
+    // The LCD drew the first lines since it was turned on: the tile, then the blank background.
+    assert!((10..=13).contains(&mmu.get(0xff44)));
+    let frame = mmu.gpu.framebuffer();
+    let black = common::pixel(frame, 0, 0);
+    for (x, y) in [(7, 0), (0, 7), (7, 7)].iter() {
+        assert_eq!(common::pixel(frame, *x, *y), black);
+    }
+    assert_ne!(common::pixel(frame, 8, 0), black);
+    assert_ne!(common::pixel(frame, 0, 8), black);
+}
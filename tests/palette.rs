@@ -0,0 +1,62 @@
+mod common;
+
+use common::Header;
+use gameboy::gpu::{ColorCorrection, Palette};
+use gameboy::memory::Memory;
+
+#[test]
+fn a_bgp_write_mid_frame_changes_the_lines_below() {
+    let mut m = common::machine(Header::default(), &common::IDLE);
+    m.run_frame();
+    m.mmu.borrow_mut().set(0xff47, 0x00);
+    while m.mmu.borrow().get(0xff44) != 72 {
+        m.next();
+    }
+    m.mmu.borrow_mut().set(0xff47, 0xff);
+    let frame = m.run_frame().framebuffer.to_vec();
+    let white = common::pixel(&frame, 0, 0);
+    let black = common::pixel(&frame, 0, 143);
+    assert_ne!(white, black);
+    for y in 0..144 {
+        let want = if y < 72 { white } else { black };
+        for x in 0..160 {
+            assert_eq!(common::pixel(&frame, x, y), want, "x {} y {}", x, y);
+        }
+    }
+}
+
+#[test]
+fn cgb_palettes_read_back_what_bcpd_and_ocpd_wrote() {
+    let cgb = Header {
+        cgb: 0x80,
+        ..Header::default()
+    };
+    let m = common::machine(cgb, &common::IDLE);
+    let mut mmu = m.mmu.borrow_mut();
+    mmu.gpu.set_color_correction(ColorCorrection::None);
+    // The LCD off, palette memory is open to the cpu at any time.
+    mmu.set(0xff40, 0x00);
+    let before: Vec<Palette> = (0..8).map(|i| mmu.gpu.bg_palette(i).unwrap()).collect();
+    // Color 2 of BG palette 3 with auto increment: R=1Fh, G=10h, B=01h, 061Fh little endian.
+    mmu.set(0xff68, 0x80 | (3 * 8 + 2 * 2));
+    mmu.set(0xff69, 0x1f);
+    mmu.set(0xff69, 0x06);
+    // Color 0 of OBJ palette 1, full blue.
+    mmu.set(0xff6a, 0x80 | 8);
+    mmu.set(0xff6b, 0x00);
+    mmu.set(0xff6b, 0x7c);
+    for (i, old) in before.iter().enumerate() {
+        let p = mmu.gpu.bg_palette(i).unwrap();
+        for c in 0..4 {
+            let want = if (i, c) == (3, 2) {
+                [0xff, 0x84, 0x08, 0xff]
+            } else {
+                old.colors[c]
+            };
+            assert_eq!(p.colors[c], want, "palette {} color {}", i, c);
+        }
+    }
+    assert_eq!(mmu.gpu.obj_palette(1).unwrap().colors[0], [0x00, 0x00, 0xff, 0xff]);
+    assert_eq!(mmu.gpu.bg_palette(8), None);
+    assert_eq!(mmu.gpu.obj_palette(8), None);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::Header;
+use gameboy::memory::Memory;
+use gameboy::motherboard::MotherBoard;
+
+const CGB: Header = Header {
+    cgb: 0x80,
+    sgb: 0x00,
+    cart_type: 0x00,
+    rom_size: 0x00,
+    ram_size: 0x00,
+};
+
+// The machine with the LCD turned off, so VRAM and OAM can be read.
+fn machine(header: Header) -> MotherBoard {
+    let m = common::machine(header, &common::IDLE);
+    m.mmu.borrow_mut().set(0xff40, 0x00);
+    m
+}
+
+fn bytes(m: &MotherBoard, range: std::ops::Range<u16>) -> Vec<u8> {
+    range.map(|a| m.mmu.borrow().get(a)).collect()
+}
+
+#[test]
+fn only_the_monochrome_boot_leaves_the_logo_in_vram() {
+    let gb = machine(Header::default());
+    let gbc = machine(CGB);
+    // The top half of the N of the logo, then its registered mark.
+    let n = [
+        0xf0, 0x00, 0xf0, 0x00, 0xfc, 0x00, 0xfc, 0x00, 0xfc, 0x00, 0xfc, 0x00, 0xf3, 0x00, 0xf3, 0x00,
+    ];
+    assert_eq!(bytes(&gb, 0x8010..0x8020), n);
+    assert_eq!(bytes(&gb, 0x8190..0x8192), [0x3c, 0x00]);
+    assert_eq!(gb.mmu.borrow().get(0x9910), 0x19);
+    assert_eq!(gb.mmu.borrow().get(0x9904), 0x01);
+    assert!(bytes(&gbc, 0x8000..0xa000).iter().all(|&b| b == 0x00));
+}
+
+#[test]
+fn monochrome_oam_repeats_every_8_bytes_and_is_cleared_on_color() {
+    let gb = bytes(&machine(Header::default()), 0xfe00..0xfea0);
+    assert!(gb.iter().any(|&b| b != 0x00));
+    for (i, &b) in gb.iter().enumerate() {
+        assert_eq!(b, gb[i % 8], "OAM byte {:02x}", i);
+    }
+    assert!(bytes(&machine(CGB), 0xfe00..0xfea0).iter().all(|&b| b == 0x00));
+}
+
+#[test]
+fn monochrome_hram_leans_by_address_parity() {
+    let ones = |m: &MotherBoard, odd: u16| -> u32 {
+        (0xff80..0xffff)
+            .filter(|a| a & 1 == odd)
+            .map(|a| m.mmu.borrow().get(a).count_ones())
+            .sum()
+    };
+    let gb = machine(Header::default());
+    // 63 bytes of 8 bits, OR3 sets 7 bits of 8 on average and AND3 1.
+    assert!(ones(&gb, 1) > 5 * ones(&gb, 0));
+    let gbc = machine(CGB);
+    assert!(ones(&gbc, 1) < 2 * ones(&gbc, 0));
+    assert_ne!(bytes(&gb, 0xff80..0xff88), bytes(&gbc, 0xff80..0xff88));
+}
+
+#[test]
+fn the_garbage_is_the_same_every_power_up() {
+    for &header in [Header::default(), CGB].iter() {
+        let a = machine(header);
+        let b = machine(header);
+        assert_eq!(bytes(&a, 0xfe00..0xfea0), bytes(&b, 0xfe00..0xfea0));
+        assert_eq!(bytes(&a, 0xff80..0xffff), bytes(&b, 0xff80..0xffff));
+    }
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::Header;
+use gameboy::memory::Memory;
+
+const CGB: Header = Header {
+    cgb: 0x80,
+    sgb: 0x00,
+    cart_type: 0x00,
+    rom_size: 0x00,
+    ram_size: 0x00,
+};
+
+// Whether a sprite of color 1 shows over the top left background tile, on a GameBoy Color with the background tiles
+// all of color bg_color. bg_priority is bit 7 of the tile attributes, obj_priority bit 7 of the sprite attributes.
+fn sprite_shows(lcdc0: bool, bg_priority: bool, obj_priority: bool, bg_color: u8) -> bool {
+    let mut m = common::machine(CGB, &common::IDLE);
+    {
+        let mut mmu = m.mmu.borrow_mut();
+        mmu.set(0xff40, 0x00);
+        // Tile 0 all of bg_color for the background, tile 1 all of color 1 for the sprite.
+        for i in 0..8 {
+            mmu.set(0x8000 + 2 * i, if bg_color & 1 != 0 { 0xff } else { 0x00 });
+            mmu.set(0x8001 + 2 * i, if bg_color & 2 != 0 { 0xff } else { 0x00 });
+            mmu.set(0x8010 + 2 * i, 0xff);
+            mmu.set(0x8011 + 2 * i, 0x00);
+        }
+        mmu.set(0xff4f, 0x01);
+        for a in 0x9800..0x9c00 {
+            mmu.set(a, if bg_priority { 0x80 } else { 0x00 });
+        }
+        mmu.set(0xff4f, 0x00);
+        // Background colors white, red, green and black, sprite colors 1-3 blue.
+        mmu.set(0xff68, 0x80);
+        for &v in [0xff, 0x7f, 0x1f, 0x00, 0xe0, 0x03, 0x00, 0x00].iter() {
+            mmu.set(0xff69, v);
+        }
+        mmu.set(0xff6a, 0x80);
+        for &v in [0xff, 0x7f, 0x00, 0x7c, 0x00, 0x7c, 0x00, 0x7c].iter() {
+            mmu.set(0xff6b, v);
+        }
+        // Sprite 0 over the top left tile.
+        mmu.set(0xfe00, 0x10);
+        mmu.set(0xfe01, 0x08);
+        mmu.set(0xfe02, 0x01);
+        mmu.set(0xfe03, if obj_priority { 0x80 } else { 0x00 });
+        mmu.set(0xff40, 0x92 | u8::from(lcdc0));
+    }
+    let mut frame = Vec::new();
+    for _ in 0..3 {
+        frame = m.run_frame().framebuffer.to_vec();
+    }
+    // The background alone at the right of the sprite.
+    let bg = common::pixel(&frame, 100, 4);
+    common::pixel(&frame, 4, 4) != bg
+}
+
+#[test]
+fn lcdc0_set_resolves_the_priority_bits() {
+    // Background color 0 is always behind.
+    for &(bg, obj) in [(false, false), (false, true), (true, false), (true, true)].iter() {
+        assert!(sprite_shows(true, bg, obj, 0), "bg {} obj {}", bg, obj);
+    }
+    // Other colors are only behind when neither priority bit is set.
+    assert!(sprite_shows(true, false, false, 1));
+    assert!(!sprite_shows(true, false, true, 1));
+    assert!(!sprite_shows(true, true, false, 1));
+    assert!(!sprite_shows(true, true, true, 1));
+}
+
+#[test]
+fn lcdc0_clear_puts_sprites_in_front() {
+    for &(bg, obj) in [(false, false), (false, true), (true, false), (true, true)].iter() {
+        for color in 0..4 {
+            assert!(
+                sprite_shows(false, bg, obj, color),
+                "bg {} obj {} color {}",
+                bg,
+                obj,
+                color
+            );
+        }
+    }
+}
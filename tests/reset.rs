@@ -0,0 +1,118 @@
+mod common;
+
+use common::Header;
+#[cfg(feature = "std")]
+use gameboy::convention::Term;
+use gameboy::memory::Memory;
+use gameboy::motherboard::SwapError;
+#[cfg(feature = "std")]
+use gameboy::register::Register;
+
+// MBC1 with 8K of battery backed RAM, kept in a file.
+#[cfg(feature = "std")]
+const BATTERY: Header = Header {
+    cgb: 0x00,
+    sgb: 0x00,
+    cart_type: 0x03,
+    rom_size: 0x00,
+    ram_size: 0x02,
+};
+
+#[cfg(feature = "std")]
+fn played(header: Header) -> gameboy::motherboard::MotherBoard {
+    let mut m = common::machine_from_file(header, &common::NOISE);
+    for _ in 0..5 {
+        m.run_frame();
+    }
+    m.mmu.borrow_mut().set(0x0000, 0x0a);
+    m.mmu.borrow_mut().set(0xa000, 0x5a);
+    m.mmu.borrow_mut().set(0xc000, 0x77);
+    m
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn reset_returns_to_power_up() {
+    let mut m = played(BATTERY);
+    assert_ne!(m.cpu.cpu.reg, Register::power_up(Term::GB));
+    m.reset();
+    assert_eq!(m.cpu.cpu.reg, Register::power_up(Term::GB));
+    assert_eq!(m.cpu.cpu.reg.pc, 0x0100);
+    assert_eq!(m.frame_count(), 0);
+    assert_eq!(m.mmu.borrow().get(0xc000), 0x00);
+    assert_eq!(m.mmu.borrow().get(0x8000), 0x00);
+    // The RAM is disabled again, once enabled it still holds the byte written before the reset.
+    assert_eq!(m.mmu.borrow().get(0xa000), 0xff);
+    m.mmu.borrow_mut().set(0x0000, 0x0a);
+    assert_eq!(m.mmu.borrow().get(0xa000), 0x5a);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn reset_with_ram_clear_zeroes_the_battery_ram() {
+    let mut m = played(BATTERY);
+    m.reset_with_ram_clear();
+    assert_eq!(m.cpu.cpu.reg, Register::power_up(Term::GB));
+    m.mmu.borrow_mut().set(0x0000, 0x0a);
+    assert_eq!(m.mmu.borrow().get(0xa000), 0x00);
+}
+
+#[test]
+fn reset_runs_the_boot_rom_again() {
+    // LD A,01h; LDH (50h),A, then filler up to 0100h.
+    let mut boot = vec![0x00; 0x100];
+    boot[..4].copy_from_slice(&[0x3e, 0x01, 0xe0, 0x50]);
+    let mut m = common::machine(Header::default(), &common::IDLE).with_boot_rom(boot);
+    for _ in 0..4 {
+        m.next();
+    }
+    assert_eq!(m.mmu.borrow().get(0x0000), 0x00);
+    m.reset();
+    assert_eq!(m.cpu.cpu.reg.pc, 0x0000);
+    assert_eq!(m.mmu.borrow().get(0x0000), 0x3e);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn load_cartridge_swaps_the_game() {
+    let mut m = played(BATTERY);
+    // LD A,42h; JR -2
+    let program = [0x3e, 0x42, 0x18, 0xfe];
+    let next = gameboy::cartridge::from_bytes(common::rom(&program)).unwrap();
+    m.load_cartridge(next).unwrap();
+    assert_eq!(m.cpu.cpu.reg, Register::power_up(Term::GB));
+    assert_eq!(m.cpu.cpu.reg.pc, 0x0100);
+    assert_eq!(m.frame_count(), 0);
+    let mmu = m.mmu.borrow();
+    assert_eq!(mmu.get(0x0101), 0xc3);
+    assert_eq!((mmu.get(0x0150), mmu.get(0x0151)), (0x3e, 0x42));
+    assert_eq!(mmu.get(0xc000), 0x00);
+    // The new cartridge has no RAM.
+    assert_eq!(mmu.get(0xa000), 0xff);
+    drop(mmu);
+    for _ in 0..3 {
+        m.next();
+    }
+    assert_eq!(m.cpu.cpu.reg.a, 0x42);
+}
+
+#[test]
+fn load_cartridge_keeps_the_boot_rom_of_its_console() {
+    let mut m = common::machine(Header::default(), &common::IDLE).with_boot_rom(vec![0x00; 0x100]);
+    m.run_frame();
+    let pc = m.cpu.cpu.reg.pc;
+    let cgb = Header {
+        cgb: 0x80,
+        ..Header::default()
+    };
+    let next = gameboy::cartridge::from_bytes(common::rom_with(cgb, &common::IDLE)).unwrap();
+    assert!(matches!(
+        m.load_cartridge(next),
+        Err(SwapError::BootRom { cgb: true, size: 0x100 })
+    ));
+    assert_eq!(m.cpu.cpu.reg.pc, pc);
+    let next = gameboy::cartridge::from_bytes(common::rom(&common::IDLE)).unwrap();
+    m.load_cartridge(next).unwrap();
+    assert_eq!(m.cpu.cpu.reg.pc, 0x0000);
+    assert_eq!(m.frame_count(), 0);
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use gameboy::motherboard::MotherBoard;
+
+// The chunks of a PNG file after the signature, as (type, data), with their CRCs checked.
+fn chunks(png: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+    let mut r = Vec::new();
+    let mut i = 8;
+    while i < png.len() {
+        let n = u32::from_be_bytes([png[i], png[i + 1], png[i + 2], png[i + 3]]) as usize;
+        let body = &png[i + 4..i + 8 + n];
+        let crc = u32::from_be_bytes([png[i + 8 + n], png[i + 9 + n], png[i + 10 + n], png[i + 11 + n]]);
+        assert_eq!(crc, common::crc32(body));
+        r.push(([body[0], body[1], body[2], body[3]], body[4..].to_vec()));
+        i += 12 + n;
+    }
+    r
+}
+
+// Inflate a zlib stream made of stored blocks only.
+fn inflate_stored(z: &[u8]) -> Vec<u8> {
+    assert_eq!(u16::from_be_bytes([z[0], z[1]]) % 31, 0);
+    let mut r = Vec::new();
+    let mut i = 2;
+    loop {
+        let last = z[i] & 0x01 == 0x01;
+        assert_eq!(z[i] & 0x06, 0x00, "not a stored block");
+        let n = usize::from(u16::from_le_bytes([z[i + 1], z[i + 2]]));
+        assert_eq!(n, usize::from(!u16::from_le_bytes([z[i + 3], z[i + 4]])));
+        r.extend_from_slice(&z[i + 5..i + 5 + n]);
+        i += 5 + n;
+        if last {
+            break;
+        }
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &v in &r {
+        a = (a + u32::from(v)) % 65521;
+        b = (b + a) % 65521;
+    }
+    assert_eq!(&z[i..], &((b << 16) | a).to_be_bytes());
+    r
+}
+
+#[test]
+fn screenshot_decodes_to_the_frame() {
+    let mut m = MotherBoard::from_bytes(common::rom(&common::NOISE)).unwrap();
+    for _ in 0..2 {
+        m.run_frame();
+    }
+    let frame = m.run_frame().framebuffer.to_vec();
+    let png = m.screenshot_png();
+    assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+    let chunks = chunks(&png);
+    let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(k, _)| k).collect();
+    assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+    // 160x144, 8 bit RGBA.
+    assert_eq!(chunks[0].1, [0, 0, 0, 160, 0, 0, 0, 144, 8, 6, 0, 0, 0]);
+
+    let raw = inflate_stored(&chunks[1].1);
+    assert_eq!(raw.len(), 144 * (160 * 4 + 1));
+    for (y, row) in raw.chunks(160 * 4 + 1).enumerate() {
+        assert_eq!(row[0], 0x00);
+        for x in 0..160 {
+            let p = common::pixel(&frame, x, y);
+            assert_eq!(row[1 + x * 4..5 + x * 4], [p[0], p[1], p[2], 0xff], "{} {}", x, y);
+        }
+    }
+}
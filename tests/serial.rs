@@ -0,0 +1,51 @@
+mod common;
+
+use gameboy::motherboard::MotherBoard;
+
+// Prints its result over the serial port the way the Blargg test ROMs do, one byte at a time with the internal clock,
+// waiting for bit 7 of SC to clear before sending the next:
+//
+//   0150  DI
+//         LD HL,0167h
+//   0154  LD A,(HL+)
+//         OR A
+//         JR Z,0165h
+//         LDH (01h),A
+//         LD A,81h
+//         LDH (02h),A
+//   015E  LDH A,(02h)
+//         ADD A,A
+//         JR C,015Eh
+//         JR 0154h
+//   0165  JR 0165h
+//   0167  "serial\n\nPassed\n", 00h
+fn machine() -> MotherBoard {
+    let mut program = vec![
+        0xf3, 0x21, 0x67, 0x01, 0x2a, 0xb7, 0x28, 0x0d, 0xe0, 0x01, 0x3e, 0x81, 0xe0, 0x02, 0xf0, 0x02, 0x87, 0x38,
+        0xfb, 0x18, 0xef, 0x18, 0xfe,
+    ];
+    program.extend_from_slice(b"serial\n\nPassed\n\0");
+    MotherBoard::from_rom(common::rom(&program))
+}
+
+#[test]
+fn captures_the_result_of_a_test_rom() {
+    let mut m = machine();
+    m.capture_serial(true);
+    for _ in 0..60 {
+        m.run_frame();
+        if m.serial_output().contains("Passed") {
+            break;
+        }
+    }
+    assert_eq!(&*m.serial_output(), "serial\n\nPassed\n");
+}
+
+#[test]
+fn nothing_is_captured_until_enabled() {
+    let mut m = machine();
+    for _ in 0..10 {
+        m.run_frame();
+    }
+    assert_eq!(&*m.serial_output(), "");
+}
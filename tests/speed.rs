@@ -0,0 +1,143 @@
+mod common;
+
+use common::Header;
+use gameboy::joypad::JoypadKey;
+use gameboy::memory::Memory;
+use gameboy::mmunit::Speed;
+
+const CGB: Header = Header {
+    cgb: 0x80,
+    sgb: 0x00,
+    cart_type: 0x00,
+    rom_size: 0x00,
+    ram_size: 0x00,
+};
+
+#[test]
+fn only_the_gbc_switches_speed() {
+    let m = common::machine(Header::default(), &common::IDLE);
+    let mut mmu = m.mmu.borrow_mut();
+    mmu.set(0xff4d, 0x01);
+    assert!(!mmu.switch_speed());
+    assert!(mmu.speed == Speed::Normal);
+    assert_eq!(mmu.get(0xff4d), 0xff);
+
+    let m = common::machine(CGB, &common::IDLE);
+    let mut mmu = m.mmu.borrow_mut();
+    // Without the switch prepared through KEY1 the speed stays.
+    assert!(!mmu.switch_speed());
+    mmu.set(0xff4d, 0x01);
+    assert!(mmu.switch_speed());
+    assert!(mmu.speed == Speed::Double);
+    assert_eq!(mmu.get(0xff4d) & 0x81, 0x80);
+    mmu.set(0xff4d, 0x01);
+    assert!(mmu.switch_speed());
+    assert!(mmu.speed == Speed::Normal);
+    assert_eq!(mmu.get(0xff4d) & 0x81, 0x00);
+}
+
+#[test]
+fn stop_switches_the_cgb_speed_and_resumes() {
+    // DI; LD A,01h; LDH (4Dh),A; STOP; INC B; JR -3
+    let mut m = common::machine(CGB, &[0xf3, 0x3e, 0x01, 0xe0, 0x4d, 0x10, 0x00, 0x04, 0x18, 0xfd]);
+    m.cpu.cpu.reg.b = 0x00;
+    for _ in 0..16 {
+        m.next();
+    }
+    assert_eq!(m.mmu.borrow().get(0xff4d) & 0x81, 0x80);
+    assert!(m.mmu.borrow().speed == Speed::Double);
+    assert!(!m.cpu.cpu.stopped);
+    assert_ne!(m.cpu.cpu.reg.b, 0x00);
+}
+
+#[test]
+fn stop_without_a_switch_waits_for_the_joypad() {
+    // DI; STOP; INC B; JR -3
+    let mut m = common::machine(Header::default(), &[0xf3, 0x10, 0x00, 0x04, 0x18, 0xfd]);
+    m.cpu.cpu.reg.b = 0x00;
+    for _ in 0..16 {
+        m.next();
+    }
+    assert!(m.cpu.cpu.stopped);
+    assert_eq!((m.cpu.cpu.reg.pc, m.cpu.cpu.reg.b), (0x0153, 0x00));
+    m.mmu.borrow_mut().set(0xff00, 0x10);
+    m.mmu.borrow_mut().joypad.keydown(JoypadKey::A);
+    for _ in 0..16 {
+        m.next();
+    }
+    assert!(!m.cpu.cpu.stopped);
+    assert_ne!(m.cpu.cpu.reg.b, 0x00);
+}
+
+#[test]
+fn key1_reads_the_speed_on_the_gbc_only() {
+    let m = common::machine(Header::default(), &common::IDLE);
+    let mut mmu = m.mmu.borrow_mut();
+    assert_eq!(mmu.get(0xff4d), 0xff);
+    mmu.set(0xff4d, 0x01);
+    assert_eq!(mmu.get(0xff4d), 0xff);
+
+    let m = common::machine(CGB, &common::IDLE);
+    let mut mmu = m.mmu.borrow_mut();
+    assert_eq!(mmu.get(0xff4d), 0x7e);
+    mmu.set(0xff4d, 0x01);
+    assert_eq!(mmu.get(0xff4d), 0x7f);
+    assert!(mmu.switch_speed());
+    assert_eq!(mmu.get(0xff4d), 0xfe);
+}
+
+#[test]
+fn double_speed_runs_twice_the_instructions_per_frame() {
+    // DI; LD A,01h; LDH (4Dh),A; STOP; loop: INC B; JR loop, and the same with the switch left unarmed by NOPs.
+    let frame = |program: &[u8]| {
+        let mut m = common::machine(CGB, program);
+        m.run_frame();
+        m.run_frame();
+        m.last_frame_stats()
+    };
+    let double = frame(&[0xf3, 0x3e, 0x01, 0xe0, 0x4d, 0x10, 0x00, 0x04, 0x18, 0xfd]);
+    let normal = frame(&[0xf3, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x18, 0xfd]);
+    assert!((70220..=70228).contains(&normal.cycles), "{:?}", normal);
+    assert!((140440..=140456).contains(&double.cycles), "{:?}", double);
+    assert!(
+        double.instructions >= 2 * normal.instructions - 1,
+        "{:?} {:?}",
+        double,
+        normal
+    );
+    assert_eq!(double.mode_entries[1], 1);
+}
+
+// Run program for two frames to settle, then one more. Returns the TIMA increments of that frame and the position of
+// the LCD at its start and end, in dots from the start of line 0.
+fn timer_frame(program: &[u8]) -> (u8, u32, u32) {
+    let mut m = common::machine(CGB, program);
+    m.run_frame();
+    m.run_frame();
+    let at = |m: &gameboy::motherboard::MotherBoard| {
+        let mmu = m.mmu.borrow();
+        u32::from(mmu.get(0xff44)) * 456 + mmu.gpu.dots()
+    };
+    let (tima, start) = (m.mmu.borrow().get(0xff05), at(&m));
+    m.run_frame();
+    let stats = m.last_frame_stats();
+    assert_eq!(stats.mode_entries, [144, 1, 144, 144]);
+    let ticks = m.mmu.borrow().get(0xff05).wrapping_sub(tima);
+    (ticks, start, at(&m))
+}
+
+#[test]
+fn double_speed_doubles_the_timer_but_not_the_frame() {
+    // DI; LD A,04h; LDH (07h),A; JR -2: TIMA at a cpu clock divided by 1024, 70224 / 1024 per frame.
+    let (normal, start, end) = timer_frame(&[0xf3, 0x3e, 0x04, 0xe0, 0x07, 0x18, 0xfe]);
+    assert!((68..=69).contains(&normal), "{}", normal);
+    assert!(end.wrapping_sub(start) < 8, "{} {}", start, end);
+    // DI; LD A,01h; LDH (4Dh),A; STOP; LD A,04h; LDH (07h),A; JR -2
+    let (double, start, end) = timer_frame(&[
+        0xf3, 0x3e, 0x01, 0xe0, 0x4d, 0x10, 0x00, 0x3e, 0x04, 0xe0, 0x07, 0x18, 0xfe,
+    ]);
+    // 140448 / 1024 per frame, give or take one for the phase of the timer the frame ends on.
+    assert!((136..=138).contains(&double), "{}", double);
+    // The same 70224 dots, the cpu runs twice the cycles.
+    assert!(end.wrapping_sub(start) < 8, "{} {}", start, end);
+}
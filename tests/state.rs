@@ -0,0 +1,85 @@
+mod common;
+
+use common::Header;
+use gameboy::memory::Memory;
+use gameboy::state::StateError;
+
+#[test]
+fn state_round_trip_mid_frame() {
+    let mut m = common::machine(Header::default(), &common::NOISE);
+    m.run_frame();
+    m.run_frame();
+    m.run_cycles(12345);
+    let state = m.save_state();
+
+    let mut copy = common::machine(Header::default(), &common::NOISE);
+    copy.load_state(&state).unwrap();
+    assert_eq!(copy.save_state(), state);
+    m.run_cycles(1000);
+    copy.run_cycles(1000);
+    assert!(m.mmu.borrow().gpu.framebuffer() == copy.mmu.borrow().gpu.framebuffer());
+    assert_eq!(m.run_frame().framebuffer, copy.run_frame().framebuffer);
+    assert_eq!(m.save_state(), copy.save_state());
+}
+
+#[test]
+fn bad_states_leave_the_machine_alone() {
+    let mut m = common::machine(Header::default(), &common::NOISE);
+    m.run_frame();
+    let state = m.save_state();
+    let mut other = common::machine(Header::default(), &common::IDLE);
+    let before = other.save_state();
+
+    assert!(matches!(other.load_state(b"nope"), Err(StateError::BadMagic)));
+    let mut stale = state.clone();
+    stale[4] = stale[4].wrapping_add(1);
+    assert!(matches!(other.load_state(&stale), Err(StateError::Version(_))));
+    let half = &state[..state.len() / 2];
+    assert!(matches!(other.load_state(half), Err(StateError::Truncated)));
+    assert_eq!(other.save_state(), before);
+}
+
+#[test]
+fn rewind_past_capacity() {
+    let mut m = common::machine(Header::default(), &common::NOISE);
+    // Three states, one every other frame.
+    m.enable_rewind(6, 2);
+    let frames: Vec<Vec<u8>> = (0..11).map(|_| m.run_frame().framebuffer.to_vec()).collect();
+    for &want in &[10, 8, 6] {
+        assert!(m.rewind_step_back());
+        assert_eq!(m.frame_count(), want);
+        assert!(m.mmu.borrow().gpu.framebuffer() == &frames[want as usize - 1][..]);
+    }
+    assert!(!m.rewind_step_back());
+    assert_eq!(m.frame_count(), 6);
+    assert!(m.rewind_step_forward());
+    assert_eq!(m.frame_count(), 8);
+    // Running on from a rewound state gives the frames seen the first time.
+    assert_eq!(m.run_frame().framebuffer, &frames[8][..]);
+}
+
+#[test]
+fn capture_mutate_restore() {
+    let mut m = common::machine(Header::default(), &common::NOISE);
+    m.run_frame();
+    let before = m.capture();
+    assert!(m.capture() == before);
+    assert_eq!(before.save_state(), &m.save_state()[..]);
+    // Run on and change the machine from outside as well.
+    for _ in 0..7 {
+        m.run_frame();
+    }
+    m.run_cycles(1234);
+    m.cpu.cpu.reg.b ^= 0xff;
+    m.mmu.borrow_mut().set(0xff06, 0x42);
+    let after = m.capture();
+    assert!(after != before);
+    assert!(after.reg != before.reg);
+    assert!(after.cycles > before.cycles);
+    assert_eq!(after.timer.tma, 0x42);
+    assert!(after.ppu != before.ppu);
+
+    m.restore(&before).unwrap();
+    assert!(m.capture() == before);
+    assert_eq!(m.save_state(), before.save_state());
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::Header;
+use gameboy::joypad::JoypadKey;
+use gameboy::memory::Memory;
+
+#[test]
+fn stop_freezes_div_until_a_button_is_pressed() {
+    // DI; LD A,10h; LDH (00h),A; STOP; loop: INC B; JR loop, with the buttons selected in P1.
+    let mut m = common::machine(
+        Header::default(),
+        &[0xf3, 0x3e, 0x10, 0xe0, 0x00, 0x10, 0x00, 0x04, 0x18, 0xfd],
+    );
+    m.run_frame();
+    assert!(m.cpu.cpu.is_stopped());
+    for _ in 0..3 {
+        m.run_cycles(70224);
+        assert!(m.cpu.cpu.is_stopped());
+        assert_eq!(m.cpu.cpu.reg.b, 0x00);
+        assert_eq!(m.mmu.borrow().get(0xff04), 0x00);
+    }
+
+    m.mmu.borrow_mut().joypad.keydown(JoypadKey::Start);
+    m.next();
+    assert!(!m.cpu.cpu.is_stopped());
+    assert_eq!(m.cpu.cpu.reg.pc, 0x0157);
+    let div = m.mmu.borrow().get(0xff04);
+    m.run_cycles(70224);
+    assert_ne!(m.cpu.cpu.reg.b, 0x00);
+    assert_eq!(m.mmu.borrow().get(0xff04).wrapping_sub(div), 0x12);
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::Header;
+use gameboy::convention::Term;
+use gameboy::memory::Memory;
+use gameboy::motherboard::MotherBoard;
+use std::cell::Cell;
+use std::rc::Rc;
+
+fn terms(cgb: u8, sgb: u8) -> (Term, Term) {
+    let rom = common::rom_with(
+        Header {
+            cgb,
+            sgb,
+            ..Header::default()
+        },
+        &[],
+    );
+    let plain = MotherBoard::from_bytes(rom.clone()).unwrap().mmu.borrow().term;
+    let auto = MotherBoard::auto(rom).unwrap().mmu.borrow().term;
+    (plain, auto)
+}
+
+#[test]
+fn only_auto_detects_the_super_gameboy() {
+    assert!(terms(0x00, 0x00) == (Term::GB, Term::GB));
+    assert!(terms(0x00, 0x03) == (Term::GB, Term::SGB));
+    assert!(terms(0x80, 0x00) == (Term::GBC, Term::GBC));
+    assert!(terms(0xc0, 0x03) == (Term::GBC, Term::GBC));
+    // Only the flags 80h and C0h mean a color game to auto, bit 7 alone does to the other constructors.
+    assert!(terms(0x84, 0x00) == (Term::GBC, Term::GB));
+}
+
+#[test]
+fn auto_reports_a_bad_rom() {
+    assert!(MotherBoard::auto(vec![0x00; 0x0100]).is_err());
+    let mut rom = common::rom(&[]);
+    rom[0x014d] ^= 0xff;
+    assert!(MotherBoard::auto(rom).is_err());
+}
+
+#[test]
+fn only_the_super_gameboy_decodes_command_packets() {
+    for &(sgb, want) in &[(0x03, 1), (0x00, 0)] {
+        let rom = common::rom_with(
+            Header {
+                sgb,
+                ..Header::default()
+            },
+            &[],
+        );
+        let m = MotherBoard::auto(rom).unwrap();
+        let count = Rc::new(Cell::new(0));
+        let c = count.clone();
+        m.mmu.borrow_mut().sgb.on_command(move |_| c.set(c.get() + 1));
+        // Reset, then 129 zeros: a packet of zeros and its stop bit.
+        let mut mmu = m.mmu.borrow_mut();
+        mmu.set(0xff00, 0x00);
+        mmu.set(0xff00, 0x30);
+        for _ in 0..129 {
+            mmu.set(0xff00, 0x20);
+            mmu.set(0xff00, 0x30);
+        }
+        assert_eq!(count.get(), want);
+    }
+}